@@ -0,0 +1,35 @@
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use std::fmt::Write;
+use whkd::parser::parse_str;
+
+const EXAMPLE: &str = include_str!("../fuzz/corpus/parse_str/example");
+
+/// A whkdrc with a few hundred bindings spread across modes, about as large as
+/// a whkdrc written by hand gets
+fn large() -> String {
+    let mut src = String::from(".shell pwsh\n.version 3\n.alias kc komorebic\n");
+    for mode in ["default", "window", "resize", "launch"] {
+        for key in 'a'..='z' {
+            writeln!(
+                src,
+                "{mode} > alt + shift + {key} ~tag(bench) : kc focus {key} ## {key}"
+            )
+            .unwrap();
+            writeln!(src, "{mode} > ctrl + alt + {key} ; {mode}").unwrap();
+        }
+    }
+
+    src
+}
+
+fn parse(c: &mut Criterion) {
+    c.bench_function("parse example", |b| b.iter(|| parse_str(EXAMPLE)));
+
+    let large = large();
+    c.bench_function("parse large", |b| b.iter(|| parse_str(&large)));
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// whkdrc is read as UTF-8, so only input which is valid UTF-8 reaches the parser
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let _ = whkd::parser::parse_str(src);
+    }
+});
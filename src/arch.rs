@@ -0,0 +1,79 @@
+use windows_sys::Win32::Foundation::BOOL;
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::System::LibraryLoader::GetProcAddress;
+use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE;
+use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_AMD64;
+use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_ARM64;
+use windows_sys::Win32::System::SystemInformation::IMAGE_FILE_MACHINE_I386;
+use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+/// The architecture whkd was built for
+pub const BUILT_FOR: IMAGE_FILE_MACHINE = if cfg!(target_arch = "aarch64") {
+    IMAGE_FILE_MACHINE_ARM64
+} else if cfg!(target_arch = "x86") {
+    IMAGE_FILE_MACHINE_I386
+} else {
+    IMAGE_FILE_MACHINE_AMD64
+};
+
+type IsWow64Process2 = unsafe extern "system" fn(
+    process: HANDLE,
+    process_machine: *mut IMAGE_FILE_MACHINE,
+    native_machine: *mut IMAGE_FILE_MACHINE,
+) -> BOOL;
+
+/// The architecture of the machine, which isn't the one whkd was built for
+/// when it runs emulated, e.g. the `x86_64` build on a Snapdragon laptop
+pub fn native_machine() -> Option<IMAGE_FILE_MACHINE> {
+    let kernel32 = "kernel32.dll\0".encode_utf16().collect::<Vec<u16>>();
+
+    unsafe {
+        // IsWow64Process2 only exists since Windows 10 1709, and linking against
+        // it would keep whkd from starting on older versions
+        let module = GetModuleHandleW(kernel32.as_ptr());
+        if module == 0 {
+            return None;
+        }
+
+        let function = GetProcAddress(module, c"IsWow64Process2".as_ptr().cast())?;
+        let is_wow64_process2 =
+            std::mem::transmute::<unsafe extern "system" fn() -> isize, IsWow64Process2>(function);
+
+        let mut process_machine = 0;
+        let mut native_machine = 0;
+        let queried = is_wow64_process2(
+            GetCurrentProcess(),
+            std::ptr::addr_of_mut!(process_machine),
+            std::ptr::addr_of_mut!(native_machine),
+        ) != 0;
+
+        queried.then_some(native_machine)
+    }
+}
+
+/// The architecture of the machine when whkd runs emulated on it
+pub fn emulated_on() -> Option<IMAGE_FILE_MACHINE> {
+    native_machine().filter(|machine| *machine == IMAGE_FILE_MACHINE_ARM64 && BUILT_FOR != *machine)
+}
+
+pub const fn name(machine: IMAGE_FILE_MACHINE) -> &'static str {
+    match machine {
+        IMAGE_FILE_MACHINE_ARM64 => "ARM64",
+        IMAGE_FILE_MACHINE_AMD64 => "x86_64",
+        IMAGE_FILE_MACHINE_I386 => "x86",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name() {
+        assert_eq!(name(IMAGE_FILE_MACHINE_ARM64), "ARM64");
+        assert_ne!(name(BUILT_FOR), "unknown");
+        assert_eq!(name(0x1234), "unknown");
+    }
+}
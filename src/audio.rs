@@ -0,0 +1,237 @@
+use crate::log;
+use crate::parser::InternalAction;
+use crate::runtime;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use windows_sys::core::GUID;
+use windows_sys::core::HRESULT;
+use windows_sys::Win32::Foundation::BOOL;
+use windows_sys::Win32::Media::Audio::eConsole;
+use windows_sys::Win32::Media::Audio::eRender;
+use windows_sys::Win32::Media::Audio::MMDeviceEnumerator;
+use windows_sys::Win32::System::Com::CoCreateInstance;
+use windows_sys::Win32::System::Com::CoInitializeEx;
+use windows_sys::Win32::System::Com::CoUninitialize;
+use windows_sys::Win32::System::Com::CLSCTX_ALL;
+use windows_sys::Win32::System::Com::COINIT_MULTITHREADED;
+
+// windows-sys leaves out COM interfaces, so the few methods of Core Audio which
+// are used are declared here in the order of their vtables
+
+const IID_IMM_DEVICE_ENUMERATOR: GUID = GUID::from_u128(0xa956_64d2_9614_4f35_a746_de8d_b636_17e6);
+const IID_IAUDIO_ENDPOINT_VOLUME: GUID = GUID::from_u128(0x5cdf_2c82_841e_4546_9722_0cf7_4078_229a);
+
+#[repr(C)]
+struct UnknownVtbl {
+    query_interface: usize,
+    add_ref: usize,
+    release: unsafe extern "system" fn(*mut c_void) -> u32,
+}
+
+#[repr(C)]
+struct DeviceEnumeratorVtbl {
+    unknown: UnknownVtbl,
+    enum_audio_endpoints: usize,
+    get_default_audio_endpoint:
+        unsafe extern "system" fn(*mut c_void, i32, i32, *mut *mut c_void) -> HRESULT,
+}
+
+#[repr(C)]
+struct DeviceVtbl {
+    unknown: UnknownVtbl,
+    activate: unsafe extern "system" fn(
+        *mut c_void,
+        *const GUID,
+        u32,
+        *const c_void,
+        *mut *mut c_void,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct EndpointVolumeVtbl {
+    unknown: UnknownVtbl,
+    register_control_change_notify: usize,
+    unregister_control_change_notify: usize,
+    get_channel_count: usize,
+    set_master_volume_level: usize,
+    set_master_volume_level_scalar:
+        unsafe extern "system" fn(*mut c_void, f32, *const GUID) -> HRESULT,
+    get_master_volume_level: usize,
+    get_master_volume_level_scalar: unsafe extern "system" fn(*mut c_void, *mut f32) -> HRESULT,
+    set_channel_volume_level: usize,
+    set_channel_volume_level_scalar: usize,
+    get_channel_volume_level: usize,
+    get_channel_volume_level_scalar: usize,
+    set_mute: unsafe extern "system" fn(*mut c_void, BOOL, *const GUID) -> HRESULT,
+    get_mute: unsafe extern "system" fn(*mut c_void, *mut BOOL) -> HRESULT,
+}
+
+/// A COM interface which is released when it is dropped
+struct Interface<V> {
+    raw: *mut c_void,
+    vtbl: PhantomData<V>,
+}
+
+impl<V> Interface<V> {
+    const fn new(raw: *mut c_void) -> Self {
+        Self {
+            raw,
+            vtbl: PhantomData,
+        }
+    }
+
+    const fn vtbl(&self) -> &V {
+        unsafe { &**self.raw.cast::<*const V>() }
+    }
+}
+
+impl<V> Drop for Interface<V> {
+    fn drop(&mut self) {
+        unsafe { ((**self.raw.cast::<*const UnknownVtbl>()).release)(self.raw) };
+    }
+}
+
+fn check(result: HRESULT, doing: &str) -> Result<()> {
+    if result < 0 {
+        return Err(eyre!("could not {doing}: HRESULT {result:#010x}"));
+    }
+
+    Ok(())
+}
+
+/// Perform an audio action away from the event loop, which needs COM to be set
+/// up on the thread that performs it
+pub fn perform(action: InternalAction) {
+    runtime::spawn_blocking(move || {
+        #[allow(clippy::cast_sign_loss)]
+        let initialized =
+            unsafe { CoInitializeEx(std::ptr::null(), COINIT_MULTITHREADED as u32) } >= 0;
+
+        let result = match &action {
+            InternalAction::Volume(step) => change_volume(*step),
+            InternalAction::ToggleMute => toggle_mute(),
+            _ => Ok(()),
+        };
+
+        if initialized {
+            unsafe { CoUninitialize() };
+        }
+
+        if let Err(error) = result {
+            log::error(format!("could not perform {action}: {error}"));
+        }
+    });
+}
+
+/// The volume control of the default output device
+fn endpoint_volume() -> Result<Interface<EndpointVolumeVtbl>> {
+    unsafe {
+        let mut enumerator = std::ptr::null_mut();
+        check(
+            CoCreateInstance(
+                &MMDeviceEnumerator,
+                std::ptr::null_mut(),
+                CLSCTX_ALL,
+                &IID_IMM_DEVICE_ENUMERATOR,
+                std::ptr::addr_of_mut!(enumerator),
+            ),
+            "list the audio devices",
+        )?;
+        let enumerator = Interface::<DeviceEnumeratorVtbl>::new(enumerator);
+
+        let mut device = std::ptr::null_mut();
+        check(
+            (enumerator.vtbl().get_default_audio_endpoint)(
+                enumerator.raw,
+                eRender,
+                eConsole,
+                std::ptr::addr_of_mut!(device),
+            ),
+            "find the default output device",
+        )?;
+        let device = Interface::<DeviceVtbl>::new(device);
+
+        let mut volume = std::ptr::null_mut();
+        check(
+            (device.vtbl().activate)(
+                device.raw,
+                &IID_IAUDIO_ENDPOINT_VOLUME,
+                CLSCTX_ALL,
+                std::ptr::null(),
+                std::ptr::addr_of_mut!(volume),
+            ),
+            "open the volume control of the default output device",
+        )?;
+
+        Ok(Interface::new(volume))
+    }
+}
+
+/// The volume after a step in percentage points, rounded to whole percentages
+#[allow(clippy::cast_precision_loss)]
+fn stepped(volume: f32, step: i32) -> f32 {
+    ((volume * 100.0).round() + step as f32).clamp(0.0, 100.0) / 100.0
+}
+
+fn change_volume(step: i32) -> Result<()> {
+    let volume = endpoint_volume()?;
+    let vtbl = volume.vtbl();
+
+    unsafe {
+        let mut level = 0.0;
+        check(
+            (vtbl.get_master_volume_level_scalar)(volume.raw, std::ptr::addr_of_mut!(level)),
+            "get the volume",
+        )?;
+        check(
+            (vtbl.set_master_volume_level_scalar)(
+                volume.raw,
+                stepped(level, step),
+                std::ptr::null(),
+            ),
+            "set the volume",
+        )?;
+
+        // raising the volume unmutes, like the volume keys of a keyboard do
+        if step > 0 {
+            check((vtbl.set_mute)(volume.raw, 0, std::ptr::null()), "unmute")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn toggle_mute() -> Result<()> {
+    let volume = endpoint_volume()?;
+    let vtbl = volume.vtbl();
+
+    unsafe {
+        let mut muted = 0;
+        check(
+            (vtbl.get_mute)(volume.raw, std::ptr::addr_of_mut!(muted)),
+            "get the mute state",
+        )?;
+        check(
+            (vtbl.set_mute)(volume.raw, BOOL::from(muted == 0), std::ptr::null()),
+            "toggle mute",
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stepped() {
+        assert!((stepped(0.5, 5) - 0.55).abs() < f32::EPSILON);
+        assert!((stepped(0.333, -5) - 0.28).abs() < f32::EPSILON);
+        assert!((stepped(0.98, 5) - 1.0).abs() < f32::EPSILON);
+        assert!(stepped(0.02, -5).abs() < f32::EPSILON);
+    }
+}
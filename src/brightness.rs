@@ -0,0 +1,119 @@
+use crate::log;
+use crate::runtime;
+use crate::window;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use windows_sys::Win32::Devices::Display::DestroyPhysicalMonitors;
+use windows_sys::Win32::Devices::Display::GetMonitorBrightness;
+use windows_sys::Win32::Devices::Display::GetNumberOfPhysicalMonitorsFromHMONITOR;
+use windows_sys::Win32::Devices::Display::GetPhysicalMonitorsFromHMONITOR;
+use windows_sys::Win32::Devices::Display::SetMonitorBrightness;
+use windows_sys::Win32::Devices::Display::PHYSICAL_MONITOR;
+use windows_sys::Win32::Graphics::Gdi::HMONITOR;
+
+/// Change the brightness of every monitor away from the event loop, as talking
+/// to monitors over DDC/CI takes a while
+pub fn change(step: i32) {
+    runtime::spawn_blocking(move || {
+        let mut changed = 0;
+        let mut errors = vec![];
+        for (monitor, _) in window::monitors() {
+            match change_monitor(monitor, step) {
+                Ok(count) => changed += count,
+                Err(error) => errors.push(error),
+            }
+        }
+
+        // monitors which don't support DDC/CI, such as most built-in laptop
+        // displays, are only worth an error if no monitor could be changed
+        if changed == 0 {
+            let reason = errors.first().map_or_else(
+                || String::from("there are no monitors"),
+                ToString::to_string,
+            );
+            log::error(format!("could not change the brightness: {reason}"));
+        }
+    });
+}
+
+/// The brightness after a step in percentage points of the range of a monitor
+fn stepped(minimum: u32, current: u32, maximum: u32, step: i32) -> u32 {
+    let range = i64::from(maximum - minimum);
+    let target = i64::from(current) + range * i64::from(step) / 100;
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let target = target.clamp(i64::from(minimum), i64::from(maximum)) as u32;
+    target
+}
+
+/// Change the brightness of the physical monitors behind a display monitor,
+/// returning how many of them were changed
+fn change_monitor(monitor: HMONITOR, step: i32) -> Result<usize> {
+    let mut count = 0;
+    if unsafe { GetNumberOfPhysicalMonitorsFromHMONITOR(monitor, std::ptr::addr_of_mut!(count)) }
+        == 0
+    {
+        return Err(eyre!("could not count the physical monitors"));
+    }
+
+    let mut physical = (0..count)
+        .map(|_| PHYSICAL_MONITOR {
+            hPhysicalMonitor: 0,
+            szPhysicalMonitorDescription: [0; 128],
+        })
+        .collect::<Vec<_>>();
+
+    if unsafe { GetPhysicalMonitorsFromHMONITOR(monitor, count, physical.as_mut_ptr()) } == 0 {
+        return Err(eyre!("could not open the physical monitors"));
+    }
+
+    let mut changed = 0;
+    let mut error = None;
+    for monitor in &physical {
+        let (mut minimum, mut current, mut maximum) = (0, 0, 0);
+        let supported = unsafe {
+            GetMonitorBrightness(
+                monitor.hPhysicalMonitor,
+                std::ptr::addr_of_mut!(minimum),
+                std::ptr::addr_of_mut!(current),
+                std::ptr::addr_of_mut!(maximum),
+            )
+        } != 0;
+
+        if !supported {
+            error = Some(eyre!(
+                "the monitor doesn't support changing its brightness over DDC/CI"
+            ));
+            continue;
+        }
+
+        let target = stepped(minimum, current, maximum, step);
+        if unsafe { SetMonitorBrightness(monitor.hPhysicalMonitor, target) } == 0 {
+            error = Some(eyre!("the monitor refused to change its brightness"));
+            continue;
+        }
+
+        changed += 1;
+    }
+
+    unsafe { DestroyPhysicalMonitors(count, physical.as_ptr()) };
+
+    match error {
+        Some(error) if changed == 0 => Err(error),
+        _ => Ok(changed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stepped() {
+        assert_eq!(stepped(0, 50, 100, 10), 60);
+        assert_eq!(stepped(0, 50, 100, -10), 40);
+        assert_eq!(stepped(0, 95, 100, 10), 100);
+        assert_eq!(stepped(20, 25, 220, -10), 20);
+        assert_eq!(stepped(0, 30, 200, 10), 50);
+    }
+}
@@ -0,0 +1,361 @@
+pub use crate::whkdrc::Backend;
+pub use crate::whkdrc::Exec;
+pub use crate::whkdrc::Shell;
+pub use crate::whkdrc::Whkdrc;
+
+use crate::parser::parser;
+use crate::whkdrc::CURRENT_VERSION;
+use chumsky::Parser;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::time::Duration;
+
+/// Assembles a whkdrc in code, e.g. for a launcher or an installer, which is
+/// printed as whkdrc by `Display` and checked by parsing what is printed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhkdrcBuilder {
+    shell: Shell,
+    shell_args: Vec<String>,
+    exec: Option<Exec>,
+    backend: Option<Backend>,
+    timeout: Option<Duration>,
+    options: Vec<String>,
+    aliases: Vec<(String, String)>,
+    bindings: Vec<HotkeyBindingBuilder>,
+}
+
+impl WhkdrcBuilder {
+    #[must_use]
+    pub const fn new(shell: Shell) -> Self {
+        Self {
+            shell,
+            shell_args: vec![],
+            exec: None,
+            backend: None,
+            timeout: None,
+            options: vec![],
+            aliases: vec![],
+            bindings: vec![],
+        }
+    }
+
+    /// An extra argument for the shell, e.g. `-NoProfile`
+    #[must_use]
+    pub fn shell_arg(mut self, arg: &str) -> Self {
+        self.shell_args.push(arg.to_string());
+        self
+    }
+
+    #[must_use]
+    pub const fn exec(mut self, exec: Exec) -> Self {
+        self.exec = Some(exec);
+        self
+    }
+
+    #[must_use]
+    pub const fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// How long a mode other than the default one waits for the next key
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// One of the names of `.options`, e.g. `check_updates`
+    #[must_use]
+    pub fn option(mut self, name: &str) -> Self {
+        self.options.push(name.to_string());
+        self
+    }
+
+    /// A word which is replaced at the start of the commands, e.g. `kc` for
+    /// `komorebic`
+    #[must_use]
+    pub fn alias(mut self, name: &str, expansion: &str) -> Self {
+        self.aliases.push((name.to_string(), expansion.to_string()));
+        self
+    }
+
+    #[must_use]
+    pub fn binding(mut self, binding: HotkeyBindingBuilder) -> Self {
+        self.bindings.push(binding);
+        self
+    }
+
+    /// Parse the whkdrc which is printed, as whkd would read it from a file
+    pub fn build(&self) -> Result<Whkdrc> {
+        if let Some(binding) = self.bindings.iter().find(|binding| {
+            binding
+                .command
+                .as_ref()
+                .is_some_and(|command| command.contains("```"))
+        }) {
+            return Err(eyre!(
+                "the command of {} can't contain ```",
+                binding.keys.join(" + ")
+            ));
+        }
+
+        parser()
+            .parse(self.to_string())
+            .map_err(|error| eyre!("could not parse whkdrc: {:?}", error))
+    }
+}
+
+impl Display for WhkdrcBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, ".shell {}", self.shell)?;
+        for arg in &self.shell_args {
+            write!(f, " {arg}")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, ".version {CURRENT_VERSION}")?;
+
+        match self.exec {
+            Some(Exec::Session) => writeln!(f, ".exec session")?,
+            Some(Exec::Direct) => writeln!(f, ".exec direct")?,
+            None => {}
+        }
+
+        match self.backend {
+            Some(Backend::Register) => writeln!(f, ".backend register")?,
+            Some(Backend::Hook) => writeln!(f, ".backend hook")?,
+            None => {}
+        }
+
+        if let Some(timeout) = self.timeout {
+            writeln!(f, ".timeout {}", timeout.as_millis())?;
+        }
+
+        if !self.options.is_empty() {
+            writeln!(f, ".options {}", self.options.join(", "))?;
+        }
+
+        for (name, expansion) in &self.aliases {
+            writeln!(f, ".alias {name} {expansion}")?;
+        }
+
+        writeln!(f)?;
+        for binding in &self.bindings {
+            writeln!(f, "{binding}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A binding of a `WhkdrcBuilder`, printed as a line of whkdrc
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotkeyBindingBuilder {
+    keys: Vec<String>,
+    mode: Option<String>,
+    command: Option<String>,
+    switch_mode: Option<String>,
+    tags: Vec<String>,
+    passthrough: bool,
+    wait: bool,
+    name: Option<String>,
+    description: Option<String>,
+}
+
+impl HotkeyBindingBuilder {
+    /// A binding of a chord such as `["alt", "shift", "h"]`
+    #[must_use]
+    pub fn new(keys: &[&str]) -> Self {
+        Self {
+            keys: keys.iter().map(ToString::to_string).collect(),
+            mode: None,
+            command: None,
+            switch_mode: None,
+            tags: vec![],
+            passthrough: false,
+            wait: false,
+            name: None,
+            description: None,
+        }
+    }
+
+    /// Bind the chord in a mode other than the default one
+    #[must_use]
+    pub fn mode(mut self, mode: &str) -> Self {
+        self.mode = Some(mode.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = Some(command.to_string());
+        self
+    }
+
+    /// Switch to a mode once the binding fires, `default` being the default mode
+    #[must_use]
+    pub fn switch_mode(mut self, mode: &str) -> Self {
+        self.switch_mode = Some(mode.to_string());
+        self
+    }
+
+    #[must_use]
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    /// Let the chord through to the focused app as well
+    #[must_use]
+    pub const fn passthrough(mut self) -> Self {
+        self.passthrough = true;
+        self
+    }
+
+    /// Wait for the command to exit, to report when it fails
+    #[must_use]
+    pub const fn wait(mut self) -> Self {
+        self.wait = true;
+        self
+    }
+
+    /// The handle which `whkd trigger` refers to the binding by
+    #[must_use]
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// What the binding does, e.g. in `whkd help`
+    #[must_use]
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+}
+
+impl Display for HotkeyBindingBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(mode) = &self.mode {
+            write!(f, "{mode} > ")?;
+        }
+
+        write!(f, "{}", self.keys.join(" + "))?;
+
+        if !self.tags.is_empty() {
+            write!(f, " ~tag({})", self.tags.join(", "))?;
+        }
+
+        if self.passthrough {
+            write!(f, " ~passthrough")?;
+        }
+
+        if self.wait {
+            write!(f, " ~wait")?;
+        }
+
+        if let Some(name) = &self.name {
+            write!(f, " ~name({name})")?;
+        }
+
+        if let Some(command) = &self.command {
+            // `;` switches modes and `#` starts a comment in whkdrc unless the
+            // command is fenced
+            if command.contains([';', '#', '\n', '\\']) {
+                write!(f, " : ```{command}```")?;
+            } else {
+                write!(f, " : {command}")?;
+            }
+        }
+
+        if let Some(mode) = &self.switch_mode {
+            write!(f, " ; {mode}")?;
+        }
+
+        if let Some(description) = &self.description {
+            write!(f, " ## {description}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let builder = WhkdrcBuilder::new(Shell::Pwsh)
+            .shell_arg("-NoProfile")
+            .backend(Backend::Hook)
+            .timeout(Duration::from_secs(2))
+            .option("verbose")
+            .alias("kc", "komorebic")
+            .binding(
+                HotkeyBindingBuilder::new(&["alt", "h"])
+                    .command("kc focus left")
+                    .tag("komorebi")
+                    .description("Focus left"),
+            )
+            .binding(HotkeyBindingBuilder::new(&["alt", "r"]).switch_mode("resize"))
+            .binding(
+                HotkeyBindingBuilder::new(&["escape"])
+                    .mode("resize")
+                    .switch_mode("default"),
+            )
+            .binding(
+                HotkeyBindingBuilder::new(&["alt", "e"])
+                    .command("echo one; echo #two")
+                    .wait(),
+            );
+
+        assert_eq!(
+            builder.to_string(),
+            format!(
+                ".shell pwsh -NoProfile\n.version {CURRENT_VERSION}\n.backend hook\n.timeout 2000\n\
+                 .options verbose\n.alias kc komorebic\n\n\
+                 alt + h ~tag(komorebi) : kc focus left ## Focus left\n\
+                 alt + r ; resize\n\
+                 resize > escape ; default\n\
+                 alt + e ~wait : ```echo one; echo #two```\n"
+            )
+        );
+
+        let whkdrc = builder.build().unwrap();
+        assert_eq!(whkdrc.shell_args, ["-NoProfile"]);
+        assert_eq!(whkdrc.backend, Backend::Hook);
+        assert!(whkdrc.options.verbose);
+        assert_eq!(whkdrc.bindings.len(), 4);
+        assert_eq!(
+            whkdrc.bindings[0].command.as_deref(),
+            Some("komorebic focus left")
+        );
+        assert_eq!(whkdrc.bindings[0].tags, ["komorebi"]);
+        assert_eq!(
+            whkdrc.bindings[0].description.as_deref(),
+            Some("Focus left")
+        );
+        assert_eq!(whkdrc.bindings[2].mode.as_deref(), Some("resize"));
+        assert_eq!(
+            whkdrc.bindings[3].command.as_deref(),
+            Some("echo one; echo #two")
+        );
+        assert!(whkdrc.bindings[3].wait);
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        let unknown_option = WhkdrcBuilder::new(Shell::Cmd)
+            .option("no_such_option")
+            .binding(HotkeyBindingBuilder::new(&["alt", "a"]).command("echo a"));
+        assert!(unknown_option.build().is_err());
+
+        let fenced = WhkdrcBuilder::new(Shell::Cmd)
+            .binding(HotkeyBindingBuilder::new(&["alt", "a"]).command("echo ```"));
+        assert!(fenced.build().is_err());
+    }
+}
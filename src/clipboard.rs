@@ -0,0 +1,150 @@
+use crate::hook;
+use crate::input;
+use crate::log;
+use crate::parser::InternalAction;
+use crate::runtime;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::time::Duration;
+use windows_sys::Win32::Foundation::GlobalFree;
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::Foundation::HGLOBAL;
+use windows_sys::Win32::System::DataExchange::CloseClipboard;
+use windows_sys::Win32::System::DataExchange::EmptyClipboard;
+use windows_sys::Win32::System::DataExchange::GetClipboardData;
+use windows_sys::Win32::System::DataExchange::OpenClipboard;
+use windows_sys::Win32::System::DataExchange::SetClipboardData;
+use windows_sys::Win32::System::Memory::GlobalAlloc;
+use windows_sys::Win32::System::Memory::GlobalLock;
+use windows_sys::Win32::System::Memory::GlobalUnlock;
+use windows_sys::Win32::System::Memory::GMEM_MOVEABLE;
+use windows_sys::Win32::System::Ole::CF_UNICODETEXT;
+
+/// How often opening the clipboard is attempted while another app holds it
+const OPEN_ATTEMPTS: u32 = 10;
+
+/// Open while it exists, so that the clipboard is closed on every return path
+struct Clipboard;
+
+impl Clipboard {
+    fn open() -> Result<Self> {
+        for _ in 0..OPEN_ATTEMPTS {
+            if unsafe { OpenClipboard(0) } != 0 {
+                return Ok(Self);
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        Err(eyre!(
+            "could not open the clipboard: {}",
+            std::io::Error::last_os_error()
+        ))
+    }
+}
+
+impl Drop for Clipboard {
+    fn drop(&mut self) {
+        unsafe { CloseClipboard() };
+    }
+}
+
+/// Perform a clipboard action away from the event loop, as another app can hold
+/// the clipboard open and typing out its text takes a while
+pub fn perform(action: InternalAction) {
+    runtime::spawn_blocking(move || {
+        let result = match &action {
+            InternalAction::ClipboardSet(text) => set(text),
+            InternalAction::ClipboardPaste => paste_as_keystrokes(),
+            InternalAction::ClipboardClear => clear(),
+            _ => Ok(()),
+        };
+
+        if let Err(error) = result {
+            log::error(format!("could not perform {action}: {error}"));
+        }
+    });
+}
+
+fn set(text: &str) -> Result<()> {
+    let wide = text.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let _clipboard = Clipboard::open()?;
+
+    unsafe {
+        EmptyClipboard();
+
+        let memory = GlobalAlloc(GMEM_MOVEABLE, std::mem::size_of_val(wide.as_slice()));
+        if memory.is_null() {
+            return Err(eyre!("could not allocate memory for the clipboard"));
+        }
+
+        let target = GlobalLock(memory).cast::<u16>();
+        if target.is_null() {
+            GlobalFree(memory);
+            return Err(eyre!("could not lock the memory for the clipboard"));
+        }
+
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), target, wide.len());
+        GlobalUnlock(memory);
+
+        // the memory belongs to the clipboard once it has been set
+        if SetClipboardData(u32::from(CF_UNICODETEXT), memory as HANDLE) == 0 {
+            let error = std::io::Error::last_os_error();
+            GlobalFree(memory);
+            return Err(eyre!("could not set the clipboard: {error}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn get() -> Result<String> {
+    let _clipboard = Clipboard::open()?;
+
+    unsafe {
+        let memory = GetClipboardData(u32::from(CF_UNICODETEXT)) as HGLOBAL;
+        if memory.is_null() {
+            return Err(eyre!("there is no text on the clipboard"));
+        }
+
+        let source = GlobalLock(memory).cast::<u16>().cast_const();
+        if source.is_null() {
+            return Err(eyre!("could not lock the text on the clipboard"));
+        }
+
+        let mut len = 0;
+        while *source.add(len) != 0 {
+            len += 1;
+        }
+
+        let text = String::from_utf16_lossy(std::slice::from_raw_parts(source, len));
+        GlobalUnlock(memory);
+
+        Ok(text)
+    }
+}
+
+fn clear() -> Result<()> {
+    let _clipboard = Clipboard::open()?;
+    if unsafe { EmptyClipboard() } == 0 {
+        return Err(eyre!(
+            "could not clear the clipboard: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Type out the text on the clipboard for apps which don't accept pasting, such
+/// as some terminals and remote desktop sessions
+fn paste_as_keystrokes() -> Result<()> {
+    let text = get()?;
+
+    // the modifiers of the chord would otherwise turn the text into shortcuts
+    if !input::send_text(&text, &hook::held_modifiers()) {
+        return Err(eyre!("could not type out the text on the clipboard"));
+    }
+
+    Ok(())
+}
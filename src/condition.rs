@@ -0,0 +1,204 @@
+use crate::idle;
+use crate::log;
+use crate::session;
+use crate::whkdrc::Shell;
+use chrono::Datelike;
+use chrono::Local;
+use chrono::NaiveTime;
+use chrono::Weekday;
+use std::time::Duration;
+
+/// A guard attached to a binding with `~when(...)`, `~when-cmd(...)`,
+/// `~between(...)`, `~days(...)`, `~only-when-idle(...)` or `~only-when-active`,
+/// which is evaluated every time the chord is pressed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Condition {
+    When(Expression),
+    WhenCmd {
+        command: String,
+        expected: String,
+    },
+    /// Local time of day, wrapping around midnight when `start` is after `end`
+    Between {
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+    Days(Vec<Weekday>),
+    /// There has been no input for at least this long, which mostly matters
+    /// for bindings fired through `whkd trigger` or MQTT
+    Idle(Duration),
+    /// There has been input within this long
+    Active(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expression {
+    /// A single operand holds when it is not empty, e.g. `$env:WHKD_WORK`
+    NotEmpty(Operand),
+    Equal(Operand, Operand),
+    NotEqual(Operand, Operand),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Env(String),
+    Literal(String),
+}
+
+impl Operand {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+
+        if let Some(name) = value.strip_prefix("$env:") {
+            if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Ok(Self::Env(name.to_string()));
+            }
+        }
+
+        for quote in ['\'', '"'] {
+            if let Some(literal) = value
+                .strip_prefix(quote)
+                .and_then(|value| value.strip_suffix(quote))
+            {
+                return Ok(Self::Literal(literal.to_string()));
+            }
+        }
+
+        Err(format!(
+            "invalid operand '{value}', expected $env:NAME or a quoted string"
+        ))
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Self::Env(name) => std::env::var(name).unwrap_or_default(),
+            Self::Literal(literal) => literal.clone(),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        if let Some((left, right)) = expression.split_once("==") {
+            Ok(Self::Equal(Operand::parse(left)?, Operand::parse(right)?))
+        } else if let Some((left, right)) = expression.split_once("!=") {
+            Ok(Self::NotEqual(
+                Operand::parse(left)?,
+                Operand::parse(right)?,
+            ))
+        } else {
+            Ok(Self::NotEmpty(Operand::parse(expression)?))
+        }
+    }
+
+    fn holds(&self) -> bool {
+        match self {
+            Self::NotEmpty(operand) => !operand.value().is_empty(),
+            Self::Equal(left, right) => left.value() == right.value(),
+            Self::NotEqual(left, right) => left.value() != right.value(),
+        }
+    }
+}
+
+/// Parse a comma-separated list of days and day ranges, e.g. `mon-fri, sun`
+pub fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+    let day = |day: &str| {
+        day.trim()
+            .parse::<Weekday>()
+            .map_err(|_| format!("invalid day '{}'", day.trim()))
+    };
+
+    let mut parsed = vec![];
+    for entry in days.split(',') {
+        if let Some((first, last)) = entry.split_once('-') {
+            let (mut current, last) = (day(first)?, day(last)?);
+            parsed.push(current);
+            while current != last {
+                current = current.succ();
+                parsed.push(current);
+            }
+        } else {
+            parsed.push(day(entry)?);
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn between(start: NaiveTime, end: NaiveTime, time: NaiveTime) -> bool {
+    if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    }
+}
+
+/// How long the command of a `~when-cmd` can take before the condition is taken
+/// not to hold
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl Condition {
+    /// Whether checking the condition runs a command in a new shell process,
+    /// which the event loop mustn't wait for
+    pub const fn runs_command(&self) -> bool {
+        matches!(self, Self::WhenCmd { .. })
+    }
+
+    pub fn holds(&self, shell: Shell) -> bool {
+        match self {
+            Self::Between { start, end } => between(*start, *end, Local::now().time()),
+            Self::Days(days) => days.contains(&Local::now().weekday()),
+            Self::Idle(idle) => idle::idle_time() >= *idle,
+            Self::Active(within) => idle::idle_time() < *within,
+            Self::When(expression) => expression.holds(),
+            Self::WhenCmd { command, expected } => {
+                match session::output_within(shell, command, COMMAND_TIMEOUT) {
+                    Ok(output) => output.trim() == expected,
+                    Err(error) => {
+                        log::error(format!("could not run condition '{command}': {error}"));
+                        false
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_between() {
+        let time = |time| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+
+        assert!(between(time("09:00"), time("17:00"), time("12:30")));
+        assert!(!between(time("09:00"), time("17:00"), time("17:00")));
+        assert!(between(time("22:00"), time("06:00"), time("23:15")));
+        assert!(between(time("22:00"), time("06:00"), time("05:59")));
+        assert!(!between(time("22:00"), time("06:00"), time("12:00")));
+    }
+
+    #[test]
+    fn test_parse_days() {
+        assert_eq!(
+            parse_days("mon-fri").unwrap(),
+            vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri
+            ]
+        );
+        assert_eq!(
+            parse_days("sat, sun").unwrap(),
+            vec![Weekday::Sat, Weekday::Sun]
+        );
+        assert_eq!(
+            parse_days("fri-mon").unwrap(),
+            vec![Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]
+        );
+        assert!(parse_days("someday").is_err());
+    }
+}
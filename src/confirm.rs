@@ -0,0 +1,67 @@
+use crate::event_loop::EventLoopProxy;
+use crate::log;
+use crate::runtime;
+use crate::HkmData;
+use crate::UserEvent;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use windows_sys::Win32::UI::WindowsAndMessaging::MessageBoxW;
+use windows_sys::Win32::UI::WindowsAndMessaging::IDYES;
+use windows_sys::Win32::UI::WindowsAndMessaging::MB_DEFBUTTON2;
+use windows_sys::Win32::UI::WindowsAndMessaging::MB_ICONWARNING;
+use windows_sys::Win32::UI::WindowsAndMessaging::MB_SETFOREGROUND;
+use windows_sys::Win32::UI::WindowsAndMessaging::MB_TOPMOST;
+use windows_sys::Win32::UI::WindowsAndMessaging::MB_YESNO;
+
+lazy_static! {
+    /// The bindings whose dialog is open, which pressing them again doesn't
+    /// open another one for
+    static ref ASKING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Ask the question of a binding with `~confirm` in a dialog, away from the
+/// event loop, and hand the binding back as `UserEvent::Confirmed` once the
+/// answer is yes
+pub fn ask(hotkey: &HkmData, question: &str, proxy: EventLoopProxy<UserEvent>) {
+    let id = hotkey.binding_id();
+    if !ASKING.lock().insert(id.clone()) {
+        return;
+    }
+
+    log::event(format!("asking before running {}", hotkey.keys.join(" + ")));
+
+    let mut confirmed = hotkey.clone();
+    confirmed.confirm = None;
+    let question = question.to_string();
+
+    runtime::spawn_blocking(move || {
+        let answer = yes_or_no(&question);
+        ASKING.lock().remove(&id);
+
+        if answer {
+            let _ = proxy.send_event(UserEvent::Confirmed(Box::new(confirmed)));
+        } else {
+            log::event(format!(
+                "not running {}, it wasn't confirmed",
+                confirmed.keys.join(" + ")
+            ));
+        }
+    });
+}
+
+/// Show a dialog which answers no unless yes is chosen, so that pressing enter
+/// out of habit doesn't go ahead
+fn yes_or_no(question: &str) -> bool {
+    let text = question.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let caption = "whkd\0".encode_utf16().collect::<Vec<u16>>();
+
+    unsafe {
+        MessageBoxW(
+            0,
+            text.as_ptr(),
+            caption.as_ptr(),
+            MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2 | MB_TOPMOST | MB_SETFOREGROUND,
+        ) == IDYES
+    }
+}
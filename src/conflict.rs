@@ -0,0 +1,170 @@
+use crate::hotkey_from_keys;
+use crate::modifier_from_string;
+use crate::process::is_running;
+use global_hotkey::hotkey::Modifiers;
+
+/// Chords which Windows or common apps register, along with the process which
+/// has to be running for an app to hold its chord
+const KNOWN_OWNERS: &[(&str, &str, Option<&str>)] = &[
+    (
+        "alt + space",
+        "PowerToys Run",
+        Some("PowerToys.PowerLauncher.exe"),
+    ),
+    (
+        "super + shift + c",
+        "the Color Picker of PowerToys",
+        Some("PowerToys.exe"),
+    ),
+    (
+        "super + shift + t",
+        "the Text Extractor of PowerToys",
+        Some("PowerToys.exe"),
+    ),
+    (
+        "super + ctrl + shift + t",
+        "Always On Top of PowerToys",
+        Some("PowerToys.exe"),
+    ),
+    (
+        "alt + z",
+        "the overlay of GeForce Experience",
+        Some("NVIDIA Share.exe"),
+    ),
+    (
+        "alt + r",
+        "the overlay of AMD Software",
+        Some("RadeonSoftware.exe"),
+    ),
+    ("ctrl + shift + escape", "Windows (Task Manager)", None),
+    ("super + shift + s", "Windows (Snipping Tool)", None),
+    ("super + alt + r", "the Xbox Game Bar", None),
+];
+
+/// The modifiers which are tried when looking for a free chord nearby, in the
+/// order in which they are added, leaving out the Windows key as Windows
+/// reserves most chords with it
+const MODIFIERS: [&str; 3] = ["shift", "alt", "ctrl"];
+
+/// Who is likely to have registered a chord, as Windows doesn't tell which
+/// window a hotkey belongs to
+pub fn owner(keys: &[String]) -> Option<String> {
+    known_owner(keys, is_running)
+}
+
+fn known_owner(keys: &[String], is_running: impl Fn(&str) -> bool) -> Option<String> {
+    let id = hotkey_from_keys(keys)?.id();
+
+    for (chord, owner, process) in KNOWN_OWNERS {
+        let known = chord.split('+').map(|key| key.trim().to_string());
+        if hotkey_from_keys(&known.collect::<Vec<_>>()).map(|hotkey| hotkey.id()) != Some(id) {
+            continue;
+        }
+
+        match process {
+            None => return Some((*owner).to_string()),
+            Some(process) if is_running(process) => {
+                return Some(format!("{owner} ({process} is running)"));
+            }
+            Some(_) => {}
+        }
+    }
+
+    let (_, mods) = keys.split_last()?;
+    mods.iter()
+        .any(|m| modifier_from_string(m) == Some(Modifiers::SUPER))
+        .then(|| String::from("Windows, which reserves most chords with the Windows key"))
+}
+
+/// The chords nearest to a chord, with a modifier added and then with one of
+/// its modifiers replaced by another
+fn alternatives(keys: &[String]) -> Vec<Vec<String>> {
+    let Some((trigger, mods)) = keys.split_last() else {
+        return vec![];
+    };
+
+    let held = mods
+        .iter()
+        .filter_map(|m| modifier_from_string(m))
+        .fold(Modifiers::empty(), |held, m| held | m);
+    let missing = MODIFIERS
+        .iter()
+        .filter(|m| modifier_from_string(m).is_some_and(|m| !held.contains(m)))
+        .collect::<Vec<_>>();
+
+    let mut alternatives = vec![];
+    for added in &missing {
+        let mut chord = mods.to_vec();
+        chord.push((**added).to_string());
+        chord.push(trigger.clone());
+        alternatives.push(chord);
+    }
+
+    for (idx, _) in mods.iter().enumerate() {
+        for replacement in &missing {
+            let mut chord = mods.to_vec();
+            chord[idx] = (**replacement).to_string();
+            chord.push(trigger.clone());
+            alternatives.push(chord);
+        }
+    }
+
+    alternatives
+}
+
+/// The nearest chord to one which couldn't be registered which is free
+pub fn suggest(keys: &[String], is_free: impl Fn(&[String]) -> bool) -> Option<Vec<String>> {
+    alternatives(keys)
+        .into_iter()
+        .find(|alternative| is_free(alternative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(chord: &str) -> Vec<String> {
+        chord.split('+').map(|key| key.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn test_alternatives() {
+        assert_eq!(
+            alternatives(&keys("alt + h")),
+            vec![
+                keys("alt + shift + h"),
+                keys("alt + ctrl + h"),
+                keys("shift + h"),
+                keys("ctrl + h"),
+            ]
+        );
+
+        assert_eq!(
+            suggest(&keys("super + shift + s"), |chord| chord
+                .iter()
+                .all(|key| key != "super")),
+            Some(keys("alt + shift + s"))
+        );
+
+        assert!(alternatives(&keys("ctrl + alt + shift + h")).is_empty());
+    }
+
+    #[test]
+    fn test_owner() {
+        let running = |image: &str| image == "PowerToys.PowerLauncher.exe";
+
+        assert_eq!(
+            known_owner(&keys("ctrl + shift + escape"), running).as_deref(),
+            Some("Windows (Task Manager)")
+        );
+        assert_eq!(
+            known_owner(&keys("Alt + Space"), running).as_deref(),
+            Some("PowerToys Run (PowerToys.PowerLauncher.exe is running)")
+        );
+        assert_eq!(known_owner(&keys("alt + z"), running), None);
+        assert!(known_owner(&keys("super + e"), running)
+            .unwrap()
+            .starts_with("Windows"));
+        assert_eq!(known_owner(&keys("ctrl + shift + h"), running), None);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::history;
+use crate::history::Entry;
+use chrono::Local;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::backtrace::Backtrace;
+use std::fmt::Write as _;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+
+/// Number of dispatched commands included in a crash report
+const CRASH_HISTORY: usize = 10;
+
+lazy_static! {
+    /// The active mode, kept here as the mode manager may be locked by the
+    /// thread which panicked
+    static ref MODE: Mutex<Option<String>> = Mutex::new(None);
+}
+
+fn crash_path() -> PathBuf {
+    dirs::data_local_dir()
+        .expect("there is no local data directory")
+        .join("whkd")
+        .join("crash.txt")
+}
+
+pub fn set_mode(mode: Option<&String>) {
+    *MODE.lock() = mode.cloned();
+}
+
+/// Write a crash report for every panic before handing it to the hook which was
+/// installed before, e.g. the one of `color_eyre` which prints it
+pub fn install_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_report(info) {
+            Ok(path) => eprintln!(
+                "whkd has crashed, the crash report was written to {}",
+                path.display()
+            ),
+            Err(error) => {
+                eprintln!("whkd has crashed, the crash report could not be written: {error}");
+            }
+        }
+
+        previous(info);
+    }));
+}
+
+fn write_report(info: &PanicHookInfo<'_>) -> Result<PathBuf> {
+    let thread = std::thread::current();
+    let mode = MODE.try_lock().map_or_else(
+        || String::from("unknown"),
+        |mode| mode.clone().unwrap_or_else(|| String::from("default")),
+    );
+
+    let report = report(
+        &format!("thread '{}' {info}", thread.name().unwrap_or("<unnamed>")),
+        &mode,
+        history::try_recent(CRASH_HISTORY).as_deref(),
+        &Backtrace::force_capture().to_string(),
+    );
+
+    let path = crash_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// `history` is `None` when it couldn't be read while crashing
+fn report(message: &str, mode: &str, history: Option<&[Entry]>, backtrace: &str) -> String {
+    let mut report = String::new();
+
+    let _ = writeln!(
+        report,
+        "whkd {} crashed at {}",
+        env!("CARGO_PKG_VERSION"),
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    let _ = writeln!(report, "{message}");
+    let _ = writeln!(report, "active mode: {mode}");
+
+    let _ = writeln!(report, "\nlast dispatched commands:");
+    match history {
+        Some([]) => report.push_str("none\n"),
+        Some(history) => {
+            for entry in history {
+                let _ = writeln!(report, "{entry}");
+            }
+        }
+        None => report.push_str("unknown\n"),
+    }
+
+    let _ = writeln!(report, "\nbacktrace:\n{backtrace}");
+    report
+}
+
+/// Print the report of the last crash
+pub fn show() -> Result<()> {
+    match std::fs::read_to_string(crash_path()) {
+        Ok(report) => print!("{report}"),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            println!("whkd has not crashed");
+        }
+        Err(error) => return Err(error.into()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let history = [Entry {
+            timestamp: 0,
+            mode: None,
+            keys: vec![String::from("alt"), String::from("h")],
+            name: None,
+            app: Some(String::from("firefox")),
+            command: String::from("komorebic focus left"),
+        }];
+
+        let crash = report(
+            "thread 'main' panicked at src/main.rs:1:1:\nboom",
+            "resize",
+            Some(&history),
+            "0: whkd::main",
+        );
+
+        assert!(crash.contains("panicked at src/main.rs:1:1:\nboom\n"));
+        assert!(crash.contains("active mode: resize\n"));
+        assert!(crash.contains("default > alt + h (firefox) : komorebic focus left\n"));
+        assert!(crash.ends_with("backtrace:\n0: whkd::main\n"));
+
+        let crash = report("boom", "default", None, "");
+        assert!(crash.contains("last dispatched commands:\nunknown\n"));
+    }
+}
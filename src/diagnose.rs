@@ -0,0 +1,103 @@
+use crate::ipc;
+use crate::ipc::SocketMessage;
+use crate::log::TagFilter;
+use color_eyre::eyre::bail;
+use color_eyre::eyre::Result;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// Record every event emitted by the running whkd instance for `duration` and
+/// bundle them into a zip file which can be attached to a bug report
+pub fn record(duration: Duration, output: Option<PathBuf>, yes: bool) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let output = output.unwrap_or_else(|| PathBuf::from(format!("whkd-diagnose-{timestamp}.zip")));
+
+    println!(
+        "whkd will record registrations, dispatched commands, mode changes, shell writes and errors for {}",
+        humantime::format_duration(duration)
+    );
+    println!(
+        "The recording is only written to {} and is not uploaded anywhere",
+        output.display()
+    );
+
+    if !yes && !confirm("Do you want to start recording? [y/N] ")? {
+        bail!("recording was not started");
+    }
+
+    let stream = ipc::send(&SocketMessage::Watch(TagFilter::default()))?;
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    let deadline = Instant::now() + duration;
+    let mut events = vec![];
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(remaining) {
+            Ok(line) => events.push(line),
+            Err(mpsc::RecvTimeoutError::Timeout) => break,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                println!("whkd stopped sending events, finishing the recording early");
+                break;
+            }
+        }
+    }
+
+    let mut zip = ZipWriter::new(File::create(&output)?);
+    let options = FileOptions::default();
+
+    zip.start_file("events.jsonl", options)?;
+    for event in &events {
+        writeln!(zip, "{event}")?;
+    }
+
+    zip.start_file("info.txt", options)?;
+    writeln!(zip, "whkd version: {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(
+        zip,
+        "os: {} ({})",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )?;
+    writeln!(zip, "recorded at: {timestamp}")?;
+    writeln!(zip, "duration: {}", humantime::format_duration(duration))?;
+    writeln!(zip, "events: {}", events.len())?;
+
+    zip.finish()?;
+
+    println!("Recorded {} events to {}", events.len(), output.display());
+
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{prompt}");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
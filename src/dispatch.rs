@@ -0,0 +1,554 @@
+use crate::executor::Executor;
+use crate::executor::Executors;
+use crate::flip_toggle;
+use crate::foreground;
+use crate::history;
+use crate::ipc;
+use crate::ipc::Event;
+use crate::log;
+use crate::log::LOG_FILTER;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::next_in_cycle;
+use crate::redact;
+use crate::session;
+use crate::stats;
+use crate::stats::Stage;
+use crate::whkdrc::RateLimit;
+use crate::whkdrc::RatePolicy;
+use crate::whkdrc::Shell;
+use crate::whkdrc::Whkdrc;
+use crate::HkmData;
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Work sent to the dispatcher thread, which owns the executor
+#[derive(Debug)]
+pub enum Dispatch {
+    /// Run the command of a binding which was pressed at the given time
+    Command {
+        hotkey: Box<HkmData>,
+        command: String,
+        count: u32,
+        pressed: Instant,
+        /// When the binding was looked up, see `--trace-latency`
+        resolved: Instant,
+    },
+    /// Run the command which flips a toggle binding, after its probe if it has
+    /// one, as the probe has to wait for a shell
+    Toggle {
+        hotkey: Box<HkmData>,
+        on: String,
+        off: String,
+        probe: Option<String>,
+        count: u32,
+        pressed: Instant,
+    },
+    /// Run the next command of a cycle binding
+    Cycle {
+        hotkey: Box<HkmData>,
+        commands: Vec<String>,
+        count: u32,
+        pressed: Instant,
+    },
+    /// Restart the shell sessions which have exited, see `ModeManager::recover`
+    Revive,
+    /// Start running commands with the directives of a reloaded whkdrc
+    Reload(Box<Whkdrc>),
+    /// Run the commands in sessions of another shell, see `InternalAction::Shell`
+    Shell(Shell),
+    /// Stop the shell sessions and the dispatcher thread
+    Shutdown,
+}
+
+impl Dispatch {
+    /// Whether both run the same command of the same binding with the same count
+    fn repeats(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Command {
+                    hotkey,
+                    command,
+                    count,
+                    ..
+                },
+                Self::Command {
+                    hotkey: other_hotkey,
+                    command: other_command,
+                    count: other_count,
+                    ..
+                },
+            ) => hotkey == other_hotkey && command == other_command && count == other_count,
+            (Self::Revive, Self::Revive) => true,
+            // every press of a toggle or a cycle advances it
+            _ => false,
+        }
+    }
+
+    /// What is dropped when the dispatch comes too early for `.rate_limit`
+    fn describe(&self) -> String {
+        match self {
+            Self::Command { command, .. } => format!("'{}'", redact::command(command)),
+            Self::Toggle { hotkey, .. } | Self::Cycle { hotkey, .. } => {
+                format!("the next command of {}", hotkey.keys.join(" + "))
+            }
+            _ => format!("{self:?}"),
+        }
+    }
+
+    /// Turn a toggle or a cycle into the command it runs now, advancing its
+    /// state, which is only done once the command is sure to be dispatched
+    pub fn resolve(self, probe: impl FnOnce(&HkmData, &str) -> Option<bool>) -> Self {
+        match self {
+            Self::Toggle {
+                hotkey,
+                on,
+                off,
+                probe: toggle_probe,
+                count,
+                pressed,
+            } => {
+                let probed = toggle_probe.and_then(|toggle_probe| probe(&hotkey, &toggle_probe));
+                let command = if flip_toggle(&hotkey, probed) {
+                    on
+                } else {
+                    off
+                };
+
+                Self::Command {
+                    hotkey,
+                    command,
+                    count,
+                    pressed,
+                    resolved: Instant::now(),
+                }
+            }
+            Self::Cycle {
+                hotkey,
+                commands,
+                count,
+                pressed,
+            } => {
+                let command = commands[next_in_cycle(&hotkey, commands.len())].clone();
+
+                Self::Command {
+                    hotkey,
+                    command,
+                    count,
+                    pressed,
+                    resolved: Instant::now(),
+                }
+            }
+            dispatch => dispatch,
+        }
+    }
+}
+
+/// When the next command may be dispatched under `.rate_limit`
+struct RateLimiter {
+    next_slot: Instant,
+}
+
+impl RateLimiter {
+    /// Whether a dispatch may run, once it has waited for its turn if the
+    /// policy lets it
+    fn admit(
+        &mut self,
+        rate_limit: Option<RateLimit>,
+        current: &Dispatch,
+        receiver: &mpsc::Receiver<Dispatch>,
+        pending: &mut VecDeque<Dispatch>,
+    ) -> bool {
+        let Some(rate_limit) = rate_limit else {
+            return true;
+        };
+
+        let now = Instant::now();
+        if now < self.next_slot {
+            match rate_limit.policy {
+                RatePolicy::Drop => {
+                    println!(
+                        "dropping {}, commands are limited to {} per second",
+                        current.describe(),
+                        rate_limit.per_second
+                    );
+                    return false;
+                }
+                RatePolicy::Queue => std::thread::sleep(self.next_slot - now),
+                RatePolicy::Coalesce => {
+                    std::thread::sleep(self.next_slot - now);
+
+                    // e.g. the repeats of a chord which is held down
+                    for waiting in receiver.try_iter() {
+                        if !waiting.repeats(current)
+                            && !pending.iter().any(|other| waiting.repeats(other))
+                        {
+                            pending.push_back(waiting);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.next_slot = Instant::now() + rate_limit.interval();
+        true
+    }
+}
+
+/// How long the probe of a toggle binding can take before the state it would
+/// have reported is taken to be unknown
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Run the probe command of a toggle binding, which reports the state as `true`
+/// / `false` (or `on` / `off`, `1` / `0`)
+fn probe_toggle(shell: Shell, probe: &str) -> Option<bool> {
+    match session::output_within(shell, probe, PROBE_TIMEOUT) {
+        Ok(output) => match output.trim().to_lowercase().as_str() {
+            "true" | "on" | "1" => Some(true),
+            "false" | "off" | "0" => Some(false),
+            _ => {
+                log::error(format!(
+                    "probe '{probe}' printed '{output}' instead of true or false"
+                ));
+                None
+            }
+        },
+        Err(error) => {
+            log::error(format!("could not run probe '{probe}': {error}"));
+            None
+        }
+    }
+}
+
+/// Details of the foreground window which are passed on to commands
+fn window_env() -> Vec<(&'static str, String)> {
+    let (app, title, pid, hwnd) = foreground::window().map_or_else(Default::default, |window| {
+        (
+            window.app_name,
+            window.title,
+            window.process_id.to_string(),
+            // formatted as `HWND(123)`
+            window
+                .window_id
+                .trim_start_matches("HWND(")
+                .trim_end_matches(')')
+                .to_string(),
+        )
+    });
+
+    vec![
+        ("WHKD_APP", app),
+        ("WHKD_TITLE", title),
+        ("WHKD_PID", pid),
+        ("WHKD_HWND", hwnd),
+    ]
+}
+
+/// Dispatch the commands sent by `press` one after another, at most as often as
+/// `.rate_limit` allows
+pub fn run_dispatcher(
+    mut whkdrc: Whkdrc,
+    mut executors: Executors,
+    receiver: &mpsc::Receiver<Dispatch>,
+) {
+    let mut pending = VecDeque::new();
+    let mut limiter = RateLimiter {
+        next_slot: Instant::now(),
+    };
+
+    while let Some(current) = pending
+        .pop_front()
+        .or_else(|| receive(receiver, &whkdrc, &mut executors))
+    {
+        match &current {
+            Dispatch::Command { .. } | Dispatch::Toggle { .. } | Dispatch::Cycle { .. } => {}
+            Dispatch::Revive => {
+                revive(&mut executors);
+                continue;
+            }
+            // the shell sessions of the old executors exit once their stdin is
+            // dropped along with them
+            Dispatch::Reload(reloaded) => {
+                whkdrc = (**reloaded).clone();
+                executors = Executors::new(&whkdrc);
+                continue;
+            }
+            Dispatch::Shell(shell) => {
+                println!("running commands with {shell}");
+                executors.switch(*shell);
+                continue;
+            }
+            Dispatch::Shutdown => {
+                executors.shut_down();
+                return;
+            }
+        }
+
+        if !limiter.admit(whkdrc.rate_limit, &current, receiver, &mut pending) {
+            continue;
+        }
+
+        if let Dispatch::Command {
+            hotkey,
+            command,
+            count,
+            pressed,
+            resolved,
+        } = current
+            .resolve(|hotkey, probe| probe_toggle(executors.shell(hotkey.mode.as_ref()), probe))
+        {
+            let executor = executors.get(executors.shell(hotkey.mode.as_ref()));
+            dispatch(executor, &hotkey, &command, count, pressed, resolved);
+        }
+    }
+}
+
+/// Wait for the next dispatch, stopping the shell sessions whenever they have
+/// gone without a command for `.shell_idle` and checking on them every
+/// `.shell_health_check`
+fn receive(
+    receiver: &mpsc::Receiver<Dispatch>,
+    whkdrc: &Whkdrc,
+    executors: &mut Executors,
+) -> Option<Dispatch> {
+    let Some(tick) = [whkdrc.shell_idle, whkdrc.shell_health_check]
+        .into_iter()
+        .flatten()
+        .min()
+    else {
+        return receiver.recv().ok();
+    };
+
+    loop {
+        // also checked between dispatches, so that sessions which are in use
+        // are checked as well
+        if let Some(interval) = whkdrc.shell_health_check {
+            check_health(executors, interval);
+        }
+
+        match receiver.recv_timeout(tick) {
+            Ok(dispatch) => return Some(dispatch),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let Some(idle) = whkdrc.shell_idle else {
+                    continue;
+                };
+
+                match executors.retire(idle) {
+                    0 => {}
+                    retired => println!(
+                        "stopped {retired} shell sessions which were idle for {}",
+                        humantime::format_duration(idle)
+                    ),
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+fn check_health(executors: &mut Executors, interval: Duration) {
+    match executors.check_health(interval) {
+        Ok(0) => {}
+        Ok(restarted) => {
+            println!("restarted {restarted} shell sessions which had exited or stopped responding");
+            #[cfg(feature = "metrics")]
+            metrics::record_shell_restarts(restarted);
+        }
+        Err(error) => log::error(format!("could not restart the shell sessions: {error}")),
+    }
+}
+
+fn revive(executors: &mut Executors) {
+    match executors.revive() {
+        Ok(0) => {}
+        Ok(revived) => {
+            println!("restarted {revived} shell sessions which had exited");
+            #[cfg(feature = "metrics")]
+            metrics::record_shell_restarts(revived);
+        }
+        Err(error) => log::error(format!("could not restart the shell sessions: {error}")),
+    }
+}
+
+pub fn dispatch(
+    executor: &mut dyn Executor,
+    hotkey: &HkmData,
+    cmd: &str,
+    count: u32,
+    pressed: Instant,
+    resolved: Instant,
+) {
+    let mut env = window_env();
+    env.extend([
+        ("WHKD_KEYS", hotkey.keys.join(" + ")),
+        ("WHKD_MODE", hotkey.mode.clone().unwrap_or_default()),
+        ("WHKD_BINDING_ID", hotkey.binding_id()),
+        ("WHKD_COUNT", count.to_string()),
+    ]);
+
+    let shown = redact::command(cmd);
+    ipc::broadcast(&Event::Dispatch {
+        mode: hotkey.mode.clone(),
+        keys: hotkey.keys.clone(),
+        name: hotkey.name.clone(),
+        command: shown.clone(),
+        tags: hotkey.tags.clone(),
+    });
+
+    if !executor.echoes() && !hotkey.silent && log::echoes() && LOG_FILTER.matches(&hotkey.tags) {
+        log::event(&shown);
+    }
+
+    let executed = if hotkey.wait {
+        executor.execute_watched(
+            cmd,
+            &env,
+            hotkey.on_fail.as_deref(),
+            hotkey.on_error.as_deref(),
+        )
+    } else {
+        executor.execute(cmd, &env)
+    };
+
+    match executed {
+        Ok(()) => history::record(hotkey, cmd),
+        Err(error) => {
+            executor.report_failure(
+                &format!("could not run '{shown}': {error}"),
+                hotkey.on_error.as_deref(),
+                &env,
+            );
+            #[cfg(feature = "metrics")]
+            metrics::record_dispatch_error();
+        }
+    }
+
+    stats::record_dispatch(hotkey, pressed.elapsed());
+
+    if stats::is_tracing() {
+        stats::record_trace(hotkey, Stage::Resolve, resolved - pressed);
+        stats::record_trace(hotkey, Stage::Write, resolved.elapsed());
+        if let Some(probe) = executor.probe_finished() {
+            stats::await_finish(hotkey.clone(), probe);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Recorder;
+    use crate::parser;
+    use chumsky::Parser as _;
+
+    /// The first binding of whkdrc
+    fn hotkey(src: &str) -> HkmData {
+        let whkdrc = parser::parser().parse(src).unwrap();
+        HkmData::try_from(&whkdrc.bindings[0]).unwrap()
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let hotkey = hotkey(
+            r"
+.shell pwsh
+
+resize > alt + h : komorebic resize-axis horizontal increase
+",
+        );
+
+        let mut recorder = Recorder::default();
+        dispatch(
+            &mut recorder,
+            &hotkey,
+            "echo resized",
+            3,
+            Instant::now(),
+            Instant::now(),
+        );
+
+        let (command, env) = &recorder.0[0];
+        assert_eq!(command, "echo resized");
+
+        let var = |name: &str| {
+            env.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(var("WHKD_KEYS"), Some("alt + h"));
+        assert_eq!(var("WHKD_MODE"), Some("resize"));
+        assert_eq!(var("WHKD_COUNT"), Some("3"));
+    }
+
+    #[test]
+    fn test_repeats() {
+        let hotkey = hotkey(
+            r"
+.shell pwsh
+
+alt + h : komorebic focus left
+",
+        );
+
+        let command = |count| Dispatch::Command {
+            hotkey: Box::new(hotkey.clone()),
+            command: String::from("komorebic focus left"),
+            count,
+            pressed: Instant::now(),
+            resolved: Instant::now(),
+        };
+
+        assert!(command(1).repeats(&command(1)));
+        assert!(!command(1).repeats(&command(2)));
+        assert!(!command(1).repeats(&Dispatch::Revive));
+        assert!(Dispatch::Revive.repeats(&Dispatch::Revive));
+    }
+
+    #[test]
+    fn test_dropped_toggle_stays_in_phase() {
+        let hotkey = hotkey(
+            r"
+.shell pwsh
+
+alt + v :: toggle { echo dropped on } { echo dropped off }
+",
+        );
+
+        let rate_limit = RateLimit {
+            per_second: 10,
+            policy: RatePolicy::Drop,
+        };
+        let mut limiter = RateLimiter {
+            next_slot: Instant::now(),
+        };
+        let (_sender, receiver) = mpsc::channel();
+        let mut pending = VecDeque::new();
+
+        let mut dispatched = vec![];
+        for wait in [false, false, true] {
+            if wait {
+                std::thread::sleep(rate_limit.interval());
+            }
+
+            let toggle = Dispatch::Toggle {
+                hotkey: Box::new(hotkey.clone()),
+                on: String::from("echo dropped on"),
+                off: String::from("echo dropped off"),
+                probe: None,
+                count: 1,
+                pressed: Instant::now(),
+            };
+
+            if limiter.admit(Some(rate_limit), &toggle, &receiver, &mut pending) {
+                if let Dispatch::Command { command, .. } = toggle.resolve(|_, _| None) {
+                    dispatched.push(command);
+                }
+            }
+        }
+
+        assert_eq!(dispatched, vec!["echo dropped on", "echo dropped off"]);
+    }
+}
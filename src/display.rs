@@ -0,0 +1,192 @@
+use crate::log;
+use crate::parser::DisplayAction;
+use crate::window;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use windows_sys::Win32::Devices::Display::SetDisplayConfig;
+use windows_sys::Win32::Devices::Display::SDC_APPLY;
+use windows_sys::Win32::Devices::Display::SDC_TOPOLOGY_CLONE;
+use windows_sys::Win32::Devices::Display::SDC_TOPOLOGY_EXTEND;
+use windows_sys::Win32::Devices::Display::SDC_TOPOLOGY_EXTERNAL;
+use windows_sys::Win32::Devices::Display::SDC_TOPOLOGY_INTERNAL;
+use windows_sys::Win32::Foundation::POINTL;
+use windows_sys::Win32::Foundation::RECT;
+use windows_sys::Win32::Graphics::Gdi::ChangeDisplaySettingsExW;
+use windows_sys::Win32::Graphics::Gdi::EnumDisplaySettingsW;
+use windows_sys::Win32::Graphics::Gdi::GetMonitorInfoW;
+use windows_sys::Win32::Graphics::Gdi::CDS_NORESET;
+use windows_sys::Win32::Graphics::Gdi::CDS_SET_PRIMARY;
+use windows_sys::Win32::Graphics::Gdi::CDS_UPDATEREGISTRY;
+use windows_sys::Win32::Graphics::Gdi::DEVMODEW;
+use windows_sys::Win32::Graphics::Gdi::DISP_CHANGE_SUCCESSFUL;
+use windows_sys::Win32::Graphics::Gdi::DM_POSITION;
+use windows_sys::Win32::Graphics::Gdi::ENUM_CURRENT_SETTINGS;
+use windows_sys::Win32::Graphics::Gdi::HMONITOR;
+use windows_sys::Win32::Graphics::Gdi::MONITORINFOEXW;
+
+pub fn perform(action: DisplayAction) {
+    let result = match action {
+        DisplayAction::Extend => set_topology(SDC_TOPOLOGY_EXTEND),
+        DisplayAction::Duplicate => set_topology(SDC_TOPOLOGY_CLONE),
+        DisplayAction::Internal => set_topology(SDC_TOPOLOGY_INTERNAL),
+        DisplayAction::External => set_topology(SDC_TOPOLOGY_EXTERNAL),
+        DisplayAction::Primary(number) => set_primary(number),
+    };
+
+    if let Err(error) = result {
+        log::error(format!("could not perform {action}: {error}"));
+    }
+}
+
+/// Switch the projection mode like Win + P, which brings back the arrangement
+/// the displays last had in that mode
+fn set_topology(topology: u32) -> Result<()> {
+    let status = unsafe {
+        SetDisplayConfig(
+            0,
+            std::ptr::null(),
+            0,
+            std::ptr::null(),
+            SDC_APPLY | topology,
+        )
+    };
+
+    if status != 0 {
+        return Err(eyre!("{}", std::io::Error::from_raw_os_error(status)));
+    }
+
+    Ok(())
+}
+
+/// The positions of the monitors once every one of them is moved along with the
+/// primary monitor to the origin of the desktop, which is what makes a monitor
+/// the primary one
+fn positions(monitors: &[RECT], primary: usize) -> Vec<(i32, i32)> {
+    let origin = &monitors[primary];
+    monitors
+        .iter()
+        .map(|monitor| (monitor.left - origin.left, monitor.top - origin.top))
+        .collect()
+}
+
+fn set_primary(number: usize) -> Result<()> {
+    let monitors = window::monitors();
+    if number > monitors.len() {
+        return Err(eyre!("there are only {} monitors", monitors.len()));
+    }
+
+    let rects = monitors
+        .iter()
+        .map(|(_, info)| info.rcMonitor)
+        .collect::<Vec<_>>();
+
+    for (index, ((monitor, _), (x, y))) in monitors
+        .iter()
+        .zip(positions(&rects, number - 1))
+        .enumerate()
+    {
+        let device = device_name(*monitor)?;
+
+        let mut mode: DEVMODEW = unsafe { std::mem::zeroed() };
+        #[allow(clippy::cast_possible_truncation)]
+        let size = std::mem::size_of::<DEVMODEW>() as u16;
+        mode.dmSize = size;
+
+        if unsafe {
+            EnumDisplaySettingsW(
+                device.as_ptr(),
+                ENUM_CURRENT_SETTINGS,
+                std::ptr::addr_of_mut!(mode),
+            )
+        } == 0
+        {
+            return Err(eyre!("could not get the settings of monitor {}", index + 1));
+        }
+
+        mode.dmFields = DM_POSITION;
+        mode.Anonymous1.Anonymous2.dmPosition = POINTL { x, y };
+
+        let mut flags = CDS_UPDATEREGISTRY | CDS_NORESET;
+        if index == number - 1 {
+            flags |= CDS_SET_PRIMARY;
+        }
+
+        let status = unsafe {
+            ChangeDisplaySettingsExW(
+                device.as_ptr(),
+                std::ptr::addr_of!(mode),
+                0,
+                flags,
+                std::ptr::null(),
+            )
+        };
+
+        if status != DISP_CHANGE_SUCCESSFUL {
+            return Err(eyre!(
+                "Windows refused to move monitor {} (code {status})",
+                index + 1
+            ));
+        }
+    }
+
+    // the monitors are only moved once all of them have been, so that they
+    // never overlap in between
+    let status = unsafe {
+        ChangeDisplaySettingsExW(std::ptr::null(), std::ptr::null(), 0, 0, std::ptr::null())
+    };
+
+    if status != DISP_CHANGE_SUCCESSFUL {
+        return Err(eyre!(
+            "Windows refused to move the monitors (code {status})"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The name of the display device of a monitor, e.g. `\\.\DISPLAY1`
+fn device_name(monitor: HMONITOR) -> Result<[u16; 32]> {
+    let mut info: MONITORINFOEXW = unsafe { std::mem::zeroed() };
+    #[allow(clippy::cast_possible_truncation)]
+    let size = std::mem::size_of::<MONITORINFOEXW>() as u32;
+    info.monitorInfo.cbSize = size;
+
+    if unsafe { GetMonitorInfoW(monitor, std::ptr::addr_of_mut!(info).cast()) } == 0 {
+        return Err(eyre!("could not get the name of the monitor"));
+    }
+
+    Ok(info.szDevice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn rect(left: i32, top: i32, right: i32, bottom: i32) -> RECT {
+        RECT {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        // a laptop to the left of the primary monitor, with another one on top
+        let monitors = [
+            rect(-1920, 360, 0, 1440),
+            rect(0, 0, 2560, 1440),
+            rect(0, -1080, 1920, 0),
+        ];
+
+        assert_eq!(
+            positions(&monitors, 0),
+            vec![(0, 0), (1920, -360), (1920, -1440)]
+        );
+        assert_eq!(
+            positions(&monitors, 1),
+            vec![(-1920, 360), (0, 0), (0, -1080)]
+        );
+    }
+}
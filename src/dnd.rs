@@ -0,0 +1,63 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use windows_sys::Win32::UI::Shell::SHQueryUserNotificationState;
+use windows_sys::Win32::UI::Shell::QUERY_USER_NOTIFICATION_STATE;
+use windows_sys::Win32::UI::Shell::QUNS_APP;
+use windows_sys::Win32::UI::Shell::QUNS_BUSY;
+use windows_sys::Win32::UI::Shell::QUNS_PRESENTATION_MODE;
+use windows_sys::Win32::UI::Shell::QUNS_QUIET_TIME;
+use windows_sys::Win32::UI::Shell::QUNS_RUNNING_D3D_FULL_SCREEN;
+
+/// Set by `.options respect_dnd` to hold back the error hooks and the messages
+/// of `~notify` while do not disturb is on
+static RESPECT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_respect(respect: bool) {
+    RESPECT.store(respect, Ordering::Relaxed);
+}
+
+/// Whether Windows holds back notifications, which it does while focus assist
+/// or quiet hours are on, while presenting and while a full screen app or game
+/// is in the foreground
+pub fn is_on() -> bool {
+    let mut state = 0;
+    if unsafe { SHQueryUserNotificationState(std::ptr::addr_of_mut!(state)) } != 0 {
+        return false;
+    }
+
+    is_quiet(state)
+}
+
+const fn is_quiet(state: QUERY_USER_NOTIFICATION_STATE) -> bool {
+    matches!(
+        state,
+        QUNS_BUSY
+            | QUNS_RUNNING_D3D_FULL_SCREEN
+            | QUNS_PRESENTATION_MODE
+            | QUNS_QUIET_TIME
+            | QUNS_APP
+    )
+}
+
+/// Whether the notifications of whkd, which are raised by the error hooks and
+/// `~notify`, are held back right now
+pub fn silenced() -> bool {
+    RESPECT.load(Ordering::Relaxed) && is_on()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows_sys::Win32::UI::Shell::QUNS_ACCEPTS_NOTIFICATIONS;
+    use windows_sys::Win32::UI::Shell::QUNS_NOT_PRESENT;
+
+    #[test]
+    fn test_is_quiet() {
+        assert!(is_quiet(QUNS_QUIET_TIME));
+        assert!(is_quiet(QUNS_PRESENTATION_MODE));
+        assert!(is_quiet(QUNS_RUNNING_D3D_FULL_SCREEN));
+        assert!(!is_quiet(QUNS_ACCEPTS_NOTIFICATIONS));
+        // the screen saver is running or the session is locked
+        assert!(!is_quiet(QUNS_NOT_PRESENT));
+    }
+}
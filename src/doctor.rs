@@ -0,0 +1,474 @@
+use crate::arch;
+use crate::conflict;
+use crate::failures::Reason;
+use crate::hotkey_from_keys;
+use crate::ipc;
+use crate::whkdrc;
+use crate::whkdrc::Backend;
+use crate::whkdrc::Shell;
+use crate::whkdrc::Whkdrc;
+use global_hotkey::GlobalHotKeyManager;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::path::PathBuf;
+use std::process::Command;
+use uds_windows::UnixStream;
+use windows_sys::Win32::Foundation::CloseHandle;
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::Security::GetTokenInformation;
+use windows_sys::Win32::Security::TokenElevation;
+use windows_sys::Win32::Security::TOKEN_ELEVATION;
+use windows_sys::Win32::Security::TOKEN_QUERY;
+use windows_sys::Win32::System::Threading::GetCurrentProcess;
+use windows_sys::Win32::System::Threading::OpenProcess;
+use windows_sys::Win32::System::Threading::OpenProcessToken;
+use windows_sys::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
+    Problem,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Warning => write!(f, "warning"),
+            Self::Problem => write!(f, "problem"),
+        }
+    }
+}
+
+struct Finding {
+    status: Status,
+    message: String,
+    /// What to do about it, for warnings and problems
+    fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warning,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn problem(message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Problem,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// A process from the output of `tasklist`
+#[derive(Debug, PartialEq, Eq)]
+struct Process {
+    image: String,
+    pid: u32,
+}
+
+impl Process {
+    /// Whether this is the process of an app-specific binding, which are named
+    /// without the `.exe` as shown by `Get-Process`
+    fn is(&self, process_name: &str) -> bool {
+        let image = self.image.to_lowercase();
+        let image = image.strip_suffix(".exe").unwrap_or(&image);
+        image == process_name.to_lowercase()
+    }
+}
+
+/// Check the common causes of whkd not working and print what was found, with
+/// a suggestion for every problem
+pub fn run(config: Option<PathBuf>) {
+    let processes = processes();
+    let instances = processes
+        .iter()
+        .filter(|process| process.is("whkd") && process.pid != std::process::id())
+        .count();
+    let running = UnixStream::connect(ipc::socket_path()).is_ok();
+
+    let mut findings = vec![];
+    let whkdrc = check_config(config, &mut findings);
+
+    findings.push(check_instances(instances, running));
+    findings.push(check_architecture());
+
+    if let Some(whkdrc) = &whkdrc {
+        findings.push(check_shell(whkdrc.shell));
+        findings.push(check_conflicts(whkdrc, instances > 0 || running));
+        findings.extend(check_elevation(whkdrc, &processes));
+    }
+
+    for finding in &findings {
+        println!("[{}] {}", finding.status, finding.message);
+        if let Some(fix) = &finding.fix {
+            println!("    {fix}");
+        }
+    }
+
+    let problems = findings
+        .iter()
+        .filter(|finding| finding.status == Status::Problem)
+        .count();
+
+    match problems {
+        0 => println!("\nno problems found"),
+        1 => println!("\n1 problem found"),
+        problems => println!("\n{problems} problems found"),
+    }
+}
+
+fn check_config(config: Option<PathBuf>, findings: &mut Vec<Finding>) -> Option<Whkdrc> {
+    let layers = match config {
+        Some(config) if !whkdrc::is_stdin(&config) && !config.is_file() => {
+            findings.push(Finding::problem(
+                format!("{} does not exist", config.display()),
+                "create it, or point --config at the file it is in",
+            ));
+            return None;
+        }
+        Some(config) => vec![config],
+        None => match Whkdrc::layers() {
+            Ok(layers) => layers,
+            Err(error) => {
+                findings.push(Finding::problem(
+                    format!("whkdrc could not be found: {error}"),
+                    "create ~/.config/whkdrc, or point WHKD_CONFIG_HOME or --config at it",
+                ));
+                return None;
+            }
+        },
+    };
+
+    let mut merged: Option<Whkdrc> = None;
+
+    for path in &layers {
+        match Whkdrc::load(path) {
+            Ok(whkdrc) => {
+                let bindings = whkdrc.bindings.len()
+                    + whkdrc
+                        .app_bindings
+                        .iter()
+                        .map(|(_, bindings)| bindings.len())
+                        .sum::<usize>();
+
+                findings.push(Finding::ok(format!(
+                    "{} was loaded with {bindings} bindings",
+                    whkdrc::source(path)
+                )));
+
+                match &mut merged {
+                    Some(merged) => merged.merge(whkdrc),
+                    None => merged = Some(whkdrc),
+                }
+            }
+            Err(error) => {
+                findings.push(Finding::problem(
+                    format!("{} could not be loaded: {error}", whkdrc::source(path)),
+                    "fix the error, `whkd --dry-run` shows whether the file loads",
+                ));
+                return None;
+            }
+        }
+    }
+
+    merged
+}
+
+fn check_instances(instances: usize, running: bool) -> Finding {
+    match (instances, running) {
+        (0, false) => Finding::ok("whkd is not running"),
+        (0, true) => Finding::warning(
+            "an instance of whkd is answering on its socket, but no whkd.exe process was found",
+            "it may be running under another user or name, stop it before starting whkd",
+        ),
+        (1, _) => Finding::ok("a single instance of whkd is running"),
+        (instances, _) => Finding::problem(
+            format!("{instances} instances of whkd are running and compete for the chords"),
+            "stop them with `taskkill /f /im whkd.exe` and start a single one",
+        ),
+    }
+}
+
+fn check_architecture() -> Finding {
+    let built_for = arch::name(arch::BUILT_FOR);
+    arch::emulated_on().map_or_else(
+        || Finding::ok(format!("whkd runs natively as a {built_for} build")),
+        |machine| {
+            Finding::warning(
+                format!(
+                    "whkd is built for {built_for} and runs emulated on this {} machine",
+                    arch::name(machine)
+                ),
+                "install the aarch64-pc-windows-msvc build of whkd, which runs natively",
+            )
+        },
+    )
+}
+
+fn check_shell(shell: Shell) -> Finding {
+    let binary = format!("{shell}.exe");
+    let path = std::env::var_os("PATH").unwrap_or_default();
+
+    find_on_path(&binary, &path).map_or_else(
+        || {
+            Finding::problem(
+                format!("{binary} is not on PATH, so no command can be run"),
+                match shell {
+                    Shell::Pwsh => "install PowerShell 7, or use `.shell powershell`",
+                    Shell::Powershell | Shell::Cmd => "add the directory it is in to PATH",
+                },
+            )
+        },
+        |found| Finding::ok(format!("{binary} was found at {}", found.display())),
+    )
+}
+
+pub fn find_on_path(binary: &str, path: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .map(|directory| directory.join(binary))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Register every chord of whkdrc and unregister it right away to find those
+/// which other apps have registered already
+fn check_conflicts(whkdrc: &Whkdrc, running: bool) -> Finding {
+    if whkdrc.backend == Backend::Hook {
+        return Finding::ok("`.backend hook` can bind chords which other apps have registered");
+    }
+
+    if running {
+        return Finding::warning(
+            "conflicting chords were not checked because whkd is running and holds its chords",
+            "stop whkd and run `whkd doctor` again to check them",
+        );
+    }
+
+    let manager = match GlobalHotKeyManager::new() {
+        Ok(manager) => manager,
+        Err(error) => {
+            return Finding::problem(
+                format!("hotkeys can't be registered at all: {error}"),
+                "try `.backend hook` instead",
+            )
+        }
+    };
+
+    let chords =
+        whkdrc
+            .bindings
+            .iter()
+            .map(|binding| (&binding.keys, binding.line))
+            .chain(whkdrc.app_bindings.iter().filter_map(|(keys, bindings)| {
+                bindings.first().map(|binding| (keys, binding.line))
+            }));
+
+    let bound = chords
+        .clone()
+        .filter_map(|(keys, _)| hotkey_from_keys(keys))
+        .map(|hotkey| hotkey.id())
+        .collect::<HashSet<_>>();
+
+    let mut checked = HashSet::new();
+    let mut conflicts = vec![];
+
+    for (keys, line) in chords {
+        let Some(hotkey) = hotkey_from_keys(keys) else {
+            continue;
+        };
+
+        if !checked.insert(hotkey.id()) {
+            continue;
+        }
+
+        match manager.register(hotkey) {
+            Ok(()) => {
+                let _ = manager.unregister(hotkey);
+            }
+            Err(error) => {
+                let mut conflict = format!(
+                    "{} (line {line}): {}",
+                    keys.join(" + "),
+                    Reason::from(error)
+                );
+                if let Some(owner) = conflict::owner(keys) {
+                    conflict = format!("{conflict}, probably {owner}");
+                }
+
+                let suggestion = conflict::suggest(keys, |keys| {
+                    hotkey_from_keys(keys).is_some_and(|hotkey| {
+                        !bound.contains(&hotkey.id())
+                            && manager.register(hotkey).is_ok()
+                            && manager.unregister(hotkey).is_ok()
+                    })
+                });
+                if let Some(suggestion) = suggestion {
+                    conflict = format!("{conflict}; {} is free", suggestion.join(" + "));
+                }
+
+                conflicts.push(conflict);
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        return Finding::ok(format!("all {} chords can be registered", checked.len()));
+    }
+
+    Finding::problem(
+        format!(
+            "{} of {} chords can't be registered:\n      {}",
+            conflicts.len(),
+            checked.len(),
+            conflicts.join("\n      ")
+        ),
+        "close the app which has registered them, bind other chords or use `.backend hook`",
+    )
+}
+
+/// An unelevated whkd can't tell which elevated app is in the foreground, and
+/// the hook and passthrough don't see keys while one is focused
+fn check_elevation(whkdrc: &Whkdrc, processes: &[Process]) -> Option<Finding> {
+    if is_elevated(std::process::id()) == Some(true) {
+        return Some(Finding::ok("whkd is running elevated"));
+    }
+
+    let mut elevated = vec![];
+
+    for (_, bindings) in &whkdrc.app_bindings {
+        for process_name in bindings
+            .iter()
+            .filter_map(|binding| binding.process_name.as_ref())
+        {
+            let is_elevated = processes.iter().any(|process| {
+                process.is(process_name) && is_elevated(process.pid).unwrap_or(true)
+            });
+
+            if is_elevated && !elevated.contains(process_name) {
+                elevated.push(process_name.clone());
+            }
+        }
+    }
+
+    if elevated.is_empty() {
+        return None;
+    }
+
+    Some(Finding::warning(
+        format!(
+            "{} run elevated but whkd doesn't, so their app-specific bindings won't be used",
+            elevated.join(", ")
+        ),
+        "run whkd elevated as well, e.g. from a scheduled task with the highest privileges",
+    ))
+}
+
+/// Whether the process runs elevated, or `None` if its token can't be read, which
+/// for an unelevated caller usually means that it does
+#[allow(clippy::cast_possible_truncation)]
+fn is_elevated(pid: u32) -> Option<bool> {
+    let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+    let mut length = 0;
+    let mut token: HANDLE = 0;
+
+    unsafe {
+        let process = if pid == std::process::id() {
+            GetCurrentProcess()
+        } else {
+            OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid)
+        };
+
+        if process == 0 {
+            return None;
+        }
+
+        let opened = OpenProcessToken(process, TOKEN_QUERY, std::ptr::addr_of_mut!(token)) != 0;
+        if pid != std::process::id() {
+            CloseHandle(process);
+        }
+
+        if !opened {
+            return None;
+        }
+
+        let queried = GetTokenInformation(
+            token,
+            TokenElevation,
+            std::ptr::addr_of_mut!(elevation).cast(),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            std::ptr::addr_of_mut!(length),
+        ) != 0;
+        CloseHandle(token);
+
+        queried.then_some(elevation.TokenIsElevated != 0)
+    }
+}
+
+fn processes() -> Vec<Process> {
+    Command::new("tasklist")
+        .args(["/FO", "CSV", "/NH"])
+        .output()
+        .map(|output| parse_tasklist(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+fn parse_tasklist(output: &str) -> Vec<Process> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().trim_matches('"').split("\",\"");
+            let image = fields.next()?;
+            let pid = fields.next()?.parse().ok()?;
+
+            Some(Process {
+                image: image.to_string(),
+                pid,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tasklist() {
+        let output = r#"
+"System Idle Process","0","Services","0","8 K"
+"whkd.exe","4242","Console","1","10,244 K"
+"firefox.exe","1337","Console","1","312,008 K"
+"#;
+
+        let processes = parse_tasklist(output);
+
+        assert_eq!(processes.len(), 3);
+        assert_eq!(
+            processes[1],
+            Process {
+                image: String::from("whkd.exe"),
+                pid: 4242,
+            }
+        );
+
+        assert!(processes[1].is("whkd"));
+        assert!(processes[2].is("Firefox"));
+        assert!(!processes[2].is("firefox.exe"));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::event_loop::EventLoopProxy;
+use crate::ipc::SocketMessage;
+use crate::log;
+use crate::UserEvent;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Opens whkdrc when `$EDITOR` isn't set, as a file without an extension has no
+/// associated app
+const FALLBACK_EDITOR: &str = "notepad";
+
+/// How often whkdrc is checked for having been saved while the editor is open
+const SAVE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    /// The whkdrc which `edit-config` opens, `None` when it was read from stdin
+    static ref CONFIG: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Remember the whkdrc to open, which is the last of the layers as it is the
+/// most specific one
+pub fn set_config(path: Option<PathBuf>) {
+    *CONFIG.lock() = path;
+}
+
+/// Open whkdrc and reload it whenever it is saved until the editor exits
+pub fn edit_config(proxy: EventLoopProxy<UserEvent>) {
+    match open() {
+        Ok((path, editor)) => reload_on_save(path, editor, proxy),
+        Err(error) => log::error(format!("could not edit whkdrc: {error}")),
+    }
+}
+
+fn open() -> Result<(PathBuf, Child)> {
+    let Some(path) = CONFIG.lock().clone() else {
+        return Err(eyre!("whkdrc was read from stdin"));
+    };
+
+    let editor = std::env::var("EDITOR").ok();
+    let (program, args) = editor_command(editor.as_deref(), &path);
+    let editor = Command::new(&program)
+        .args(args)
+        .spawn()
+        .map_err(|error| eyre!("could not start {program}: {error}"))?;
+
+    Ok((path, editor))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Reload whkdrc whenever it is saved while the editor is running. Editors
+/// which hand the file to a window that is already open exit right away, so
+/// their saves go unnoticed
+fn reload_on_save(path: PathBuf, mut editor: Child, proxy: EventLoopProxy<UserEvent>) {
+    std::thread::spawn(move || {
+        let mut last_modified = modified(&path);
+
+        loop {
+            let exited = !matches!(editor.try_wait(), Ok(None));
+            if !exited {
+                std::thread::sleep(SAVE_POLL_INTERVAL);
+            }
+
+            let modified = modified(&path);
+            if modified != last_modified {
+                last_modified = modified;
+                if proxy
+                    .send_event(UserEvent::Message(SocketMessage::Reload))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+
+            if exited {
+                break;
+            }
+        }
+    });
+}
+
+/// The program and arguments which open the file, where `$EDITOR` can contain
+/// arguments of its own, e.g. `code --wait`
+fn editor_command(editor: Option<&str>, path: &Path) -> (String, Vec<String>) {
+    let mut words = editor
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    if words.is_empty() {
+        words.push(String::from(FALLBACK_EDITOR));
+    }
+
+    let program = words.remove(0);
+    words.push(path.display().to_string());
+
+    (program, words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor_command() {
+        let path = Path::new("whkdrc");
+
+        assert_eq!(
+            editor_command(Some("code --wait"), path),
+            (
+                String::from("code"),
+                vec![String::from("--wait"), String::from("whkdrc")]
+            )
+        );
+        assert_eq!(
+            editor_command(None, path),
+            (String::from("notepad"), vec![String::from("whkdrc")])
+        );
+        assert_eq!(
+            editor_command(Some("  "), path),
+            (String::from("notepad"), vec![String::from("whkdrc")])
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::sync::mpsc;
+use windows_sys::Win32::System::Threading::GetCurrentThreadId;
+use windows_sys::Win32::UI::WindowsAndMessaging::DispatchMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::PeekMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::PostQuitMessage;
+use windows_sys::Win32::UI::WindowsAndMessaging::PostThreadMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::TranslateMessage;
+use windows_sys::Win32::UI::WindowsAndMessaging::MSG;
+use windows_sys::Win32::UI::WindowsAndMessaging::PM_NOREMOVE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_APP;
+
+/// Posted to the thread of the event loop to wake it up for a user event
+const WM_USER_EVENT: u32 = WM_APP + 1;
+
+/// The message loop of the thread which owns the hotkey registrations and the
+/// keyboard hook, which both rely on messages being pumped on that thread
+pub struct EventLoop<T> {
+    sender: mpsc::Sender<T>,
+    receiver: mpsc::Receiver<T>,
+    thread_id: u32,
+}
+
+/// Sends user events to the event loop from any thread
+#[derive(Debug)]
+pub struct EventLoopProxy<T> {
+    sender: mpsc::Sender<T>,
+    thread_id: u32,
+}
+
+impl<T> Clone for EventLoopProxy<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            thread_id: self.thread_id,
+        }
+    }
+}
+
+impl<T> EventLoopProxy<T> {
+    /// A proxy for tests which run without an event loop, whose events are
+    /// dropped
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        let (sender, _) = mpsc::channel();
+        Self {
+            sender,
+            thread_id: 0,
+        }
+    }
+
+    pub fn send_event(&self, event: T) -> Result<()> {
+        self.sender
+            .send(event)
+            .map_err(|_| eyre!("the event loop has stopped"))?;
+
+        if unsafe { PostThreadMessageW(self.thread_id, WM_USER_EVENT, 0, 0) } == 0 {
+            return Err(eyre!(
+                "could not wake up the event loop: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> EventLoop<T> {
+    /// Create the event loop on the current thread, which is where it has to run
+    pub fn new() -> Self {
+        // the message queue of a thread is only created once it calls into
+        // user32, and messages posted to it before then are lost
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+        unsafe { PeekMessageW(std::ptr::addr_of_mut!(msg), 0, 0, 0, PM_NOREMOVE) };
+
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            thread_id: unsafe { GetCurrentThreadId() },
+        }
+    }
+
+    pub fn create_proxy(&self) -> EventLoopProxy<T> {
+        EventLoopProxy {
+            sender: self.sender.clone(),
+            thread_id: self.thread_id,
+        }
+    }
+
+    /// Pump messages until the thread is asked to quit, see `quit`, handling the user events
+    /// sent since the last message
+    pub fn run(self, mut handler: impl FnMut(T)) -> Result<()> {
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+
+        loop {
+            match unsafe { GetMessageW(std::ptr::addr_of_mut!(msg), 0, 0, 0) } {
+                0 => return Ok(()),
+                -1 => {
+                    return Err(eyre!(
+                        "could not get the next message: {}",
+                        std::io::Error::last_os_error()
+                    ))
+                }
+                _ => {}
+            }
+
+            // thread messages have no window to be dispatched to
+            if msg.hwnd != 0 {
+                unsafe {
+                    TranslateMessage(std::ptr::addr_of!(msg));
+                    DispatchMessageW(std::ptr::addr_of!(msg));
+                }
+            }
+
+            for event in self.receiver.try_iter() {
+                handler(event);
+            }
+        }
+    }
+}
+
+/// Make `EventLoop::run` return once the events it is handling are done, which
+/// has to be called on the thread of the event loop
+pub fn quit() {
+    unsafe { PostQuitMessage(0) };
+}
@@ -0,0 +1,649 @@
+use crate::dnd;
+use crate::hook;
+use crate::input;
+use crate::ipc;
+use crate::ipc::Event;
+use crate::log;
+use crate::redact;
+use crate::runtime;
+use crate::session;
+use crate::session::SessionPool;
+use crate::whkdrc::Exec;
+use crate::whkdrc::Shell;
+use crate::whkdrc::Whkdrc;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use uds_windows::UnixStream;
+
+/// Runs the commands of bindings on behalf of the dispatcher
+pub trait Executor: Send {
+    /// Run a command with details of the binding and the foreground window in
+    /// its environment
+    fn execute(&mut self, command: &str, env: &[(&str, String)]) -> Result<()>;
+
+    /// Run a command like `execute`, but watch it until it exits to report when
+    /// it fails and run the `on_fail` command
+    fn execute_watched(
+        &mut self,
+        command: &str,
+        env: &[(&str, String)],
+        _on_fail: Option<&str>,
+        _message: Option<&str>,
+    ) -> Result<()> {
+        self.execute(command, env)
+    }
+
+    /// Report a command which failed, along with the `~on-error` message of its
+    /// binding if it has one
+    fn report_failure(&self, error: &str, message: Option<&str>, _env: &[(&str, String)]) {
+        log::error(describe(error, message));
+    }
+
+    /// Start whatever has stopped running again, returning how many there were
+    fn revive(&mut self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Start whatever has stopped running or responding again, checking at most
+    /// once every `interval`, returning how many there were
+    fn check_health(&mut self, _interval: Duration) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Stop whatever hasn't been used for `idle`, returning how many there were
+    fn retire(&mut self, _idle: Duration) -> usize {
+        0
+    }
+
+    /// Stop whatever is still running as whkd shuts down
+    fn shut_down(&mut self) {}
+
+    /// Whether the commands show up in the output of whkd by themselves, so
+    /// that whkd doesn't need to print them
+    fn echoes(&self) -> bool {
+        false
+    }
+
+    /// A file which is created once the command which was just run has
+    /// finished, if whkd can tell, see `--trace-latency`
+    fn probe_finished(&mut self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Writes commands to a pool of long-lived shell sessions
+pub struct SessionExecutor {
+    shell: Shell,
+    on_error: Option<String>,
+    pool: SessionPool,
+}
+
+impl Executor for SessionExecutor {
+    fn execute(&mut self, command: &str, env: &[(&str, String)]) -> Result<()> {
+        let stdin = self
+            .pool
+            .next()
+            .map_err(|error| eyre!("could not start a shell session: {error}"))?;
+
+        writeln!(stdin, "{}", session::with_env(self.shell, env, command))
+            .map_err(|error| eyre!("could not write to the shell session: {error}"))?;
+
+        ipc::broadcast(&Event::ShellWrite {
+            command: redact::command(command),
+        });
+
+        Ok(())
+    }
+
+    // a session never tells when a command exits, so the command gets a
+    // process of its own
+    fn execute_watched(
+        &mut self,
+        command: &str,
+        env: &[(&str, String)],
+        on_fail: Option<&str>,
+        message: Option<&str>,
+    ) -> Result<()> {
+        watch(
+            self.shell,
+            self.on_error.as_deref(),
+            command,
+            env,
+            on_fail,
+            message,
+        )
+    }
+
+    fn report_failure(&self, error: &str, message: Option<&str>, env: &[(&str, String)]) {
+        report(self.shell, self.on_error.as_deref(), error, message, env);
+    }
+
+    fn revive(&mut self) -> Result<usize> {
+        self.pool.revive()
+    }
+
+    fn check_health(&mut self, interval: Duration) -> Result<usize> {
+        self.pool.check_health(interval)
+    }
+
+    fn retire(&mut self, idle: Duration) -> usize {
+        self.pool.retire(idle)
+    }
+
+    fn shut_down(&mut self) {
+        self.pool.shut_down();
+    }
+
+    fn echoes(&self) -> bool {
+        // the prompt of cmd is followed by the command
+        self.shell == Shell::Cmd
+    }
+
+    // a session runs one command after another, so it gets to the probe once
+    // the command has finished
+    fn probe_finished(&mut self) -> Option<PathBuf> {
+        self.pool.probe_last().ok()
+    }
+}
+
+/// Spawns a new shell process for every command
+pub struct DirectExecutor {
+    shell: Shell,
+    on_error: Option<String>,
+}
+
+impl Executor for DirectExecutor {
+    fn execute(&mut self, command: &str, env: &[(&str, String)]) -> Result<()> {
+        session::spawn_direct(self.shell, command, env)
+            .map(drop)
+            .map_err(|error| eyre!("could not spawn {}: {error}", self.shell))
+    }
+
+    fn execute_watched(
+        &mut self,
+        command: &str,
+        env: &[(&str, String)],
+        on_fail: Option<&str>,
+        message: Option<&str>,
+    ) -> Result<()> {
+        watch(
+            self.shell,
+            self.on_error.as_deref(),
+            command,
+            env,
+            on_fail,
+            message,
+        )
+    }
+
+    fn report_failure(&self, error: &str, message: Option<&str>, env: &[(&str, String)]) {
+        report(self.shell, self.on_error.as_deref(), error, message, env);
+    }
+}
+
+/// Sends the `komorebic` commands which it can translate straight to the socket
+/// of komorebi, which saves starting a process for each of them, and hands
+/// every other command to the executor it wraps
+pub struct KomorebiExecutor {
+    inner: Box<dyn Executor>,
+    /// Whether the last command went to komorebi rather than the inner executor
+    sent: bool,
+}
+
+impl Executor for KomorebiExecutor {
+    fn execute(&mut self, command: &str, env: &[(&str, String)]) -> Result<()> {
+        self.sent = false;
+        if let Some(message) = komorebi_message(command) {
+            // komorebic reports it properly when komorebi isn't running
+            match send_to_komorebi(&message) {
+                Ok(()) => {
+                    self.sent = true;
+                    return Ok(());
+                }
+                Err(error) => log::error(format!(
+                    "could not reach komorebi, running '{}' instead: {error}",
+                    redact::command(command)
+                )),
+            }
+        }
+
+        self.inner.execute(command, env)
+    }
+
+    // the exit code of a watched command only comes from komorebic itself
+    fn execute_watched(
+        &mut self,
+        command: &str,
+        env: &[(&str, String)],
+        on_fail: Option<&str>,
+        message: Option<&str>,
+    ) -> Result<()> {
+        self.sent = false;
+        self.inner.execute_watched(command, env, on_fail, message)
+    }
+
+    fn report_failure(&self, error: &str, message: Option<&str>, env: &[(&str, String)]) {
+        self.inner.report_failure(error, message, env);
+    }
+
+    fn revive(&mut self) -> Result<usize> {
+        self.inner.revive()
+    }
+
+    fn check_health(&mut self, interval: Duration) -> Result<usize> {
+        self.inner.check_health(interval)
+    }
+
+    fn retire(&mut self, idle: Duration) -> usize {
+        self.inner.retire(idle)
+    }
+
+    fn shut_down(&mut self) {
+        self.inner.shut_down();
+    }
+
+    fn echoes(&self) -> bool {
+        self.inner.echoes()
+    }
+
+    // komorebi handles its messages as they arrive, which can't be waited for
+    fn probe_finished(&mut self) -> Option<PathBuf> {
+        if self.sent {
+            None
+        } else {
+            self.inner.probe_finished()
+        }
+    }
+}
+
+/// The message which komorebi handles for a `komorebic` command, for the
+/// commands which bindings use most and only when they are written plainly,
+/// e.g. `komorebic focus left`
+fn komorebi_message(command: &str) -> Option<Value> {
+    let direction = |direction: &str| match direction {
+        "left" => Some("Left"),
+        "right" => Some("Right"),
+        "up" => Some("Up"),
+        "down" => Some("Down"),
+        _ => None,
+    };
+
+    let words = command.split_whitespace().collect::<Vec<_>>();
+    let message = match words.as_slice() {
+        ["komorebic", "focus", to] => json!({ "type": "FocusWindow", "content": direction(to)? }),
+        ["komorebic", "move", to] => json!({ "type": "MoveWindow", "content": direction(to)? }),
+        ["komorebic", "stack", to] => json!({ "type": "StackWindow", "content": direction(to)? }),
+        ["komorebic", "resize-axis", axis, sizing] => {
+            let axis = match *axis {
+                "horizontal" => "Horizontal",
+                "vertical" => "Vertical",
+                _ => return None,
+            };
+            let sizing = match *sizing {
+                "increase" => "Increase",
+                "decrease" => "Decrease",
+                _ => return None,
+            };
+
+            json!({ "type": "ResizeAxis", "content": [axis, sizing] })
+        }
+        ["komorebic", "focus-workspace", number] => {
+            json!({ "type": "FocusWorkspaceNumber", "content": number.parse::<usize>().ok()? })
+        }
+        ["komorebic", "move-to-workspace", number] => json!({
+            "type": "MoveContainerToWorkspaceNumber",
+            "content": number.parse::<usize>().ok()?
+        }),
+        ["komorebic", action] => {
+            let kind = match *action {
+                "toggle-float" => "ToggleFloat",
+                "toggle-monocle" => "ToggleMonocle",
+                "toggle-maximize" => "ToggleMaximize",
+                "retile" => "Retile",
+                "close" => "Close",
+                "minimize" => "Minimize",
+                _ => return None,
+            };
+
+            json!({ "type": kind })
+        }
+        _ => return None,
+    };
+
+    Some(message)
+}
+
+fn send_to_komorebi(message: &Value) -> Result<()> {
+    let socket = dirs::data_local_dir()
+        .ok_or_else(|| eyre!("there is no local data directory"))?
+        .join("komorebi")
+        .join("komorebi.sock");
+
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{message}")?;
+    Ok(())
+}
+
+/// Synthesizes the chord which a command names, e.g. `ctrl + v`, instead of
+/// running it
+pub struct InputExecutor;
+
+impl Executor for InputExecutor {
+    fn execute(&mut self, command: &str, _env: &[(&str, String)]) -> Result<()> {
+        let keys = command
+            .split('+')
+            .map(str::trim)
+            .map(String::from)
+            .collect::<Vec<_>>();
+
+        let chord = hook::Chord::parse(&keys)
+            .ok_or_else(|| eyre!("{command} isn't a chord, see `whkd keys`"))?;
+
+        if input::send_chord(&chord.keys(), &hook::held_modifiers()) {
+            Ok(())
+        } else {
+            Err(eyre!("could not send {command}, the input was blocked"))
+        }
+    }
+}
+
+/// Spawn a command and wait for it to exit away from the dispatcher, reporting
+/// it and running `on_fail` with the exit code in `WHKD_EXIT_CODE` if it fails
+fn watch(
+    shell: Shell,
+    hook: Option<&str>,
+    command: &str,
+    env: &[(&str, String)],
+    on_fail: Option<&str>,
+    message: Option<&str>,
+) -> Result<()> {
+    let mut child = session::spawn_direct(shell, command, env)
+        .map_err(|error| eyre!("could not spawn {shell}: {error}"))?;
+
+    let command = redact::command(command);
+    let hook = hook.map(ToString::to_string);
+    let on_fail = on_fail.map(ToString::to_string);
+    let message = message.map(ToString::to_string);
+    let env = env
+        .iter()
+        .map(|(name, value)| ((*name).to_string(), value.clone()))
+        .collect::<Vec<_>>();
+
+    runtime::spawn_blocking(move || {
+        let status = match child.wait() {
+            Ok(status) if status.success() => return,
+            Ok(status) => status,
+            Err(error) => {
+                log::error(format!("could not wait for '{command}': {error}"));
+                return;
+            }
+        };
+
+        let code = status
+            .code()
+            .map_or_else(|| String::from("unknown"), |code| code.to_string());
+
+        let mut env = env
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect::<Vec<_>>();
+        env.push(("WHKD_EXIT_CODE", code.clone()));
+
+        report(
+            shell,
+            hook.as_deref(),
+            &format!("'{command}' failed with exit code {code}"),
+            message.as_deref(),
+            &env,
+        );
+
+        if let Some(on_fail) = on_fail {
+            if let Err(error) = session::spawn_direct(shell, &on_fail, &env) {
+                log::error(format!("could not run '{on_fail}': {error}"));
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn describe(error: &str, message: Option<&str>) -> String {
+    message.map_or_else(
+        || error.to_string(),
+        |message| format!("{message}: {error}"),
+    )
+}
+
+/// Print the error of a command which failed and run the `.on_error` command
+/// with the `~on-error` message of the binding, or else the error, in
+/// `WHKD_ERROR`
+fn report(
+    shell: Shell,
+    hook: Option<&str>,
+    error: &str,
+    message: Option<&str>,
+    env: &[(&str, String)],
+) {
+    log::error(describe(error, message));
+
+    let Some(hook) = hook else {
+        return;
+    };
+
+    if dnd::silenced() {
+        return;
+    }
+
+    let mut env = env.to_vec();
+    env.push(("WHKD_ERROR", message.unwrap_or(error).to_string()));
+    if let Err(error) = session::spawn_direct(shell, hook, &env) {
+        log::error(format!("could not run '{hook}': {error}"));
+    }
+}
+
+/// The executors of the shell of whkdrc and of the shells of `.mode <name> shell
+/// <shell>`, which are only created once they have a command to run
+pub struct Executors {
+    whkdrc: Whkdrc,
+    /// The shell of `.shell`, or the one switched to with `; shell`
+    shell: Shell,
+    by_shell: HashMap<Shell, Box<dyn Executor>>,
+}
+
+impl Executors {
+    pub fn new(whkdrc: &Whkdrc) -> Self {
+        Self {
+            whkdrc: whkdrc.clone(),
+            shell: whkdrc.shell,
+            by_shell: HashMap::new(),
+        }
+    }
+
+    /// The shell which runs the commands of a mode
+    pub fn shell(&self, mode: Option<&String>) -> Shell {
+        self.whkdrc.mode_shell(mode).unwrap_or(self.shell)
+    }
+
+    /// The executor of a shell, which is created the first time
+    pub fn get(&mut self, shell: Shell) -> &mut dyn Executor {
+        let whkdrc = &self.whkdrc;
+        self.by_shell
+            .entry(shell)
+            .or_insert_with(|| new(&with_shell(whkdrc, shell)))
+            .as_mut()
+    }
+
+    /// Run the commands of the modes without a shell of their own with another
+    /// shell, stopping the sessions of the previous one unless a mode uses it
+    pub fn switch(&mut self, shell: Shell) {
+        self.shell = shell;
+
+        let mode_shells = &self.whkdrc.mode_shells;
+        self.by_shell.retain(|kept, _| {
+            *kept == shell || mode_shells.iter().any(|(_, mode_shell)| mode_shell == kept)
+        });
+    }
+
+    pub fn revive(&mut self) -> Result<usize> {
+        let mut revived = 0;
+        for executor in self.by_shell.values_mut() {
+            revived += executor.revive()?;
+        }
+
+        Ok(revived)
+    }
+
+    pub fn check_health(&mut self, interval: Duration) -> Result<usize> {
+        let mut restarted = 0;
+        for executor in self.by_shell.values_mut() {
+            restarted += executor.check_health(interval)?;
+        }
+
+        Ok(restarted)
+    }
+
+    pub fn retire(&mut self, idle: Duration) -> usize {
+        self.by_shell
+            .values_mut()
+            .map(|executor| executor.retire(idle))
+            .sum()
+    }
+
+    pub fn shut_down(&mut self) {
+        for executor in self.by_shell.values_mut() {
+            executor.shut_down();
+        }
+    }
+}
+
+/// whkdrc with commands run by another shell, which is started without the
+/// arguments of `.shell` and the lines of `.shell_init` as they are meant for
+/// the shell of whkdrc
+fn with_shell(whkdrc: &Whkdrc, shell: Shell) -> Whkdrc {
+    let mut switched = whkdrc.clone();
+    if shell != whkdrc.shell {
+        switched.shell = shell;
+        switched.shell_args.clear();
+        switched.shell_init.clear();
+    }
+
+    switched
+}
+
+/// The executor for the `.exec` directive of whkdrc
+pub fn new(whkdrc: &Whkdrc) -> Box<dyn Executor> {
+    let executor: Box<dyn Executor> = match whkdrc.exec {
+        Exec::Session => Box::new(SessionExecutor {
+            shell: whkdrc.shell,
+            on_error: whkdrc.on_error.clone(),
+            pool: SessionPool::new(
+                whkdrc.shell,
+                &whkdrc.shell_args,
+                whkdrc.shell_instances,
+                &whkdrc.shell_init,
+            ),
+        }),
+        Exec::Direct => Box::new(DirectExecutor {
+            shell: whkdrc.shell,
+            on_error: whkdrc.on_error.clone(),
+        }),
+    };
+
+    if whkdrc.options.komorebi_pipe {
+        Box::new(KomorebiExecutor {
+            inner: executor,
+            sent: false,
+        })
+    } else {
+        executor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_komorebi_message() {
+        assert_eq!(
+            komorebi_message("komorebic focus left"),
+            Some(json!({ "type": "FocusWindow", "content": "Left" }))
+        );
+        assert_eq!(
+            komorebi_message("komorebic  resize-axis horizontal increase"),
+            Some(json!({ "type": "ResizeAxis", "content": ["Horizontal", "Increase"] }))
+        );
+        assert_eq!(
+            komorebi_message("komorebic focus-workspace 2"),
+            Some(json!({ "type": "FocusWorkspaceNumber", "content": 2 }))
+        );
+        assert_eq!(
+            komorebi_message("komorebic toggle-float"),
+            Some(json!({ "type": "ToggleFloat" }))
+        );
+
+        // left to komorebic itself
+        assert_eq!(komorebi_message("komorebic focus sideways"), None);
+        assert_eq!(komorebi_message("komorebic focus-workspace next"), None);
+        assert_eq!(komorebi_message("komorebic focus left; echo done"), None);
+        assert_eq!(komorebi_message("komorebic start --whkd"), None);
+        assert_eq!(komorebi_message("echo komorebic focus left"), None);
+    }
+
+    #[test]
+    fn test_with_shell() {
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh -NoProfile
+.shell_init [
+    Import-Module komorebi
+]
+
+alt + h : komorebic focus left
+",
+            )
+            .unwrap();
+
+        let switched = with_shell(&whkdrc, Shell::Cmd);
+        assert_eq!(switched.shell, Shell::Cmd);
+        assert!(switched.shell_args.is_empty());
+        assert!(switched.shell_init.is_empty());
+
+        let same = with_shell(&whkdrc, Shell::Pwsh);
+        assert_eq!(same.shell_args, whkdrc.shell_args);
+        assert_eq!(same.shell_init, whkdrc.shell_init);
+    }
+
+    #[test]
+    fn test_mode_shells() {
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh
+.mode legacy shell cmd
+
+alt + l ; legacy
+legacy > b : build.bat
+",
+            )
+            .unwrap();
+
+        let mut executors = Executors::new(&whkdrc);
+        let legacy = Some(String::from("legacy"));
+        assert_eq!(executors.shell(legacy.as_ref()), Shell::Cmd);
+        assert_eq!(executors.shell(None), Shell::Pwsh);
+
+        executors.switch(Shell::Powershell);
+        assert_eq!(executors.shell(legacy.as_ref()), Shell::Cmd);
+        assert_eq!(executors.shell(None), Shell::Powershell);
+    }
+}
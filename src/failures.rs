@@ -0,0 +1,217 @@
+use crate::ipc;
+use crate::ipc::SocketMessage;
+use crate::log;
+use crate::HkmData;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::BufRead;
+use std::io::BufReader;
+
+lazy_static! {
+    static ref FAILURES: Mutex<Vec<Failure>> = Mutex::new(vec![]);
+}
+
+/// A binding whose chord could not be registered the last time its mode was
+/// activated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub mode: Option<String>,
+    pub keys: Vec<String>,
+    pub process_name: Option<String>,
+    pub reason: Reason,
+    /// Line of the binding in whkdrc, unless whkd added the binding itself
+    pub line: Option<usize>,
+    /// Who has likely registered the chord already
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// The nearest chord which is free
+    #[serde(default)]
+    pub suggestion: Option<Vec<String>>,
+}
+
+impl Failure {
+    pub fn new(data: &HkmData, reason: Reason) -> Self {
+        Self {
+            mode: data.mode.clone(),
+            keys: data.keys.clone(),
+            process_name: data.process_name.clone(),
+            reason,
+            line: data.line,
+            owner: None,
+            suggestion: None,
+        }
+    }
+
+    fn chord(&self) -> String {
+        self.process_name.as_ref().map_or_else(
+            || self.keys.join(" + "),
+            |process_name| format!("{} [{process_name}]", self.keys.join(" + ")),
+        )
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.chord())?;
+        if let Some(line) = self.line {
+            write!(f, " (line {line})")?;
+        }
+
+        write!(f, ": {}", self.reason)?;
+        if let Some(owner) = &self.owner {
+            write!(f, ", probably {owner}")?;
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "; {} is free", suggestion.join(" + "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reason {
+    /// Another app has registered the chord already
+    AlreadyRegistered,
+    /// The key can't be registered or captured by the hook
+    InvalidKey,
+    Other(String),
+}
+
+impl From<global_hotkey::Error> for Reason {
+    fn from(error: global_hotkey::Error) -> Self {
+        match error {
+            global_hotkey::Error::AlreadyRegistered(_) => Self::AlreadyRegistered,
+            // raised for keys without a virtual key code
+            global_hotkey::Error::FailedToRegister(_) => Self::InvalidKey,
+            error => Self::Other(error.to_string()),
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyRegistered => write!(f, "already registered by another app"),
+            Self::InvalidKey => write!(f, "the key can't be registered"),
+            Self::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// Replace the failures of a mode with those of its latest registration and
+/// print them as a single report
+pub fn report(mode: Option<&String>, bindings: usize, failures: Vec<Failure>) {
+    if !failures.is_empty() {
+        println!(
+            "{} of {bindings} bindings in mode {} could not be registered:",
+            failures.len(),
+            mode.map_or("default", String::as_str),
+        );
+
+        for failure in &failures {
+            log::error(format!("could not register {failure}"));
+        }
+    }
+
+    let mut all = FAILURES.lock();
+    all.retain(|failure| failure.mode.as_ref() != mode);
+    all.extend(failures);
+}
+
+/// Forget the failures of a binding once its chord has been registered
+pub fn resolve(data: &HkmData) {
+    FAILURES
+        .lock()
+        .retain(|failure| failure.mode != data.mode || failure.keys != data.keys);
+}
+
+pub fn snapshot() -> Vec<Failure> {
+    FAILURES.lock().clone()
+}
+
+/// Fetch the registration failures of the running whkd instance and print them,
+/// or as JSON
+pub fn show(json: bool) -> Result<()> {
+    let stream = ipc::send(&SocketMessage::Failures)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    if json {
+        print!("{line}");
+        return Ok(());
+    }
+
+    let failures: Vec<Failure> = serde_json::from_str(&line)?;
+    if failures.is_empty() {
+        println!("no chord has failed to register");
+    }
+
+    for failure in &failures {
+        println!(
+            "{} > {failure}",
+            failure.mode.as_deref().unwrap_or("default")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let failure = Failure {
+            mode: None,
+            keys: vec![String::from("alt"), String::from("h")],
+            process_name: Some(String::from("Firefox")),
+            reason: Reason::AlreadyRegistered,
+            line: Some(12),
+            owner: None,
+            suggestion: None,
+        };
+
+        assert_eq!(
+            failure.to_string(),
+            "alt + h [Firefox] (line 12): already registered by another app"
+        );
+
+        let failure = Failure {
+            line: None,
+            reason: Reason::InvalidKey,
+            ..failure
+        };
+
+        assert_eq!(
+            failure.to_string(),
+            "alt + h [Firefox]: the key can't be registered"
+        );
+
+        let failure = Failure {
+            keys: vec![String::from("alt"), String::from("space")],
+            process_name: None,
+            reason: Reason::AlreadyRegistered,
+            owner: Some(String::from("PowerToys Run")),
+            suggestion: Some(vec![
+                String::from("alt"),
+                String::from("shift"),
+                String::from("space"),
+            ]),
+            ..failure
+        };
+
+        assert_eq!(
+            failure.to_string(),
+            "alt + space: already registered by another app, probably PowerToys Run; \
+             alt + shift + space is free"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::log;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::process::Command;
+use windows_sys::Win32::Foundation::CloseHandle;
+use windows_sys::Win32::Foundation::BOOL;
+use windows_sys::Win32::Foundation::HWND;
+use windows_sys::Win32::Foundation::LPARAM;
+use windows_sys::Win32::System::Threading::OpenProcess;
+use windows_sys::Win32::System::Threading::QueryFullProcessImageNameW;
+use windows_sys::Win32::System::Threading::PROCESS_NAME_WIN32;
+use windows_sys::Win32::System::Threading::PROCESS_QUERY_LIMITED_INFORMATION;
+use windows_sys::Win32::UI::WindowsAndMessaging::EnumWindows;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetWindowTextW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetWindowThreadProcessId;
+use windows_sys::Win32::UI::WindowsAndMessaging::IsIconic;
+use windows_sys::Win32::UI::WindowsAndMessaging::IsWindowVisible;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetForegroundWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::ShowWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::GW_OWNER;
+use windows_sys::Win32::UI::WindowsAndMessaging::SW_RESTORE;
+
+/// Hosts the windows of Windows Terminal, which `wt` hands its arguments to
+const TERMINAL: &str = "WindowsTerminal.exe";
+
+/// Bring the first window of a process to the front, or launch it if it has no
+/// window, e.g. `firefox.exe` or the full path to an executable
+pub fn focus_or_launch(target: &str) {
+    let found = find_window(&|hwnd| image_of(hwnd).is_some_and(|image| matches(target, &image)));
+    if let Err(error) = found.map_or_else(|| launch(target), focus) {
+        log::error(format!("could not focus or launch {target}: {error}"));
+    }
+}
+
+/// Bring the window of Windows Terminal which shows a profile to the front, or
+/// open the profile in a tab whose title stays at the name of the profile, which
+/// is how its window is found again
+pub fn terminal(profile: &str) {
+    let found = find_window(&|hwnd| {
+        title_of(hwnd).is_some_and(|title| title == profile)
+            && image_of(hwnd).is_some_and(|image| matches(TERMINAL, &image))
+    });
+
+    let result = found.map_or_else(
+        || {
+            Command::new("wt")
+                .args(terminal_args(profile))
+                .spawn()
+                .map(drop)
+                .map_err(|error| eyre!("could not start wt: {error}"))
+        },
+        focus,
+    );
+
+    if let Err(error) = result {
+        log::error(format!(
+            "could not focus or open the {profile} terminal: {error}"
+        ));
+    }
+}
+
+/// Open a tab of the profile, whose title isn't changed by the shell
+const fn terminal_args(profile: &str) -> [&str; 6] {
+    [
+        "new-tab",
+        "--profile",
+        profile,
+        "--title",
+        profile,
+        "--suppressApplicationTitle",
+    ]
+}
+
+/// Whether the image of a process is the executable named by the target, which
+/// may leave out `.exe`
+fn matches(target: &str, image: &str) -> bool {
+    let file_name = |path: &str| {
+        path.rsplit(['\\', '/'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+
+    let target = file_name(target);
+    let image = file_name(image);
+
+    image == target || image.strip_suffix(".exe") == Some(target.as_str())
+}
+
+fn image_of(hwnd: HWND) -> Option<String> {
+    let mut pid = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, std::ptr::addr_of_mut!(pid)) };
+
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if process == 0 {
+        return None;
+    }
+
+    let mut buffer = [0u16; 1024];
+    #[allow(clippy::cast_possible_truncation)]
+    let mut len = buffer.len() as u32;
+    let queried = unsafe {
+        QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            buffer.as_mut_ptr(),
+            std::ptr::addr_of_mut!(len),
+        )
+    } != 0;
+
+    unsafe { CloseHandle(process) };
+
+    queried.then(|| String::from_utf16_lossy(&buffer[..len as usize]))
+}
+
+fn title_of(hwnd: HWND) -> Option<String> {
+    let mut buffer = [0u16; 512];
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let len = unsafe { GetWindowTextW(hwnd, buffer.as_mut_ptr(), buffer.len() as i32) };
+
+    usize::try_from(len)
+        .ok()
+        .filter(|len| *len > 0)
+        .map(|len| String::from_utf16_lossy(&buffer[..len]))
+}
+
+/// The first visible top-level window which is accepted, from the top
+pub fn find_window(accept: &dyn Fn(HWND) -> bool) -> Option<HWND> {
+    struct Search<'a> {
+        accept: &'a dyn Fn(HWND) -> bool,
+        found: Option<HWND>,
+    }
+
+    unsafe extern "system" fn visit(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let search = &mut *(lparam as *mut Search);
+
+        // owned windows are dialogs and tool windows of another window
+        if IsWindowVisible(hwnd) != 0 && GetWindow(hwnd, GW_OWNER) == 0 && (search.accept)(hwnd) {
+            search.found = Some(hwnd);
+            return 0;
+        }
+
+        1
+    }
+
+    let mut search = Search {
+        accept,
+        found: None,
+    };
+
+    unsafe { EnumWindows(Some(visit), std::ptr::addr_of_mut!(search) as LPARAM) };
+
+    search.found
+}
+
+pub fn focus(hwnd: HWND) -> Result<()> {
+    unsafe {
+        if IsIconic(hwnd) != 0 {
+            ShowWindow(hwnd, SW_RESTORE);
+        }
+
+        if SetForegroundWindow(hwnd) == 0 {
+            return Err(eyre!("Windows refused to bring the window to the front"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Launch through `start`, which also finds the executables registered under
+/// App Paths such as `firefox.exe` which aren't on the PATH
+fn launch(target: &str) -> Result<()> {
+    Command::new("cmd")
+        .args(["/C", "start", "", target])
+        .spawn()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let image = r"C:\Program Files\Mozilla Firefox\firefox.exe";
+
+        assert!(matches("firefox.exe", image));
+        assert!(matches("Firefox", image));
+        assert!(matches(image, image));
+        assert!(!matches("fire", image));
+        assert!(!matches("firefox.exe", r"C:\Windows\explorer.exe"));
+    }
+
+    #[test]
+    fn test_terminal_args() {
+        assert_eq!(
+            terminal_args("Ubuntu"),
+            [
+                "new-tab",
+                "--profile",
+                "Ubuntu",
+                "--title",
+                "Ubuntu",
+                "--suppressApplicationTitle"
+            ]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use active_win_pos_rs::ActiveWindow;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long a lookup of the foreground window is reused, short enough that the
+/// window can't have changed in between without a keypress noticing
+const TTL: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The foreground window which tests on this thread pretend to have, if
+    /// they pretend at all
+    #[allow(clippy::option_option)]
+    static PRETENDED: std::cell::RefCell<Option<Option<ActiveWindow>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// The last lookup of the foreground window and when it was made
+#[derive(Debug, Default)]
+struct Cache {
+    entry: Option<(Instant, Option<ActiveWindow>)>,
+}
+
+impl Cache {
+    fn get(
+        &mut self,
+        now: Instant,
+        query: impl FnOnce() -> Option<ActiveWindow>,
+    ) -> Option<ActiveWindow> {
+        if let Some((queried, window)) = &self.entry {
+            if now.duration_since(*queried) < TTL {
+                return window.clone();
+            }
+        }
+
+        let window = query();
+        self.entry = Some((now, window.clone()));
+        window
+    }
+}
+
+/// The foreground window, which is only looked up again once the previous
+/// lookup is older than `TTL`, so that chords pressed in quick succession
+/// don't query it every time
+pub fn window() -> Option<ActiveWindow> {
+    #[cfg(test)]
+    if let Some(window) = PRETENDED.with(|pretended| pretended.borrow().clone()) {
+        return window;
+    }
+
+    CACHE.lock().get(Instant::now(), || {
+        active_win_pos_rs::get_active_window().ok()
+    })
+}
+
+/// The process name of the foreground window
+pub fn app_name() -> Option<String> {
+    window().map(|window| window.app_name)
+}
+
+/// Forget the last lookup, as the foreground window has changed
+pub fn invalidate() {
+    CACHE.lock().entry = None;
+}
+
+/// Make the window of an app (or no window at all) the foreground window for
+/// the rest of the current test
+#[cfg(test)]
+pub fn pretend(app_name: Option<&str>) {
+    let window = app_name.map(|app_name| ActiveWindow {
+        app_name: app_name.to_string(),
+        ..ActiveWindow::default()
+    });
+
+    PRETENDED.with(|pretended| *pretended.borrow_mut() = Some(window));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(app_name: &str) -> ActiveWindow {
+        ActiveWindow {
+            app_name: app_name.to_string(),
+            ..ActiveWindow::default()
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut cache = Cache::default();
+        let now = Instant::now();
+
+        let first = cache.get(now, || Some(window("firefox")));
+        assert_eq!(first.unwrap().app_name, "firefox");
+
+        let cached = cache.get(now + TTL / 2, || Some(window("code")));
+        assert_eq!(cached.unwrap().app_name, "firefox");
+
+        let expired = cache.get(now + TTL, || Some(window("code")));
+        assert_eq!(expired.unwrap().app_name, "code");
+
+        cache.entry = None;
+        let invalidated = cache.get(now + TTL, || Some(window("explorer")));
+        assert_eq!(invalidated.unwrap().app_name, "explorer");
+    }
+}
@@ -0,0 +1,337 @@
+use crate::chord_id;
+use crate::dispatch::dispatch;
+use crate::dispatch::Dispatch;
+use crate::executor::Executor;
+use crate::foreground;
+use crate::handle_chord;
+use crate::mode::ModeManager;
+use crate::parser;
+use crate::whkdrc::Backend;
+use crate::EventLoopProxy;
+use chumsky::Parser as _;
+use color_eyre::eyre::Result;
+use parking_lot::Mutex;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+/// Keeps the commands it is given instead of running them
+#[derive(Default)]
+pub struct Recorder(pub Vec<(String, Vec<(String, String)>)>);
+
+impl Executor for Recorder {
+    fn execute(&mut self, command: &str, env: &[(&str, String)]) -> Result<()> {
+        self.0.push((
+            command.to_string(),
+            env.iter()
+                .map(|(name, value)| ((*name).to_string(), value.clone()))
+                .collect(),
+        ));
+
+        Ok(())
+    }
+}
+
+/// What has been dispatched so far, shared with the mode change callback so
+/// that commands and mode changes are logged in the order they happened
+struct Recording {
+    receiver: mpsc::Receiver<Dispatch>,
+    recorder: Recorder,
+    log: Vec<String>,
+}
+
+impl Recording {
+    /// Log something which happened after the pending dispatches
+    fn push(&mut self, entry: String) {
+        self.flush();
+        self.log.push(entry);
+    }
+
+    /// Run the pending dispatches through the recorder and log them
+    fn flush(&mut self) {
+        while let Ok(work) = self.receiver.try_recv() {
+            // toggles aren't probed, as the harness doesn't run anything
+            let work = work.resolve(|_, _| None);
+            let Dispatch::Command {
+                hotkey,
+                command,
+                count,
+                pressed,
+                resolved,
+            } = work
+            else {
+                self.log.push(format!("{work:?}"));
+                continue;
+            };
+
+            dispatch(
+                &mut self.recorder,
+                &hotkey,
+                &command,
+                count,
+                pressed,
+                resolved,
+            );
+            for (command, env) in self.recorder.0.drain(..) {
+                let count = env
+                    .iter()
+                    .find(|(name, _)| name == "WHKD_COUNT")
+                    .map(|(_, count)| count.as_str());
+
+                match count {
+                    Some("1") | None => self.log.push(format!("run {command}")),
+                    Some(count) => self.log.push(format!("run {command} x{count}")),
+                }
+            }
+        }
+    }
+}
+
+/// Presses chords of a whkdrc without a keyboard and logs the commands which
+/// are run and the modes which are switched to, e.g. `run komorebic focus left`
+/// and `mode resize`
+pub struct Harness {
+    mode_manager: ModeManager,
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl Harness {
+    /// Load a whkdrc with the keyboard hook as the backend, so that no chord is
+    /// registered with Windows
+    pub fn new(src: &str) -> Self {
+        let mut whkdrc = parser::parser().parse(src).unwrap();
+        whkdrc.backend = Backend::Hook;
+
+        let (sender, receiver) = mpsc::channel();
+        let mode_manager =
+            ModeManager::new(&whkdrc, sender, EventLoopProxy::detached(), None).unwrap();
+        mode_manager.activate_profile(None);
+        foreground::pretend(None);
+
+        let recording = Arc::new(Mutex::new(Recording {
+            receiver,
+            recorder: Recorder::default(),
+            log: vec![],
+        }));
+
+        let subscribed = recording.clone();
+        mode_manager.subscribe(move |_, to| {
+            subscribed
+                .lock()
+                .push(format!("mode {}", to.map_or("default", String::as_str)));
+        });
+
+        Self {
+            mode_manager,
+            recording,
+        }
+    }
+
+    /// Bring the window of an app to the foreground
+    pub fn focus(&self, app_name: &str) {
+        foreground::pretend(Some(app_name));
+        self.mode_manager.focus(Some(app_name));
+    }
+
+    /// Hold down or release the key of a `.layer`
+    pub fn hold(&self, mode: &str, held: bool) {
+        self.mode_manager.hold_layer(&mode.to_string(), held);
+    }
+
+    /// Press a chord the way the keyboard hook would report it
+    pub fn press(&self, chord: &str) {
+        let keys = chord
+            .split('+')
+            .map(|key| key.trim().to_string())
+            .collect::<Vec<_>>();
+
+        match chord_id(self.mode_manager.backend, &keys)
+            .filter(|id| self.mode_manager.registered.lock().contains_key(id))
+        {
+            Some(id) => handle_chord(&self.mode_manager, id),
+            None => self.recording.lock().push(format!("unbound {chord}")),
+        }
+    }
+
+    /// Everything which happened since the last call
+    pub fn take(&self) -> Vec<String> {
+        let mut recording = self.recording.lock();
+        recording.flush();
+        std::mem::take(&mut recording.log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modes() {
+        let harness = Harness::new(
+            r"
+.shell pwsh
+.mode resize count
+
+alt + h : komorebic focus left
+alt + r ; resize
+resize > h : komorebic resize-axis horizontal increase
+resize > escape ; default
+",
+        );
+
+        harness.press("alt + h");
+        harness.press("alt + r");
+        harness.press("h");
+        harness.press("3");
+        harness.press("h");
+        harness.press("escape");
+        harness.press("h");
+
+        assert_eq!(
+            harness.take(),
+            vec![
+                "run komorebic focus left",
+                "mode resize",
+                "run komorebic resize-axis horizontal increase",
+                "run komorebic resize-axis horizontal increase x3",
+                "mode default",
+                "unbound h",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_digits_without_count() {
+        let harness = Harness::new(
+            r"
+.shell pwsh
+
+alt + r ; resize
+resize > h : komorebic resize-axis horizontal increase
+",
+        );
+
+        harness.press("alt + r");
+        harness.press("3");
+        harness.press("h");
+
+        assert_eq!(
+            harness.take(),
+            vec![
+                "mode resize",
+                "unbound 3",
+                "run komorebic resize-axis horizontal increase",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_app_bindings() {
+        let harness = Harness::new(
+            r#"
+.shell pwsh
+
+alt + n [
+    Firefox : echo "hello firefox"
+]
+
+alt + n : echo "hello everything else"
+"#,
+        );
+
+        harness.focus("Firefox");
+        harness.press("alt + n");
+        harness.focus("Code");
+        harness.press("alt + n");
+
+        assert_eq!(
+            harness.take(),
+            vec![
+                r#"run echo "hello firefox""#,
+                r#"run echo "hello everything else""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chaining() {
+        let harness = Harness::new(
+            r"
+.shell pwsh
+
+alt + j : komorebic focus down ; resize
+alt + k ; trigger alt + j
+resize > j : komorebic resize-axis vertical increase
+",
+        );
+
+        harness.press("alt + k");
+        harness.press("j");
+
+        assert_eq!(
+            harness.take(),
+            vec![
+                "run komorebic focus down",
+                "mode resize",
+                "run komorebic resize-axis vertical increase",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layer() {
+        let harness = Harness::new(
+            r"
+.shell pwsh
+.layer CapsLock nav
+
+alt + r ; resize
+resize > h : komorebic resize-axis horizontal increase
+nav > h : komorebic focus left
+",
+        );
+
+        harness.press("alt + r");
+        harness.hold("nav", true);
+        harness.press("h");
+        harness.hold("nav", false);
+        harness.press("h");
+
+        assert_eq!(
+            harness.take(),
+            vec![
+                "mode resize",
+                "mode nav",
+                "run komorebic focus left",
+                "mode resize",
+                "run komorebic resize-axis horizontal increase",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toggle_binding() {
+        let harness = Harness::new(
+            r"
+.shell pwsh
+
+alt + s ~name(screenshot) : snippingtool /clip
+alt + t ; toggle-binding screenshot
+",
+        );
+
+        harness.press("alt + s");
+        harness.press("alt + t");
+        harness.press("alt + s");
+        harness.press("alt + t");
+        harness.press("alt + s");
+
+        assert_eq!(
+            harness.take(),
+            vec![
+                "run snippingtool /clip",
+                "unbound alt + s",
+                "run snippingtool /clip",
+            ]
+        );
+    }
+}
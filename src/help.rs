@@ -0,0 +1,109 @@
+use crate::log;
+use crate::parser::InternalAction;
+use crate::HkmData;
+use std::process::Command;
+
+/// Shows the help, where it can be searched with ctrl + f
+const VIEWER: &str = "notepad";
+
+/// Open the bindings of a mode in a window of their own, as a text file which
+/// is written again every time so that it follows the live state
+pub fn show(mode: Option<&String>, bindings: &[HkmData]) {
+    let path = std::env::temp_dir().join("whkd-help.txt");
+    if let Err(error) = std::fs::write(&path, format(mode, bindings)) {
+        log::error(format!("could not write the help: {error}"));
+        return;
+    }
+
+    if let Err(error) = Command::new(VIEWER).arg(&path).spawn() {
+        log::error(format!("could not start {VIEWER}: {error}"));
+    }
+}
+
+/// What a binding does, its description if it has one
+fn summary(data: &HkmData) -> String {
+    if let Some(description) = &data.description {
+        return description.clone();
+    }
+
+    let mut summary = vec![];
+    if let Some(command) = &data.command {
+        summary.push(format!(": {command}"));
+    }
+
+    match &data.internal_action {
+        Some(action @ (InternalAction::Cycle(_) | InternalAction::Toggle { .. })) => {
+            summary.push(format!(":: {action}"));
+        }
+        Some(action) => summary.push(format!("; {action}")),
+        None => {}
+    }
+
+    summary.join(" ")
+}
+
+/// One line for every binding with the chords lined up, in the order of whkdrc
+fn format(mode: Option<&String>, bindings: &[HkmData]) -> String {
+    let mut bindings = bindings.iter().collect::<Vec<_>>();
+    bindings.sort_by_key(|data| data.line.unwrap_or(usize::MAX));
+
+    let chords = bindings
+        .iter()
+        .map(|data| data.keys.join(" + "))
+        .collect::<Vec<_>>();
+    let width = chords.iter().map(String::len).max().unwrap_or_default();
+
+    let mut lines = vec![
+        format!(
+            "Bindings of the {} mode",
+            mode.map_or("default", String::as_str)
+        ),
+        String::new(),
+    ];
+
+    for (data, chord) in bindings.iter().zip(&chords) {
+        let mut line = format!("{chord:width$}  {}", summary(data));
+        if let Some(process_name) = &data.process_name {
+            line = format!("{line} (for {process_name})");
+        }
+
+        lines.push(line);
+    }
+
+    // Notepad of older versions of Windows only breaks lines at `\r\n`
+    format!("{}\r\n", lines.join("\r\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_format() {
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh
+
+alt + h : komorebic focus left ## Focus window to the left
+alt + shift + r ; resize
+",
+            )
+            .unwrap();
+
+        let bindings = whkdrc
+            .bindings
+            .iter()
+            .map(|binding| HkmData::try_from(binding).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            format(None, &bindings),
+            "Bindings of the default mode\r\n\r\n\
+             alt + h          Focus window to the left\r\n\
+             alt + shift + r  ; resize\r\n"
+        );
+    }
+}
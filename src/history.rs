@@ -0,0 +1,140 @@
+use crate::foreground;
+use crate::ipc;
+use crate::ipc::SocketMessage;
+use crate::redact;
+use crate::HkmData;
+use chrono::Local;
+use chrono::TimeZone;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Number of dispatched commands kept in memory
+const HISTORY_LENGTH: usize = 100;
+
+lazy_static! {
+    /// The entries along with the binding and the command as it was run, which
+    /// the entry only has as it is shown
+    static ref HISTORY: Mutex<VecDeque<(Entry, HkmData, String)>> =
+        Mutex::new(VecDeque::with_capacity(HISTORY_LENGTH));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub mode: Option<String>,
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Process of the foreground window when the command was dispatched
+    pub app: Option<String>,
+    pub command: String,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let time = i64::try_from(self.timestamp)
+            .ok()
+            .and_then(|timestamp| Local.timestamp_millis_opt(timestamp).single())
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+
+        let name = self
+            .name
+            .as_ref()
+            .map(|name| format!(" ~name({name})"))
+            .unwrap_or_default();
+
+        write!(
+            f,
+            "{time}  {} > {}{name} ({}) : {}",
+            self.mode.as_deref().unwrap_or("default"),
+            self.keys.join(" + "),
+            self.app.as_deref().unwrap_or("unknown app"),
+            self.command,
+        )
+    }
+}
+
+pub fn record(data: &HkmData, command: &str) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| u64::try_from(since.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default();
+
+    let entry = Entry {
+        timestamp,
+        mode: data.mode.clone(),
+        keys: data.keys.clone(),
+        name: data.name.clone(),
+        app: foreground::app_name(),
+        command: redact::command(command),
+    };
+
+    let mut history = HISTORY.lock();
+    if history.len() == HISTORY_LENGTH {
+        history.pop_front();
+    }
+
+    history.push_back((entry, data.clone(), command.to_string()));
+}
+
+/// The most recently dispatched command and its binding, for `; repeat-last`
+pub fn last() -> Option<(HkmData, String)> {
+    HISTORY
+        .lock()
+        .back()
+        .map(|(_, data, command)| (data.clone(), command.clone()))
+}
+
+pub fn snapshot() -> Vec<Entry> {
+    HISTORY
+        .lock()
+        .iter()
+        .map(|(entry, _, _)| entry.clone())
+        .collect()
+}
+
+/// The last `count` dispatched commands, unless the history is locked, e.g. by
+/// a thread which has panicked while holding it
+pub fn try_recent(count: usize) -> Option<Vec<Entry>> {
+    let history = HISTORY.try_lock()?;
+    Some(
+        history
+            .iter()
+            .skip(history.len().saturating_sub(count))
+            .map(|(entry, _, _)| entry.clone())
+            .collect(),
+    )
+}
+
+/// Fetch the recently dispatched commands of the running whkd instance and print
+/// them oldest first, or as JSON
+pub fn show(json: bool) -> Result<()> {
+    let stream = ipc::send(&SocketMessage::History)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    if json {
+        print!("{line}");
+        return Ok(());
+    }
+
+    let history: Vec<Entry> = serde_json::from_str(&line)?;
+    for entry in &history {
+        println!("{entry}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,879 @@
+use crate::event_loop::EventLoopProxy;
+use crate::input;
+use crate::key_code_from_string;
+use crate::runtime;
+use crate::whkdrc::Whkdrc;
+use crate::UserEvent;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::time::Duration;
+use std::time::Instant;
+use windows_sys::Win32::Foundation::LPARAM;
+use windows_sys::Win32::Foundation::LRESULT;
+use windows_sys::Win32::Foundation::WPARAM;
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::GetAsyncKeyState;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_LCONTROL;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_LMENU;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_LSHIFT;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_LWIN;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_RCONTROL;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_RMENU;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_RSHIFT;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_RWIN;
+use windows_sys::Win32::UI::WindowsAndMessaging::CallNextHookEx;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetWindowsHookExW;
+use windows_sys::Win32::UI::WindowsAndMessaging::HC_ACTION;
+use windows_sys::Win32::UI::WindowsAndMessaging::KBDLLHOOKSTRUCT;
+use windows_sys::Win32::UI::WindowsAndMessaging::WH_KEYBOARD_LL;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_KEYDOWN;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_SYSKEYDOWN;
+
+/// Names, left and right virtual keys of the modifiers, in the order of
+/// `Chord::modifiers`
+pub const MODIFIERS: [(&str, VIRTUAL_KEY, VIRTUAL_KEY); 4] = [
+    ("ctrl", VK_LCONTROL, VK_RCONTROL),
+    ("alt", VK_LMENU, VK_RMENU),
+    ("shift", VK_LSHIFT, VK_RSHIFT),
+    ("super", VK_LWIN, VK_RWIN),
+];
+
+lazy_static! {
+    /// Chords of the active mode, read by the hook procedure
+    static ref BOUND: Mutex<Vec<Bound>> = Mutex::new(vec![]);
+    /// Keys whose key down was swallowed, so that their key up is swallowed too,
+    /// and whether they were synthesized by whkd
+    static ref SWALLOWED: Mutex<HashSet<(VIRTUAL_KEY, bool)>> = Mutex::new(HashSet::new());
+    /// Chords which are swallowed even if they aren't bound in the active mode,
+    /// so that e.g. the shortcuts of Windows can be taken over in some modes
+    /// without triggering in the others
+    static ref SUPPRESSED: Mutex<Vec<Chord>> = Mutex::new(vec![]);
+    /// A modifier-only chord which is held down and fires when it is released
+    static ref TAP: Mutex<Option<(Bound, Instant)>> = Mutex::new(None);
+    /// Modifier-only chords which are held for longer than this don't fire
+    static ref TAP_THRESHOLD: Mutex<Duration> = Mutex::new(Duration::ZERO);
+    static ref STICKY: Mutex<Sticky> = Mutex::new(Sticky::default());
+    static ref ROLLING: Mutex<Rolling> = Mutex::new(Rolling::default());
+    /// Keys of `.layer` which activate a mode while they are held down, the
+    /// mode and whether the key is held down
+    static ref LAYERS: Mutex<Vec<(VIRTUAL_KEY, String, bool)>> = Mutex::new(vec![]);
+    static ref PROXY: Mutex<Option<EventLoopProxy<UserEvent>>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Side {
+    Either,
+    Left,
+    Right,
+}
+
+/// How many keys which aren't modifiers a rolling chord can have besides `key`
+const MAX_ROLLED: usize = 3;
+
+/// A chord as seen by the keyboard hook, which unlike `RegisterHotKey` can tell
+/// left and right modifiers apart and can be made up of modifiers only or of
+/// several keys which aren't modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+    modifiers: [Option<Side>; 4],
+    key: Option<VIRTUAL_KEY>,
+    /// The other keys of a rolling chord such as `j + k`, which go down
+    /// together with `key` in any order
+    rolled: [Option<VIRTUAL_KEY>; MAX_ROLLED],
+}
+
+impl Chord {
+    /// Parse the keys of a binding, e.g. `["lalt", "h"]`, `["ctrl", "shift"]` or
+    /// `["j", "k"]`
+    pub fn parse(keys: &[String]) -> Option<Self> {
+        let mut chord = Self {
+            modifiers: [None; 4],
+            key: None,
+            rolled: [None; MAX_ROLLED],
+        };
+
+        let mut others = vec![];
+        for key in keys {
+            match modifier(key) {
+                Some((group, side)) if others.is_empty() => chord.modifiers[group] = Some(side),
+                Some(_) => return None,
+                None => others.push(input::vk_from_code(key_code_from_string(key)?)?),
+            }
+        }
+
+        // the keys of a rolling chord can be written in any order
+        others.sort_unstable();
+        others.dedup();
+        if others.len() > MAX_ROLLED + 1 {
+            return None;
+        }
+
+        let mut others = others.into_iter();
+        chord.key = others.next();
+        for (rolled, vk) in chord.rolled.iter_mut().zip(others) {
+            *rolled = Some(vk);
+        }
+
+        Some(chord)
+    }
+
+    /// The keys of the chord which aren't modifiers
+    fn others(self) -> impl Iterator<Item = VIRTUAL_KEY> {
+        self.key
+            .into_iter()
+            .chain(self.rolled.into_iter().flatten())
+    }
+
+    /// The keys which make up the chord, modifiers first and the left one of a
+    /// modifier where either side will do
+    pub fn keys(self) -> Vec<VIRTUAL_KEY> {
+        MODIFIERS
+            .iter()
+            .zip(self.modifiers)
+            .filter_map(|((_, left, right), side)| match side? {
+                Side::Right => Some(*right),
+                Side::Either | Side::Left => Some(*left),
+            })
+            .chain(self.others())
+            .collect()
+    }
+
+    /// Whether the chord can only be bound with `.backend hook`
+    pub fn needs_hook(self) -> bool {
+        self.key.is_none()
+            || self.is_rolling()
+            || self
+                .modifiers
+                .iter()
+                .any(|side| matches!(side, Some(Side::Left | Side::Right)))
+    }
+
+    pub const fn is_modifier_only(self) -> bool {
+        self.key.is_none()
+    }
+
+    /// Whether the chord has more than one key which isn't a modifier, which
+    /// the hook holds back until the rest of the chord follows
+    pub const fn is_rolling(self) -> bool {
+        self.rolled[0].is_some()
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn id(self) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    fn has_modifier(self, vk: VIRTUAL_KEY) -> bool {
+        MODIFIERS
+            .iter()
+            .zip(self.modifiers)
+            .any(|((_, left, right), side)| side.is_some() && (vk == *left || vk == *right))
+    }
+
+    /// Whether pressing `vk` completes the chord, given which keys are held down
+    fn matches(self, vk: VIRTUAL_KEY, held: impl Fn(VIRTUAL_KEY) -> bool) -> bool {
+        // rolling chords are completed by `rolled_by`
+        if self.is_rolling() {
+            return false;
+        }
+
+        // modifier-only chords are completed by any of their modifiers
+        let key_matches = self
+            .key
+            .map_or_else(|| self.has_modifier(vk), |key| key == vk);
+
+        key_matches && self.modifiers_held(held)
+    }
+
+    /// Whether `vk` is one of the keys of a rolling chord whose modifiers are
+    /// held down
+    fn rolls(self, vk: VIRTUAL_KEY, held: impl Fn(VIRTUAL_KEY) -> bool) -> bool {
+        self.is_rolling() && self.others().any(|key| key == vk) && self.modifiers_held(held)
+    }
+
+    /// Whether every key of a rolling chord is among the keys which went down
+    fn rolled_by(self, down: &[VIRTUAL_KEY]) -> bool {
+        self.is_rolling() && self.others().all(|key| down.contains(&key))
+    }
+
+    fn modifiers_held(self, held: impl Fn(VIRTUAL_KEY) -> bool) -> bool {
+        MODIFIERS
+            .iter()
+            .zip(self.modifiers)
+            .all(
+                |((_, left, right), side)| match (side, held(*left), held(*right)) {
+                    (None, left, right) => !left && !right,
+                    (Some(Side::Either), left, right) => left || right,
+                    (Some(Side::Left), left, right) => left && !right,
+                    (Some(Side::Right), left, right) => !left && right,
+                },
+            )
+    }
+}
+
+/// A modifier name with an optional `l` or `r` prefix in any case, as the index
+/// into `MODIFIERS` and the side
+fn modifier(key: &str) -> Option<(usize, Side)> {
+    let key = key.to_lowercase();
+    MODIFIERS
+        .iter()
+        .enumerate()
+        .find_map(|(group, (name, _, _))| {
+            if key == *name {
+                Some((group, Side::Either))
+            } else if key.strip_prefix('l') == Some(name) {
+                Some((group, Side::Left))
+            } else if key.strip_prefix('r') == Some(name) {
+                Some((group, Side::Right))
+            } else {
+                None
+            }
+        })
+}
+
+/// Whether a key of a binding is a modifier, e.g. `alt` or `RShift`
+pub fn is_modifier_name(key: &str) -> bool {
+    modifier(key).is_some()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bound {
+    id: u32,
+    chord: Chord,
+    passthrough: bool,
+    /// Whether the input synthesized by whkd fires the chord
+    recursive: bool,
+}
+
+/// Modifiers of `.sticky_modifiers` which were pressed and released on their
+/// own, and count towards the next key which isn't a modifier
+#[derive(Debug, Default)]
+struct Sticky {
+    /// How long a released modifier stays latched, nothing is latched if zero
+    window: Duration,
+    latched: Vec<(VIRTUAL_KEY, Instant)>,
+    /// Whether another key was pressed since a modifier went down, in which
+    /// case the modifier was used as part of a chord already
+    chorded: bool,
+}
+
+impl Sticky {
+    const fn press_modifier(&mut self) {
+        self.chorded = false;
+    }
+
+    /// Latch a modifier which is released, returning whether it was latched
+    fn release_modifier(&mut self, vk: VIRTUAL_KEY, now: Instant) -> bool {
+        if self.window.is_zero() || self.chorded {
+            return false;
+        }
+
+        self.latched.retain(|(latched, _)| *latched != vk);
+        self.latched.push((vk, now));
+        true
+    }
+
+    /// The modifiers which count towards a key which isn't a modifier, which
+    /// are used up by it
+    fn take(&mut self, now: Instant) -> Vec<VIRTUAL_KEY> {
+        self.chorded = true;
+        let window = self.window;
+        self.latched
+            .drain(..)
+            .filter(|(_, released)| now.duration_since(*released) <= window)
+            .map(|(vk, _)| vk)
+            .collect()
+    }
+}
+
+/// Keys of rolling chords which went down and are held back from the foreground
+/// app until the rest of their chord follows within `.rollover`
+#[derive(Debug, Default)]
+struct Rolling {
+    window: Duration,
+    held_back: Vec<(VIRTUAL_KEY, Instant)>,
+}
+
+impl Rolling {
+    /// Hold back a key which went down, returning whether it is the first one,
+    /// which the rest of the chord has to follow within the window
+    fn hold_back(&mut self, vk: VIRTUAL_KEY, now: Instant) -> bool {
+        self.held_back.push((vk, now));
+        self.held_back.len() == 1
+    }
+
+    fn is_held_back(&self, vk: VIRTUAL_KEY) -> bool {
+        self.held_back.iter().any(|(held_back, _)| *held_back == vk)
+    }
+
+    /// The keys which are held back, in the order they went down
+    fn keys(&self) -> Vec<VIRTUAL_KEY> {
+        self.held_back.iter().map(|(vk, _)| *vk).collect()
+    }
+
+    /// Whether the rest of the chord didn't follow the first key in time
+    fn expired(&self, now: Instant) -> bool {
+        self.held_back
+            .first()
+            .is_some_and(|(_, pressed)| now.duration_since(*pressed) >= self.window)
+    }
+
+    fn take(&mut self) -> Vec<VIRTUAL_KEY> {
+        self.held_back.drain(..).map(|(vk, _)| vk).collect()
+    }
+}
+
+fn is_modifier_key(vk: VIRTUAL_KEY) -> bool {
+    MODIFIERS
+        .iter()
+        .any(|(_, left, right)| vk == *left || vk == *right)
+}
+
+/// Install the keyboard hook, which has to happen on the event loop thread as
+/// the hook procedure is called from its message loop
+pub fn install(proxy: EventLoopProxy<UserEvent>, whkdrc: &Whkdrc) -> Result<()> {
+    configure(whkdrc)?;
+    *PROXY.lock() = Some(proxy);
+
+    let hook = unsafe {
+        SetWindowsHookExW(
+            WH_KEYBOARD_LL,
+            Some(hook_proc),
+            GetModuleHandleW(std::ptr::null()),
+            0,
+        )
+    };
+
+    if hook == 0 {
+        return Err(eyre!(
+            "could not install the keyboard hook: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Apply the directives of whkdrc which the hook procedure reads, also when
+/// whkdrc is reloaded
+pub fn configure(whkdrc: &Whkdrc) -> Result<()> {
+    let suppressed = whkdrc
+        .suppress
+        .iter()
+        .map(|keys| {
+            Chord::parse(keys)
+                .filter(|chord| !chord.is_modifier_only() && !chord.is_rolling())
+                .ok_or_else(|| eyre!("{} can't be suppressed", keys.join(" + ")))
+        })
+        .collect::<Result<_>>()?;
+
+    let layers = whkdrc
+        .layers
+        .iter()
+        .map(|(key, mode)| {
+            Chord::parse(std::slice::from_ref(key))
+                .and_then(|chord| chord.key)
+                .map(|vk| (vk, mode.clone(), false))
+                .ok_or_else(|| eyre!("{key} can't be held for a layer"))
+        })
+        .collect::<Result<_>>()?;
+
+    *SUPPRESSED.lock() = suppressed;
+    *LAYERS.lock() = layers;
+    *TAP_THRESHOLD.lock() = whkdrc.tap_threshold;
+    *STICKY.lock() = Sticky {
+        window: whkdrc.sticky_modifiers.unwrap_or_default(),
+        ..Sticky::default()
+    };
+    *ROLLING.lock() = Rolling {
+        window: whkdrc.rollover,
+        ..Rolling::default()
+    };
+
+    Ok(())
+}
+
+/// Start reacting to a chord. A chord which is bound more than once (e.g. for
+/// different apps) is only let through if all of its bindings pass it through,
+/// and only fired by whkd's own input if all of them allow recursion
+pub fn register(id: u32, chord: Chord, passthrough: bool, recursive: bool) {
+    let mut bound = BOUND.lock();
+    match bound.iter_mut().find(|bound| bound.id == id) {
+        Some(existing) => {
+            existing.passthrough &= passthrough;
+            existing.recursive &= recursive;
+        }
+        None => bound.push(Bound {
+            id,
+            chord,
+            passthrough,
+            recursive,
+        }),
+    }
+}
+
+pub fn unregister(id: u32) {
+    BOUND.lock().retain(|bound| bound.id != id);
+}
+
+/// Whether the hook keeps a chord from reaching the foreground app
+pub fn swallows(id: u32) -> bool {
+    BOUND
+        .lock()
+        .iter()
+        .any(|bound| bound.id == id && !bound.passthrough && bound.chord.key.is_some())
+}
+
+pub fn is_down(vk: VIRTUAL_KEY) -> bool {
+    unsafe { GetAsyncKeyState(i32::from(vk)) < 0 }
+}
+
+/// The modifiers which are held down, e.g. those of the chord of a binding
+/// which is being handled
+pub fn held_modifiers() -> Vec<VIRTUAL_KEY> {
+    MODIFIERS
+        .iter()
+        .flat_map(|(_, left, right)| [*left, *right])
+        .filter(|vk| is_down(*vk))
+        .collect()
+}
+
+unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    #[allow(clippy::cast_possible_wrap)]
+    if code == HC_ACTION as i32 {
+        let event = &*(lparam as *const KBDLLHOOKSTRUCT);
+
+        // keys passed through by whkd must reach the foreground app, and input
+        // synthesized by other apps is handled like physical input
+        #[allow(clippy::cast_possible_truncation)]
+        if event.dwExtraInfo != input::PASSED
+            && handle(
+                matches!(wparam as u32, WM_KEYDOWN | WM_SYSKEYDOWN),
+                event.vkCode as VIRTUAL_KEY,
+                event.dwExtraInfo == input::INJECTED,
+            )
+        {
+            return 1;
+        }
+    }
+
+    CallNextHookEx(0, code, wparam, lparam)
+}
+
+fn fire(bound: Bound) {
+    send(UserEvent::Chord(bound.id));
+}
+
+fn send(event: UserEvent) {
+    if let Some(proxy) = PROXY.lock().as_ref() {
+        // the event loop only goes away when whkd exits
+        let _ = proxy.send_event(event);
+    }
+}
+
+/// Activate the mode of a `.layer` when its key goes down and leave it when the
+/// key goes up, returning whether `vk` is the key of a layer. The key itself is
+/// always swallowed, so that e.g. caps lock isn't toggled
+fn hold_layer(down: bool, vk: VIRTUAL_KEY) -> bool {
+    // auto-repeat doesn't activate the mode again
+    let changed = LAYERS
+        .lock()
+        .iter_mut()
+        .find(|(key, _, _)| *key == vk)
+        .map(|(_, mode, held)| (std::mem::replace(held, down) != down).then(|| mode.clone()));
+
+    let Some(changed) = changed else {
+        return false;
+    };
+
+    if let Some(mode) = changed {
+        send(UserEvent::Layer { mode, held: down });
+    }
+
+    true
+}
+
+/// Releasing a lone alt or win opens the menu bar or the start menu, which is
+/// what it looks like when the key pressed with it was swallowed
+fn mask_modifiers() {
+    if [VK_LMENU, VK_RMENU, VK_LWIN, VK_RWIN]
+        .into_iter()
+        .any(is_down)
+    {
+        input::send_key(input::VK_MASK);
+    }
+}
+
+/// React to a key event, returning whether it should be swallowed
+fn handle(down: bool, vk: VIRTUAL_KEY, injected: bool) -> bool {
+    if injected {
+        return handle_injected(down, vk);
+    }
+
+    if hold_layer(down, vk) {
+        return true;
+    }
+
+    if !down {
+        // a key of a rolling chord which is let go of before the rest of the
+        // chord followed reaches the foreground app after all
+        if ROLLING.lock().is_held_back(vk) {
+            let_go(Some((vk, false)));
+            return true;
+        }
+
+        // a modifier-only chord is tapped when one of its modifiers is
+        // released before any other key was pressed
+        let tap = TAP.lock().take();
+        let mut tapped = false;
+        if let Some((bound, pressed)) = tap {
+            if bound.chord.has_modifier(vk) && pressed.elapsed() <= *TAP_THRESHOLD.lock() {
+                fire(bound);
+                tapped = true;
+                if !bound.passthrough {
+                    mask_modifiers();
+                }
+            }
+        }
+
+        // a latched alt or win is on its way to a chord, not to the menu bar
+        // or the start menu
+        if !tapped && is_modifier_key(vk) && STICKY.lock().release_modifier(vk, Instant::now()) {
+            mask_modifiers();
+        }
+
+        return SWALLOWED.lock().remove(&(vk, false));
+    }
+
+    // the key state seen here doesn't include the event being handled yet
+    if is_down(vk) {
+        // auto-repeat neither starts nor cancels a tap
+        if TAP.lock().is_some() {
+            return false;
+        }
+    } else {
+        *TAP.lock() = None;
+        if is_modifier_key(vk) {
+            STICKY.lock().press_modifier();
+        }
+
+        if roll(vk) {
+            return true;
+        }
+    }
+
+    // another key going down lets go of the keys which are held back for a
+    // rolling chord, which have to reach the foreground app before it
+    if !ROLLING.lock().held_back.is_empty() {
+        let swallowed = press(vk);
+        let_go((!swallowed).then_some((vk, true)));
+        return true;
+    }
+
+    press(vk)
+}
+
+/// React to a key going down which isn't held back for a rolling chord,
+/// returning whether it should be swallowed
+fn press(vk: VIRTUAL_KEY) -> bool {
+    let latched = if is_modifier_key(vk) {
+        vec![]
+    } else {
+        STICKY.lock().take(Instant::now())
+    };
+
+    let held = |key| key == vk || is_down(key) || latched.contains(&key);
+    let Some(bound) = BOUND
+        .lock()
+        .iter()
+        .find(|bound| bound.chord.matches(vk, held))
+        .copied()
+    else {
+        if SUPPRESSED
+            .lock()
+            .iter()
+            .any(|chord| chord.matches(vk, held))
+        {
+            SWALLOWED.lock().insert((vk, false));
+            mask_modifiers();
+            return true;
+        }
+
+        return false;
+    };
+
+    // swallowing a modifier would leave it stuck for the foreground app
+    if bound.chord.is_modifier_only() {
+        *TAP.lock() = Some((bound, Instant::now()));
+        return false;
+    }
+
+    fire(bound);
+
+    if bound.passthrough {
+        return false;
+    }
+
+    SWALLOWED.lock().insert((vk, false));
+    mask_modifiers();
+
+    true
+}
+
+/// Hold back a key of a rolling chord of the active mode until the rest of the
+/// chord follows and fire the chord once it is complete, returning whether `vk`
+/// is a key of a rolling chord
+fn roll(vk: VIRTUAL_KEY) -> bool {
+    let chords = BOUND
+        .lock()
+        .iter()
+        .filter(|bound| bound.chord.rolls(vk, is_down))
+        .copied()
+        .collect::<Vec<_>>();
+
+    if chords.is_empty() {
+        return false;
+    }
+
+    // auto-repeat of a key which is held back or whose chord was swallowed
+    if ROLLING.lock().is_held_back(vk) || SWALLOWED.lock().contains(&(vk, false)) {
+        return true;
+    }
+
+    let (first, down, window) = {
+        let mut rolling = ROLLING.lock();
+        let first = rolling.hold_back(vk, Instant::now());
+        (first, rolling.keys(), rolling.window)
+    };
+
+    let Some(bound) = chords
+        .into_iter()
+        .find(|bound| bound.chord.rolled_by(&down))
+    else {
+        if first {
+            runtime::after(window, expire);
+        }
+
+        return true;
+    };
+
+    // keys which are held back for other chords went down in the meantime
+    let held_back = ROLLING.lock().take();
+    let in_chord = |key: &VIRTUAL_KEY| bound.chord.others().any(|other| other == *key);
+    let let_through = held_back
+        .iter()
+        .filter(|key| bound.passthrough || !in_chord(key))
+        .map(|key| (*key, true))
+        .collect::<Vec<_>>();
+
+    fire(bound);
+
+    if !bound.passthrough {
+        SWALLOWED.lock().extend(
+            held_back
+                .iter()
+                .filter(|key| in_chord(key))
+                .map(|key| (*key, false)),
+        );
+        mask_modifiers();
+    }
+
+    if !let_through.is_empty() {
+        input::send_keys(&let_through);
+    }
+
+    true
+}
+
+/// Let go of the keys which are held back once the rest of their chord didn't
+/// follow within `.rollover`
+fn expire() {
+    let expired = ROLLING.lock().expired(Instant::now());
+    if expired {
+        let_go(None);
+    }
+}
+
+/// Let the keys which are held back for a rolling chord reach the foreground
+/// app, followed by `then`
+fn let_go(then: Option<(VIRTUAL_KEY, bool)>) {
+    let mut keys = ROLLING
+        .lock()
+        .take()
+        .into_iter()
+        .map(|vk| (vk, true))
+        .collect::<Vec<_>>();
+
+    keys.extend(then);
+    if !keys.is_empty() {
+        input::send_keys(&keys);
+    }
+}
+
+/// React to a key event synthesized by whkd, e.g. for `@send` or to pass a chord
+/// through, which only fires bindings with `~allow-recursion` so that a binding
+/// sending its own chord can't set itself off again and again
+fn handle_injected(down: bool, vk: VIRTUAL_KEY) -> bool {
+    if !down {
+        return SWALLOWED.lock().remove(&(vk, true));
+    }
+
+    let held = |key| key == vk || is_down(key);
+    let Some(bound) = BOUND
+        .lock()
+        .iter()
+        .find(|bound| bound.recursive && bound.chord.matches(vk, held))
+        .copied()
+    else {
+        return false;
+    };
+
+    // taps are left to the physical keyboard
+    if bound.chord.is_modifier_only() {
+        return false;
+    }
+
+    fire(bound);
+
+    if bound.passthrough {
+        return false;
+    }
+
+    SWALLOWED.lock().insert((vk, true));
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_H;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_J;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_K;
+
+    fn chord(keys: &str) -> Chord {
+        let keys = keys
+            .split('+')
+            .map(|key| key.trim().to_string())
+            .collect::<Vec<_>>();
+
+        Chord::parse(&keys).unwrap()
+    }
+
+    #[test]
+    fn test_chord_matches() {
+        let alt_h = chord("alt + h");
+        assert!(!alt_h.needs_hook());
+        assert!(alt_h.matches(VK_H, |key| key == VK_RMENU));
+        assert!(!alt_h.matches(VK_H, |_| false));
+        assert!(!alt_h.matches(VK_H, |key| key == VK_LMENU || key == VK_LSHIFT));
+        assert_eq!(alt_h, chord("alt + h"));
+        assert_ne!(alt_h.id(), chord("lalt + h").id());
+
+        let lalt_h = chord("lalt + h");
+        assert!(lalt_h.needs_hook());
+        assert!(lalt_h.matches(VK_H, |key| key == VK_LMENU));
+        assert!(!lalt_h.matches(VK_H, |key| key == VK_RMENU));
+
+        let ctrl_shift = chord("ctrl + shift");
+        assert!(ctrl_shift.needs_hook());
+        assert!(ctrl_shift.matches(VK_LSHIFT, |key| key == VK_LSHIFT || key == VK_RCONTROL));
+        assert!(!ctrl_shift.matches(VK_LSHIFT, |key| key == VK_LSHIFT));
+        assert!(!ctrl_shift.matches(VK_H, |key| key == VK_LSHIFT || key == VK_LCONTROL));
+
+        assert_eq!(chord("ctrl + alt + h"), chord("alt + ctrl + h"));
+        assert!(Chord::parse(&["h".to_string(), "alt".to_string()]).is_none());
+
+        assert_eq!(
+            chord("shift + rctrl + h").keys(),
+            [VK_RCONTROL, VK_LSHIFT, VK_H]
+        );
+    }
+
+    #[test]
+    fn test_rolling_chord() {
+        let j_k = chord("j + k");
+        assert!(j_k.is_rolling());
+        assert!(j_k.needs_hook());
+        assert_eq!(j_k, chord("k + j"));
+        assert_eq!(j_k.keys(), [VK_J, VK_K]);
+        assert!(!chord("alt + h").is_rolling());
+
+        // completed by `rolled_by` rather than by a single key
+        assert!(!j_k.matches(VK_K, |key| key == VK_J));
+        assert!(j_k.rolls(VK_J, |_| false));
+        assert!(!j_k.rolls(VK_H, |_| false));
+        assert!(!j_k.rolls(VK_J, |key| key == VK_LMENU));
+        assert!(j_k.rolled_by(&[VK_K, VK_H, VK_J]));
+        assert!(!j_k.rolled_by(&[VK_K]));
+
+        let alt_j_k = chord("alt + j + k");
+        assert!(alt_j_k.rolls(VK_K, |key| key == VK_LMENU));
+        assert!(!alt_j_k.rolls(VK_K, |_| false));
+
+        assert!(Chord::parse(&["j".to_string(), "alt".to_string(), "k".to_string()]).is_none());
+        assert!(Chord::parse(&["a", "s", "d", "f", "g"].map(String::from)).is_none());
+    }
+
+    #[test]
+    fn test_rolling() {
+        let start = Instant::now();
+        let mut rolling = Rolling {
+            window: Duration::from_millis(50),
+            ..Rolling::default()
+        };
+
+        assert!(!rolling.expired(start));
+        assert!(rolling.hold_back(VK_J, start));
+        assert!(!rolling.hold_back(VK_K, start + Duration::from_millis(20)));
+        assert!(rolling.is_held_back(VK_K));
+        assert_eq!(rolling.keys(), [VK_J, VK_K]);
+
+        // the window starts with the first key
+        assert!(!rolling.expired(start + Duration::from_millis(40)));
+        assert!(rolling.expired(start + Duration::from_millis(50)));
+
+        assert_eq!(rolling.take(), [VK_J, VK_K]);
+        assert!(!rolling.is_held_back(VK_J));
+        assert!(!rolling.expired(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_sticky() {
+        let start = Instant::now();
+        let mut sticky = Sticky {
+            window: Duration::from_secs(1),
+            ..Sticky::default()
+        };
+
+        // alt, then shift, then h, one at a time
+        sticky.press_modifier();
+        assert!(sticky.release_modifier(VK_LMENU, start));
+        sticky.press_modifier();
+        assert!(sticky.release_modifier(VK_LSHIFT, start));
+        assert_eq!(sticky.take(start), [VK_LMENU, VK_LSHIFT]);
+        assert!(sticky.take(start).is_empty());
+
+        // alt held down for alt + h isn't latched once it is released
+        sticky.press_modifier();
+        sticky.take(start);
+        assert!(!sticky.release_modifier(VK_LMENU, start));
+
+        sticky.press_modifier();
+        sticky.release_modifier(VK_LMENU, start);
+        assert!(sticky.take(start + Duration::from_secs(2)).is_empty());
+
+        let mut off = Sticky::default();
+        off.press_modifier();
+        assert!(!off.release_modifier(VK_LMENU, start));
+    }
+}
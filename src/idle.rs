@@ -0,0 +1,73 @@
+use crate::event_loop::EventLoopProxy;
+use crate::UserEvent;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use windows_sys::Win32::System::SystemInformation::GetTickCount;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::GetLastInputInfo;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::LASTINPUTINFO;
+
+/// How recent the last input has to be for `~only-when-active` without a
+/// duration of its own
+pub const ACTIVE_WITHIN: Duration = Duration::from_mins(1);
+
+/// How often the idle time is checked for `@on-idle`
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// How long there has been no keyboard or mouse input in the session
+pub fn idle_time() -> Duration {
+    let mut info = LASTINPUTINFO {
+        #[allow(clippy::cast_possible_truncation)]
+        cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+        dwTime: 0,
+    };
+
+    if unsafe { GetLastInputInfo(std::ptr::addr_of_mut!(info)) } == 0 {
+        return Duration::ZERO;
+    }
+
+    // both tick counts wrap around after 49.7 days
+    let now = unsafe { GetTickCount() };
+    Duration::from_millis(u64::from(now.wrapping_sub(info.dwTime)))
+}
+
+/// Send the idle time to the event loop every `POLL_INTERVAL` for `@on-idle`,
+/// starting at most once no matter how often whkdrc is reloaded
+pub fn watch(proxy: EventLoopProxy<UserEvent>) {
+    if WATCHING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if proxy.send_event(UserEvent::Idle(idle_time())).is_err() {
+            break;
+        }
+    });
+}
+
+/// Whether the idle time has reached `threshold` since it was last checked,
+/// which happens again every time the session goes idle after some input
+pub fn crossed(threshold: Duration, previous: Duration, idle: Duration) -> bool {
+    previous < threshold && threshold <= idle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossed() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+
+        assert!(crossed(minutes(10), minutes(9), minutes(10)));
+        assert!(!crossed(minutes(10), minutes(10), minutes(11)));
+        assert!(!crossed(minutes(10), minutes(3), minutes(4)));
+
+        // input in between starts a new idle period
+        assert!(!crossed(minutes(10), minutes(12), Duration::from_secs(2)));
+        assert!(crossed(minutes(10), Duration::from_secs(2), minutes(10)));
+    }
+}
@@ -0,0 +1,540 @@
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use std::fmt::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// AHK scripts (`^!h::Run foo`)
+    Ahk,
+    /// sxhkdrc files, including brace expansion (`super + {h,l}`)
+    Sxhkd,
+}
+
+/// The bindings translated from another hotkey tool, along with every line
+/// which could not be translated and why
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Conversion {
+    bindings: Vec<String>,
+    unconverted: Vec<(usize, String, String)>,
+}
+
+impl Conversion {
+    fn skip(&mut self, idx: usize, line: &str, reason: &str) {
+        self.unconverted
+            .push((idx + 1, line.to_string(), reason.to_string()));
+    }
+
+    fn push(&mut self, idx: usize, line: &str, keys: &[String], command: &str) {
+        // `;` switches modes and `#` starts a comment in whkdrc unless the
+        // command is fenced
+        if command.contains("```") {
+            self.skip(idx, line, "commands containing ``` are not supported");
+        } else if command.contains([';', '#']) {
+            self.bindings
+                .push(format!("{} : ```{command}```", keys.join(" + ")));
+        } else {
+            self.bindings
+                .push(format!("{} : {command}", keys.join(" + ")));
+        }
+    }
+
+    fn render(&self, source: &Path) -> String {
+        let mut whkdrc = String::from(".shell pwsh\n\n");
+
+        let _ = writeln!(whkdrc, "# imported from {}", source.display());
+        for binding in &self.bindings {
+            let _ = writeln!(whkdrc, "{binding}");
+        }
+
+        if !self.unconverted.is_empty() {
+            let _ = writeln!(whkdrc, "\n# the following lines could not be converted");
+            for (line_number, line, reason) in &self.unconverted {
+                let _ = writeln!(whkdrc, "# line {line_number} ({reason}): {line}");
+            }
+        }
+
+        whkdrc
+    }
+}
+
+/// Translate the hotkeys of another tool into whkdrc bindings, writing them to
+/// `output` or printing them if no output file is given
+pub fn run(from: Format, path: &Path, output: Option<PathBuf>) -> Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+
+    let conversion = match from {
+        Format::Ahk => ahk(&contents),
+        Format::Sxhkd => sxhkd(&contents),
+    };
+
+    let whkdrc = conversion.render(path);
+
+    match output {
+        Some(output) => {
+            std::fs::write(&output, whkdrc)?;
+            println!(
+                "Converted {} bindings to {}, {} lines could not be converted and are listed at the end of the file",
+                conversion.bindings.len(),
+                output.display(),
+                conversion.unconverted.len(),
+            );
+        }
+        None => print!("{whkdrc}"),
+    }
+
+    Ok(())
+}
+
+fn ahk(contents: &str) -> Conversion {
+    let mut conversion = Conversion::default();
+
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let Some((hotkey, action)) = line.split_once("::") else {
+            continue;
+        };
+
+        if hotkey.is_empty() {
+            conversion.skip(idx, line, "hotstrings are not supported");
+            continue;
+        }
+
+        let keys = match ahk_hotkey(hotkey) {
+            Ok(keys) => keys,
+            Err(reason) => {
+                conversion.skip(idx, line, reason);
+                continue;
+            }
+        };
+
+        match ahk_action(action) {
+            Ok(command) => conversion.push(idx, line, &keys, &command),
+            Err(reason) => conversion.skip(idx, line, reason),
+        }
+    }
+
+    conversion
+}
+
+fn ahk_hotkey(hotkey: &str) -> Result<Vec<String>, &'static str> {
+    if hotkey.contains(" & ") {
+        return Err("custom combinations are not supported");
+    }
+
+    if hotkey.to_lowercase().ends_with(" up") {
+        return Err("key-up hotkeys are not supported");
+    }
+
+    let mut keys = vec![];
+    let mut chars = hotkey.char_indices();
+    let key = loop {
+        let Some((idx, c)) = chars.next() else {
+            return Err("missing key");
+        };
+
+        match c {
+            '^' => keys.push("ctrl"),
+            '!' => keys.push("alt"),
+            '+' => keys.push("shift"),
+            '#' => keys.push("super"),
+            // side-specific modifiers, wildcards and passthrough have no equivalent
+            '<' | '>' | '*' | '~' | '$' => {}
+            _ => break &hotkey[idx..],
+        }
+    };
+
+    let key = key_name(key).ok_or("unknown key")?;
+
+    let mut keys = keys.into_iter().map(String::from).collect::<Vec<_>>();
+    keys.push(key);
+
+    Ok(keys)
+}
+
+/// Translate an AHK key name or an X keysym into a whkdrc key
+fn key_name(key: &str) -> Option<String> {
+    let lower = key.to_lowercase();
+
+    let named = match lower.as_str() {
+        "space" => "Space",
+        "tab" => "Tab",
+        "enter" | "return" => "Enter",
+        "escape" | "esc" => "Escape",
+        "backspace" | "bs" => "Backspace",
+        "delete" | "del" => "Delete",
+        "insert" | "ins" => "Insert",
+        "home" => "Home",
+        "end" => "End",
+        "pgup" | "prior" => "PageUp",
+        "pgdn" | "next" => "PageDown",
+        "up" => "ArrowUp",
+        "down" => "ArrowDown",
+        "left" => "ArrowLeft",
+        "right" => "ArrowRight",
+        "printscreen" | "print" => "PrintScreen",
+        "capslock" | "caps_lock" => "CapsLock",
+        "volume_up" | "xf86audioraisevolume" => "AudioVolumeUp",
+        "volume_down" | "xf86audiolowervolume" => "AudioVolumeDown",
+        "volume_mute" | "xf86audiomute" => "AudioVolumeMute",
+        "media_play_pause" | "xf86audioplay" => "MediaPlayPause",
+        "media_next" | "xf86audionext" => "MediaTrackNext",
+        "media_prev" | "xf86audioprev" => "MediaTrackPrevious",
+        _ => "",
+    };
+
+    if !named.is_empty() {
+        return Some(named.to_string());
+    }
+
+    if lower.len() == 1 && lower.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Some(lower);
+    }
+
+    if let Some(number) = lower.strip_prefix('f') {
+        if number
+            .parse::<u8>()
+            .is_ok_and(|number| (1..=24).contains(&number))
+        {
+            return Some(format!("F{number}"));
+        }
+    }
+
+    if let Some(number) = lower.strip_prefix("numpad") {
+        if number.len() == 1 && number.chars().all(|c| c.is_ascii_digit()) {
+            return Some(format!("Numpad{number}"));
+        }
+    }
+
+    None
+}
+
+fn ahk_action(action: &str) -> Result<String, &'static str> {
+    // strip a trailing comment
+    let action = action
+        .split_once(" ;")
+        .map_or(action, |(action, _)| action)
+        .trim();
+
+    if action.is_empty() || action == "{" {
+        return Err("multi-line hotkeys are not supported");
+    }
+
+    let Some(target) = action
+        .strip_prefix("Run")
+        .filter(|rest| rest.starts_with([' ', ',']))
+    else {
+        return Err("only Run actions are supported");
+    };
+
+    let target = target.trim_start_matches([' ', ',']).trim();
+
+    if target.contains('%') {
+        return Err("variables are not supported");
+    }
+
+    Ok(format!("Start-Process {target}"))
+}
+
+fn sxhkd(contents: &str) -> Conversion {
+    let mut conversion = Conversion::default();
+
+    // a trailing backslash continues a line
+    let mut lines: Vec<(usize, String)> = vec![];
+    let mut continuation = false;
+    for (idx, line) in contents.lines().enumerate() {
+        let (line, continues) = line
+            .strip_suffix('\\')
+            .map_or((line, false), |line| (line, true));
+
+        match lines.last_mut() {
+            Some((_, last)) if continuation => last.push_str(line.trim_start()),
+            _ => lines.push((idx, line.to_string())),
+        }
+
+        continuation = continues;
+    }
+
+    let mut lines = lines.into_iter().peekable();
+    while let Some((idx, line)) = lines.next() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with([' ', '\t']) {
+            conversion.skip(idx, line.trim(), "command without a hotkey");
+            continue;
+        }
+
+        let chord = line.trim();
+        let mut commands = vec![];
+        while let Some((_, command)) =
+            lines.next_if(|(_, next)| next.starts_with([' ', '\t']) && !next.trim().is_empty())
+        {
+            commands.push(command.trim().to_string());
+        }
+
+        let command = match commands.as_slice() {
+            [command] => command,
+            [] => {
+                conversion.skip(idx, chord, "missing command");
+                continue;
+            }
+            _ => {
+                conversion.skip(idx, chord, "multi-line commands are not supported");
+                continue;
+            }
+        };
+
+        match sxhkd_bindings(chord, command) {
+            Ok(bindings) => {
+                for (keys, command) in bindings {
+                    conversion.push(idx, chord, &keys, &command);
+                }
+            }
+            Err(reason) => conversion.skip(idx, chord, reason),
+        }
+    }
+
+    conversion
+}
+
+/// Expand the brace sequences of an sxhkd hotkey, where every sequence in the
+/// command follows the sequence at the same position in the chord
+fn sxhkd_bindings(chord: &str, command: &str) -> Result<Vec<(Vec<String>, String)>, &'static str> {
+    if chord.contains([':', ';']) {
+        return Err("chord chains are not supported");
+    }
+
+    let (chord_parts, chord_sequences) = sequences(chord)?;
+    let (command_parts, command_sequences) = sequences(command)?;
+
+    let follows = command_sequences.is_empty()
+        || (command_sequences.len() == chord_sequences.len()
+            && command_sequences
+                .iter()
+                .zip(&chord_sequences)
+                .all(|(command, chord)| command.len() == chord.len()));
+
+    if !follows {
+        return Err("the brace sequences of the hotkey and command do not match");
+    }
+
+    let mut bindings = vec![];
+    let mut choice = vec![0; chord_sequences.len()];
+
+    loop {
+        let keys = sxhkd_keys(&fill(&chord_parts, &chord_sequences, &choice))?;
+        let command = if command_sequences.is_empty() {
+            command.to_string()
+        } else {
+            fill(&command_parts, &command_sequences, &choice)
+        };
+
+        bindings.push((keys, command));
+
+        // step through every combination of the sequences like an odometer
+        let Some(position) = (0..choice.len())
+            .rev()
+            .find(|&position| choice[position] + 1 < chord_sequences[position].len())
+        else {
+            break;
+        };
+
+        choice[position] += 1;
+        for later in &mut choice[position + 1..] {
+            *later = 0;
+        }
+    }
+
+    Ok(bindings)
+}
+
+/// Split `text` into its literal parts and its `{a,b,c}` sequences, with `_`
+/// standing for an empty element and `1-9` or `a-z` for a range
+fn sequences(text: &str) -> Result<(Vec<String>, Vec<Vec<String>>), &'static str> {
+    let mut parts = vec![];
+    let mut sequences = vec![];
+    let mut rest = text;
+
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or("unclosed brace sequence")?;
+
+        parts.push(rest[..start].to_string());
+
+        let mut sequence = vec![];
+        for element in rest[start + 1..end].split(',') {
+            let mut chars = element.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some('_'), None, None, None) => sequence.push(String::new()),
+                (Some(first), Some('-'), Some(last), None) if first < last => {
+                    sequence.extend((first..=last).map(String::from));
+                }
+                _ => sequence.push(element.to_string()),
+            }
+        }
+
+        sequences.push(sequence);
+        rest = &rest[end + 1..];
+    }
+
+    parts.push(rest.to_string());
+
+    Ok((parts, sequences))
+}
+
+fn fill(parts: &[String], sequences: &[Vec<String>], choice: &[usize]) -> String {
+    let mut filled = String::new();
+    for (idx, part) in parts.iter().enumerate() {
+        filled.push_str(part);
+        if let (Some(sequence), Some(choice)) = (sequences.get(idx), choice.get(idx)) {
+            filled.push_str(&sequence[*choice]);
+        }
+    }
+
+    filled
+}
+
+fn sxhkd_keys(chord: &str) -> Result<Vec<String>, &'static str> {
+    let mut keys = vec![];
+
+    for key in chord
+        .split('+')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+    {
+        let key = key.trim_start_matches('~');
+        if key.starts_with('@') {
+            return Err("key-release hotkeys are not supported");
+        }
+
+        match key.to_lowercase().as_str() {
+            "super" | "mod4" => keys.push(String::from("super")),
+            "alt" | "mod1" => keys.push(String::from("alt")),
+            "ctrl" | "control" => keys.push(String::from("ctrl")),
+            "shift" => keys.push(String::from("shift")),
+            _ => keys.push(key_name(key).ok_or("unknown key")?),
+        }
+    }
+
+    if keys.is_empty() {
+        return Err("missing key");
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_ahk() {
+        let src = r#"#Requires AutoHotkey v2.0
+; launchers
+^!h::Run foo
+#Enter::Run "C:\Program Files\WezTerm\wezterm-gui.exe"
+<^>+F11::Run, notepad.exe  ; editor
+::btw::by the way
+a & b::Run bar
+^j::Send "hello"
+^k::
+{
+    MsgBox "hi"
+}
+"#;
+
+        let conversion = ahk(src);
+        assert_eq!(
+            conversion.bindings,
+            vec![
+                "ctrl + alt + h : Start-Process foo",
+                r#"super + Enter : Start-Process "C:\Program Files\WezTerm\wezterm-gui.exe""#,
+                "ctrl + shift + F11 : Start-Process notepad.exe",
+            ]
+        );
+        assert_eq!(
+            conversion
+                .unconverted
+                .iter()
+                .map(|(line_number, _, _)| *line_number)
+                .collect::<Vec<_>>(),
+            vec![6, 7, 8, 9]
+        );
+
+        let whkdrc = conversion.render(Path::new("script.ahk"));
+        assert_eq!(parser().parse(whkdrc).unwrap().bindings.len(), 3);
+    }
+
+    #[test]
+    fn test_sxhkd() {
+        let src = r"# focus
+super + {h,j,k,l}
+    bspc node -f {west,south,north,east}
+
+super + {_,shift + }{1-3}
+    bspc {desktop -f,node -d} '^{1-3}'
+
+super + Return
+    alacritty
+
+super + e
+    echo one; echo two
+
+super + alt + \
+r
+    bspc wm -r
+
+super + @space
+    rofi -show run
+
+super + x ; q
+    bspc quit
+
+super + p
+    echo one
+    echo two
+";
+
+        let conversion = sxhkd(src);
+        assert_eq!(
+            conversion.bindings,
+            vec![
+                "super + h : bspc node -f west",
+                "super + j : bspc node -f south",
+                "super + k : bspc node -f north",
+                "super + l : bspc node -f east",
+                "super + 1 : bspc desktop -f '^1'",
+                "super + 2 : bspc desktop -f '^2'",
+                "super + 3 : bspc desktop -f '^3'",
+                "super + shift + 1 : bspc node -d '^1'",
+                "super + shift + 2 : bspc node -d '^2'",
+                "super + shift + 3 : bspc node -d '^3'",
+                "super + Enter : alacritty",
+                "super + e : ```echo one; echo two```",
+                "super + alt + r : bspc wm -r",
+            ]
+        );
+        assert_eq!(
+            conversion
+                .unconverted
+                .iter()
+                .map(|(line_number, _, _)| *line_number)
+                .collect::<Vec<_>>(),
+            vec![18, 21, 24]
+        );
+
+        let whkdrc = conversion.render(Path::new("sxhkdrc"));
+        assert_eq!(parser().parse(whkdrc).unwrap().bindings.len(), 13);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::input;
+use crate::log;
+use crate::session;
+use crate::whkdrc::LockKey;
+use crate::whkdrc::ModeIndicator;
+use crate::whkdrc::Shell;
+use crate::whkdrc::Whkdrc;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::GetKeyState;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_CAPITAL;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_NUMLOCK;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_SCROLL;
+
+lazy_static! {
+    static ref INDICATORS: Mutex<Indicators> = Mutex::new(Indicators {
+        shell: Shell::Pwsh,
+        configured: vec![],
+        lit: vec![],
+    });
+}
+
+struct Indicators {
+    /// The shell which the commands of `.mode_indicator cmd` run in
+    shell: Shell,
+    configured: Vec<ModeIndicator>,
+    /// Lock keys which whkd turned on, and only those are turned off again, so
+    /// that a lock key which was on already stays on
+    lit: Vec<LockKey>,
+}
+
+const fn vk(key: LockKey) -> VIRTUAL_KEY {
+    match key {
+        LockKey::Caps => VK_CAPITAL,
+        LockKey::Num => VK_NUMLOCK,
+        LockKey::Scroll => VK_SCROLL,
+    }
+}
+
+fn is_on(key: LockKey) -> bool {
+    // the low bit of the state of a lock key is whether it is toggled on
+    let state = unsafe { GetKeyState(i32::from(vk(key))) };
+    state & 1 != 0
+}
+
+/// Take the indicators of whkdrc, also when it is reloaded, turning off the
+/// lights of the previous ones
+pub fn configure(whkdrc: &Whkdrc) {
+    reset();
+
+    let mut indicators = INDICATORS.lock();
+    indicators.shell = whkdrc.shell;
+    indicators.configured.clone_from(&whkdrc.mode_indicators);
+}
+
+/// Show a mode which was just activated, `None` being the default mode
+pub fn show(mode: Option<&String>) {
+    // the commands are spawned without holding on to the indicators
+    let (shell, configured) = {
+        let indicators = INDICATORS.lock();
+        (indicators.shell, indicators.configured.clone())
+    };
+
+    for indicator in &configured {
+        match indicator {
+            ModeIndicator::Lock(key) if mode.is_some() => light(*key),
+            ModeIndicator::Lock(key) => dim(*key),
+            ModeIndicator::Command(command) => {
+                let env = [(
+                    "WHKD_MODE",
+                    mode.map_or("default", String::as_str).to_string(),
+                )];
+                if let Err(error) = session::spawn_direct(shell, command, &env) {
+                    log::error(format!(
+                        "could not run the mode indicator '{command}': {error}"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Turn the light of a lock key on, unless it is on already
+fn light(key: LockKey) {
+    if !is_on(key) && input::send_key(vk(key)) {
+        let mut indicators = INDICATORS.lock();
+        if !indicators.lit.contains(&key) {
+            indicators.lit.push(key);
+        }
+    }
+}
+
+/// Turn the light of a lock key off if whkd turned it on
+fn dim(key: LockKey) {
+    let was_lit = {
+        let mut indicators = INDICATORS.lock();
+        let was_lit = indicators.lit.contains(&key);
+        indicators.lit.retain(|lit| *lit != key);
+        was_lit
+    };
+
+    if was_lit && is_on(key) {
+        input::send_key(vk(key));
+    }
+}
+
+/// Turn off the lights which whkd turned on, e.g. as it shuts down
+pub fn reset() {
+    let lit = std::mem::take(&mut INDICATORS.lock().lit);
+    for key in lit {
+        if is_on(key) {
+            input::send_key(vk(key));
+        }
+    }
+}
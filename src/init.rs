@@ -0,0 +1,401 @@
+use crate::doctor;
+use crate::process;
+use crate::setup;
+use crate::whkdrc;
+use crate::whkdrc::Shell;
+use crate::whkdrc::Whkdrc;
+use crate::whkdrc::CURRENT_VERSION;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::io::Write;
+use std::path::Path;
+
+/// How the bindings of the starter whkdrc are pressed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Leader {
+    /// Chords such as `alt + h`
+    Alt,
+    /// Chords such as `super + h`, which need `.backend hook` as Windows owns
+    /// most of them
+    Super,
+    /// `ctrl + alt + space` enters a mode in which a single key runs a command
+    Mode,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Navigation {
+    Vim,
+    Arrows,
+}
+
+impl Navigation {
+    /// The keys for left, down, up and right
+    const fn keys(self) -> [&'static str; 4] {
+        match self {
+            Self::Vim => ["h", "j", "k", "l"],
+            Self::Arrows => ["ArrowLeft", "ArrowDown", "ArrowUp", "ArrowRight"],
+        }
+    }
+}
+
+/// The answers to the questions of `whkd init`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Answers {
+    shell: Shell,
+    leader: Leader,
+    navigation: Navigation,
+    komorebi: bool,
+}
+
+/// Ask a few questions and write a starter whkdrc from the answers, keeping a
+/// backup of the whkdrc it replaces, then offer to start whkd after logon
+pub fn run(config: Option<&Path>) -> Result<()> {
+    let path = match config {
+        Some(config) if whkdrc::is_stdin(config) => {
+            return Err(eyre!("whkd init can't write whkdrc to stdin"))
+        }
+        // the path is remembered for autostart, which doesn't run in this directory
+        Some(config) => std::env::current_dir()?.join(config),
+        None => Whkdrc::default_path()?,
+    };
+
+    let backup = path.with_extension("bak");
+    if path.exists()
+        && !confirm(
+            &format!(
+                "{} exists already, replace it? It is kept as {}",
+                path.display(),
+                backup.display()
+            ),
+            false,
+        )?
+    {
+        println!("Left {} as it is", path.display());
+        return Ok(());
+    }
+
+    let on_path =
+        |binary: &str| doctor::find_on_path(binary, &std::env::var_os("PATH").unwrap_or_default());
+
+    let komorebi = (process::is_running("komorebi.exe") || on_path("komorebic.exe").is_some())
+        && confirm(
+            "komorebi was found, add bindings to manage its windows?",
+            true,
+        )?;
+
+    let shells = [
+        (Shell::Pwsh, "pwsh (PowerShell 7)"),
+        (Shell::Powershell, "powershell (Windows PowerShell)"),
+        (Shell::Cmd, "cmd"),
+    ];
+    let shell = choose(
+        "Which shell should run the commands?",
+        &shells,
+        usize::from(on_path("pwsh.exe").is_none()),
+    )?;
+
+    let leader = choose(
+        "How should the bindings be pressed?",
+        &[
+            (Leader::Alt, "alt + key"),
+            (Leader::Super, "super + key, with the keyboard hook"),
+            (
+                Leader::Mode,
+                "ctrl + alt + space, then a single key (a leader key)",
+            ),
+        ],
+        0,
+    )?;
+
+    let navigation = if leader == Leader::Super {
+        println!("super + l always locks the screen, so the arrow keys move between windows");
+        Navigation::Arrows
+    } else {
+        choose(
+            "Which keys should move between windows?",
+            &[
+                (Navigation::Vim, "h, j, k and l"),
+                (Navigation::Arrows, "the arrow keys"),
+            ],
+            0,
+        )?
+    };
+
+    let starter = render(Answers {
+        shell,
+        leader,
+        navigation,
+        komorebi,
+    });
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    if path.exists() {
+        std::fs::copy(&path, &backup)?;
+    }
+
+    std::fs::write(&path, starter)?;
+    println!("Wrote {}", path.display());
+
+    if confirm("Start whkd automatically after logon?", false)? {
+        setup::autostart(config.is_some().then_some(path.as_path()))?;
+    }
+
+    println!("Run whkd to use the bindings, or `whkd reload` if it is running already");
+    Ok(())
+}
+
+/// Ask a yes or no question, with the answer used when nothing is typed
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+
+    loop {
+        let answer = ask(&format!("{question} {hint} "))?;
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer y or n"),
+        }
+    }
+}
+
+/// Ask a question with numbered choices until one of them is picked, with the
+/// choice at `default` used when nothing is typed
+fn choose<T: Copy>(question: &str, choices: &[(T, &str)], default: usize) -> Result<T> {
+    println!("{question}");
+    for (idx, (_, label)) in choices.iter().enumerate() {
+        println!("  {}) {label}", idx + 1);
+    }
+
+    loop {
+        let answer = ask(&format!("[{}] ", default + 1))?;
+        if answer.is_empty() {
+            return Ok(choices[default].0);
+        }
+
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=choices.len()).contains(&number) => return Ok(choices[number - 1].0),
+            _ => println!("Please pick a number from 1 to {}", choices.len()),
+        }
+    }
+}
+
+fn ask(prompt: &str) -> Result<String> {
+    print!("{prompt}");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        // stdin was closed, so the defaults are taken from here on
+        return Ok(String::new());
+    }
+
+    Ok(answer.trim().to_string())
+}
+
+/// A whkdrc being written, whose bindings are pressed the way the answers ask
+struct Starter {
+    out: String,
+    leader: Leader,
+}
+
+impl Starter {
+    fn line(&mut self, line: &str) {
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// A comment above a section, after an empty line
+    fn section(&mut self, comment: &str) {
+        self.line(&format!("\n# {comment}"));
+    }
+
+    fn bind(&mut self, keys: &str, action: &str) {
+        let binding = match self.leader {
+            Leader::Alt => format!("alt + {keys} {action}"),
+            Leader::Super => format!("super + {keys} {action}"),
+            // commands return to the default mode right away, and the other
+            // actions once no key has been pressed for a moment
+            Leader::Mode if action.starts_with(':') => {
+                format!("leader > {keys} {action} ; default")
+            }
+            Leader::Mode => format!("leader > {keys} {action}"),
+        };
+
+        self.line(&binding);
+    }
+}
+
+/// Write the starter whkdrc for the answers, with a comment above every section
+fn render(answers: Answers) -> String {
+    let mut starter = Starter {
+        out: String::new(),
+        leader: answers.leader,
+    };
+
+    starter.line(
+        "# Starter whkdrc written by `whkd init`, the README of whkd lists everything else it can do",
+    );
+    starter.line(&format!(".shell {}", answers.shell));
+    starter.line(&format!(".version {CURRENT_VERSION}"));
+
+    match answers.leader {
+        Leader::Alt => {}
+        Leader::Super => {
+            starter.section(
+                "Windows owns most super + key chords, which only the keyboard hook can bind over",
+            );
+            starter.line(
+                "# (except super + l, which always locks the screen, so the arrow keys move between windows)",
+            );
+            starter.line(".backend hook");
+        }
+        Leader::Mode => {
+            starter.section(
+                "ctrl + alt + space enters the leader mode, where a single key runs a command,",
+            );
+            starter.line("# escape leaves it and so does waiting for two seconds");
+            starter.line("ctrl + alt + Space ~timeout(2000) ; leader");
+            starter.line("leader > escape ; default");
+        }
+    }
+
+    starter.section("whkd itself");
+    starter.bind("o", ": whkd reload");
+    starter.bind("shift + e", "; edit-config");
+    starter.bind("F1", "; help");
+
+    // no binding can take super + l over from Windows
+    let navigation = match answers.leader {
+        Leader::Super => Navigation::Arrows,
+        Leader::Alt | Leader::Mode => answers.navigation,
+    };
+
+    if answers.komorebi {
+        komorebi(&mut starter, navigation.keys());
+    } else {
+        apps(&mut starter, navigation.keys());
+    }
+
+    starter.out
+}
+
+/// Bindings to focus, move and resize the windows of komorebi and switch its
+/// workspaces
+fn komorebi(starter: &mut Starter, [left, down, up, right]: [&str; 4]) {
+    let directions = [(left, "left"), (down, "down"), (up, "up"), (right, "right")];
+
+    starter.section("focus windows");
+    for (key, direction) in directions {
+        starter.bind(key, &format!(": komorebic focus {direction}"));
+    }
+
+    starter.section("move windows");
+    for (key, direction) in directions {
+        starter.bind(
+            &format!("shift + {key}"),
+            &format!(": komorebic move {direction}"),
+        );
+    }
+
+    starter.section("layout");
+    starter.bind("t", ": komorebic toggle-float");
+    starter.bind("m", ": komorebic toggle-monocle");
+    starter.bind("shift + r", ": komorebic retile");
+
+    starter.section("workspaces, which komorebi counts from 0");
+    for workspace in 1..=4 {
+        starter.bind(
+            &workspace.to_string(),
+            &format!(": komorebic focus-workspace {}", workspace - 1),
+        );
+    }
+
+    starter.section("resize windows in a mode of their own until escape is pressed");
+    starter.bind("r", "; resize");
+    for (key, axis, change) in [
+        (left, "horizontal", "decrease"),
+        (down, "vertical", "decrease"),
+        (up, "vertical", "increase"),
+        (right, "horizontal", "increase"),
+    ] {
+        starter.line(&format!(
+            "resize > {key} : komorebic resize-axis {axis} {change}"
+        ));
+    }
+    starter.line("resize > escape ; default");
+}
+
+/// Bindings to launch a few apps and manage the focused window without a
+/// window manager
+fn apps(starter: &mut Starter, [left, down, up, right]: [&str; 4]) {
+    starter.section("apps");
+    starter.bind("return", ": wt");
+    starter.bind("e", ": explorer");
+
+    starter.section("windows");
+    starter.bind("shift + q", "; @close");
+    starter.bind("m", "; @maximize");
+    starter.bind("c", "; @center");
+
+    starter.section(&format!(
+        "{left}, {down}, {up} and {right} are left free for a tiling window manager such as komorebi"
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_render() {
+        for leader in [Leader::Alt, Leader::Super, Leader::Mode] {
+            for navigation in [Navigation::Vim, Navigation::Arrows] {
+                for komorebi in [true, false] {
+                    let starter = render(Answers {
+                        shell: Shell::Pwsh,
+                        leader,
+                        navigation,
+                        komorebi,
+                    });
+
+                    let whkdrc = parser().parse(starter.as_str()).unwrap();
+                    assert_eq!(whkdrc.version, CURRENT_VERSION);
+                    assert!(lint::check(&whkdrc).is_empty(), "{starter}");
+                    assert!(
+                        !whkdrc
+                            .bindings
+                            .iter()
+                            .any(|binding| binding.keys == ["super", "l"]),
+                        "{starter}"
+                    );
+                    assert_eq!(
+                        whkdrc.bindings.iter().any(|binding| binding
+                            .command
+                            .as_deref()
+                            .is_some_and(|command| command.contains("komorebic"))),
+                        komorebi
+                    );
+                }
+            }
+        }
+
+        let starter = render(Answers {
+            shell: Shell::Cmd,
+            leader: Leader::Mode,
+            navigation: Navigation::Arrows,
+            komorebi: true,
+        });
+
+        assert!(starter.contains(".shell cmd\n"));
+        assert!(starter.contains("leader > ArrowLeft : komorebic focus left ; default\n"));
+        assert!(starter.contains("leader > r ; resize\n"));
+        assert!(starter.contains("resize > ArrowUp : komorebic resize-axis vertical increase\n"));
+    }
+}
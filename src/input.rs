@@ -0,0 +1,282 @@
+use global_hotkey::hotkey::Code;
+#[allow(clippy::wildcard_imports)]
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::*;
+
+/// Marks the input synthesized by whkd, which the keyboard hook only fires the
+/// bindings with `~allow-recursion` for
+pub const INJECTED: usize = 0x776b_6864;
+
+/// Marks the keys which whkd passes through, which the keyboard hook always
+/// lets through as they were already handled
+pub const PASSED: usize = 0x776b_6870;
+
+/// An unassigned virtual key, sent to keep Windows from treating a modifier whose
+/// chord was swallowed as having been pressed on its own
+pub const VK_MASK: VIRTUAL_KEY = 0xE8;
+
+/// Virtual key codes of the keys which can be used in whkdrc bindings, in both
+/// directions so that the keyboard hook can map events back to chords
+pub const KEYS: &[(Code, VIRTUAL_KEY)] = &[
+    (Code::KeyA, VK_A),
+    (Code::KeyB, VK_B),
+    (Code::KeyC, VK_C),
+    (Code::KeyD, VK_D),
+    (Code::KeyE, VK_E),
+    (Code::KeyF, VK_F),
+    (Code::KeyG, VK_G),
+    (Code::KeyH, VK_H),
+    (Code::KeyI, VK_I),
+    (Code::KeyJ, VK_J),
+    (Code::KeyK, VK_K),
+    (Code::KeyL, VK_L),
+    (Code::KeyM, VK_M),
+    (Code::KeyN, VK_N),
+    (Code::KeyO, VK_O),
+    (Code::KeyP, VK_P),
+    (Code::KeyQ, VK_Q),
+    (Code::KeyR, VK_R),
+    (Code::KeyS, VK_S),
+    (Code::KeyT, VK_T),
+    (Code::KeyU, VK_U),
+    (Code::KeyV, VK_V),
+    (Code::KeyW, VK_W),
+    (Code::KeyX, VK_X),
+    (Code::KeyY, VK_Y),
+    (Code::KeyZ, VK_Z),
+    (Code::Digit0, VK_0),
+    (Code::Digit1, VK_1),
+    (Code::Digit2, VK_2),
+    (Code::Digit3, VK_3),
+    (Code::Digit4, VK_4),
+    (Code::Digit5, VK_5),
+    (Code::Digit6, VK_6),
+    (Code::Digit7, VK_7),
+    (Code::Digit8, VK_8),
+    (Code::Digit9, VK_9),
+    (Code::F1, VK_F1),
+    (Code::F2, VK_F2),
+    (Code::F3, VK_F3),
+    (Code::F4, VK_F4),
+    (Code::F5, VK_F5),
+    (Code::F6, VK_F6),
+    (Code::F7, VK_F7),
+    (Code::F8, VK_F8),
+    (Code::F9, VK_F9),
+    (Code::F10, VK_F10),
+    (Code::F11, VK_F11),
+    (Code::F12, VK_F12),
+    (Code::F13, VK_F13),
+    (Code::F14, VK_F14),
+    (Code::F15, VK_F15),
+    (Code::F16, VK_F16),
+    (Code::F17, VK_F17),
+    (Code::F18, VK_F18),
+    (Code::F19, VK_F19),
+    (Code::F20, VK_F20),
+    (Code::F21, VK_F21),
+    (Code::F22, VK_F22),
+    (Code::F23, VK_F23),
+    (Code::F24, VK_F24),
+    (Code::Equal, VK_OEM_PLUS),
+    (Code::Comma, VK_OEM_COMMA),
+    (Code::Minus, VK_OEM_MINUS),
+    (Code::Period, VK_OEM_PERIOD),
+    (Code::Semicolon, VK_OEM_1),
+    (Code::Slash, VK_OEM_2),
+    (Code::Backquote, VK_OEM_3),
+    (Code::BracketLeft, VK_OEM_4),
+    (Code::Backslash, VK_OEM_5),
+    (Code::BracketRight, VK_OEM_6),
+    (Code::Quote, VK_OEM_7),
+    (Code::Backspace, VK_BACK),
+    (Code::Tab, VK_TAB),
+    (Code::Space, VK_SPACE),
+    (Code::Enter, VK_RETURN),
+    (Code::CapsLock, VK_CAPITAL),
+    (Code::Escape, VK_ESCAPE),
+    (Code::PageUp, VK_PRIOR),
+    (Code::PageDown, VK_NEXT),
+    (Code::End, VK_END),
+    (Code::Home, VK_HOME),
+    (Code::ArrowLeft, VK_LEFT),
+    (Code::ArrowUp, VK_UP),
+    (Code::ArrowRight, VK_RIGHT),
+    (Code::ArrowDown, VK_DOWN),
+    (Code::PrintScreen, VK_SNAPSHOT),
+    (Code::Insert, VK_INSERT),
+    (Code::Delete, VK_DELETE),
+    (Code::NumLock, VK_NUMLOCK),
+    (Code::ScrollLock, VK_SCROLL),
+    (Code::Numpad0, VK_NUMPAD0),
+    (Code::Numpad1, VK_NUMPAD1),
+    (Code::Numpad2, VK_NUMPAD2),
+    (Code::Numpad3, VK_NUMPAD3),
+    (Code::Numpad4, VK_NUMPAD4),
+    (Code::Numpad5, VK_NUMPAD5),
+    (Code::Numpad6, VK_NUMPAD6),
+    (Code::Numpad7, VK_NUMPAD7),
+    (Code::Numpad8, VK_NUMPAD8),
+    (Code::Numpad9, VK_NUMPAD9),
+    (Code::NumpadAdd, VK_ADD),
+    (Code::NumpadDecimal, VK_DECIMAL),
+    (Code::NumpadDivide, VK_DIVIDE),
+    (Code::NumpadMultiply, VK_MULTIPLY),
+    (Code::NumpadSubtract, VK_SUBTRACT),
+    (Code::AudioVolumeDown, VK_VOLUME_DOWN),
+    (Code::AudioVolumeUp, VK_VOLUME_UP),
+    (Code::AudioVolumeMute, VK_VOLUME_MUTE),
+    (Code::MediaPlayPause, VK_MEDIA_PLAY_PAUSE),
+    (Code::MediaStop, VK_MEDIA_STOP),
+    (Code::MediaTrackNext, VK_MEDIA_NEXT_TRACK),
+    (Code::MediaTrackPrevious, VK_MEDIA_PREV_TRACK),
+];
+
+pub fn vk_from_code(code: Code) -> Option<VIRTUAL_KEY> {
+    KEYS.iter().find(|(key, _)| *key == code).map(|(_, vk)| *vk)
+}
+
+pub fn code_from_vk(vk: VIRTUAL_KEY) -> Option<Code> {
+    KEYS.iter()
+        .find(|(_, key)| *key == vk)
+        .map(|(code, _)| *code)
+}
+
+/// Pass a press and release of a key through, on top of whatever modifiers are
+/// physically held down at the moment
+pub fn send_key(vk: VIRTUAL_KEY) -> bool {
+    let input = |flags| INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: 0,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: PASSED,
+            },
+        },
+    };
+
+    let inputs = [input(0), input(KEYEVENTF_KEYUP)];
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let sent = unsafe {
+        SendInput(
+            inputs.len() as u32,
+            inputs.as_ptr(),
+            std::mem::size_of::<INPUT>() as i32,
+        )
+    };
+
+    sent as usize == inputs.len()
+}
+
+/// Pass key downs (`true`) and ups through in order, e.g. those of a rolling
+/// chord which were held back
+pub fn send_keys(keys: &[(VIRTUAL_KEY, bool)]) -> bool {
+    let inputs = keys
+        .iter()
+        .map(|(vk, down)| INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: *vk,
+                    wScan: 0,
+                    dwFlags: if *down { 0 } else { KEYEVENTF_KEYUP },
+                    time: 0,
+                    dwExtraInfo: PASSED,
+                },
+            },
+        })
+        .collect::<Vec<_>>();
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let sent = unsafe {
+        SendInput(
+            inputs.len() as u32,
+            inputs.as_ptr(),
+            std::mem::size_of::<INPUT>() as i32,
+        )
+    };
+
+    sent as usize == inputs.len()
+}
+
+/// Type out text as unicode characters, after releasing the keys which are held
+/// down so that they don't apply to it
+pub fn send_text(text: &str, release: &[VIRTUAL_KEY]) -> bool {
+    let input = |vk, scan, flags| INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: scan,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: INJECTED,
+            },
+        },
+    };
+
+    let mut inputs = release
+        .iter()
+        .map(|vk| input(*vk, 0, KEYEVENTF_KEYUP))
+        .collect::<Vec<_>>();
+
+    for unit in text.replace("\r\n", "\n").encode_utf16() {
+        // most apps only start a new line for the enter key
+        if unit == u16::from(b'\n') {
+            inputs.push(input(VK_RETURN, 0, 0));
+            inputs.push(input(VK_RETURN, 0, KEYEVENTF_KEYUP));
+        } else {
+            inputs.push(input(0, unit, KEYEVENTF_UNICODE));
+            inputs.push(input(0, unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP));
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let sent = unsafe {
+        SendInput(
+            inputs.len() as u32,
+            inputs.as_ptr(),
+            std::mem::size_of::<INPUT>() as i32,
+        )
+    };
+
+    sent as usize == inputs.len()
+}
+
+/// Press the keys of a chord in order and release them in reverse, after
+/// releasing the keys which are held down so that they don't apply to it
+pub fn send_chord(keys: &[VIRTUAL_KEY], release: &[VIRTUAL_KEY]) -> bool {
+    let input = |vk, flags| INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: 0,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: INJECTED,
+            },
+        },
+    };
+
+    let inputs = release
+        .iter()
+        .map(|vk| input(*vk, KEYEVENTF_KEYUP))
+        .chain(keys.iter().map(|vk| input(*vk, 0)))
+        .chain(keys.iter().rev().map(|vk| input(*vk, KEYEVENTF_KEYUP)))
+        .collect::<Vec<_>>();
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let sent = unsafe {
+        SendInput(
+            inputs.len() as u32,
+            inputs.as_ptr(),
+            std::mem::size_of::<INPUT>() as i32,
+        )
+    };
+
+    sent as usize == inputs.len()
+}
@@ -0,0 +1,341 @@
+use crate::event_loop::EventLoopProxy;
+use crate::failures;
+use crate::history;
+use crate::log::TagFilter;
+use crate::stats;
+use crate::UserEvent;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+use uds_windows::UnixListener;
+use uds_windows::UnixStream;
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(vec![]);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub enum SocketMessage {
+    Watch(TagFilter),
+    EnableTag(String),
+    DisableTag(String),
+    Trigger {
+        mode: Option<String>,
+        keys: Vec<String>,
+    },
+    /// Trigger the binding with a `~name`
+    TriggerName(String),
+    /// Enable or disable the binding with a `~name`
+    ToggleBinding(String),
+    /// Switch to a mode, `None` being the default mode, e.g. for `whkd://mode/<name>`
+    Mode(Option<String>),
+    Stats,
+    History,
+    Failures,
+    Reload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "content")]
+pub enum Event {
+    Dispatch {
+        mode: Option<String>,
+        keys: Vec<String>,
+        #[serde(default)]
+        name: Option<String>,
+        command: String,
+        tags: Vec<String>,
+    },
+    ShellWrite {
+        command: String,
+    },
+    ModeChange {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    ProfileChange {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    Registered {
+        mode: Option<String>,
+        keys: Vec<String>,
+    },
+    Unregistered {
+        mode: Option<String>,
+        keys: Vec<String>,
+    },
+    TagState {
+        tag: String,
+        enabled: bool,
+    },
+    BindingState {
+        name: String,
+        enabled: bool,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Event {
+    fn tags(&self) -> &[String] {
+        match self {
+            Self::Dispatch { tags, .. } => tags,
+            _ => &[],
+        }
+    }
+}
+
+fn mode_name(mode: Option<&String>) -> &str {
+    mode.map_or("default", String::as_str)
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dispatch {
+                mode,
+                keys,
+                name,
+                command,
+                tags,
+            } => {
+                if !tags.is_empty() {
+                    write!(f, "[{}] ", tags.join(", "))?;
+                }
+
+                if let Some(mode) = mode {
+                    write!(f, "{mode} > ")?;
+                }
+
+                write!(f, "{}", keys.join(" + "))?;
+                if let Some(name) = name {
+                    write!(f, " ~name({name})")?;
+                }
+
+                write!(f, " : {command}")
+            }
+            Self::ShellWrite { command } => write!(f, "shell <- {command}"),
+            Self::ModeChange { from, to } => write!(
+                f,
+                "mode changed from {} to {}",
+                mode_name(from.as_ref()),
+                mode_name(to.as_ref())
+            ),
+            Self::ProfileChange { from, to } => write!(
+                f,
+                "profile changed from {} to {}",
+                from.as_deref().unwrap_or("none"),
+                to.as_deref().unwrap_or("none")
+            ),
+            Self::Registered { mode, keys } => write!(
+                f,
+                "registered {} in {}",
+                keys.join(" + "),
+                mode_name(mode.as_ref())
+            ),
+            Self::Unregistered { mode, keys } => write!(
+                f,
+                "unregistered {} in {}",
+                keys.join(" + "),
+                mode_name(mode.as_ref())
+            ),
+            Self::TagState { tag, enabled } => write!(
+                f,
+                "bindings tagged {tag} {}",
+                if *enabled { "enabled" } else { "disabled" }
+            ),
+            Self::BindingState { name, enabled } => write!(
+                f,
+                "binding {name} {}",
+                if *enabled { "enabled" } else { "disabled" }
+            ),
+            Self::Error { message } => write!(f, "error: {message}"),
+        }
+    }
+}
+
+/// How long a client has to send its message after connecting
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest message a client can send, far longer than any message needs,
+/// so that a client can't make whkd buffer a line without end
+const MAX_MESSAGE_LEN: u64 = 64 * 1024;
+
+/// How long writing an event to a `whkd watch` client can take before the
+/// client is taken to have stopped reading and is dropped
+const SUBSCRIBER_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Events waiting to be written to a `whkd watch` client before it is dropped
+/// for falling behind
+const SUBSCRIBER_QUEUE: usize = 256;
+
+/// A `whkd watch` client, whose events are written on a thread of its own so
+/// that a client which stops reading never holds up a dispatch
+struct Subscriber {
+    queue: SyncSender<String>,
+    filter: TagFilter,
+}
+
+impl Subscriber {
+    fn new(stream: UnixStream, filter: TagFilter) -> std::io::Result<Self> {
+        // a write to a client which has stopped reading would otherwise block
+        // the thread for good, even once the subscriber has been dropped
+        stream.set_write_timeout(Some(SUBSCRIBER_WRITE_TIMEOUT))?;
+
+        let (queue, events) = std::sync::mpsc::sync_channel::<String>(SUBSCRIBER_QUEUE);
+        std::thread::spawn(move || {
+            let mut stream = stream;
+            // ends once the client has gone away or stopped reading, or once the
+            // subscriber is dropped and the queue with it
+            for json in events {
+                if writeln!(stream, "{json}").is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { queue, filter })
+    }
+}
+
+pub fn socket_path() -> PathBuf {
+    dirs::data_local_dir()
+        .expect("there is no local data directory")
+        .join("whkd")
+        .join("whkd.sock")
+}
+
+/// Accept connections on the whkd socket, forwarding the messages which are not
+/// answered here to the event loop so that hotkeys are (un)registered on its thread
+pub fn listen(proxy: EventLoopProxy<UserEvent>) -> Result<()> {
+    let socket = socket_path();
+    if let Some(parent) = socket.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // the socket of an instance which is still running must not be taken over,
+    // as nothing could reach that instance any more
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(eyre!(
+            "another instance of whkd is running and listening on {}",
+            socket.display()
+        ));
+    }
+
+    // a stale socket is left behind if a previous instance did not exit cleanly
+    match std::fs::remove_file(&socket) {
+        Ok(()) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error.into()),
+    }
+
+    let listener = UnixListener::bind(&socket)?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                // every connection is served on a thread of its own, so that a
+                // client which is slow to send its message holds up no other
+                Ok(stream) => {
+                    let proxy = proxy.clone();
+                    std::thread::spawn(move || {
+                        if let Err(error) = handle_connection(stream, &proxy) {
+                            println!("Error while handling socket connection: {error}");
+                        }
+                    });
+                }
+                Err(error) => {
+                    println!("Error while accepting socket connection: {error}");
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(stream: UnixStream, proxy: &EventLoopProxy<UserEvent>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = String::new();
+    let read = BufReader::new(stream.try_clone()?.take(MAX_MESSAGE_LEN)).read_line(&mut line)?;
+    if read == 0 {
+        // e.g. another instance which checked whether this one is running
+        return Ok(());
+    }
+
+    if read as u64 == MAX_MESSAGE_LEN && !line.ends_with('\n') {
+        return Err(eyre!("the message is longer than {MAX_MESSAGE_LEN} bytes"));
+    }
+
+    match serde_json::from_str::<SocketMessage>(&line)? {
+        SocketMessage::Watch(filter) => {
+            let subscriber = Subscriber::new(stream, filter)?;
+            SUBSCRIBERS.lock().push(subscriber);
+        }
+        SocketMessage::Stats => {
+            let mut stream = stream;
+            writeln!(stream, "{}", serde_json::to_string(&stats::snapshot())?)?;
+        }
+        SocketMessage::History => {
+            let mut stream = stream;
+            writeln!(stream, "{}", serde_json::to_string(&history::snapshot())?)?;
+        }
+        SocketMessage::Failures => {
+            let mut stream = stream;
+            writeln!(stream, "{}", serde_json::to_string(&failures::snapshot())?)?;
+        }
+        message => proxy.send_event(UserEvent::Message(message))?,
+    }
+
+    Ok(())
+}
+
+/// Queue an event for every subscriber whose tag filter matches, dropping any
+/// subscriber that has gone away or whose queue is full
+pub fn broadcast(event: &Event) {
+    let mut subscribers = SUBSCRIBERS.lock();
+    if subscribers.is_empty() {
+        return;
+    }
+
+    let Ok(json) = serde_json::to_string(event) else {
+        return;
+    };
+
+    subscribers.retain(|subscriber| {
+        !subscriber.filter.matches(event.tags()) || subscriber.queue.try_send(json.clone()).is_ok()
+    });
+}
+
+pub fn send(message: &SocketMessage) -> Result<UnixStream> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    writeln!(stream, "{}", serde_json::to_string(message)?)?;
+    Ok(stream)
+}
+
+pub fn watch(filter: TagFilter) -> Result<()> {
+    let stream = send(&SocketMessage::Watch(filter))?;
+
+    for line in BufReader::new(stream).lines() {
+        let event: Event = serde_json::from_str(&line?)?;
+        println!("{event}");
+    }
+
+    Ok(())
+}
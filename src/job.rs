@@ -0,0 +1,89 @@
+use crate::log;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use std::os::windows::io::AsRawHandle;
+use std::process::Child;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::JobObjects::AssignProcessToJobObject;
+use windows_sys::Win32::System::JobObjects::CreateJobObjectW;
+use windows_sys::Win32::System::JobObjects::JobObjectExtendedLimitInformation;
+use windows_sys::Win32::System::JobObjects::SetInformationJobObject;
+use windows_sys::Win32::System::JobObjects::JOBOBJECT_EXTENDED_LIMIT_INFORMATION;
+use windows_sys::Win32::System::JobObjects::JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+use windows_sys::Win32::System::JobObjects::JOB_OBJECT_LIMIT_SILENT_BREAKAWAY_OK;
+
+/// Set by `.options kill_commands` to also tie the processes of direct
+/// commands to whkd
+static KILL_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// The job whose processes are killed once whkd exits or crashes, as the
+    /// handle is only ever closed by Windows along with the process
+    static ref JOB: Option<HANDLE> = match create() {
+        Ok(job) => Some(job),
+        Err(error) => {
+            log::error(format!("could not create a job for the shell sessions: {error}"));
+            None
+        }
+    };
+}
+
+fn create() -> Result<HANDLE> {
+    let job = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+    if job == 0 {
+        return Err(eyre!("{}", std::io::Error::last_os_error()));
+    }
+
+    let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+    // the processes started by a process of the job, such as the apps which
+    // are launched from a shell session, are left out of it so that they
+    // outlive whkd
+    info.BasicLimitInformation.LimitFlags =
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE | JOB_OBJECT_LIMIT_SILENT_BREAKAWAY_OK;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let size = std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32;
+    if unsafe {
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            std::ptr::addr_of!(info).cast(),
+            size,
+        )
+    } == 0
+    {
+        return Err(eyre!("{}", std::io::Error::last_os_error()));
+    }
+
+    Ok(job)
+}
+
+pub fn set_kill_commands(kill_commands: bool) {
+    KILL_COMMANDS.store(kill_commands, Ordering::Relaxed);
+}
+
+/// Kill a process once whkd exits, which is always done for shell sessions
+pub fn assign(child: &Child) {
+    let Some(job) = *JOB else {
+        return;
+    };
+
+    if unsafe { AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE) } == 0 {
+        log::error(format!(
+            "could not tie process {} to whkd: {}",
+            child.id(),
+            std::io::Error::last_os_error()
+        ));
+    }
+}
+
+/// Kill the process of a direct command once whkd exits if `.options
+/// kill_commands` is set
+pub fn assign_command(child: &Child) {
+    if KILL_COMMANDS.load(Ordering::Relaxed) {
+        assign(child);
+    }
+}
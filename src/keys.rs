@@ -0,0 +1,216 @@
+use crate::hook;
+use crate::input;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use global_hotkey::hotkey::Code;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use windows_sys::Win32::Foundation::LPARAM;
+use windows_sys::Win32::Foundation::LRESULT;
+use windows_sys::Win32::Foundation::WPARAM;
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_CONTROL;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_MENU;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_SHIFT;
+use windows_sys::Win32::UI::WindowsAndMessaging::CallNextHookEx;
+use windows_sys::Win32::UI::WindowsAndMessaging::DispatchMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::PostQuitMessage;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetWindowsHookExW;
+use windows_sys::Win32::UI::WindowsAndMessaging::UnhookWindowsHookEx;
+use windows_sys::Win32::UI::WindowsAndMessaging::HC_ACTION;
+use windows_sys::Win32::UI::WindowsAndMessaging::KBDLLHOOKSTRUCT;
+use windows_sys::Win32::UI::WindowsAndMessaging::MSG;
+use windows_sys::Win32::UI::WindowsAndMessaging::WH_KEYBOARD_LL;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_KEYDOWN;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_SYSKEYDOWN;
+
+/// Other names which `key_code_from_string` accepts for a key
+const ALIASES: &[(Code, &str)] = &[(Code::Escape, "esc"), (Code::Enter, "return")];
+
+lazy_static! {
+    /// The chord pressed while listening, written by the hook procedure
+    static ref IDENTIFIED: Mutex<Option<Identified>> = Mutex::new(None);
+}
+
+/// A chord pressed while listening, as the modifiers held down and the key
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Identified {
+    /// Modifier names without and with the `l` or `r` prefix of their side
+    modifiers: Vec<(&'static str, String)>,
+    vk: VIRTUAL_KEY,
+}
+
+impl Identified {
+    fn new(vk: VIRTUAL_KEY, held: impl Fn(VIRTUAL_KEY) -> bool) -> Self {
+        let modifiers = hook::MODIFIERS
+            .iter()
+            .filter_map(|(name, left, right)| match (held(*left), held(*right)) {
+                (false, false) => None,
+                (true, false) => Some((*name, format!("l{name}"))),
+                (false, true) => Some((*name, format!("r{name}"))),
+                (true, true) => Some((*name, (*name).to_string())),
+            })
+            .collect();
+
+        Self { modifiers, vk }
+    }
+
+    /// The chord as it is written in whkdrc, with the modifiers of either side
+    /// or only the side which was held down
+    fn syntax(&self, sided: bool) -> Option<String> {
+        let key = key_name(input::code_from_vk(self.vk)?);
+        let mut keys = self
+            .modifiers
+            .iter()
+            .map(|(name, sided_name)| {
+                if sided {
+                    sided_name.clone()
+                } else {
+                    (*name).to_string()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        keys.push(key);
+        Some(keys.join(" + "))
+    }
+}
+
+/// The name of a key in whkdrc, e.g. `h`, `1` or `ArrowLeft`
+fn key_name(code: Code) -> String {
+    let name = code.to_string();
+    name.strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+        .map_or_else(|| name.clone(), str::to_lowercase)
+}
+
+/// Print the names of every modifier and key which can be used in bindings
+pub fn list() {
+    println!("Modifiers (prefixed with l or r, they only match one side with `.backend hook`):");
+    for (name, _, _) in hook::MODIFIERS {
+        println!("  {name}");
+    }
+
+    println!("\nKeys:");
+    for (code, _) in input::KEYS {
+        let aliases = ALIASES
+            .iter()
+            .filter(|(aliased, _)| aliased == code)
+            .map(|(_, alias)| *alias)
+            .collect::<Vec<_>>();
+
+        if aliases.is_empty() {
+            println!("  {}", key_name(*code));
+        } else {
+            println!("  {} ({})", key_name(*code), aliases.join(", "));
+        }
+    }
+}
+
+/// Capture the next chord with a keyboard hook, keeping it from the foreground
+/// app, and print how it is written in whkdrc
+pub fn listen() -> Result<()> {
+    println!("Press the chord to identify");
+
+    let hook = unsafe {
+        SetWindowsHookExW(
+            WH_KEYBOARD_LL,
+            Some(hook_proc),
+            GetModuleHandleW(std::ptr::null()),
+            0,
+        )
+    };
+
+    if hook == 0 {
+        return Err(eyre!(
+            "could not install the keyboard hook: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    let mut msg: MSG = unsafe { std::mem::zeroed() };
+    while unsafe { GetMessageW(std::ptr::addr_of_mut!(msg), 0, 0, 0) } > 0 {
+        unsafe { DispatchMessageW(std::ptr::addr_of!(msg)) };
+    }
+
+    unsafe { UnhookWindowsHookEx(hook) };
+
+    let identified = IDENTIFIED
+        .lock()
+        .take()
+        .ok_or_else(|| eyre!("no chord was pressed"))?;
+
+    let (Some(syntax), Some(sided)) = (identified.syntax(false), identified.syntax(true)) else {
+        return Err(eyre!(
+            "the key with the virtual key code {:#04x} can't be bound",
+            identified.vk
+        ));
+    };
+
+    println!("{syntax}");
+    if sided != syntax {
+        println!("{sided} (with `.backend hook`, to only match the modifiers on this side)");
+    }
+
+    Ok(())
+}
+
+fn is_modifier(vk: VIRTUAL_KEY) -> bool {
+    [VK_CONTROL, VK_MENU, VK_SHIFT].contains(&vk)
+        || hook::MODIFIERS
+            .iter()
+            .any(|(_, left, right)| vk == *left || vk == *right)
+}
+
+unsafe extern "system" fn hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    #[allow(clippy::cast_possible_wrap)]
+    if code == HC_ACTION as i32 {
+        let event = &*(lparam as *const KBDLLHOOKSTRUCT);
+
+        #[allow(clippy::cast_possible_truncation)]
+        let vk = event.vkCode as VIRTUAL_KEY;
+
+        #[allow(clippy::cast_possible_truncation)]
+        if matches!(wparam as u32, WM_KEYDOWN | WM_SYSKEYDOWN) && !is_modifier(vk) {
+            *IDENTIFIED.lock() = Some(Identified::new(vk, hook::is_down));
+            PostQuitMessage(0);
+            return 1;
+        }
+    }
+
+    CallNextHookEx(0, code, wparam, lparam)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_code_from_string;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_H;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_LMENU;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_LSHIFT;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::VK_RSHIFT;
+
+    #[test]
+    fn test_key_names() {
+        for (code, _) in input::KEYS {
+            assert_eq!(key_code_from_string(&key_name(*code)), Some(*code));
+        }
+
+        for (code, alias) in ALIASES {
+            assert_eq!(key_code_from_string(alias), Some(*code));
+        }
+    }
+
+    #[test]
+    fn test_syntax() {
+        let identified = Identified::new(VK_H, |vk| [VK_LMENU, VK_LSHIFT, VK_RSHIFT].contains(&vk));
+
+        assert_eq!(identified.syntax(false).as_deref(), Some("alt + shift + h"));
+        assert_eq!(identified.syntax(true).as_deref(), Some("lalt + shift + h"));
+
+        let unknown = Identified::new(0xFF, |_| false);
+        assert_eq!(unknown.syntax(false), None);
+    }
+}
@@ -0,0 +1,1842 @@
+#![warn(clippy::all, clippy::nursery, clippy::pedantic)]
+#![allow(clippy::missing_errors_doc, clippy::redundant_pub_crate)]
+
+use crate::condition::Condition;
+use crate::dispatch::run_dispatcher;
+use crate::dispatch::Dispatch;
+use crate::event_loop::EventLoop;
+use crate::event_loop::EventLoopProxy;
+use crate::executor::Executors;
+use crate::ipc::Event;
+use crate::ipc::SocketMessage;
+use crate::log::TagFilter;
+use crate::mode::create_hotkeys_manager;
+use crate::mode::resolve;
+use crate::mode::ModeManager;
+use crate::notifications::SessionEvent;
+use crate::parser::HotkeyBinding;
+use crate::parser::InternalAction;
+use crate::whkdrc::Backend;
+use crate::whkdrc::Exec;
+use crate::whkdrc::Whkdrc;
+use clap::Args;
+use clap::Parser;
+use clap::Subcommand;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use global_hotkey::hotkey;
+use global_hotkey::hotkey::Code;
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::hotkey::Modifiers;
+use global_hotkey::GlobalHotKeyEvent;
+use global_hotkey::GlobalHotKeyManager;
+use global_hotkey::HotKeyState;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use windows_hotkeys::error::HkError;
+
+mod arch;
+mod audio;
+mod brightness;
+pub mod builder;
+mod clipboard;
+mod condition;
+mod confirm;
+mod conflict;
+mod crash;
+mod diagnose;
+mod dispatch;
+mod display;
+mod dnd;
+mod doctor;
+mod editor;
+mod event_loop;
+mod executor;
+mod failures;
+mod focus;
+mod foreground;
+#[cfg(test)]
+mod harness;
+mod help;
+mod history;
+mod hook;
+mod idle;
+mod import;
+mod indicator;
+mod init;
+mod input;
+mod ipc;
+mod job;
+mod keys;
+mod lint;
+mod locale;
+mod log;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod migrate;
+mod mode;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod notifications;
+mod osd;
+pub mod parser;
+mod process;
+mod redact;
+mod runtime;
+mod session;
+mod setup;
+mod shutdown;
+mod sound;
+mod state;
+mod stats;
+#[cfg(feature = "update")]
+mod update;
+mod whkdrc;
+mod window;
+
+lazy_static! {
+    /// Position of the next command of every cycle binding which has been pressed
+    static ref CYCLES: Mutex<HashMap<HkmData, usize>> = Mutex::new(HashMap::new());
+    /// Whether the `on` command of a toggle binding was the last one dispatched
+    static ref TOGGLES: Mutex<HashMap<HkmData, bool>> = Mutex::new(HashMap::new());
+    /// When every binding with a `~cooldown` last fired
+    static ref LAST_FIRED: Mutex<HashMap<HkmData, Instant>> = Mutex::new(HashMap::new());
+    /// How many `; trigger` bindings are being pressed within each other
+    static ref TRIGGER_DEPTH: Mutex<usize> = Mutex::new(0);
+}
+
+/// Bindings which trigger each other are stopped this many bindings deep
+const MAX_TRIGGER_DEPTH: usize = 8;
+
+/// Events handled on the event loop thread, which owns the hotkey registrations
+#[derive(Debug)]
+pub enum UserEvent {
+    /// A message forwarded from the socket, e.g. by `whkd disable`
+    Message(SocketMessage),
+    /// A registered chord was pressed, or captured by the keyboard hook, see
+    /// `hook::register`
+    Chord(u32),
+    /// The question of a binding with `~confirm` was answered with yes, see
+    /// `confirm::ask`
+    Confirmed(Box<HkmData>),
+    /// The `~when-cmd` conditions of the bindings of a chord or trigger have
+    /// been checked and this one is to be pressed, see `ModeManager::press_chosen`
+    Chosen { hotkey: Box<HkmData>, press: Press },
+    /// How long there has been no input, checked regularly for `@on-idle`, see
+    /// `idle::watch`
+    Idle(Duration),
+    /// The key of a `.layer` went down or up, see `hook::hold_layer`
+    Layer { mode: String, held: bool },
+    /// The timeout of a mode has run out, see `ModeManager::arm_timeout`
+    Timeout(u64),
+    /// Time to retry failed registrations, see `ModeManager::retry_registrations`
+    RetryRegistration { generation: u64, attempt: u32 },
+    /// Another app has been focused, see `watch_foreground`
+    Foreground(Option<String>),
+    /// The session was unlocked or connected to, or the machine woke up
+    Session(SessionEvent),
+    /// whkd was asked to stop, see `shutdown::request`
+    Shutdown,
+}
+
+/// How a binding came to be pressed, which is carried along while the
+/// `~when-cmd` conditions of its chord are checked away from the event loop
+#[derive(Debug)]
+pub enum Press {
+    /// Its chord was pressed at the given time, see `handle_chord`
+    Chord(Instant),
+    /// It was triggered this many bindings deep, see `press_triggered`
+    Trigger { shown: String, depth: usize },
+}
+
+impl Press {
+    fn apply(self, mode_manager: &ModeManager, hotkey: &HkmData) {
+        match self {
+            Self::Chord(pressed) => press_chord(mode_manager, hotkey, pressed),
+            Self::Trigger { shown, depth } => press_triggered(mode_manager, hotkey, &shown, depth),
+        }
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HkmData {
+    pub mode: Option<String>,
+    pub keys: Vec<String>,
+    pub mod_keys: Option<Modifiers>,
+    pub vkey: hotkey::Code,
+    pub command: Option<String>,
+    pub internal_action: Option<InternalAction>,
+    pub process_name: Option<String>,
+    pub tags: Vec<String>,
+    pub conditions: Vec<Condition>,
+    pub passthrough: bool,
+    pub timeout: Option<Duration>,
+    pub cooldown: Option<Duration>,
+    pub wait: bool,
+    pub on_fail: Option<String>,
+    pub on_error: Option<String>,
+    pub description: Option<String>,
+    pub allow_recursion: bool,
+    pub respect_dnd: bool,
+    pub notify: Option<String>,
+    pub sound: Option<PathBuf>,
+    pub confirm: Option<String>,
+    /// Keep the command out of the output, see `~silent`
+    pub silent: bool,
+    pub name: Option<String>,
+    pub profile: Option<String>,
+    /// `None` for the bindings which whkd adds itself
+    pub line: Option<usize>,
+}
+
+// a binding which whkd adds itself, without a chord or anything else set
+impl Default for HkmData {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            keys: vec![],
+            mod_keys: None,
+            vkey: Code::Unidentified,
+            command: None,
+            internal_action: None,
+            process_name: None,
+            tags: vec![],
+            conditions: vec![],
+            passthrough: false,
+            timeout: None,
+            cooldown: None,
+            wait: false,
+            on_fail: None,
+            on_error: None,
+            description: None,
+            allow_recursion: false,
+            respect_dnd: false,
+            notify: None,
+            sound: None,
+            confirm: None,
+            silent: false,
+            name: None,
+            profile: None,
+            line: None,
+        }
+    }
+}
+
+impl TryFrom<&HotkeyBinding> for HkmData {
+    type Error = HkError;
+
+    fn try_from(value: &HotkeyBinding) -> Result<Self, Self::Error> {
+        let (trigger, mods) = value.keys.split_last().unwrap();
+        let mut mod_keys = Modifiers::empty();
+        let vkey =
+            key_code_from_string(trigger).ok_or_else(|| HkError::InvalidKey(trigger.clone()))?;
+        for m in mods {
+            mod_keys |= modifier_from_string(m).ok_or_else(|| HkError::InvalidKey(m.clone()))?;
+        }
+
+        let mod_keys = if mod_keys.is_empty() {
+            None
+        } else {
+            Some(mod_keys)
+        };
+
+        Ok(Self {
+            mode: value.mode.clone(),
+            keys: value.keys.clone(),
+            mod_keys,
+            vkey,
+            command: value.command.clone(),
+            internal_action: value.internal_action.clone(),
+            process_name: value.process_name.clone(),
+            tags: value.tags.clone(),
+            conditions: value.conditions.clone(),
+            passthrough: value.passthrough,
+            timeout: value.timeout,
+            cooldown: value.cooldown,
+            wait: value.wait,
+            on_fail: value.on_fail.clone(),
+            on_error: value.on_error.clone(),
+            description: value.description.clone(),
+            allow_recursion: value.allow_recursion,
+            respect_dnd: value.respect_dnd,
+            notify: value.notify.clone(),
+            sound: value.sound.clone(),
+            confirm: value.confirm.clone(),
+            silent: value.silent,
+            name: value.name.clone(),
+            profile: value.profile.clone(),
+            line: Some(value.line),
+        })
+    }
+}
+
+impl HkmData {
+    /// The binding of a digit which adds to the count in a mode
+    fn count(mode: &str, digit: u32) -> Self {
+        let keys = vec![digit.to_string()];
+        let vkey = key_code_from_string(&keys[0]).expect("digits are valid keys");
+
+        Self {
+            mode: Some(mode.to_string()),
+            keys,
+            vkey,
+            internal_action: Some(InternalAction::Count(digit)),
+            ..Default::default()
+        }
+    }
+
+    /// The stand-in binding of an `@on-idle` command, which isn't bound to a
+    /// chord
+    fn on_idle(idle: Duration, command: &str) -> Self {
+        Self {
+            keys: vec![format!("@on-idle({})", humantime::format_duration(idle))],
+            command: Some(command.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Identifies the binding by its `~name`, or otherwise by its mode, chord and
+    /// app, so that the id stays the same across restarts and edits of the command
+    fn binding_id(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        // FNV-1a, which unlike `DefaultHasher` is guaranteed not to change
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mode = self.mode.as_deref().unwrap_or_default();
+        let process_name = self.process_name.as_deref().unwrap_or_default();
+        let keys = self.keys.join("+").to_lowercase();
+
+        for part in [mode, &keys, process_name] {
+            for byte in part.bytes().chain([0]) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+
+        format!("{hash:016x}")
+    }
+}
+
+fn key_code_from_string(key: &str) -> Option<Code> {
+    match key.to_lowercase().as_str() {
+        "a" => Some(Code::KeyA),
+        "b" => Some(Code::KeyB),
+        "c" => Some(Code::KeyC),
+        "d" => Some(Code::KeyD),
+        "e" => Some(Code::KeyE),
+        "f" => Some(Code::KeyF),
+        "g" => Some(Code::KeyG),
+        "h" => Some(Code::KeyH),
+        "i" => Some(Code::KeyI),
+        "j" => Some(Code::KeyJ),
+        "k" => Some(Code::KeyK),
+        "l" => Some(Code::KeyL),
+        "m" => Some(Code::KeyM),
+        "n" => Some(Code::KeyN),
+        "o" => Some(Code::KeyO),
+        "p" => Some(Code::KeyP),
+        "q" => Some(Code::KeyQ),
+        "r" => Some(Code::KeyR),
+        "s" => Some(Code::KeyS),
+        "t" => Some(Code::KeyT),
+        "u" => Some(Code::KeyU),
+        "v" => Some(Code::KeyV),
+        "w" => Some(Code::KeyW),
+        "x" => Some(Code::KeyX),
+        "y" => Some(Code::KeyY),
+        "z" => Some(Code::KeyZ),
+        "0" => Some(Code::Digit0),
+        "1" => Some(Code::Digit1),
+        "2" => Some(Code::Digit2),
+        "3" => Some(Code::Digit3),
+        "4" => Some(Code::Digit4),
+        "5" => Some(Code::Digit5),
+        "6" => Some(Code::Digit6),
+        "7" => Some(Code::Digit7),
+        "8" => Some(Code::Digit8),
+        "9" => Some(Code::Digit9),
+        "escape" => Some(Code::Escape),
+        "esc" => Some(Code::Escape),
+        "return" => Some(Code::Enter),
+        // modifier-only chords, see `hook::Chord`
+        "ctrl" | "lctrl" => Some(Code::ControlLeft),
+        "rctrl" => Some(Code::ControlRight),
+        "alt" | "lalt" => Some(Code::AltLeft),
+        "ralt" => Some(Code::AltRight),
+        "shift" | "lshift" => Some(Code::ShiftLeft),
+        "rshift" => Some(Code::ShiftRight),
+        "super" | "lsuper" => Some(Code::MetaLeft),
+        "rsuper" => Some(Code::MetaRight),
+        // key names such as `ArrowLeft` in any case
+        _ => Code::from_str(key).ok().or_else(|| {
+            input::KEYS
+                .iter()
+                .map(|(code, _)| *code)
+                .find(|code| code.to_string().eq_ignore_ascii_case(key))
+        }),
+    }
+}
+
+fn hotkey_from_keys(keys: &[String]) -> Option<HotKey> {
+    let (trigger, mods) = keys.split_last()?;
+    let vkey = key_code_from_string(trigger)?;
+    let mod_keys = mods.iter().try_fold(Modifiers::empty(), |mod_keys, m| {
+        Some(mod_keys | modifier_from_string(m)?)
+    })?;
+
+    Some(HotKey::new(
+        (!mod_keys.is_empty()).then_some(mod_keys),
+        vkey,
+    ))
+}
+
+/// Identifies the chord of a binding, bindings with the same id share a single
+/// registration
+fn chord_id(backend: Backend, keys: &[String]) -> Option<u32> {
+    match backend {
+        Backend::Register => hotkey_from_keys(keys).map(|hotkey| hotkey.id()),
+        Backend::Hook => hook::Chord::parse(keys).map(hook::Chord::id),
+    }
+}
+
+/// The modifier of a key in front of the last one of a chord, or `None` for a
+/// key which isn't one, e.g. `ctlr`, which the `unknown_key` lint reports
+fn modifier_from_string(modifier: &str) -> Option<Modifiers> {
+    match modifier.to_lowercase().as_str() {
+        "ctrl" | "lctrl" | "rctrl" => Some(Modifiers::CONTROL),
+        "alt" | "lalt" | "ralt" => Some(Modifiers::ALT),
+        "shift" | "lshift" | "rshift" => Some(Modifiers::SHIFT),
+        "super" | "lsuper" | "rsuper" => Some(Modifiers::SUPER),
+        _ => None,
+    }
+}
+
+// flags of the command line are naturally bools
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser)]
+#[clap(author, about, version)]
+struct Cli {
+    /// Path to whkdrc, or `-` to read it from stdin
+    #[clap(action, short, long)]
+    config: Option<PathBuf>,
+    /// Spawn a new shell process for every command instead of using a shared session
+    #[clap(action, long)]
+    no_session: bool,
+    /// Profile to activate on startup, defaults to the first profile in whkdrc
+    #[clap(action, long)]
+    profile: Option<String>,
+    /// Print the command each chord would run without registering any hotkeys
+    #[clap(action, long)]
+    dry_run: bool,
+    /// Keep running without bindings while whkdrc is missing or can't be loaded,
+    /// and load it once it has been created or fixed
+    #[clap(action, long, conflicts_with = "dry_run")]
+    wait_for_config: bool,
+    /// Abort on any warning about whkdrc which isn't allowed with `.allow`, like `.strict`
+    #[clap(action, long)]
+    strict: bool,
+    /// Leave out what is printed for every keypress, such as the commands which are run
+    #[clap(action, short, long)]
+    quiet: bool,
+    /// Time each stage of handling a binding and report their percentiles in `whkd stats`
+    #[clap(action, long)]
+    trace_latency: bool,
+    /// Load whkdrc without running the commands of `.include-cmd`
+    #[clap(action, long)]
+    no_include_cmd: bool,
+    /// Serve Prometheus metrics on this port of localhost
+    #[cfg(feature = "metrics")]
+    #[clap(action, long)]
+    metrics: Option<u16>,
+    #[clap(subcommand)]
+    subcommand: Option<SubCommand>,
+}
+
+#[derive(Subcommand)]
+enum SubCommand {
+    /// Stream the commands dispatched by the running whkd instance
+    Watch(Watch),
+    /// Capture diagnostic information from the running whkd instance for a bug report
+    Diagnose(Diagnose),
+    /// Check the common causes of whkd not working, such as conflicting chords
+    Doctor,
+    /// Load whkdrc without starting whkd and print the warnings about it
+    Check(Check),
+    /// Register the whkd:// links, and optionally a context menu entry for whkdrc files in Explorer
+    Setup(Setup),
+    /// Pass a whkd:// link such as `whkd://reload` or `whkd://mode/resize` to the running whkd instance
+    OpenUri(OpenUri),
+    /// Ask a few questions and write a starter whkdrc from the answers
+    Init,
+    /// Check whether a newer release of whkd is available on GitHub
+    CheckUpdate,
+    /// Upgrade whkdrc to the current syntax, keeping a backup of the original
+    Migrate,
+    /// Translate the hotkeys of another tool into whkdrc bindings
+    Import(Import),
+    /// Re-enable the bindings with a tag in the running whkd instance
+    Enable(Tag),
+    /// Disable the bindings with a tag in the running whkd instance until they are enabled again
+    Disable(Tag),
+    /// Disable the binding with a `~name` in the running whkd instance, or enable it again
+    Toggle(Toggle),
+    /// Run the binding for a chord in the running whkd instance as if it had been pressed
+    Trigger(Trigger),
+    /// Show how often each binding has been used and how long its dispatch took
+    Stats(Report),
+    /// Show the most recently dispatched commands
+    History(Report),
+    /// Show the bindings whose chords could not be registered and why
+    Failures(Report),
+    /// Load whkdrc again in the running whkd instance, e.g. after it was synced
+    Reload,
+    /// Print the report written when whkd last crashed
+    CrashReport,
+    /// List the names of the keys and modifiers which can be used in bindings
+    Keys(Keys),
+    /// Parse a single binding and show how whkd understands it
+    Parse(Parse),
+}
+
+#[derive(Args)]
+struct Report {
+    /// Print JSON instead of a table
+    #[clap(action, long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct Trigger {
+    /// Chord to trigger, e.g. `alt + h`
+    #[clap(required_unless_present = "name", num_args = 1..)]
+    chord: Vec<String>,
+    /// Mode to look the chord up in, defaults to the active mode
+    #[clap(action, long)]
+    mode: Option<String>,
+    /// Trigger the binding with this `~name` instead of a chord
+    #[clap(action, long, conflicts_with_all = ["chord", "mode"])]
+    name: Option<String>,
+}
+
+#[derive(Args)]
+struct Import {
+    /// Format of the file to import
+    #[clap(action, long, value_enum)]
+    from: import::Format,
+    /// File to import
+    path: PathBuf,
+    /// Write the bindings to this file instead of printing them
+    #[clap(action, short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct Check {
+    /// Fail on any warning which isn't allowed with `.allow`, like `.strict`
+    #[clap(action, long)]
+    strict: bool,
+}
+
+#[derive(Args)]
+struct Setup {
+    /// Also add "Validate with whkd" to the context menu of whkdrc files in Explorer
+    #[clap(action, long)]
+    context_menu: bool,
+    /// Remove what `whkd setup` registered
+    #[clap(action, long, conflicts_with = "context_menu")]
+    uninstall: bool,
+}
+
+#[derive(Args)]
+struct OpenUri {
+    uri: String,
+}
+
+#[derive(Args)]
+struct Keys {
+    /// Wait for the next chord to be pressed and print how it is written in whkdrc
+    #[clap(action, long)]
+    listen: bool,
+}
+
+#[derive(Args)]
+struct Parse {
+    /// Binding as it is written in whkdrc, e.g. `alt + shift + h : komorebic resize left`
+    #[clap(required = true, num_args = 1..)]
+    binding: Vec<String>,
+}
+
+#[derive(Args)]
+struct Tag {
+    tag: String,
+}
+
+#[derive(Args)]
+struct Toggle {
+    /// `~name` of the binding
+    name: String,
+}
+
+#[derive(Args)]
+struct Watch {
+    /// Only show dispatches of bindings with this tag (can be given multiple times)
+    #[clap(action, long = "tag")]
+    tags: Vec<String>,
+}
+
+#[derive(Args)]
+struct Diagnose {
+    /// Record all events for this long (e.g. 30s, 2m) and write them to a zip file
+    #[clap(action, long, value_parser = humantime::parse_duration)]
+    record: Duration,
+    /// Path of the zip file to write
+    #[clap(action, short, long)]
+    output: Option<PathBuf>,
+    /// Skip the confirmation prompt
+    #[clap(action, short, long)]
+    yes: bool,
+}
+
+/// Run the command line of whkd, which `main` is nothing more than, so that
+/// other programs can use the parser from this library
+pub fn run() -> Result<()> {
+    color_eyre::install()?;
+    let cli = Cli::parse();
+
+    whkdrc::set_include_commands(!cli.no_include_cmd);
+
+    if let Some(subcommand) = cli.subcommand {
+        return run_subcommand(subcommand, cli.config);
+    }
+
+    crash::install_hook();
+    log::set_quiet(cli.quiet);
+    stats::set_tracing(cli.trace_latency);
+
+    let source = Source {
+        config: cli.config,
+        no_session: cli.no_session,
+        strict: cli.strict,
+    };
+
+    let (mut whkdrc, waiting) = load_initial(&source, cli.wait_for_config)?;
+    let profile = match cli.profile {
+        // the profile is checked once whkdrc has been loaded
+        Some(_) if waiting => None,
+        Some(profile) if !whkdrc.profiles.contains(&profile) => {
+            return Err(eyre!("profile {profile} is not defined in whkdrc"));
+        }
+        Some(profile) => Some(profile),
+        None => whkdrc.profiles.first().cloned(),
+    };
+
+    if cli.dry_run {
+        return dry_run(&whkdrc, profile.as_ref());
+    }
+
+    #[cfg(feature = "update")]
+    if whkdrc.options.check_updates {
+        update::check_in_background();
+    }
+    #[cfg(not(feature = "update"))]
+    if whkdrc.options.check_updates {
+        log::error(String::from(
+            "whkd was built without update checks, install it with `--features update` to use `check_updates`",
+        ));
+    }
+
+    #[cfg(feature = "mqtt")]
+    mqtt::connect(whkdrc.mqtt.clone());
+
+    wait_for_startup(&whkdrc);
+    let executors = Executors::new(&whkdrc);
+
+    let event_loop = EventLoop::new();
+    listen(&event_loop)?;
+
+    #[cfg(feature = "metrics")]
+    if let Some(port) = cli.metrics {
+        metrics::listen(port)?;
+    }
+
+    let hotkeys_manager = set_up_backend(&mut whkdrc, &event_loop)?;
+
+    // commands are run on a dedicated thread so that a blocked shell pipe can't
+    // stall the processing of hotkey events
+    let (dispatch_sender, dispatch_receiver) = mpsc::channel();
+    let mut mode_manager = ModeManager::new(
+        &whkdrc,
+        dispatch_sender,
+        event_loop.create_proxy(),
+        hotkeys_manager,
+    )?;
+    subscribe_to_modes(&mode_manager);
+    mode_manager.activate_profile(profile);
+    print_summary(&source, &whkdrc);
+
+    let mut watching_foreground = start_watching(&whkdrc, &event_loop.create_proxy());
+    if waiting {
+        wait_for_config(&source, event_loop.create_proxy());
+    }
+
+    let dispatcher =
+        std::thread::spawn(move || run_dispatcher(whkdrc, executors, &dispatch_receiver));
+
+    event_loop.run(move |event| match event {
+        UserEvent::Message(SocketMessage::Reload) => {
+            match reload(&mut mode_manager, &source, &mut watching_foreground) {
+                Ok(()) => println!("reloaded whkdrc"),
+                Err(error) => log::error(format!("could not reload whkdrc: {error}")),
+            }
+        }
+        UserEvent::Message(message) => handle_message(&mode_manager, message),
+        UserEvent::Chord(id) => handle_chord(&mode_manager, id),
+        UserEvent::Confirmed(hotkey) => press(&mode_manager, &hotkey, Instant::now()),
+        UserEvent::Chosen { hotkey, press } => press.apply(&mode_manager, &hotkey),
+        UserEvent::Layer { mode, held } => mode_manager.hold_layer(&mode, held),
+        UserEvent::Idle(idle) => mode_manager.idle(idle),
+        UserEvent::Timeout(generation) => mode_manager.expire(generation),
+        UserEvent::RetryRegistration {
+            generation,
+            attempt,
+        } => mode_manager.retry_registrations(generation, attempt),
+        UserEvent::Foreground(app_name) => mode_manager.focus(app_name.as_deref()),
+        UserEvent::Session(event) => mode_manager.recover(event),
+        UserEvent::Shutdown => mode_manager.shut_down(),
+    })?;
+
+    let _ = dispatcher.join();
+    shutdown::finish();
+    Ok(())
+}
+
+/// Load whkdrc as whkd starts, or an empty one to wait for whkdrc with if it
+/// can't be loaded yet and `--wait-for-config` is given
+fn load_initial(source: &Source, wait_for_config: bool) -> Result<(Whkdrc, bool)> {
+    if wait_for_config && source.config.as_deref().is_some_and(whkdrc::is_stdin) {
+        return Err(eyre!("--wait-for-config can't wait for whkdrc from stdin"));
+    }
+
+    let (whkdrc, waiting) = match source.load() {
+        Ok(whkdrc) => (whkdrc, false),
+        Err(error) if wait_for_config => {
+            log::error(format!("could not load whkdrc, waiting for it: {error:#}"));
+            (Whkdrc::empty(), true)
+        }
+        Err(error) => return Err(error),
+    };
+    configure_output(&whkdrc);
+
+    Ok((whkdrc, waiting))
+}
+
+/// Create the manager of registered hotkeys, or install the keyboard hook for
+/// `.backend hook`
+fn set_up_backend(
+    whkdrc: &mut Whkdrc,
+    event_loop: &EventLoop<UserEvent>,
+) -> Result<Option<Arc<GlobalHotKeyManager>>> {
+    // the hook stands in for registered hotkeys when they can't be used at
+    // all, so that whkd started at logon doesn't die before it gets to them
+    let hotkeys_manager = match whkdrc.backend {
+        Backend::Register => match create_hotkeys_manager() {
+            Ok(manager) => Some(Arc::new(manager)),
+            Err(error) => {
+                log::error(format!("{error}, falling back to `.backend hook`"));
+                whkdrc.backend = Backend::Hook;
+                None
+            }
+        },
+        Backend::Hook => None,
+    };
+
+    check_hook_only(whkdrc)?;
+    if whkdrc.backend == Backend::Hook {
+        hook::install(event_loop.create_proxy(), whkdrc)?;
+    }
+
+    Ok(hotkeys_manager)
+}
+
+/// Let the rest of whkd know whenever the mode changes
+fn subscribe_to_modes(mode_manager: &ModeManager) {
+    mode_manager.subscribe(|from, to| {
+        ipc::broadcast(&Event::ModeChange {
+            from: from.cloned(),
+            to: to.cloned(),
+        });
+    });
+    mode_manager.subscribe(|_, to| crash::set_mode(to));
+    mode_manager.subscribe(|from, to| {
+        if from != to {
+            sound::mode_changed();
+            indicator::show(to);
+        }
+    });
+    #[cfg(feature = "metrics")]
+    mode_manager.subscribe(|_, _| metrics::record_mode_switch());
+}
+
+fn handle_chord(mode_manager: &ModeManager, id: u32) {
+    let pressed = Instant::now();
+    let Some(candidates) = mode_manager.registered.lock().get(&id).cloned() else {
+        log::error(format!("received an event for unknown hotkey {id}"));
+        return;
+    };
+
+    mode_manager.press_chosen(candidates, Press::Chord(pressed));
+}
+
+fn press_chord(mode_manager: &ModeManager, hotkey: &HkmData, pressed: Instant) {
+    if let Some(app_name) = mode_manager.ignored_app() {
+        log::event(format!(
+            "passing {} through to {app_name}",
+            hotkey.keys.join(" + ")
+        ));
+        mode_manager.pass_through(hotkey);
+        return;
+    }
+
+    press(mode_manager, hotkey, pressed);
+
+    if hotkey.passthrough {
+        mode_manager.pass_through(hotkey);
+    }
+}
+
+/// How long `.wait_for_process` waits for its processes before whkd registers
+/// the bindings anyway
+const PROCESS_WAIT_LIMIT: Duration = Duration::from_secs(90);
+
+/// How often the processes of `.wait_for_process` are looked for
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reject the directives which only the keyboard hook can apply
+fn check_hook_only(whkdrc: &Whkdrc) -> Result<()> {
+    if whkdrc.backend == Backend::Hook {
+        return Ok(());
+    }
+
+    if !whkdrc.suppress.is_empty() {
+        return Err(eyre!("`.suppress` can only be used with `.backend hook`"));
+    }
+
+    if whkdrc.sticky_modifiers.is_some() {
+        return Err(eyre!(
+            "`.sticky_modifiers` can only be used with `.backend hook`"
+        ));
+    }
+
+    if !whkdrc.layers.is_empty() {
+        return Err(eyre!("`.layer` can only be used with `.backend hook`"));
+    }
+
+    Ok(())
+}
+
+/// Hold off registering the bindings for `.startup_delay` and until the
+/// processes of `.wait_for_process` are running, e.g. right after logon
+fn wait_for_startup(whkdrc: &Whkdrc) {
+    if let Some(delay) = whkdrc.startup_delay {
+        println!(
+            "waiting {} before registering the bindings",
+            humantime::format_duration(delay)
+        );
+        std::thread::sleep(delay);
+    }
+
+    let started = Instant::now();
+    for image in &whkdrc.wait_for_processes {
+        if process::is_running(image) {
+            continue;
+        }
+
+        println!("waiting for {image} to start before registering the bindings");
+        while !process::is_running(image) {
+            if started.elapsed() >= PROCESS_WAIT_LIMIT {
+                log::error(format!(
+                    "{image} has not started within {}, registering the bindings anyway",
+                    humantime::format_duration(PROCESS_WAIT_LIMIT)
+                ));
+                return;
+            }
+
+            std::thread::sleep(PROCESS_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Take what whkdrc changes about the output, and the options which the
+/// executors check, once it has been loaded
+fn configure_output(whkdrc: &Whkdrc) {
+    log::set_verbose(whkdrc.options.verbose);
+    log::set_echo(whkdrc.echo);
+    sound::set_mode_sound(whkdrc.mode_sound.clone());
+    indicator::configure(whkdrc);
+    redact::configure(whkdrc);
+    job::set_kill_commands(whkdrc.options.kill_commands);
+    dnd::set_respect(whkdrc.options.respect_dnd);
+}
+
+fn handle_message(mode_manager: &ModeManager, message: SocketMessage) {
+    match message {
+        SocketMessage::EnableTag(tag) => mode_manager.perform(&InternalAction::EnableTag(tag)),
+        SocketMessage::DisableTag(tag) => mode_manager.perform(&InternalAction::DisableTag(tag)),
+        SocketMessage::ToggleBinding(name) => {
+            mode_manager.perform(&InternalAction::ToggleBinding(name));
+        }
+        SocketMessage::Trigger { mode, keys } => trigger(mode_manager, mode, &keys),
+        SocketMessage::TriggerName(name) => trigger_name(mode_manager, &name),
+        // a mode without bindings would leave nothing to switch back with
+        SocketMessage::Mode(mode) if !mode_manager.modes.contains(&mode) => {
+            log::error(format!(
+                "there is no mode {}",
+                mode.as_deref().unwrap_or("default")
+            ));
+        }
+        SocketMessage::Mode(mode) => mode_manager.perform(&InternalAction::Mode(mode)),
+        SocketMessage::Watch(_)
+        | SocketMessage::Stats
+        | SocketMessage::History
+        | SocketMessage::Failures
+        | SocketMessage::Reload => {}
+    }
+}
+
+/// Where whkdrc is loaded from and the flags which change it, kept to load it
+/// again for `whkd reload`
+#[derive(Clone)]
+struct Source {
+    config: Option<PathBuf>,
+    no_session: bool,
+    strict: bool,
+}
+
+impl Source {
+    fn layers(&self) -> Result<Vec<PathBuf>> {
+        // an explicit `--config` is loaded on its own
+        self.config
+            .as_ref()
+            .map_or_else(Whkdrc::layers, |config| Ok(vec![config.clone()]))
+    }
+
+    fn load(&self) -> Result<Whkdrc> {
+        let layers = self.layers()?;
+        let mut whkdrc = Whkdrc::load_layers(&layers)?;
+        editor::set_config(
+            layers
+                .last()
+                .filter(|path| !whkdrc::is_stdin(path))
+                .cloned(),
+        );
+
+        if self.no_session {
+            whkdrc.exec = Exec::Direct;
+        }
+
+        whkdrc.lints.strict |= self.strict;
+        lint::run(&whkdrc)?;
+
+        Ok(whkdrc)
+    }
+}
+
+/// Forward chords, socket messages, session notifications and requests to shut
+/// down to the event loop
+fn listen(event_loop: &EventLoop<UserEvent>) -> Result<()> {
+    // registered chords are reported to the hidden window of global_hotkey, from
+    // where they are forwarded like the chords captured by the hook
+    let proxy = event_loop.create_proxy();
+    GlobalHotKeyEvent::set_event_handler(Some(move |event: GlobalHotKeyEvent| {
+        if event.state() == HotKeyState::Pressed {
+            let _ = proxy.send_event(UserEvent::Chord(event.id));
+        }
+    }));
+
+    ipc::listen(event_loop.create_proxy())?;
+
+    if let Err(error) = notifications::listen(event_loop.create_proxy()) {
+        log::error(error.to_string());
+    }
+
+    shutdown::install(event_loop.create_proxy())
+}
+
+/// Load whkdrc again and swap the bindings of the mode manager for the new ones,
+/// keeping the old ones if it can't be loaded
+fn reload(
+    mode_manager: &mut ModeManager,
+    source: &Source,
+    watching_foreground: &mut bool,
+) -> Result<()> {
+    if source.config.as_deref().is_some_and(whkdrc::is_stdin) {
+        return Err(eyre!("whkdrc was read from stdin"));
+    }
+
+    let mut whkdrc = source.load()?;
+
+    // the hook can be installed later on, but not removed again
+    if mode_manager.backend == Backend::Hook && whkdrc.backend == Backend::Register {
+        println!("whkd has to be restarted to stop using `.backend hook`");
+        whkdrc.backend = Backend::Hook;
+    }
+
+    check_hook_only(&whkdrc)?;
+
+    let profile = mode_manager
+        .profile
+        .lock()
+        .clone()
+        .filter(|profile| whkdrc.profiles.contains(profile))
+        .or_else(|| whkdrc.profiles.first().cloned());
+
+    let reloaded = mode_manager.reloaded(&whkdrc)?;
+    match (mode_manager.backend, whkdrc.backend) {
+        (Backend::Register, Backend::Hook) => hook::install(mode_manager.proxy.clone(), &whkdrc)?,
+        (Backend::Hook, Backend::Hook) => hook::configure(&whkdrc)?,
+        (_, Backend::Register) => {}
+    }
+
+    mode_manager.unregister_all();
+    if let Some(path) = &mode_manager.state_file {
+        if whkdrc.state_file.as_ref() != Some(path) {
+            state::remove(path);
+        }
+    }
+
+    *mode_manager = reloaded;
+    configure_output(&whkdrc);
+    #[cfg(feature = "mqtt")]
+    mqtt::connect(whkdrc.mqtt.clone());
+    mode_manager.activate_profile(profile);
+
+    if !*watching_foreground && watches_foreground(&whkdrc) {
+        watch_foreground(mode_manager.proxy.clone());
+        *watching_foreground = true;
+    }
+
+    if !whkdrc.on_idle.is_empty() {
+        idle::watch(mode_manager.proxy.clone());
+    }
+
+    mode_manager.dispatch(Dispatch::Reload(Box::new(whkdrc)));
+
+    Ok(())
+}
+
+fn run_subcommand(subcommand: SubCommand, config: Option<PathBuf>) -> Result<()> {
+    match subcommand {
+        SubCommand::Migrate => match config {
+            Some(config) if whkdrc::is_stdin(&config) => Err(eyre!(
+                "whkdrc can't be migrated in place when it is read from stdin"
+            )),
+            Some(config) => migrate::run(&config),
+            None => migrate::run(&Whkdrc::default_path()?),
+        },
+
+        SubCommand::Watch(args) => ipc::watch(TagFilter(args.tags)),
+        SubCommand::Diagnose(args) => diagnose::record(args.record, args.output, args.yes),
+        SubCommand::Doctor => {
+            doctor::run(config);
+            Ok(())
+        }
+        SubCommand::Check(args) => check(config, args.strict),
+        SubCommand::Setup(args) if args.uninstall => setup::uninstall(),
+        SubCommand::Setup(args) => setup::install(args.context_menu),
+        SubCommand::OpenUri(args) => setup::open_uri(&args.uri),
+        SubCommand::Init => init::run(config.as_deref()),
+        #[cfg(feature = "update")]
+        SubCommand::CheckUpdate => update::check(),
+        #[cfg(not(feature = "update"))]
+        SubCommand::CheckUpdate => Err(eyre!(
+            "whkd was built without update checks, install it with `--features update` to use `check-update`"
+        )),
+        SubCommand::Import(args) => import::run(args.from, &args.path, args.output),
+        SubCommand::Enable(args) => ipc::send(&SocketMessage::EnableTag(args.tag)).map(drop),
+        SubCommand::Disable(args) => ipc::send(&SocketMessage::DisableTag(args.tag)).map(drop),
+        SubCommand::Toggle(args) => ipc::send(&SocketMessage::ToggleBinding(args.name)).map(drop),
+        SubCommand::Stats(args) => stats::show(args.json),
+        SubCommand::History(args) => history::show(args.json),
+        SubCommand::Failures(args) => failures::show(args.json),
+        SubCommand::CrashReport => crash::show(),
+        SubCommand::Reload => ipc::send(&SocketMessage::Reload).map(drop),
+        SubCommand::Keys(args) if args.listen => keys::listen(),
+        SubCommand::Keys(_) => {
+            keys::list();
+            Ok(())
+        }
+        SubCommand::Parse(args) => parse(&args.binding.join(" ")),
+        SubCommand::Trigger(Trigger {
+            name: Some(name), ..
+        }) => ipc::send(&SocketMessage::TriggerName(name)).map(drop),
+        SubCommand::Trigger(args) => ipc::send(&SocketMessage::Trigger {
+            mode: args.mode,
+            keys: args
+                .chord
+                .join(" ")
+                .split('+')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+        .map(drop),
+    }
+}
+
+/// Print where whkdrc was loaded from and how many bindings it has once whkd
+/// has started, along with the number of chords which couldn't be registered
+fn print_summary(source: &Source, whkdrc: &Whkdrc) {
+    let layers = source.layers().unwrap_or_default();
+    for line in summary(&layers, whkdrc, failures::snapshot().len()) {
+        println!("{line}");
+    }
+}
+
+fn summary(layers: &[PathBuf], whkdrc: &Whkdrc, failures: usize) -> Vec<String> {
+    // modes in the order in which they first appear in whkdrc
+    let mut modes: Vec<(Option<&String>, usize)> = vec![];
+    for binding in &whkdrc.bindings {
+        match modes
+            .iter_mut()
+            .find(|(mode, _)| *mode == binding.mode.as_ref())
+        {
+            Some((_, count)) => *count += 1,
+            None => modes.push((binding.mode.as_ref(), 1)),
+        }
+    }
+
+    let config = layers
+        .iter()
+        .map(|path| whkdrc::source(path))
+        .collect::<Vec<_>>();
+    let bindings = modes
+        .iter()
+        .map(|(mode, count)| format!("{count} in {}", mode.map_or("default", String::as_str)))
+        .collect::<Vec<_>>();
+    let shells = std::iter::once(whkdrc.shell.to_string())
+        .chain(
+            whkdrc
+                .mode_shells
+                .iter()
+                .map(|(mode, shell)| format!("{shell} in {mode}")),
+        )
+        .collect::<Vec<_>>();
+    let app_bindings = whkdrc
+        .app_bindings
+        .iter()
+        .map(|(_, bindings)| bindings.len())
+        .sum::<usize>();
+
+    vec![
+        format!("whkd {} is running", env!("CARGO_PKG_VERSION")),
+        format!("config:       {}", config.join(", ")),
+        format!("shell:        {}", shells.join(", ")),
+        format!(
+            "bindings:     {}",
+            if bindings.is_empty() {
+                String::from("none")
+            } else {
+                bindings.join(", ")
+            }
+        ),
+        format!("app bindings: {app_bindings}"),
+        if failures == 0 {
+            String::from("failures:     none")
+        } else {
+            format!("failures:     {failures}, see `whkd failures`")
+        },
+    ]
+}
+
+/// Print the command every chord would run right now, resolving app-specific
+/// bindings against the foreground window, without registering anything
+fn dry_run(whkdrc: &Whkdrc, profile: Option<&String>) -> Result<()> {
+    let app_name = active_win_pos_rs::get_active_window()
+        .map(|window| window.app_name)
+        .ok();
+
+    println!(
+        "Resolving bindings for the foreground app {}",
+        app_name.as_deref().unwrap_or("(unknown)")
+    );
+
+    // chords in the order in which they first appear in whkdrc
+    let mut chords: Vec<(Option<String>, u32, Vec<HkmData>)> = vec![];
+    let inherited = whkdrc.inherited_bindings()?;
+    whkdrc.macros()?;
+    for binding in whkdrc
+        .app_bindings
+        .iter()
+        .flat_map(|(_, bindings)| bindings)
+        .chain(&whkdrc.bindings)
+        .chain(&inherited)
+        .filter(|binding| hook::Chord::parse(&binding.keys).is_some())
+        .filter(|binding| binding.profile.is_none() || binding.profile.as_ref() == profile)
+    {
+        let data = HkmData::try_from(binding)?;
+        let id = HotKey::new(data.mod_keys, data.vkey).id();
+
+        match chords
+            .iter_mut()
+            .find(|(mode, chord_id, _)| *mode == data.mode && *chord_id == id)
+        {
+            Some((_, _, candidates)) => candidates.push(data),
+            None => chords.push((data.mode.clone(), id, vec![data])),
+        }
+    }
+
+    for (mode, _, candidates) in &chords {
+        let keys = candidates[0].keys.join(" + ");
+        let mode = mode.as_deref().unwrap_or("default");
+
+        let resolved = resolve(candidates, app_name.as_deref(), |data| {
+            data.conditions
+                .iter()
+                .all(|condition| condition.holds(whkdrc.shell))
+        });
+
+        match resolved {
+            Some(data) => {
+                print!("{mode} > {keys}");
+                if let Some(command) = &data.command {
+                    print!(" : {command}");
+                }
+
+                match &data.internal_action {
+                    Some(
+                        action @ (InternalAction::Cycle(_)
+                        | InternalAction::Toggle { .. }
+                        | InternalAction::Chain(_)),
+                    ) => print!(" :: {action}"),
+                    Some(action) => print!(" ; {action}"),
+                    None => {}
+                }
+
+                if let Some(process_name) = &data.process_name {
+                    print!(" (for {process_name})");
+                }
+
+                if let Some(description) = &data.description {
+                    print!(" ## {description}");
+                }
+
+                println!();
+            }
+            None => println!("{mode} > {keys} does nothing"),
+        }
+    }
+
+    for (mode, keys) in whkdrc.kept_free() {
+        println!(
+            "{} > {} is kept free",
+            mode.map_or("default", String::as_str),
+            keys.join(" + ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Print every field of a single binding as the parser understands it, with its
+/// keys resolved to the key code and modifiers which get registered
+fn parse(binding: &str) -> Result<()> {
+    let bindings = parser::parse_binding(binding).map_err(|errors| {
+        let errors = errors
+            .iter()
+            .map(|(column, error)| format!("column {column}: {error}"))
+            .collect::<Vec<_>>();
+
+        eyre!("could not parse the binding\n{}", errors.join("\n"))
+    })?;
+
+    if bindings.is_empty() {
+        println!("there is no binding on this line");
+    }
+
+    for binding in &bindings {
+        let Some((trigger, mods)) = binding.keys.split_last() else {
+            continue;
+        };
+
+        println!(
+            "mode:        {}",
+            binding.mode.as_deref().unwrap_or("default")
+        );
+        println!("keys:        {}", binding.keys.join(" + "));
+        if let Some(name) = &binding.name {
+            println!("name:        {name}");
+        }
+        if let Some(description) = &binding.description {
+            println!("description: {description}");
+        }
+        match key_code_from_string(trigger) {
+            Some(code) => println!("key code:    {code:?}"),
+            None => println!("key code:    unknown key {trigger}, see `whkd keys`"),
+        }
+        println!(
+            "modifiers:   {:?}",
+            mods.iter()
+                .filter_map(|m| modifier_from_string(m))
+                .fold(Modifiers::empty(), |acc, m| acc | m)
+        );
+        println!(
+            "command:     {}",
+            binding.command.as_deref().unwrap_or("none")
+        );
+        println!(
+            "action:      {}",
+            binding
+                .internal_action
+                .as_ref()
+                .map_or_else(|| String::from("none"), ToString::to_string)
+        );
+        println!(
+            "app:         {}",
+            binding.process_name.as_deref().unwrap_or("any")
+        );
+        println!("tags:        {}", binding.tags.join(", "));
+        println!("conditions:  {:?}", binding.conditions);
+        println!("passthrough: {}", binding.passthrough);
+        if binding.allow_recursion {
+            println!("recursion:   allowed");
+        }
+        if binding.respect_dnd {
+            println!("dnd:         respected");
+        }
+        if let Some(notify) = &binding.notify {
+            println!("notify:      {notify}");
+        }
+        if let Some(sound) = &binding.sound {
+            println!("sound:       {}", sound.display());
+        }
+        if let Some(confirm) = &binding.confirm {
+            println!("confirm:     {confirm}");
+        }
+        println!("timeout:     {:?}", binding.timeout);
+        println!("cooldown:    {:?}", binding.cooldown);
+        println!("wait:        {}", binding.wait);
+        if let Some(on_fail) = &binding.on_fail {
+            println!("on fail:     {on_fail}");
+        }
+        if let Some(on_error) = &binding.on_error {
+            println!("on error:    {on_error}");
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Load whkdrc the way whkd does when it starts, which prints the warnings
+/// about it, without registering any bindings
+fn check(config: Option<PathBuf>, strict: bool) -> Result<()> {
+    let source = Source {
+        config,
+        no_session: false,
+        strict,
+    };
+
+    let whkdrc = source.load()?;
+    let warnings = lint::check(&whkdrc)
+        .into_iter()
+        .filter(|(found, _)| whkdrc.lints.level(*found) == lint::Level::Warn)
+        .count();
+
+    match warnings {
+        0 => println!("whkdrc has no problems"),
+        warnings => println!("whkdrc has {warnings} warning(s)"),
+    }
+
+    Ok(())
+}
+
+/// Handle a binding the same way whether its chord was pressed or it was
+/// triggered through `whkd trigger`
+fn press(mode_manager: &ModeManager, hotkey: &HkmData, pressed: Instant) {
+    // the binding comes back without the question once it has been answered
+    if let Some(question) = &hotkey.confirm {
+        confirm::ask(hotkey, question, mode_manager.proxy.clone());
+        return;
+    }
+
+    if cooling_down(hotkey) {
+        log::event(format!(
+            "ignoring {}, it is cooling down",
+            hotkey.keys.join(" + ")
+        ));
+        return;
+    }
+
+    if hotkey.respect_dnd && dnd::is_on() {
+        log::event(format!(
+            "ignoring {}, do not disturb is on",
+            hotkey.keys.join(" + ")
+        ));
+        return;
+    }
+
+    stats::record_invocation(hotkey);
+
+    if let Some(message) = &hotkey.notify {
+        osd::show(message);
+    }
+
+    if let Some(sound) = &hotkey.sound {
+        sound::play(sound);
+    }
+
+    let count = match hotkey.internal_action {
+        Some(InternalAction::Count(_)) => 0,
+        _ => mode_manager.count.lock().take().unwrap_or(1),
+    };
+
+    let send = |hotkey: HkmData, command: String| {
+        mode_manager.dispatch(Dispatch::Command {
+            hotkey: Box::new(hotkey),
+            command,
+            count,
+            pressed,
+            resolved: Instant::now(),
+        });
+    };
+
+    if let Some(command) = &hotkey.command {
+        send(hotkey.clone(), command.clone());
+    }
+
+    match &hotkey.internal_action {
+        Some(InternalAction::RepeatLast) => match history::last() {
+            Some((last, command)) => send(last, command),
+            None => log::event("there is no command to repeat yet"),
+        },
+        // the dispatcher advances them once it is sure to run their command
+        Some(InternalAction::Cycle(commands)) => mode_manager.dispatch(Dispatch::Cycle {
+            hotkey: Box::new(hotkey.clone()),
+            commands: commands.clone(),
+            count,
+            pressed,
+        }),
+        Some(InternalAction::Toggle { on, off, probe }) => {
+            mode_manager.dispatch(Dispatch::Toggle {
+                hotkey: Box::new(hotkey.clone()),
+                on: on.clone(),
+                off: off.clone(),
+                probe: probe.clone(),
+                count,
+                pressed,
+            });
+        }
+        Some(InternalAction::Chain(steps)) => mode_manager.run_steps(hotkey, steps.clone(), count),
+        Some(InternalAction::RunMacro(name)) => mode_manager.run_macro(hotkey, name, count),
+        Some(InternalAction::EditConfig) => editor::edit_config(mode_manager.proxy.clone()),
+        Some(InternalAction::Help) => {
+            let mode = mode_manager.mode.lock().clone();
+            help::show(mode.as_ref(), &mode_manager.enabled_bindings(mode.as_ref()));
+        }
+        Some(InternalAction::Trigger { mode, keys }) => {
+            trigger(mode_manager, mode.clone(), keys);
+        }
+        Some(InternalAction::TriggerName(name)) => trigger_name(mode_manager, name),
+        Some(InternalAction::Shell(shell)) => mode_manager.dispatch(Dispatch::Shell(*shell)),
+        Some(action) if action.is_native() => perform_native(action),
+        Some(action) => mode_manager.perform(action),
+        None => {}
+    }
+
+    mode_manager.arm_timeout(hotkey.timeout);
+}
+
+/// Press the binding of a chord in a mode, or in the active mode if none is given
+fn trigger(mode_manager: &ModeManager, mode: Option<String>, keys: &[String]) {
+    let mode = match mode {
+        Some(mode) if mode == "default" => None,
+        Some(mode) => Some(mode),
+        None => mode_manager.mode(),
+    };
+
+    let candidates = mode_manager.candidates_in(mode.as_ref(), keys);
+    if candidates.is_empty() {
+        log::error(format!(
+            "could not trigger {}, it is not bound in mode {}",
+            keys.join(" + "),
+            mode.as_deref().unwrap_or("default")
+        ));
+        return;
+    }
+
+    mode_manager.press_chosen(
+        candidates,
+        Press::Trigger {
+            shown: keys.join(" + "),
+            depth: *TRIGGER_DEPTH.lock(),
+        },
+    );
+}
+
+/// Press the binding with a `~name`, in the active mode if it is bound there
+fn trigger_name(mode_manager: &ModeManager, name: &str) {
+    let candidates = mode_manager.candidates_named(name);
+    if candidates.is_empty() {
+        log::error(format!(
+            "could not trigger {name}, no enabled binding has that name"
+        ));
+        return;
+    }
+
+    mode_manager.press_chosen(
+        candidates,
+        Press::Trigger {
+            shown: name.to_string(),
+            depth: *TRIGGER_DEPTH.lock(),
+        },
+    );
+}
+
+/// Press a triggered binding, `depth` being how many bindings deep it was
+/// triggered, which is carried along when it was chosen away from the event loop
+fn press_triggered(mode_manager: &ModeManager, hotkey: &HkmData, shown: &str, depth: usize) {
+    if depth >= MAX_TRIGGER_DEPTH {
+        log::error(format!(
+            "not triggering {shown}, bindings trigger each other more than {MAX_TRIGGER_DEPTH} deep"
+        ));
+        return;
+    }
+
+    let outer = std::mem::replace(&mut *TRIGGER_DEPTH.lock(), depth + 1);
+    press(mode_manager, hotkey, Instant::now());
+    *TRIGGER_DEPTH.lock() = outer;
+}
+
+/// Perform one of the actions written with `@`, which don't need the mode manager
+fn perform_native(action: &InternalAction) {
+    match action {
+        InternalAction::ClipboardSet(_)
+        | InternalAction::ClipboardPaste
+        | InternalAction::ClipboardClear => clipboard::perform(action.clone()),
+        InternalAction::FocusOrLaunch(target) => focus::focus_or_launch(target),
+        InternalAction::Terminal(profile) => focus::terminal(profile),
+        #[cfg(feature = "mqtt")]
+        InternalAction::Mqtt { topic, payload } => mqtt::publish(topic, payload),
+        #[cfg(not(feature = "mqtt"))]
+        InternalAction::Mqtt { .. } => log::error(String::from(
+            "whkd was built without MQTT, install it with `--features mqtt` to use `@mqtt`",
+        )),
+        InternalAction::Window(action) => window::perform(*action),
+        InternalAction::Display(action) => display::perform(*action),
+        InternalAction::Volume(_) | InternalAction::ToggleMute => audio::perform(action.clone()),
+        InternalAction::Brightness(step) => brightness::change(*step),
+        _ => {}
+    }
+}
+
+/// Whether a binding with a `~cooldown` fired too recently, marking it as fired
+/// now otherwise
+fn cooling_down(hotkey: &HkmData) -> bool {
+    let Some(cooldown) = hotkey.cooldown else {
+        return false;
+    };
+
+    let now = Instant::now();
+    let mut last_fired = LAST_FIRED.lock();
+    if let Some(fired) = last_fired.get(hotkey) {
+        if now.duration_since(*fired) < cooldown {
+            return true;
+        }
+    }
+
+    last_fired.insert(hotkey.clone(), now);
+    drop(last_fired);
+
+    false
+}
+
+/// Advance the position of a cycle binding, returning the position before
+fn next_in_cycle(hotkey: &HkmData, len: usize) -> usize {
+    let mut cycles = CYCLES.lock();
+    let position = cycles.entry(hotkey.clone()).or_insert(0);
+    let current = *position % len;
+    *position = (current + 1) % len;
+    drop(cycles);
+
+    current
+}
+
+/// Flip the state of a toggle binding, starting from the probed state if there
+/// is one, and return whether it is now on
+fn flip_toggle(hotkey: &HkmData, probed: Option<bool>) -> bool {
+    let mut toggles = TOGGLES.lock();
+    let state = toggles.entry(hotkey.clone()).or_insert(false);
+    let on = !probed.unwrap_or(*state);
+    *state = on;
+    drop(toggles);
+
+    on
+}
+
+/// How often the foreground window is checked for `.app_mode` and `.ignore`
+const FOREGROUND_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Check whkdrc until it can be parsed and reload it then, e.g. once it has
+/// been synced by a dotfile manager after logon
+fn wait_for_config(source: &Source, proxy: EventLoopProxy<UserEvent>) {
+    let source = source.clone();
+    std::thread::spawn(move || {
+        let mut last_error = None;
+
+        loop {
+            std::thread::sleep(CONFIG_POLL_INTERVAL);
+
+            match source
+                .layers()
+                .and_then(|layers| Whkdrc::load_layers(&layers))
+            {
+                Ok(_) => {
+                    println!("whkdrc can be loaded now");
+                    let _ = proxy.send_event(UserEvent::Message(SocketMessage::Reload));
+                    break;
+                }
+                // only printed when it changes, e.g. from a missing file to
+                // one which can't be parsed
+                Err(error) => {
+                    let error = format!("{error:#}");
+                    if last_error.as_ref() != Some(&error) {
+                        println!("still waiting for whkdrc: {error}");
+                        last_error = Some(error);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Start watching the foreground app and the time without input if whkdrc
+/// depends on them, returning whether the foreground app is watched
+fn start_watching(whkdrc: &Whkdrc, proxy: &EventLoopProxy<UserEvent>) -> bool {
+    if !whkdrc.on_idle.is_empty() {
+        idle::watch(proxy.clone());
+    }
+
+    let watching_foreground = watches_foreground(whkdrc);
+    if watching_foreground {
+        watch_foreground(proxy.clone());
+    }
+
+    watching_foreground
+}
+
+/// Whether whkdrc has directives which depend on the foreground app
+const fn watches_foreground(whkdrc: &Whkdrc) -> bool {
+    !whkdrc.app_modes.is_empty() || (whkdrc.options.unregister_ignored && !whkdrc.ignore.is_empty())
+}
+
+/// Send an event whenever the process of the foreground window changes
+fn watch_foreground(proxy: EventLoopProxy<UserEvent>) {
+    std::thread::spawn(move || {
+        let mut focused = None;
+
+        loop {
+            let app_name = active_win_pos_rs::get_active_window()
+                .map(|window| window.app_name)
+                .ok();
+
+            if app_name != focused {
+                foreground::invalidate();
+                focused.clone_from(&app_name);
+                if proxy.send_event(UserEvent::Foreground(app_name)).is_err() {
+                    break;
+                }
+            }
+
+            std::thread::sleep(FOREGROUND_POLL_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::whkdrc::Shell;
+    use chumsky::Parser as _;
+
+    fn candidates(src: &str) -> Vec<HkmData> {
+        let whkdrc = parser::parser().parse(src).unwrap();
+        whkdrc
+            .app_bindings
+            .iter()
+            .flat_map(|(_, bindings)| bindings)
+            .chain(&whkdrc.bindings)
+            .map(|binding| HkmData::try_from(binding).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_summary() {
+        let whkdrc = parser::parser()
+            .parse(
+                r#"
+.shell pwsh
+
+alt + n [
+    Firefox : echo "hello firefox"
+    Code : echo "hello code"
+]
+
+alt + h : komorebic focus left
+alt + r ; resize
+resize > h : komorebic resize-axis horizontal decrease
+"#,
+            )
+            .unwrap();
+
+        let summary = summary(&[PathBuf::from("whkdrc")], &whkdrc, 1);
+        assert_eq!(summary[1], "config:       whkdrc");
+        assert_eq!(summary[2], "shell:        pwsh");
+        assert_eq!(summary[3], "bindings:     2 in default, 1 in resize");
+        assert_eq!(summary[4], "app bindings: 2");
+        assert_eq!(summary[5], "failures:     1, see `whkd failures`");
+    }
+
+    #[test]
+    fn test_resolve_app_and_global_bindings() {
+        let candidates = candidates(
+            r#"
+.shell pwsh
+
+alt + n [
+    Firefox : echo "hello firefox"
+]
+
+alt + n : echo "hello everyone else"
+"#,
+        );
+
+        let firefox = resolve(&candidates, Some("Firefox"), |_| true).unwrap();
+        assert_eq!(firefox.command.as_deref(), Some(r#"echo "hello firefox""#));
+
+        let other = resolve(&candidates, Some("Code"), |_| true).unwrap();
+        assert_eq!(
+            other.command.as_deref(),
+            Some(r#"echo "hello everyone else""#)
+        );
+
+        let unknown = resolve(&candidates, None, |_| true).unwrap();
+        assert_eq!(
+            unknown.command.as_deref(),
+            Some(r#"echo "hello everyone else""#)
+        );
+    }
+
+    #[test]
+    fn test_resolve_without_global_fallback() {
+        let candidates = candidates(
+            r#"
+.shell pwsh
+
+alt + n [
+    Firefox : echo "hello firefox"
+]
+
+alt + h : echo "unrelated"
+"#,
+        );
+
+        let candidates = candidates
+            .into_iter()
+            .filter(|data| data.keys == ["alt", "n"])
+            .collect::<Vec<_>>();
+
+        assert!(resolve(&candidates, Some("Code"), |_| true).is_none());
+        assert!(resolve(&candidates, Some("Firefox"), |_| true).is_some());
+    }
+
+    #[test]
+    fn test_binding_id() {
+        let candidates = candidates(
+            r#"
+.shell pwsh
+
+alt + n [
+    Firefox : echo "hello firefox"
+]
+
+alt + n : echo "hello everyone else"
+Alt + N : echo "hello again"
+window > alt + n : echo "hello window"
+"#,
+        );
+
+        let ids = candidates
+            .iter()
+            .map(HkmData::binding_id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids[0].len(), 16);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ids[1], ids[2]);
+        assert_ne!(ids[1], ids[3]);
+    }
+
+    #[test]
+    fn test_hotkey_from_keys() {
+        let candidates = candidates(
+            r#"
+.shell pwsh
+
+alt + shift + h : echo "hello"
+F11 : echo "fullscreen"
+"#,
+        );
+
+        for data in &candidates {
+            let hotkey = hotkey_from_keys(&data.keys).unwrap();
+            assert_eq!(hotkey.id(), HotKey::new(data.mod_keys, data.vkey).id());
+        }
+
+        assert!(hotkey_from_keys(&[String::from("alt"), String::from("nokey")]).is_none());
+        assert!(hotkey_from_keys(&[String::from("ctlr"), String::from("a")]).is_none());
+
+        let shouted = ["ALT", "Shift", "ARROWLEFT"].map(String::from);
+        assert_eq!(
+            hotkey_from_keys(&shouted).unwrap().id(),
+            HotKey::new(Some(Modifiers::ALT | Modifiers::SHIFT), Code::ArrowLeft).id()
+        );
+    }
+
+    #[test]
+    fn test_resolve_conditional_bindings() {
+        let candidates = candidates(
+            r#"
+.shell pwsh
+
+alt + n ~when("'work' == 'home'") : echo "home"
+alt + n ~when("'work' != 'home'") : echo "work"
+alt + n : echo "fallback"
+"#,
+        );
+
+        let holds = |data: &HkmData| {
+            data.conditions
+                .iter()
+                .all(|condition| condition.holds(Shell::Pwsh))
+        };
+
+        let work = resolve(&candidates, None, holds).unwrap();
+        assert_eq!(work.command.as_deref(), Some(r#"echo "work""#));
+
+        let fallback = resolve(&candidates, None, |data| data.conditions.is_empty()).unwrap();
+        assert_eq!(fallback.command.as_deref(), Some(r#"echo "fallback""#));
+    }
+}
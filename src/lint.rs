@@ -0,0 +1,500 @@
+use crate::chord_id;
+use crate::hook;
+use crate::parser::HotkeyBinding;
+use crate::parser::InternalAction;
+use crate::whkdrc::Exec;
+use crate::whkdrc::Whkdrc;
+use crate::whkdrc::CURRENT_VERSION;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+/// Above this many bindings in a single mode (or in total) we start warning that
+/// Windows may refuse further hotkey registrations
+const RECOMMENDED_MAX_BINDINGS: usize = 512;
+
+/// A check of whkdrc whose findings are warnings unless `.allow`, `.deny` or
+/// `.strict` change its level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// A key which whkd doesn't know, whose binding is left out
+    UnknownKey,
+    /// A chord bound more than once in a mode, where only the first binding is used
+    DuplicateChord,
+    /// A mode which no binding switches to
+    UnreachableMode,
+    /// A mode which is switched to but has no bindings
+    UnknownMode,
+    /// A mode which can be switched to but not out of
+    TrappingMode,
+    /// An `.alias` defined again, which replaces the earlier definition
+    AliasShadowing,
+    /// More bindings than Windows can be relied on to register
+    TooManyBindings,
+    /// A version of the syntax which `whkd migrate` can upgrade
+    OutdatedSyntax,
+    /// A directive which has no effect next to the others
+    IgnoredDirective,
+}
+
+const LINTS: [(Lint, &str); 9] = [
+    (Lint::UnknownKey, "unknown_key"),
+    (Lint::DuplicateChord, "duplicate_chord"),
+    (Lint::UnreachableMode, "unreachable_mode"),
+    (Lint::UnknownMode, "unknown_mode"),
+    (Lint::TrappingMode, "trapping_mode"),
+    (Lint::AliasShadowing, "alias_shadowing"),
+    (Lint::TooManyBindings, "too_many_bindings"),
+    (Lint::OutdatedSyntax, "outdated_syntax"),
+    (Lint::IgnoredDirective, "ignored_directive"),
+];
+
+impl Lint {
+    pub fn from_name(name: &str) -> Option<Self> {
+        LINTS
+            .iter()
+            .find(|(_, lint_name)| *lint_name == name)
+            .map(|(lint, _)| *lint)
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = LINTS
+            .iter()
+            .find(|(lint, _)| lint == self)
+            .map_or("unknown", |(_, name)| name);
+
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Lints {
+    /// Deny every lint which isn't allowed with `.allow`
+    pub strict: bool,
+    /// Levels set with `.allow` and `.deny`, where the last one of a lint wins
+    pub levels: Vec<(Lint, Level)>,
+}
+
+impl Lints {
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels
+            .iter()
+            .rev()
+            .find(|(set, _)| *set == lint)
+            .map_or(
+                if self.strict {
+                    Level::Deny
+                } else {
+                    Level::Warn
+                },
+                |(_, level)| *level,
+            )
+    }
+}
+
+/// Print the findings of every lint at its level, failing if any are denied
+pub fn run(whkdrc: &Whkdrc) -> Result<()> {
+    let mut denied = 0;
+    for (lint, message) in check(whkdrc) {
+        match whkdrc.lints.level(lint) {
+            Level::Allow => {}
+            Level::Warn => println!("Warning: {message} [{lint}]"),
+            Level::Deny => {
+                println!("Error: {message} [{lint}]");
+                denied += 1;
+            }
+        }
+    }
+
+    if denied > 0 {
+        return Err(eyre!(
+            "whkdrc has {denied} denied warnings, fix them or use `.allow` to let them through"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The findings of every lint, regardless of their level
+pub fn check(whkdrc: &Whkdrc) -> Vec<(Lint, String)> {
+    let mut findings = vec![];
+
+    if whkdrc.version < CURRENT_VERSION {
+        findings.push((
+            Lint::OutdatedSyntax,
+            format!(
+                "whkdrc uses version {} of the syntax, run `whkd migrate` to upgrade it to version {CURRENT_VERSION}",
+                whkdrc.version
+            ),
+        ));
+    }
+
+    if whkdrc.exec == Exec::Direct && !whkdrc.shell_init.is_empty() {
+        findings.push((
+            Lint::IgnoredDirective,
+            String::from(".shell_init is ignored when every command is spawned as its own process"),
+        ));
+    }
+
+    for alias in &whkdrc.shadowed_aliases {
+        findings.push((
+            Lint::AliasShadowing,
+            format!("alias {alias} is defined more than once, only the last definition is used"),
+        ));
+    }
+
+    let own = whkdrc
+        .app_bindings
+        .iter()
+        .flat_map(|(_, bindings)| bindings)
+        .chain(&whkdrc.bindings)
+        .collect::<Vec<_>>();
+
+    for binding in &own {
+        if hook::Chord::parse(&binding.keys).is_none() {
+            findings.push((
+                Lint::UnknownKey,
+                format!(
+                    "{} on line {} has a key which whkd doesn't know, see `whkd keys`",
+                    binding.keys.join(" + "),
+                    binding.line
+                ),
+            ));
+        }
+    }
+
+    check_modes(whkdrc, &own, &mut findings);
+    findings
+}
+
+/// Duplicate chords and the number of bindings of every mode, including those
+/// which it inherits, and whether it can be reached
+fn check_modes(whkdrc: &Whkdrc, own: &[&HotkeyBinding], findings: &mut Vec<(Lint, String)>) {
+    // errors of the hierarchy are reported when the modes are flattened
+    let inherited = whkdrc.inherited_bindings().unwrap_or_default();
+    let mut modes: Vec<(&Option<String>, Vec<&HotkeyBinding>)> = vec![];
+    for binding in own.iter().copied().chain(&inherited) {
+        match modes.iter_mut().find(|(mode, _)| **mode == binding.mode) {
+            Some((_, bindings)) => bindings.push(binding),
+            None => modes.push((&binding.mode, vec![binding])),
+        }
+    }
+
+    for (mode, bindings) in &modes {
+        let mode_name = mode.as_deref().unwrap_or("default");
+
+        let mut seen = HashSet::new();
+        for binding in bindings {
+            let Some(id) = hook::Chord::parse(&binding.keys)
+                .and_then(|_| chord_id(whkdrc.backend, &binding.keys))
+            else {
+                continue;
+            };
+
+            if !seen.insert((
+                id,
+                &binding.process_name,
+                &binding.profile,
+                &binding.conditions,
+            )) {
+                findings.push((
+                    Lint::DuplicateChord,
+                    format!(
+                        "{} is bound more than once{} in mode {mode_name}, only the first binding will be used",
+                        binding.keys.join(" + "),
+                        binding
+                            .process_name
+                            .as_ref()
+                            .map(|process| format!(" for {process}"))
+                            .unwrap_or_default(),
+                    ),
+                ));
+            }
+        }
+
+        if bindings.len() > RECOMMENDED_MAX_BINDINGS {
+            findings.push((
+                Lint::TooManyBindings,
+                format!(
+                    "mode {mode_name} has {} bindings, registrations may start to fail above {RECOMMENDED_MAX_BINDINGS}",
+                    bindings.len()
+                ),
+            ));
+        }
+    }
+
+    let total = modes
+        .iter()
+        .map(|(_, bindings)| bindings.len())
+        .sum::<usize>();
+    if total > RECOMMENDED_MAX_BINDINGS {
+        findings.push((
+            Lint::TooManyBindings,
+            format!(
+                "whkdrc has {total} bindings across all modes, registrations may start to fail above {RECOMMENDED_MAX_BINDINGS}"
+            ),
+        ));
+    }
+
+    let names = modes
+        .iter()
+        .filter_map(|(mode, _)| mode.as_ref())
+        .collect::<Vec<_>>();
+
+    check_reachable(whkdrc, own, names.iter().copied(), findings);
+    check_targets(whkdrc, own, &names, findings);
+    check_escapes(whkdrc, own, &modes, findings);
+}
+
+fn check_reachable<'a>(
+    whkdrc: &Whkdrc,
+    own: &[&HotkeyBinding],
+    modes: impl Iterator<Item = &'a String>,
+    findings: &mut Vec<(Lint, String)>,
+) {
+    // cycling through the modes reaches every one of them
+    let cycles = own.iter().any(|binding| {
+        matches!(
+            binding.internal_action,
+            Some(InternalAction::NextMode | InternalAction::PrevMode)
+        )
+    });
+
+    if !cycles {
+        let targets = own
+            .iter()
+            .filter_map(|binding| match &binding.internal_action {
+                Some(InternalAction::Mode(Some(mode))) => Some(mode),
+                _ => None,
+            })
+            .chain(whkdrc.app_modes.iter().map(|(_, mode)| mode))
+            .chain(whkdrc.layers.iter().map(|(_, mode)| mode))
+            .collect::<HashSet<_>>();
+
+        for mode in modes {
+            if !targets.contains(mode) {
+                findings.push((
+                    Lint::UnreachableMode,
+                    format!("mode {mode} can't be reached, no binding switches to it"),
+                ));
+            }
+        }
+    }
+}
+
+/// Bindings, `.app_mode` and `.layer` switching to modes which have no bindings
+fn check_targets(
+    whkdrc: &Whkdrc,
+    own: &[&HotkeyBinding],
+    modes: &[&String],
+    findings: &mut Vec<(Lint, String)>,
+) {
+    for binding in own {
+        if let Some(InternalAction::Mode(Some(mode))) = &binding.internal_action {
+            if !modes.contains(&mode) {
+                findings.push((
+                    Lint::UnknownMode,
+                    format!(
+                        "{} on line {} switches to mode {mode}, which has no bindings",
+                        binding.keys.join(" + "),
+                        binding.line
+                    ),
+                ));
+            }
+        }
+    }
+
+    for (process, mode) in &whkdrc.app_modes {
+        if !modes.contains(&mode) {
+            findings.push((
+                Lint::UnknownMode,
+                format!(".app_mode {process} switches to mode {mode}, which has no bindings"),
+            ));
+        }
+    }
+
+    for (key, mode) in &whkdrc.layers {
+        if !modes.contains(&mode) {
+            findings.push((
+                Lint::UnknownMode,
+                format!(".layer {key} switches to mode {mode}, which has no bindings"),
+            ));
+        }
+    }
+}
+
+/// Modes which bindings switch to without a way back, neither a binding of
+/// the mode switching out of it nor a timeout, which would keep the bindings of
+/// every other mode out of reach until whkd is restarted
+fn check_escapes(
+    whkdrc: &Whkdrc,
+    own: &[&HotkeyBinding],
+    modes: &[(&Option<String>, Vec<&HotkeyBinding>)],
+    findings: &mut Vec<(Lint, String)>,
+) {
+    if whkdrc.timeout.is_some() {
+        return;
+    }
+
+    for (mode, bindings) in modes {
+        let Some(name) = mode else {
+            continue;
+        };
+
+        // modes of `.app_mode` and `.layer` are left once the app loses focus
+        // or the key is released
+        if whkdrc
+            .app_modes
+            .iter()
+            .chain(&whkdrc.layers)
+            .any(|(_, target)| target == name)
+        {
+            continue;
+        }
+
+        let mut entering = own
+            .iter()
+            .filter(|binding| {
+                binding.internal_action == Some(InternalAction::Mode(Some(name.clone())))
+            })
+            .peekable();
+
+        if entering.peek().is_none() || entering.any(|binding| binding.timeout.is_some()) {
+            continue;
+        }
+
+        let leaves = bindings
+            .iter()
+            .any(|binding| match &binding.internal_action {
+                Some(InternalAction::Mode(target)) => target != *mode,
+                Some(
+                    InternalAction::ModeBack
+                    | InternalAction::NextMode
+                    | InternalAction::PrevMode
+                    | InternalAction::Profile(_),
+                ) => true,
+                _ => false,
+            });
+
+        if !leaves {
+            findings.push((
+                Lint::TrappingMode,
+                format!(
+                    "mode {name} has no binding which switches out of it and no timeout, \
+                     whkd would be stuck in it once it is active"
+                ),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_check() {
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh
+.version 3
+.alias focus komorebic focus
+.alias focus komorebic focus-monitor
+
+alt + h : focus left
+alt + h : focus right
+alt + nope : echo unknown
+stray > h : echo stray
+alt + w ; window
+window > escape ; default",
+            )
+            .unwrap();
+
+        let lints = check(&whkdrc)
+            .into_iter()
+            .map(|(lint, _)| lint)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            lints,
+            vec![
+                Lint::AliasShadowing,
+                Lint::UnknownKey,
+                Lint::DuplicateChord,
+                Lint::UnreachableMode,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_modes() {
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh
+.version 3
+
+alt + w ; window
+alt + r ; resize
+alt + t ~timeout(2000) ; timed
+alt + x ; missing
+window > escape ; default
+resize > h : komorebic resize-axis horizontal increase
+timed > h : echo timed",
+            )
+            .unwrap();
+
+        let findings = check(&whkdrc);
+        let lints = findings.iter().map(|(lint, _)| *lint).collect::<Vec<_>>();
+        assert_eq!(lints, vec![Lint::UnknownMode, Lint::TrappingMode]);
+        assert!(findings[0].1.contains("missing"));
+        assert!(findings[1].1.contains("resize"));
+
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh
+.version 3
+.timeout 800
+
+alt + r ; resize
+resize > h : komorebic resize-axis horizontal increase",
+            )
+            .unwrap();
+
+        assert!(check(&whkdrc).is_empty());
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut lints = Lints::default();
+        assert_eq!(lints.level(Lint::DuplicateChord), Level::Warn);
+
+        lints.strict = true;
+        lints.levels.push((Lint::DuplicateChord, Level::Allow));
+        assert_eq!(lints.level(Lint::DuplicateChord), Level::Allow);
+        assert_eq!(lints.level(Lint::UnknownKey), Level::Deny);
+
+        lints.levels.push((Lint::DuplicateChord, Level::Deny));
+        assert_eq!(lints.level(Lint::DuplicateChord), Level::Deny);
+
+        for (lint, name) in LINTS {
+            assert_eq!(Lint::from_name(name), Some(lint));
+            assert_eq!(lint.to_string(), name);
+        }
+    }
+}
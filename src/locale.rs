@@ -0,0 +1,122 @@
+/// The language of the key names in whkdrc, set with `.locale` anywhere in the
+/// file, so that configs shared in other languages can use the names printed
+/// on their keyboards, e.g. `strg + entf`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+}
+
+/// Key names of each locale and the key names of whkd which they stand for
+const GERMAN: &[(&str, &str)] = &[
+    ("strg", "ctrl"),
+    ("lstrg", "lctrl"),
+    ("rstrg", "rctrl"),
+    ("umschalt", "shift"),
+    ("umsch", "shift"),
+    ("altgr", "ralt"),
+    ("entf", "Delete"),
+    ("einfg", "Insert"),
+    ("pos1", "Home"),
+    ("ende", "End"),
+    ("bildauf", "PageUp"),
+    ("bildab", "PageDown"),
+    ("eingabe", "Enter"),
+    ("leertaste", "Space"),
+    ("druck", "PrintScreen"),
+];
+
+const FRENCH: &[(&str, &str)] = &[
+    ("maj", "shift"),
+    ("lmaj", "lshift"),
+    ("rmaj", "rshift"),
+    ("altgr", "ralt"),
+    ("suppr", "Delete"),
+    ("inser", "Insert"),
+    ("debut", "Home"),
+    ("fin", "End"),
+    ("pgprec", "PageUp"),
+    ("pgsuiv", "PageDown"),
+    ("entree", "Enter"),
+    ("echap", "Escape"),
+    ("espace", "Space"),
+    ("retour", "Backspace"),
+    ("impr", "PrintScreen"),
+];
+
+const SPANISH: &[(&str, &str)] = &[
+    ("mayus", "shift"),
+    ("lmayus", "lshift"),
+    ("rmayus", "rshift"),
+    ("altgr", "ralt"),
+    ("supr", "Delete"),
+    ("inicio", "Home"),
+    ("fin", "End"),
+    ("repag", "PageUp"),
+    ("avpag", "PageDown"),
+    ("intro", "Enter"),
+    ("espacio", "Space"),
+    ("retroceso", "Backspace"),
+    ("impr", "PrintScreen"),
+];
+
+impl Locale {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "en" => Some(Self::English),
+            "de" => Some(Self::German),
+            "fr" => Some(Self::French),
+            "es" => Some(Self::Spanish),
+            _ => None,
+        }
+    }
+
+    /// The locale set with `.locale`, which applies to the whole file
+    pub fn detect(src: &str) -> Self {
+        src.lines()
+            .filter_map(|line| line.trim().strip_prefix(".locale"))
+            .filter_map(|name| name.split_whitespace().next())
+            .filter_map(Self::from_name)
+            .next_back()
+            .unwrap_or_default()
+    }
+
+    const fn names(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::English => &[],
+            Self::German => GERMAN,
+            Self::French => FRENCH,
+            Self::Spanish => SPANISH,
+        }
+    }
+
+    /// The name whkd knows a key by, leaving names which aren't localized as
+    /// they are, so that the English names can always be used
+    pub fn translate(self, key: String) -> String {
+        self.names()
+            .iter()
+            .find(|(localized, _)| key.eq_ignore_ascii_case(localized))
+            .map_or(key, |(_, name)| (*name).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(Locale::German.translate(String::from("Strg")), "ctrl");
+        assert_eq!(Locale::German.translate(String::from("entf")), "Delete");
+        assert_eq!(Locale::German.translate(String::from("alt")), "alt");
+        assert_eq!(Locale::French.translate(String::from("maj")), "shift");
+        assert_eq!(Locale::English.translate(String::from("strg")), "strg");
+
+        assert_eq!(Locale::detect(".shell pwsh\n.locale fr\n"), Locale::French);
+        assert_eq!(Locale::detect(".locale xx"), Locale::English);
+        assert_eq!(Locale::detect("alt + h : echo"), Locale::English);
+    }
+}
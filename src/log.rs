@@ -0,0 +1,105 @@
+use crate::ipc;
+use crate::ipc::Event;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// Set by `--quiet` to leave out what is printed for every keypress
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Set by `.options verbose` to print it even when stdout isn't a console
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Cleared by `.echo off` to leave out the commands which are run
+static ECHO: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    /// Whether stdout is a console, which it isn't when whkd runs in the
+    /// background with its output redirected to a file or nowhere
+    static ref TERMINAL: bool = std::io::stdout().is_terminal();
+
+    // comma-separated list of tags, e.g. `WHKD_LOG=media,audio`
+    pub static ref LOG_FILTER: TagFilter = TagFilter::from_env("WHKD_LOG");
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagFilter(pub Vec<String>);
+
+impl TagFilter {
+    pub fn from_env(var: &str) -> Self {
+        std::env::var(var).map_or_else(|_| Self::default(), |value| Self::parse(&value))
+    }
+
+    pub fn parse(value: &str) -> Self {
+        Self(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    /// An empty filter matches everything, otherwise at least one tag must match
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.0.is_empty() || tags.iter().any(|tag| self.0.contains(tag))
+    }
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn set_echo(echo: bool) {
+    ECHO.store(echo, Ordering::Relaxed);
+}
+
+/// Whether the commands which are run are printed, unless a binding is `~silent`
+pub fn echoes() -> bool {
+    ECHO.load(Ordering::Relaxed)
+}
+
+const fn prints_events(quiet: bool, verbose: bool, terminal: bool) -> bool {
+    !quiet && (verbose || terminal)
+}
+
+/// Print what whkd does in response to a keypress or a change of the foreground
+/// window, which is left out in the background unless `.options verbose` is set
+/// and always with `--quiet`
+pub fn event(message: impl Display) {
+    if prints_events(
+        QUIET.load(Ordering::Relaxed),
+        VERBOSE.load(Ordering::Relaxed),
+        *TERMINAL,
+    ) {
+        println!("{message}");
+    }
+}
+
+/// Print an error and forward it to any connected `whkd watch` subscribers
+pub fn error(message: String) {
+    println!("Error: {message}");
+    ipc::broadcast(&Event::Error { message });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prints_events() {
+        assert!(prints_events(false, false, true));
+        assert!(!prints_events(false, false, false));
+        assert!(prints_events(false, true, false));
+        assert!(!prints_events(true, true, true));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::stats;
+use crate::stats::BindingStats;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+
+lazy_static! {
+    static ref COUNTERS: Mutex<Counters> = Mutex::new(Counters::default());
+}
+
+/// Counters which aren't kept per binding by `stats`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Counters {
+    dispatch_errors: u64,
+    shell_restarts: u64,
+    mode_switches: u64,
+}
+
+pub fn record_dispatch_error() {
+    COUNTERS.lock().dispatch_errors += 1;
+}
+
+pub fn record_shell_restarts(restarts: usize) {
+    COUNTERS.lock().shell_restarts += restarts as u64;
+}
+
+pub fn record_mode_switch() {
+    COUNTERS.lock().mode_switches += 1;
+}
+
+/// Serve the metrics in the Prometheus text format on `127.0.0.1:<port>/metrics`
+pub fn listen(port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(error) = handle_connection(stream) {
+                        println!("Error while handling metrics request: {error}");
+                    }
+                }
+                Err(error) => {
+                    println!("Error while accepting metrics connection: {error}");
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // the headers are read as well, as closing a connection with unread data
+    // resets it before the response arrives
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", render(&stats::snapshot(), *COUNTERS.lock())),
+        _ => ("404 Not Found", String::from("not found, try /metrics\n")),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+/// Escape a label value, which is written between double quotes
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn render(stats: &[BindingStats], counters: Counters) -> String {
+    let mut lines = vec![
+        String::from("# HELP whkd_presses_total Presses of the chord of each binding"),
+        String::from("# TYPE whkd_presses_total counter"),
+    ];
+
+    for binding in stats {
+        lines.push(format!(
+            "whkd_presses_total{{mode=\"{}\",keys=\"{}\",app=\"{}\"}} {}",
+            label(binding.mode.as_deref().unwrap_or("default")),
+            label(&binding.keys.join(" + ")),
+            label(binding.process_name.as_deref().unwrap_or_default()),
+            binding.invocations
+        ));
+    }
+
+    for (name, help, value) in [
+        (
+            "whkd_dispatch_errors_total",
+            "Commands which could not be run",
+            counters.dispatch_errors,
+        ),
+        (
+            "whkd_shell_restarts_total",
+            "Shell sessions started again after they had exited",
+            counters.shell_restarts,
+        ),
+        (
+            "whkd_mode_switches_total",
+            "Switches from one mode to another",
+            counters.mode_switches,
+        ),
+    ] {
+        lines.push(format!("# HELP {name} {help}"));
+        lines.push(format!("# TYPE {name} counter"));
+        lines.push(format!("{name} {value}"));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // the samples of `BindingStats` are private to the stats module
+        let mut binding = BindingStats::default();
+        binding.keys = vec![String::from("alt"), String::from("h")];
+        binding.process_name = Some(String::from("Google \"Chrome\""));
+        binding.command = Some(String::from("komorebic focus left"));
+        binding.invocations = 3;
+        binding.dispatches = 3;
+        let stats = [binding];
+
+        let metrics = render(
+            &stats,
+            Counters {
+                dispatch_errors: 1,
+                shell_restarts: 0,
+                mode_switches: 4,
+            },
+        );
+
+        assert!(metrics.contains(
+            "whkd_presses_total{mode=\"default\",keys=\"alt + h\",app=\"Google \\\"Chrome\\\"\"} 3\n"
+        ));
+        assert!(metrics.contains("whkd_dispatch_errors_total 1\n"));
+        assert!(metrics.contains("whkd_mode_switches_total 4\n"));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::parser::parser;
+use crate::whkdrc::CURRENT_VERSION;
+use chumsky::Parser;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::path::Path;
+
+/// Rewrite a whkdrc written for an older version of the syntax into the
+/// current syntax, keeping a backup of the original next to it
+pub fn run(path: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let version = detect_version(&contents)?;
+
+    if version >= CURRENT_VERSION {
+        println!("{} is already at version {CURRENT_VERSION}", path.display());
+        return Ok(());
+    }
+
+    let migrated = migrate(&contents, version);
+    parser()
+        .parse(migrated.as_str())
+        .map_err(|error| eyre!("the migrated whkdrc could not be parsed: {:?}", error))?;
+
+    let backup = path.with_extension(format!("v{version}.bak"));
+    std::fs::copy(path, &backup)?;
+    std::fs::write(path, migrated)?;
+
+    println!(
+        "Migrated {} from version {version} to {CURRENT_VERSION}, the original was saved to {}",
+        path.display(),
+        backup.display()
+    );
+
+    Ok(())
+}
+
+/// Files without a `.version` directive predate versioning and are version 1
+fn detect_version(contents: &str) -> Result<u32> {
+    contents
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(".version"))
+        .map_or(Ok(1), |version| {
+            version
+                .trim()
+                .parse()
+                .map_err(|_| eyre!("invalid .version directive: {}", version.trim()))
+        })
+}
+
+fn migrate(contents: &str, from: u32) -> String {
+    let mut lines = contents.lines().map(String::from).collect::<Vec<_>>();
+
+    if from < 2 {
+        for line in &mut lines {
+            *line = tilde_attributes(line);
+        }
+    }
+
+    // a file which picks its comments with `.comments` keeps them
+    let picks_comments = lines
+        .iter()
+        .any(|line| line.trim().starts_with(".comments"));
+
+    if from < 3 && !picks_comments {
+        let mut raw = false;
+        for line in &mut lines {
+            *line = double_hash_comments(line, &mut raw);
+        }
+    }
+
+    set_version(&mut lines);
+
+    let mut migrated = lines.join("\n");
+    if contents.ends_with('\n') {
+        migrated.push('\n');
+    }
+
+    migrated
+}
+
+/// Version 2 writes binding attributes as `~tag(...)` instead of `@tag(...)`
+fn tilde_attributes(line: &str) -> String {
+    // attributes sit between the chord and the command, so leave the command alone
+    let end = line.find([':', ';', '[']).unwrap_or(line.len());
+    let (attributes, rest) = line.split_at(end);
+
+    format!("{}{rest}", attributes.replace("@tag(", "~tag("))
+}
+
+/// Version 3 only ends a command at `##`, so a `#` which starts a comment at the
+/// end of a line is doubled, outside of the fences of raw commands, which may span
+/// many lines
+fn double_hash_comments(line: &str, raw: &mut bool) -> String {
+    if !*raw && line.trim_start().starts_with('#') {
+        return line.to_string();
+    }
+
+    let mut chars = line.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '`' if line[idx..].starts_with("```") => {
+                *raw = !*raw;
+                chars.nth(1);
+            }
+            _ if *raw => {}
+            // `\#` still keeps a `#` in the command
+            '\\' => {
+                chars.next();
+            }
+            '#' if line[idx..].starts_with("##") => break,
+            '#' => return format!("{}#{}", &line[..idx], &line[idx..]),
+            _ => {}
+        }
+    }
+
+    line.to_string()
+}
+
+fn set_version(lines: &mut Vec<String>) {
+    let directive = format!(".version {CURRENT_VERSION}");
+
+    if let Some(line) = lines
+        .iter_mut()
+        .find(|line| line.trim().starts_with(".version"))
+    {
+        *line = directive;
+    } else {
+        let shell = lines
+            .iter()
+            .position(|line| line.split_whitespace().next() == Some(".shell"));
+
+        lines.insert(shell.map_or(0, |idx| idx + 1), directive);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_v1() {
+        let src = r#".shell pwsh
+
+alt + p @tag(media) : echo "@tag(media)"
+alt + m @tag(media, audio) @tag(volume) : nircmd mutesysvolume 2
+"#;
+
+        assert_eq!(detect_version(src).unwrap(), 1);
+
+        let migrated = migrate(src, 1);
+        assert_eq!(
+            migrated,
+            r#".shell pwsh
+.version 3
+
+alt + p ~tag(media) : echo "@tag(media)"
+alt + m ~tag(media, audio) ~tag(volume) : nircmd mutesysvolume 2
+"#
+        );
+
+        assert_eq!(detect_version(&migrated).unwrap(), CURRENT_VERSION);
+        assert_eq!(parser().parse(migrated).unwrap().version, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_v2() {
+        let src = r".shell pwsh # the shell
+# focus
+.version 2
+
+alt + h : komorebic focus left # left
+alt + o : start https://example.com/\#top ## Open the docs
+alt + j : ```
+$count = 3 # retries
+``` # down
+";
+
+        let migrated = migrate(src, 2);
+        assert_eq!(
+            migrated,
+            r".shell pwsh ## the shell
+# focus
+.version 3
+
+alt + h : komorebic focus left ## left
+alt + o : start https://example.com/\#top ## Open the docs
+alt + j : ```
+$count = 3 # retries
+``` ## down
+"
+        );
+
+        let whkdrc = parser().parse(migrated).unwrap();
+        assert_eq!(
+            whkdrc.bindings[0].command.as_deref(),
+            Some("komorebic focus left")
+        );
+        assert_eq!(
+            whkdrc.bindings[1].command.as_deref(),
+            Some("start https://example.com/#top")
+        );
+        assert_eq!(
+            whkdrc.bindings[2].command.as_deref(),
+            Some("$count = 3 # retries")
+        );
+
+        // the `#` of files which pick their comments are left alone
+        let src = ".shell pwsh\n.comments hash\nalt + h : echo # hi\n";
+        assert_eq!(
+            migrate(src, 2),
+            ".shell pwsh\n.version 3\n.comments hash\nalt + h : echo # hi\n"
+        );
+    }
+}
@@ -0,0 +1,1382 @@
+use crate::chord_id;
+use crate::condition::Condition;
+use crate::conflict;
+use crate::dispatch::Dispatch;
+use crate::event_loop;
+use crate::event_loop::EventLoopProxy;
+use crate::executor::Executor;
+use crate::executor::InputExecutor;
+use crate::failures;
+use crate::failures::Failure;
+use crate::failures::Reason;
+use crate::foreground;
+use crate::hook;
+use crate::hotkey_from_keys;
+use crate::idle;
+use crate::indicator;
+use crate::input;
+use crate::ipc;
+use crate::ipc::Event;
+use crate::log;
+use crate::notifications::SessionEvent;
+use crate::parser::InternalAction;
+use crate::parser::Step;
+use crate::perform_native;
+use crate::runtime;
+use crate::state;
+use crate::stats;
+use crate::whkdrc::Backend;
+use crate::whkdrc::RemotePolicy;
+use crate::whkdrc::Shell;
+use crate::whkdrc::Whkdrc;
+use crate::HkmData;
+use crate::Press;
+use crate::UserEvent;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::GlobalHotKeyManager;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+const HOTKEYS_MANAGER_ATTEMPTS: u32 = 6;
+
+/// Creating the hotkey manager can fail transiently when whkd is started very
+/// early during logon, so retry with an exponential backoff before giving up
+pub fn create_hotkeys_manager() -> Result<GlobalHotKeyManager> {
+    let mut delay = Duration::from_millis(250);
+
+    for attempt in 1..=HOTKEYS_MANAGER_ATTEMPTS {
+        match GlobalHotKeyManager::new() {
+            Ok(manager) => return Ok(manager),
+            Err(error) => {
+                println!(
+                    "Error while creating the hotkey manager (attempt {attempt}/{HOTKEYS_MANAGER_ATTEMPTS}): {error}"
+                );
+
+                if attempt < HOTKEYS_MANAGER_ATTEMPTS {
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+            }
+        }
+    }
+
+    Err(eyre!(
+        "could not create the hotkey manager after {HOTKEYS_MANAGER_ATTEMPTS} attempts"
+    ))
+}
+
+/// How often the registration of chords which failed to register is retried
+const REGISTRATION_RETRIES: u32 = 5;
+
+/// Delay before the first retry of a failed registration, doubled for every
+/// retry after it
+const REGISTRATION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// This many failed registrations during a single mode activation are treated as
+/// a sign that a registration limit has been reached rather than as conflicts
+const MASS_FAILURE_THRESHOLD: usize = 5;
+
+fn report_registration_failures(
+    mode: Option<&String>,
+    mode_bindings: &[HkmData],
+    failures: &[usize],
+) {
+    let mode = mode.map_or("default", String::as_str);
+
+    if failures.len() < MASS_FAILURE_THRESHOLD {
+        return;
+    }
+
+    if let (Some(first), Some(last)) = (failures.first(), failures.last()) {
+        let chord = |idx: &usize| {
+            mode_bindings
+                .get(*idx)
+                .map(|data| data.keys.join(" + "))
+                .unwrap_or_default()
+        };
+
+        log::error(format!(
+            "registration failed en masse in mode {mode} for bindings #{} ({}) to #{} ({}); \
+             the Windows hotkey registration limit may have been reached, \
+             consider reducing the number of bindings in this mode or switching to `.backend hook`",
+            first + 1,
+            chord(first),
+            last + 1,
+            chord(last),
+        ));
+    }
+}
+
+/// When a chord has both app-specific and global bindings, the binding for the
+/// process of the active window wins and the global binding is the fallback.
+/// Within each of those, the first binding whose conditions hold is used.
+pub fn resolve<'a>(
+    candidates: &'a [HkmData],
+    app_name: Option<&str>,
+    holds: impl Fn(&HkmData) -> bool,
+) -> Option<&'a HkmData> {
+    app_name
+        .and_then(|app_name| {
+            candidates
+                .iter()
+                .find(|data| data.process_name.as_deref() == Some(app_name) && holds(data))
+        })
+        .or_else(|| {
+            candidates
+                .iter()
+                .find(|data| data.process_name.is_none() && holds(data))
+        })
+}
+
+/// Called with the previous and the new mode whenever the active mode changes,
+/// `None` being the default mode
+pub type ModeCallback = Box<dyn Fn(Option<&String>, Option<&String>) + Send>;
+
+#[derive(Clone)]
+pub struct ModeManager {
+    shell: Shell,
+    pub backend: Backend,
+    /// Every mode with bindings in the order they appear in whkdrc, starting
+    /// with the default mode
+    pub modes: Arc<Vec<Option<String>>>,
+    pub mode: Arc<Mutex<Option<String>>>,
+    /// `None` until the mode has changed for the first time
+    #[allow(clippy::option_option)]
+    previous_mode: Arc<Mutex<Option<Option<String>>>>,
+    pub profile: Arc<Mutex<Option<String>>>,
+    disabled_tags: Arc<Mutex<HashSet<String>>>,
+    /// The `~name` of every binding which `toggle-binding` has disabled
+    disabled_names: Arc<Mutex<HashSet<String>>>,
+    binding_map: Arc<HashMap<Option<String>, Vec<HkmData>>>,
+    hotkeys: Arc<Mutex<HashMap<HkmData, HotKey>>>,
+    pub registered: Arc<Mutex<HashMap<u32, Vec<HkmData>>>>,
+    /// Registers the chords with `.backend register`, and is `None` with
+    /// `.backend hook`, which the hotkey manager is never needed for again
+    hotkeys_manager: Option<Arc<GlobalHotKeyManager>>,
+    /// Digits typed in a mode before the chord of the next binding
+    pub count: Arc<Mutex<Option<u32>>>,
+    timeout: Option<Duration>,
+    /// Incremented on every press so that earlier timeouts are ignored
+    timeout_generation: Arc<Mutex<u64>>,
+    /// Incremented whenever the active mode is registered so that retries of
+    /// earlier registrations are abandoned
+    registration_generation: Arc<Mutex<u64>>,
+    callbacks: Arc<Mutex<Vec<ModeCallback>>>,
+    dispatcher: mpsc::Sender<Dispatch>,
+    macros: Arc<HashMap<String, Vec<Step>>>,
+    /// Process names and the mode which is active while they are focused
+    app_modes: Vec<(String, String)>,
+    /// The mode to return to once the app of an `.app_mode` loses focus
+    #[allow(clippy::option_option)]
+    app_mode_return: Arc<Mutex<Option<Option<String>>>>,
+    /// The mode to return to once the key of a `.layer` is released
+    #[allow(clippy::option_option)]
+    layer_return: Arc<Mutex<Option<Option<String>>>>,
+    /// The stand-in bindings of the `@on-idle` commands and how long there has
+    /// to be no input before they run
+    on_idle: Vec<(Duration, HkmData)>,
+    /// How long there had been no input when it was last checked
+    idle: Arc<Mutex<Duration>>,
+    ignore: Vec<String>,
+    unregister_ignored: bool,
+    /// Set while every chord is unregistered because an ignored app is focused
+    suspended: Arc<Mutex<bool>>,
+    remote_policy: RemotePolicy,
+    /// Whether the session is used over remote desktop
+    remote: Arc<Mutex<bool>>,
+    /// The mode to return to once the session is local again
+    #[allow(clippy::option_option)]
+    remote_return: Arc<Mutex<Option<Option<String>>>>,
+    /// Where the mode and pause state are written for tools which can't
+    /// connect to the whkd socket
+    pub state_file: Option<PathBuf>,
+    pub proxy: EventLoopProxy<UserEvent>,
+}
+
+/// Digits which aren't bound in a mode of `.mode <name> count` add up to a
+/// count for the next binding, e.g. `5 h` in a resize mode
+fn add_count_bindings(
+    count_modes: &[String],
+    binding_map: &mut HashMap<Option<String>, Vec<HkmData>>,
+    hotkeys: &mut HashMap<HkmData, HotKey>,
+) {
+    for mode in count_modes {
+        for digit in 0..=9 {
+            let data = HkmData::count(mode, digit);
+            let mode_bindings = binding_map.entry(Some(mode.clone())).or_default();
+            if mode_bindings.iter().any(|bound| bound.keys == data.keys) {
+                continue;
+            }
+
+            mode_bindings.push(data.clone());
+            hotkeys.insert(data.clone(), HotKey::new(None, data.vkey));
+        }
+    }
+}
+
+impl ModeManager {
+    pub fn new(
+        whkdrc: &Whkdrc,
+        dispatcher: mpsc::Sender<Dispatch>,
+        proxy: EventLoopProxy<UserEvent>,
+        hotkeys_manager: Option<Arc<GlobalHotKeyManager>>,
+    ) -> Result<Self> {
+        let backend = whkdrc.backend;
+        if backend == Backend::Register && hotkeys_manager.is_none() {
+            return Err(eyre!("`.backend register` needs the hotkey manager"));
+        }
+
+        let mut binding_map = HashMap::new();
+        let mut hotkeys = HashMap::new();
+        let mut modes = vec![None];
+
+        // the hierarchy of modes is flattened here, with the inherited bindings
+        // after those of the mode itself
+        let inherited = whkdrc.inherited_bindings()?;
+        let bindings = whkdrc
+            .app_bindings
+            .iter()
+            .flat_map(|(_, bindings)| bindings)
+            .chain(&whkdrc.bindings)
+            .chain(&inherited)
+            // reported by the unknown_key lint
+            .filter(|binding| hook::Chord::parse(&binding.keys).is_some());
+
+        for binding in bindings {
+            let data = HkmData::try_from(binding)?;
+            if backend == Backend::Register
+                && hook::Chord::parse(&data.keys).is_some_and(hook::Chord::needs_hook)
+            {
+                return Err(eyre!(
+                    "{} can only be bound with `.backend hook`",
+                    data.keys.join(" + ")
+                ));
+            }
+
+            stats::track(&data);
+            if !modes.contains(&data.mode) {
+                modes.push(data.mode.clone());
+            }
+
+            binding_map
+                .entry(data.mode.clone())
+                .or_insert_with(Vec::new)
+                .push(data.clone());
+
+            let hotkey = HotKey::new(data.mod_keys, data.vkey);
+            hotkeys.insert(data, hotkey);
+        }
+
+        add_count_bindings(&whkdrc.count_modes, &mut binding_map, &mut hotkeys);
+
+        Ok(Self {
+            shell: whkdrc.shell,
+            backend,
+            modes: Arc::new(modes),
+            mode: Arc::new(Mutex::new(None)),
+            previous_mode: Arc::new(Mutex::new(None)),
+            profile: Arc::new(Mutex::new(None)),
+            disabled_tags: Arc::new(Mutex::new(HashSet::new())),
+            disabled_names: Arc::new(Mutex::new(HashSet::new())),
+            binding_map: Arc::new(binding_map),
+            hotkeys: Arc::new(Mutex::new(hotkeys)),
+            registered: Arc::new(Mutex::new(HashMap::new())),
+            hotkeys_manager,
+            count: Arc::new(Mutex::new(None)),
+            timeout: whkdrc.timeout,
+            timeout_generation: Arc::new(Mutex::new(0)),
+            registration_generation: Arc::new(Mutex::new(0)),
+            callbacks: Arc::new(Mutex::new(vec![])),
+            dispatcher,
+            macros: Arc::new(whkdrc.macros()?),
+            app_modes: whkdrc.app_modes.clone(),
+            app_mode_return: Arc::new(Mutex::new(None)),
+            layer_return: Arc::new(Mutex::new(None)),
+            on_idle: whkdrc
+                .on_idle
+                .iter()
+                .map(|(idle, command)| (*idle, HkmData::on_idle(*idle, command)))
+                .collect(),
+            idle: Arc::new(Mutex::new(Duration::ZERO)),
+            ignore: whkdrc.ignore.clone(),
+            unregister_ignored: whkdrc.options.unregister_ignored,
+            suspended: Arc::new(Mutex::new(false)),
+            remote_policy: whkdrc.remote_session.clone(),
+            remote: Arc::new(Mutex::new(false)),
+            remote_return: Arc::new(Mutex::new(None)),
+            state_file: whkdrc.state_file.clone(),
+            proxy,
+        })
+    }
+
+    /// A mode manager for the bindings of a reloaded whkdrc, which keeps the
+    /// subscriptions, disabled tags and remote state of this one
+    pub fn reloaded(&self, whkdrc: &Whkdrc) -> Result<Self> {
+        let mut reloaded = Self::new(
+            whkdrc,
+            self.dispatcher.clone(),
+            self.proxy.clone(),
+            self.hotkeys_manager.clone(),
+        )?;
+
+        reloaded.disabled_tags = self.disabled_tags.clone();
+        reloaded.disabled_names = self.disabled_names.clone();
+        reloaded.callbacks = self.callbacks.clone();
+        reloaded.remote = self.remote.clone();
+        reloaded.idle = self.idle.clone();
+        // sharing the generations keeps the timeouts and registration retries
+        // which are still pending for this one from applying to the new one
+        reloaded.timeout_generation = self.timeout_generation.clone();
+        reloaded.registration_generation = self.registration_generation.clone();
+
+        Ok(reloaded)
+    }
+
+    /// The bindings of a chord in any mode, as if it had been pressed while
+    /// that mode was active
+    pub fn candidates_in(&self, mode: Option<&String>, keys: &[String]) -> Vec<HkmData> {
+        let Some(id) = chord_id(self.backend, keys) else {
+            return vec![];
+        };
+
+        self.enabled_bindings(mode)
+            .into_iter()
+            .filter(|data| chord_id(self.backend, &data.keys) == Some(id))
+            .collect()
+    }
+
+    /// The bindings with a `~name`, preferring the active mode over the others
+    /// as a name can be bound in several modes
+    pub fn candidates_named(&self, name: &str) -> Vec<HkmData> {
+        let active = self.mode();
+        let modes = std::iter::once(active.clone()).chain(
+            self.binding_map
+                .keys()
+                .filter(|mode| **mode != active)
+                .cloned(),
+        );
+
+        modes
+            .map(|mode| {
+                self.enabled_bindings(mode.as_ref())
+                    .into_iter()
+                    .filter(|data| data.name.as_deref() == Some(name))
+                    .collect::<Vec<_>>()
+            })
+            .find(|candidates| !candidates.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// Press the binding of a chord or trigger which `resolve` chooses from its
+    /// candidates. The `~when-cmd` conditions run a shell, so when there are
+    /// any they are checked away from the event loop and the chosen binding is
+    /// handed back as `UserEvent::Chosen`
+    pub fn press_chosen(&self, candidates: Vec<HkmData>, press: Press) {
+        let app_name = if candidates.iter().any(|data| data.process_name.is_some()) {
+            let app_name = foreground::app_name();
+            if app_name.is_none() {
+                log::error(String::from("could not query the active window"));
+            }
+
+            app_name
+        } else {
+            None
+        };
+
+        let runs_command = candidates
+            .iter()
+            .flat_map(|data| &data.conditions)
+            .any(Condition::runs_command);
+
+        let shell = self.shell;
+        let choose = move || {
+            let chosen = resolve(&candidates, app_name.as_deref(), |data| {
+                data.conditions
+                    .iter()
+                    .all(|condition| condition.holds(shell))
+            })
+            .cloned();
+
+            if chosen.is_none() {
+                log::event(format!(
+                    "not pressing {}, the conditions of none of its bindings hold",
+                    candidates[0].keys.join(" + ")
+                ));
+            }
+
+            chosen
+        };
+
+        if !runs_command {
+            if let Some(hotkey) = choose() {
+                press.apply(self, &hotkey);
+            }
+
+            return;
+        }
+
+        let proxy = self.proxy.clone();
+        runtime::spawn_blocking(move || {
+            if let Some(hotkey) = choose() {
+                let _ = proxy.send_event(UserEvent::Chosen {
+                    hotkey: Box::new(hotkey),
+                    press,
+                });
+            }
+        });
+    }
+
+    /// Swap the full set of bindings for those of `profile` (plus the bindings
+    /// shared by all profiles) and return to the default mode
+    pub fn activate_profile(&self, profile: Option<String>) {
+        // lets `~when("$env:WHKD_PROFILE == 'work'")` guards see the active profile
+        std::env::set_var("WHKD_PROFILE", profile.as_deref().unwrap_or_default());
+
+        let previous = std::mem::replace(&mut *self.profile.lock(), profile.clone());
+        ipc::broadcast(&Event::ProfileChange {
+            from: previous,
+            to: profile,
+        });
+
+        // the default mode is never refused, see `activate_mode`
+        let _ = self.activate_mode(&None);
+    }
+
+    pub fn perform(&self, action: &InternalAction) {
+        match action {
+            InternalAction::Mode(mode) => self.switch_mode(mode.as_ref()),
+            InternalAction::NextMode => self.switch_mode(self.cycle_mode(true).as_ref()),
+            InternalAction::PrevMode => self.switch_mode(self.cycle_mode(false).as_ref()),
+            InternalAction::ModeBack => {
+                let previous = self.previous_mode.lock().clone();
+                match previous {
+                    Some(mode) => self.switch_mode(mode.as_ref()),
+                    None => log::event("there is no previous mode to return to"),
+                }
+            }
+            InternalAction::Profile(profile) => {
+                log::event(format!("setting profile to {profile}"));
+                self.activate_profile(Some(profile.clone()));
+            }
+            InternalAction::EnableTag(tag) | InternalAction::DisableTag(tag) => {
+                let enabled = matches!(action, InternalAction::EnableTag(_));
+                log::event(format!(
+                    "{} bindings tagged {tag}",
+                    if enabled { "enabling" } else { "disabling" }
+                ));
+                self.set_tag_enabled(tag, enabled);
+            }
+            InternalAction::ToggleBinding(name) => self.toggle_binding(name),
+            InternalAction::Count(digit) => {
+                let mut count = self.count.lock();
+                *count = Some(
+                    count
+                        .unwrap_or_default()
+                        .saturating_mul(10)
+                        .saturating_add(*digit),
+                );
+            }
+            // dispatching is not up to the mode manager, see `press`
+            InternalAction::RepeatLast
+            | InternalAction::Cycle(_)
+            | InternalAction::Toggle { .. }
+            | InternalAction::Chain(_)
+            | InternalAction::ClipboardSet(_)
+            | InternalAction::ClipboardPaste
+            | InternalAction::ClipboardClear
+            | InternalAction::FocusOrLaunch(_)
+            | InternalAction::Terminal(_)
+            | InternalAction::Mqtt { .. }
+            | InternalAction::Window(_)
+            | InternalAction::Display(_)
+            | InternalAction::Volume(_)
+            | InternalAction::ToggleMute
+            | InternalAction::Brightness(_)
+            | InternalAction::RunMacro(_)
+            | InternalAction::Trigger { .. }
+            | InternalAction::TriggerName(_)
+            | InternalAction::EditConfig
+            | InternalAction::Help
+            | InternalAction::Shell(_) => {}
+        }
+    }
+
+    /// Return to the default mode unless another key is pressed within the
+    /// timeout of the binding which was just pressed (or `.timeout`)
+    pub fn arm_timeout(&self, timeout: Option<Duration>) {
+        let generation = {
+            let mut generation = self.timeout_generation.lock();
+            *generation += 1;
+            *generation
+        };
+
+        let Some(timeout) = timeout.or(self.timeout) else {
+            return;
+        };
+
+        if self.mode.lock().is_none() {
+            return;
+        }
+
+        let proxy = self.proxy.clone();
+        runtime::after(timeout, move || {
+            let _ = proxy.send_event(UserEvent::Timeout(generation));
+        });
+    }
+
+    pub fn expire(&self, generation: u64) {
+        if *self.timeout_generation.lock() != generation {
+            return;
+        }
+
+        if self.mode.lock().is_some() {
+            log::event("no key was pressed in time, returning to the default mode");
+            self.switch_mode(None);
+        }
+    }
+
+    /// Activate the mode of an `.app_mode` when its app is focused, and return to
+    /// the mode which was active before once no such app is focused anymore
+    pub fn focus(&self, app_name: Option<&str>) {
+        if self.unregister_ignored {
+            self.suspend_while_ignored(app_name);
+        }
+
+        let app_mode = app_name.and_then(|app_name| {
+            self.app_modes
+                .iter()
+                .find(|(process_name, _)| process_name == app_name)
+        });
+
+        let mut app_mode_return = self.app_mode_return.lock();
+        match app_mode {
+            Some((process_name, mode)) => {
+                let active = self.mode.lock().clone();
+                if active.as_ref() == Some(mode) {
+                    return;
+                }
+
+                // switching between two apps keeps the mode from before the first
+                if app_mode_return.is_none() {
+                    *app_mode_return = Some(active);
+                }
+
+                drop(app_mode_return);
+                log::event(format!("{process_name} is focused"));
+                self.switch_mode(Some(mode));
+            }
+            None => {
+                if let Some(mode) = app_mode_return.take() {
+                    drop(app_mode_return);
+                    self.switch_mode(mode.as_ref());
+                }
+            }
+        }
+    }
+
+    /// Activate the mode of a `.layer` while its key is held down, and return to
+    /// the mode which was active before once it is released
+    pub fn hold_layer(&self, mode: &String, held: bool) {
+        let mut layer_return = self.layer_return.lock();
+        if held {
+            // holding the key of another layer keeps the mode from before the first
+            if layer_return.is_none() {
+                *layer_return = Some(self.mode());
+            }
+
+            drop(layer_return);
+            self.switch_mode(Some(mode));
+        } else if let Some(previous) = layer_return.take() {
+            drop(layer_return);
+            self.switch_mode(previous.as_ref());
+        }
+    }
+
+    /// Run the `@on-idle` commands whose duration the time without input has
+    /// reached since it was last checked
+    pub fn idle(&self, idle: Duration) {
+        let previous = std::mem::replace(&mut *self.idle.lock(), idle);
+
+        for (threshold, hotkey) in &self.on_idle {
+            if !idle::crossed(*threshold, previous, idle) {
+                continue;
+            }
+
+            log::event(format!(
+                "no input for {}, running {}",
+                humantime::format_duration(*threshold),
+                hotkey.keys.join(" + ")
+            ));
+            if let Some(command) = &hotkey.command {
+                self.dispatch(Dispatch::Command {
+                    hotkey: Box::new(hotkey.clone()),
+                    command: command.clone(),
+                    count: 1,
+                    pressed: Instant::now(),
+                    resolved: Instant::now(),
+                });
+            }
+        }
+    }
+
+    /// The active mode, `None` being the default mode
+    #[must_use]
+    pub fn mode(&self) -> Option<String> {
+        self.mode.lock().clone()
+    }
+
+    /// Call `callback` on every change of the active mode, which must not
+    /// subscribe again from within the callback
+    pub fn subscribe(&self, callback: impl Fn(Option<&String>, Option<&String>) + Send + 'static) {
+        self.callbacks.lock().push(Box::new(callback));
+    }
+
+    /// Activate a mode and register its chords, `None` being the default mode
+    pub fn activate(&self, mode: Option<&String>) -> Result<()> {
+        self.activate_mode(&mode.cloned())
+    }
+
+    /// Register the active mode again and restart the shell sessions which have
+    /// exited, as either can be lost while the session is locked or asleep
+    pub fn recover(&self, event: SessionEvent) {
+        println!("{event}");
+
+        // switching modes registers the chords of the new mode anyway
+        let switched = match event {
+            SessionEvent::Connected { remote } => self.set_remote(remote),
+            SessionEvent::Unlocked | SessionEvent::Resumed => false,
+        };
+
+        if !switched {
+            println!("registering the chords of the active mode again");
+            self.unregister_all();
+            self.register_active_mode();
+        }
+
+        self.dispatch(Dispatch::Revive);
+    }
+
+    /// Unregister every chord and stop the shell sessions before the event
+    /// loop returns
+    pub fn shut_down(&self) {
+        self.unregister_all();
+        indicator::reset();
+        if let Some(path) = &self.state_file {
+            state::remove(path);
+        }
+
+        self.dispatch(Dispatch::Shutdown);
+        event_loop::quit();
+    }
+
+    /// Hand work to the dispatcher thread
+    pub fn dispatch(&self, work: Dispatch) {
+        if self.dispatcher.send(work).is_err() {
+            log::error(String::from(
+                "could not dispatch, the dispatch thread has stopped",
+            ));
+        }
+    }
+
+    /// Run the steps of a macro, see `run_steps`
+    pub fn run_macro(&self, hotkey: &HkmData, name: &str, count: u32) {
+        if let Some(steps) = self.macros.get(name).cloned() {
+            self.run_steps(hotkey, steps, count);
+        }
+    }
+
+    /// Run the steps of a `:: chain` or a macro on a thread of their own, so
+    /// that `@sleep` holds up neither the dispatcher nor the other bindings
+    pub fn run_steps(&self, hotkey: &HkmData, steps: Vec<Step>, count: u32) {
+        let dispatcher = self.dispatcher.clone();
+        let hotkey = hotkey.clone();
+        runtime::spawn_blocking(move || {
+            for step in steps {
+                match step {
+                    // commands are dispatched in order, but the next step
+                    // doesn't wait for them to finish
+                    Step::Command(command) => {
+                        let work = Dispatch::Command {
+                            hotkey: Box::new(hotkey.clone()),
+                            command,
+                            count,
+                            pressed: Instant::now(),
+                            resolved: Instant::now(),
+                        };
+
+                        if dispatcher.send(work).is_err() {
+                            log::error(String::from(
+                                "could not dispatch, the dispatch thread has stopped",
+                            ));
+                            return;
+                        }
+                    }
+                    Step::Action(action) => perform_native(&action),
+                    Step::Send(keys) => {
+                        if let Err(error) = InputExecutor.execute(&keys.join(" + "), &[]) {
+                            log::error(error.to_string());
+                        }
+                    }
+                    Step::Sleep(duration) => std::thread::sleep(duration),
+                }
+            }
+        });
+    }
+
+    /// Apply `.remote_session` when the session changes between local and remote,
+    /// returning whether the mode was switched
+    fn set_remote(&self, remote: bool) -> bool {
+        if std::mem::replace(&mut *self.remote.lock(), remote) == remote {
+            return false;
+        }
+
+        match &self.remote_policy {
+            RemotePolicy::Keep => false,
+            RemotePolicy::Pause => {
+                if remote {
+                    println!("pausing every binding until the session is local again");
+                } else {
+                    println!("resuming the bindings");
+                }
+
+                self.write_state();
+                false
+            }
+            RemotePolicy::Mode(mode) => {
+                let previous = if remote {
+                    let active = self.mode.lock().clone();
+                    *self.remote_return.lock() = Some(active);
+                    Some(Some(mode.clone()))
+                } else {
+                    self.remote_return.lock().take()
+                };
+
+                let Some(mode) = previous else {
+                    return false;
+                };
+
+                self.switch_mode(mode.as_ref());
+                true
+            }
+        }
+    }
+
+    /// Unregister every chord while an app of `.ignore` is focused so that it
+    /// receives them, and register the active mode again once it loses focus
+    fn suspend_while_ignored(&self, app_name: Option<&str>) {
+        let ignored =
+            app_name.filter(|app_name| self.ignore.iter().any(|ignored| ignored == app_name));
+
+        let mut suspended = self.suspended.lock();
+        if ignored.is_some() == *suspended {
+            return;
+        }
+
+        *suspended = ignored.is_some();
+        drop(suspended);
+
+        if let Some(app_name) = ignored {
+            log::event(format!("{app_name} is focused, unregistering every chord"));
+            self.unregister_all();
+        } else {
+            log::event("registering the chords of the active mode again");
+            self.register_active_mode();
+        }
+
+        self.write_state();
+    }
+
+    /// Write the mode and pause state to `.state_file`, if there is one
+    fn write_state(&self) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+
+        let state = state::State {
+            mode: self.mode(),
+            profile: self.profile.lock().clone(),
+            paused: self.paused(),
+        };
+
+        if let Err(error) = state::write(path, &state) {
+            log::error(format!(
+                "could not write the state file {}: {error}",
+                path.display()
+            ));
+        }
+    }
+
+    /// The app of `.ignore` which is focused, if any
+    pub fn ignored_app(&self) -> Option<String> {
+        if self.ignore.is_empty() {
+            return None;
+        }
+
+        foreground::app_name().filter(|app_name| self.ignore.contains(app_name))
+    }
+
+    fn switch_mode(&self, mode: Option<&String>) {
+        log::event(format!("setting mode to {mode:?}"));
+        if let Err(error) = self.activate(mode) {
+            log::error(format!("could not activate mode {mode:?}: {error}"));
+        }
+    }
+
+    /// The mode declared after (or before) the active one, wrapping around
+    fn cycle_mode(&self, forward: bool) -> Option<String> {
+        let mode = self.mode.lock().clone();
+        let idx = self
+            .modes
+            .iter()
+            .position(|declared| *declared == mode)
+            .unwrap_or_default();
+
+        let len = self.modes.len();
+        let next = if forward {
+            (idx + 1) % len
+        } else {
+            (idx + len - 1) % len
+        };
+
+        self.modes[next].clone()
+    }
+
+    /// Enable or disable every binding with `tag`, re-registering the bindings
+    /// of the active mode
+    fn set_tag_enabled(&self, tag: &str, enabled: bool) {
+        let changed = if enabled {
+            self.disabled_tags.lock().remove(tag)
+        } else {
+            self.disabled_tags.lock().insert(tag.to_string())
+        };
+
+        if !changed {
+            return;
+        }
+
+        ipc::broadcast(&Event::TagState {
+            tag: tag.to_string(),
+            enabled,
+        });
+
+        self.unregister_all();
+        self.register_active_mode();
+    }
+
+    /// Disable the bindings with a `~name` if they are enabled and enable them
+    /// otherwise, registering or unregistering only their own chords
+    fn toggle_binding(&self, name: &str) {
+        let named = |data: &HkmData| data.name.as_deref() == Some(name);
+        if !self.binding_map.values().flatten().any(named) {
+            log::error(format!("could not toggle {name}, no binding has that name"));
+            return;
+        }
+
+        let enabled = {
+            let mut disabled_names = self.disabled_names.lock();
+            let enabled = disabled_names.remove(name);
+            if !enabled {
+                disabled_names.insert(name.to_string());
+            }
+
+            enabled
+        };
+
+        log::event(format!(
+            "{} the binding {name}",
+            if enabled { "enabling" } else { "disabling" }
+        ));
+        ipc::broadcast(&Event::BindingState {
+            name: name.to_string(),
+            enabled,
+        });
+
+        // the bindings of other modes are left out once those are activated
+        if self.paused() {
+            return;
+        }
+
+        let mode = self.mode();
+        if enabled {
+            let bindings = self
+                .enabled_bindings(mode.as_ref())
+                .into_iter()
+                .filter(named)
+                .collect::<Vec<_>>();
+
+            for (idx, reason) in self.register_bindings(&bindings) {
+                log::error(format!(
+                    "could not register {}: {reason}",
+                    bindings[idx].keys.join(" + ")
+                ));
+            }
+        } else {
+            for data in self.binding_map.get(&mode).into_iter().flatten() {
+                if named(data) {
+                    self.unregister_binding(data);
+                }
+            }
+        }
+    }
+
+    /// Take a single binding out of the registration of its chord, which is
+    /// only released once no other binding of the active mode shares it
+    fn unregister_binding(&self, data: &HkmData) {
+        let Some(id) = chord_id(self.backend, &data.keys) else {
+            return;
+        };
+
+        let mut registered = self.registered.lock();
+        let Some(bindings) = registered.get_mut(&id) else {
+            return;
+        };
+
+        bindings.retain(|bound| bound != data);
+        if !bindings.is_empty() {
+            // the hook combines the passthrough of the bindings which are left
+            if self.backend == Backend::Hook {
+                hook::unregister(id);
+                for bound in bindings.iter() {
+                    if let Some(chord) = hook::Chord::parse(&bound.keys) {
+                        hook::register(id, chord, bound.passthrough, bound.allow_recursion);
+                    }
+                }
+            }
+
+            return;
+        }
+
+        registered.remove(&id);
+        drop(registered);
+
+        let Some(hotkey) = self.hotkeys.lock().get(data).copied() else {
+            return;
+        };
+
+        if let Err(err) = self.unregister(id, hotkey) {
+            log::error(format!(
+                "could not unregister {}: {err}",
+                data.keys.join(" + ")
+            ));
+        } else {
+            ipc::broadcast(&Event::Unregistered {
+                mode: data.mode.clone(),
+                keys: data.keys.clone(),
+            });
+        }
+    }
+
+    /// Let the chord of a `~passthrough` binding reach the foreground app by
+    /// releasing the registration and re-injecting the key while the user is
+    /// still holding the modifiers
+    pub fn pass_through(&self, data: &HkmData) {
+        // the hook lets the chord through by itself, unless another binding of
+        // the chord doesn't
+        if self.backend == Backend::Hook
+            && !chord_id(self.backend, &data.keys).is_some_and(hook::swallows)
+        {
+            return;
+        }
+
+        let Some(hotkey) = self.hotkeys.lock().get(data).copied() else {
+            return;
+        };
+
+        let Some(vk) = input::vk_from_code(data.vkey) else {
+            log::error(format!(
+                "could not pass {} through, the key can't be re-injected",
+                data.keys.join(" + ")
+            ));
+            return;
+        };
+
+        // keys passed through by whkd are ignored by the hook
+        if self.backend == Backend::Hook {
+            if !input::send_key(vk) {
+                log::error(format!(
+                    "could not pass {} through, the input was blocked",
+                    data.keys.join(" + ")
+                ));
+            }
+
+            return;
+        }
+
+        if let Err(err) = self.hotkeys_manager().unregister(hotkey) {
+            log::error(format!(
+                "could not unregister {} to pass it through: {err}",
+                data.keys.join(" + ")
+            ));
+            return;
+        }
+
+        if !input::send_key(vk) {
+            log::error(format!(
+                "could not pass {} through, the input was blocked",
+                data.keys.join(" + ")
+            ));
+        }
+
+        if let Err(err) = self.hotkeys_manager().register(hotkey) {
+            log::error(format!(
+                "could not register {} again after passing it through: {err}",
+                data.keys.join(" + ")
+            ));
+        }
+    }
+
+    fn activate_mode(&self, mode: &Option<String>) -> Result<()> {
+        // chords bound in both modes stay registered, so that a chord which is
+        // held down across the switch isn't lost in between
+        self.unregister_except(&self.chord_ids(mode.as_ref()));
+        *self.count.lock() = None;
+
+        let previous = std::mem::replace(&mut *self.mode.lock(), mode.clone());
+        let failures = self.register_active_mode();
+
+        // in a partly registered mode the chords which failed would reach the
+        // foreground app instead, so the previous mode is restored. The default
+        // mode is the exception, as another app holding one of its chords
+        // mustn't keep `esc ; default` from getting out of a mode, and its
+        // failures are left to the retries instead
+        if failures > 0 && previous != *mode && mode.is_some() {
+            self.unregister_except(&self.chord_ids(previous.as_ref()));
+            self.mode.lock().clone_from(&previous);
+            self.register_active_mode();
+
+            return Err(eyre!(
+                "{failures} of its chords could not be registered, staying in mode {}",
+                previous.as_deref().unwrap_or("default")
+            ));
+        }
+
+        if previous != *mode {
+            *self.previous_mode.lock() = Some(previous.clone());
+        }
+
+        for callback in self.callbacks.lock().iter() {
+            callback(previous.as_ref(), mode.as_ref());
+        }
+
+        self.write_state();
+        Ok(())
+    }
+
+    /// The hotkey manager of `.backend register`, which `new` makes sure of
+    fn hotkeys_manager(&self) -> &GlobalHotKeyManager {
+        self.hotkeys_manager
+            .as_deref()
+            .expect("`.backend register` always has a hotkey manager")
+    }
+
+    /// Start reacting to the chord of a binding with the configured backend
+    fn register(&self, id: u32, data: &HkmData, hotkey: HotKey) -> Result<(), Reason> {
+        match self.backend {
+            Backend::Register => self
+                .hotkeys_manager()
+                .register(hotkey)
+                .map_err(Reason::from),
+            Backend::Hook => {
+                let chord = hook::Chord::parse(&data.keys).ok_or(Reason::InvalidKey)?;
+                hook::register(id, chord, data.passthrough, data.allow_recursion);
+                Ok(())
+            }
+        }
+    }
+
+    fn unregister(&self, id: u32, hotkey: HotKey) -> Result<(), String> {
+        match self.backend {
+            Backend::Register => self
+                .hotkeys_manager()
+                .unregister(hotkey)
+                .map_err(|err| err.to_string()),
+            Backend::Hook => {
+                hook::unregister(id);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unregister_all(&self) {
+        self.unregister_except(&HashSet::new());
+    }
+
+    /// Unregister every chord other than those in `keep`, whose registrations
+    /// are left in place for the bindings of the next mode to be added to
+    fn unregister_except(&self, keep: &HashSet<u32>) {
+        let hotkeys = &self.hotkeys.lock();
+        let mut registered = self.registered.lock();
+
+        let mut dropped = vec![];
+        registered.retain(|id, bindings| {
+            if !keep.contains(id) {
+                dropped.push((*id, std::mem::take(bindings)));
+                return false;
+            }
+
+            bindings.clear();
+
+            // the hook combines the passthrough of every binding of a chord
+            if self.backend == Backend::Hook {
+                hook::unregister(*id);
+            }
+
+            true
+        });
+
+        drop(registered);
+
+        // app-specific bindings share a single registration for their chord
+        for (id, data, hotkey) in dropped
+            .into_iter()
+            .filter_map(|(id, bindings)| bindings.into_iter().next().map(|data| (id, data)))
+            .filter_map(|(id, data)| hotkeys.get(&data).map(|hotkey| (id, data, *hotkey)))
+        {
+            if let Err(err) = self.unregister(id, hotkey) {
+                log::error(format!(
+                    "could not unregister {}: {err}",
+                    data.keys.join(" + ")
+                ));
+            } else {
+                ipc::broadcast(&Event::Unregistered {
+                    mode: data.mode,
+                    keys: data.keys,
+                });
+            }
+        }
+    }
+
+    /// The bindings of `mode` which belong to the active profile and haven't
+    /// been disabled by their tag or name
+    pub fn enabled_bindings(&self, mode: Option<&String>) -> Vec<HkmData> {
+        let profile = self.profile.lock().clone();
+        let disabled_tags = self.disabled_tags.lock().clone();
+        let disabled_names = self.disabled_names.lock().clone();
+
+        self.binding_map
+            .get(&mode.cloned())
+            .into_iter()
+            .flatten()
+            .filter(|data| data.profile.is_none() || data.profile == profile)
+            .filter(|data| !data.tags.iter().any(|tag| disabled_tags.contains(tag)))
+            .filter(|data| {
+                !data
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| disabled_names.contains(name))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Whether no chord is registered until the ignored app loses focus or the
+    /// session is local again
+    fn paused(&self) -> bool {
+        *self.suspended.lock() || (self.remote_policy == RemotePolicy::Pause && *self.remote.lock())
+    }
+
+    /// The chords which `register_active_mode` would register for `mode`
+    fn chord_ids(&self, mode: Option<&String>) -> HashSet<u32> {
+        if self.paused() {
+            return HashSet::new();
+        }
+
+        let hotkeys = self.hotkeys.lock();
+        self.enabled_bindings(mode)
+            .iter()
+            .filter(|data| hotkeys.contains_key(data))
+            .filter_map(|data| chord_id(self.backend, &data.keys))
+            .collect()
+    }
+
+    /// Register the chords of the active mode, reporting those which fail and
+    /// retrying them with `.backend register`, and return how many bindings
+    /// could not be registered
+    fn register_active_mode(&self) -> usize {
+        if self.paused() {
+            return 0;
+        }
+
+        let mode = self.mode.lock().clone();
+        let mode_bindings = self.enabled_bindings(mode.as_ref());
+        let failures = self.register_bindings(&mode_bindings);
+
+        let generation = {
+            let mut generation = self.registration_generation.lock();
+            *generation += 1;
+            *generation
+        };
+
+        failures::report(
+            mode.as_ref(),
+            mode_bindings.len(),
+            failures
+                .iter()
+                .map(|(idx, reason)| {
+                    self.advise(Failure::new(&mode_bindings[*idx], reason.clone()))
+                })
+                .collect(),
+        );
+
+        if !failures.is_empty() {
+            let failures = failures.iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
+            report_registration_failures(mode.as_ref(), &mode_bindings, &failures);
+
+            // another app may only hold the chord for a moment, e.g. right after logon
+            if self.backend == Backend::Register {
+                self.schedule_registration_retry(generation, 1);
+            }
+        }
+
+        failures.len()
+    }
+
+    /// Add who likely holds a chord which another app has registered and the
+    /// nearest chord which is free instead
+    fn advise(&self, mut failure: Failure) -> Failure {
+        if failure.reason != Reason::AlreadyRegistered {
+            return failure;
+        }
+
+        let bound = self
+            .binding_map
+            .values()
+            .flatten()
+            .filter_map(|data| chord_id(self.backend, &data.keys))
+            .collect::<HashSet<_>>();
+
+        failure.owner = conflict::owner(&failure.keys);
+        failure.suggestion = conflict::suggest(&failure.keys, |keys| {
+            let Some(hotkey) = hotkey_from_keys(keys) else {
+                return false;
+            };
+
+            if bound.contains(&hotkey.id()) || self.hotkeys_manager().register(hotkey).is_err() {
+                return false;
+            }
+
+            let _ = self.hotkeys_manager().unregister(hotkey);
+            true
+        });
+
+        failure
+    }
+
+    /// Register the chords of `bindings`, returning the index of every binding
+    /// which could not be registered along with the reason
+    fn register_bindings(&self, bindings: &[HkmData]) -> Vec<(usize, Reason)> {
+        // the indices are those of `bindings`, which the failures are reported for
+        let hotkeys = self.hotkeys.lock();
+        let chords = bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, h)| hotkeys.get(h).map(|hotkey| (idx, h, *hotkey)))
+            .collect::<Vec<_>>();
+        drop(hotkeys);
+
+        let mut registered = self.registered.lock();
+        let mut failures = vec![];
+        let mut failed_ids: HashMap<u32, Reason> = HashMap::new();
+
+        for (idx, data, hotkey) in chords {
+            let Some(id) = chord_id(self.backend, &data.keys) else {
+                failures.push((idx, Reason::InvalidKey));
+                continue;
+            };
+
+            if let Some(existing) = registered.get_mut(&id) {
+                existing.push(data.clone());
+
+                // the hook only lets a chord through if all of its bindings do
+                if let (Backend::Hook, Some(chord)) = (self.backend, hook::Chord::parse(&data.keys))
+                {
+                    hook::register(id, chord, data.passthrough, data.allow_recursion);
+                }
+
+                continue;
+            }
+
+            if let Some(err) = failed_ids.get(&id) {
+                failures.push((idx, err.clone()));
+                continue;
+            }
+
+            if let Err(err) = self.register(id, data, hotkey) {
+                failures.push((idx, err.clone()));
+                failed_ids.insert(id, err);
+            } else {
+                registered.insert(id, vec![data.clone()]);
+                ipc::broadcast(&Event::Registered {
+                    mode: data.mode.clone(),
+                    keys: data.keys.clone(),
+                });
+            }
+        }
+        drop(registered);
+
+        failures
+    }
+
+    fn schedule_registration_retry(&self, generation: u64, attempt: u32) {
+        let delay = REGISTRATION_RETRY_DELAY * 2_u32.pow(attempt - 1);
+        let proxy = self.proxy.clone();
+        runtime::after(delay, move || {
+            let _ = proxy.send_event(UserEvent::RetryRegistration {
+                generation,
+                attempt,
+            });
+        });
+    }
+
+    /// Try to register the chords of the active mode which failed to register
+    /// again, unless the bindings have been re-registered since
+    pub fn retry_registrations(&self, generation: u64, attempt: u32) {
+        if *self.registration_generation.lock() != generation {
+            return;
+        }
+
+        let mode = self.mode.lock().clone();
+        let pending = {
+            let registered = self.registered.lock();
+            self.enabled_bindings(mode.as_ref())
+                .into_iter()
+                .filter(|data| {
+                    chord_id(self.backend, &data.keys)
+                        .is_some_and(|id| !registered.contains_key(&id))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let failures = self.register_bindings(&pending);
+        let mut logged = HashSet::new();
+        for (idx, data) in pending.iter().enumerate() {
+            let keys = data.keys.join(" + ");
+            if !logged.insert(keys.clone()) {
+                continue;
+            }
+
+            match failures.iter().find(|(failed, _)| *failed == idx) {
+                None => {
+                    println!("registered {keys} on retry {attempt}");
+                    failures::resolve(data);
+                }
+                Some((_, reason)) if attempt == REGISTRATION_RETRIES => log::error(format!(
+                    "giving up on registering {keys} after {attempt} retries: {reason}"
+                )),
+                Some(_) => {}
+            }
+        }
+
+        if !failures.is_empty() && attempt < REGISTRATION_RETRIES {
+            self.schedule_registration_retry(generation, attempt + 1);
+        }
+    }
+}
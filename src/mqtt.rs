@@ -0,0 +1,237 @@
+use crate::log;
+use crate::whkdrc::MqttBroker;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long the broker keeps the connection open without hearing from whkd,
+/// which pings it twice as often
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// How long connecting to the broker may take before a message is given up on
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    /// Hands messages to the thread which owns the connection to the broker
+    static ref CLIENT: Mutex<Option<mpsc::Sender<(String, String)>>> = Mutex::new(None);
+}
+
+/// Connect to the broker of `.mqtt` on a thread of its own, which stays
+/// connected and connects again whenever a message can't be published. The
+/// client of a previous whkdrc disconnects once it is replaced
+pub fn connect(broker: Option<MqttBroker>) {
+    let Some(broker) = broker else {
+        *CLIENT.lock() = None;
+        return;
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    *CLIENT.lock() = Some(sender);
+
+    std::thread::spawn(move || run(&broker, &receiver));
+}
+
+pub fn publish(topic: &str, payload: &str) {
+    let sent = CLIENT.lock().as_ref().is_some_and(|client| {
+        client
+            .send((topic.to_string(), payload.to_string()))
+            .is_ok()
+    });
+
+    if !sent {
+        log::error(format!(
+            "could not publish to {topic}, there is no `.mqtt broker` in whkdrc"
+        ));
+    }
+}
+
+fn run(broker: &MqttBroker, receiver: &mpsc::Receiver<(String, String)>) {
+    let mut connection = open(broker)
+        .map_err(|error| log::error(format!("could not connect to {}: {error}", broker.address)))
+        .ok();
+
+    loop {
+        match receiver.recv_timeout(KEEP_ALIVE / 2) {
+            Ok((topic, payload)) => {
+                let packet = publish_packet(&topic, payload.as_bytes());
+
+                // a connection which was dropped by the broker only fails once
+                // it is written to, so the message is sent again on a new one
+                let sent = connection
+                    .as_mut()
+                    .is_some_and(|stream| send(stream, &packet).is_ok());
+
+                if !sent {
+                    connection = open(broker)
+                        .and_then(|mut stream| {
+                            send(&mut stream, &packet)?;
+                            Ok(stream)
+                        })
+                        .map_err(|error| {
+                            log::error(format!("could not publish to {topic}: {error}"));
+                        })
+                        .ok();
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Some(stream) = &mut connection {
+                    if send(stream, &PINGREQ).is_err() {
+                        connection = None;
+                    }
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn open(broker: &MqttBroker) -> Result<TcpStream> {
+    let address = std::net::ToSocketAddrs::to_socket_addrs(&broker.address)?
+        .next()
+        .ok_or_else(|| eyre!("{} has no address", broker.address))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+    let client_id = format!("whkd-{}", std::process::id());
+    stream.write_all(&connect_packet(&client_id, broker.login.as_ref()))?;
+
+    let mut connack = [0; 4];
+    stream.read_exact(&mut connack)?;
+    match connack {
+        [0x20, 0x02, _, 0] => Ok(stream),
+        [0x20, 0x02, _, 4 | 5] => Err(eyre!("the broker refused the username or password")),
+        [0x20, 0x02, _, code] => Err(eyre!("the broker refused the connection with code {code}")),
+        _ => Err(eyre!("the broker didn't answer like an MQTT broker")),
+    }
+}
+
+/// Write a packet after reading what the broker has sent since the last one,
+/// which is only ever the answers to pings
+fn send(stream: &mut TcpStream, packet: &[u8]) -> std::io::Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut buffer = [0; 64];
+    let drained = loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => break Err(std::io::Error::from(ErrorKind::ConnectionAborted)),
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::WouldBlock => break Ok(()),
+            Err(error) => break Err(error),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    drained?;
+
+    stream.write_all(packet)
+}
+
+const PINGREQ: [u8; 2] = [0xc0, 0x00];
+
+/// The remaining length of a packet, in 7 bits per byte with the highest bit
+/// set on every byte but the last
+fn encode_length(mut length: usize, packet: &mut Vec<u8>) {
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_string(value: &[u8], packet: &mut Vec<u8>) {
+    #[allow(clippy::cast_possible_truncation)]
+    packet.extend((value.len() as u16).to_be_bytes());
+    packet.extend(value);
+}
+
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    encode_length(body.len(), &mut packet);
+    packet.extend(body);
+    packet
+}
+
+/// `CONNECT` of MQTT 3.1.1 with a clean session
+fn connect_packet(client_id: &str, login: Option<&(String, String)>) -> Vec<u8> {
+    let mut flags = 0x02;
+    if login.is_some() {
+        flags |= 0xc0;
+    }
+
+    let mut body = vec![];
+    encode_string(b"MQTT", &mut body);
+    body.extend([0x04, flags]);
+    #[allow(clippy::cast_possible_truncation)]
+    body.extend((KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    encode_string(client_id.as_bytes(), &mut body);
+    if let Some((username, password)) = login {
+        encode_string(username.as_bytes(), &mut body);
+        encode_string(password.as_bytes(), &mut body);
+    }
+
+    packet(0x10, &body)
+}
+
+/// `PUBLISH` at most once, which is all a hotkey needs
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = vec![];
+    encode_string(topic.as_bytes(), &mut body);
+    body.extend(payload);
+
+    packet(0x30, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_length() {
+        let encoded = |length| {
+            let mut packet = vec![];
+            encode_length(length, &mut packet);
+            packet
+        };
+
+        assert_eq!(encoded(0), vec![0x00]);
+        assert_eq!(encoded(127), vec![0x7f]);
+        assert_eq!(encoded(128), vec![0x80, 0x01]);
+        assert_eq!(encoded(16_383), vec![0xff, 0x7f]);
+        assert_eq!(encoded(16_384), vec![0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(
+            publish_packet("a/b", b"on"),
+            vec![0x30, 0x07, 0x00, 0x03, b'a', b'/', b'b', b'o', b'n']
+        );
+
+        assert_eq!(
+            connect_packet("w", None),
+            vec![
+                0x10, 0x0d, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x1e, 0x00, 0x01,
+                b'w'
+            ]
+        );
+
+        let login = (String::from("u"), String::from("p"));
+        let packet = connect_packet("w", Some(&login));
+        assert_eq!(packet[9], 0xc2);
+        assert!(packet.ends_with(&[0x00, 0x01, b'u', 0x00, 0x01, b'p']));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::event_loop::EventLoopProxy;
+use crate::shutdown;
+use crate::UserEvent;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::sync::mpsc;
+use windows_sys::Win32::Foundation::HWND;
+use windows_sys::Win32::Foundation::LPARAM;
+use windows_sys::Win32::Foundation::LRESULT;
+use windows_sys::Win32::Foundation::WPARAM;
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::System::Power::RegisterSuspendResumeNotification;
+use windows_sys::Win32::System::RemoteDesktop::WTSRegisterSessionNotification;
+use windows_sys::Win32::System::RemoteDesktop::NOTIFY_FOR_THIS_SESSION;
+use windows_sys::Win32::UI::WindowsAndMessaging::CreateWindowExW;
+use windows_sys::Win32::UI::WindowsAndMessaging::DefWindowProcW;
+use windows_sys::Win32::UI::WindowsAndMessaging::DispatchMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetSystemMetrics;
+use windows_sys::Win32::UI::WindowsAndMessaging::RegisterClassW;
+use windows_sys::Win32::UI::WindowsAndMessaging::DEVICE_NOTIFY_WINDOW_HANDLE;
+use windows_sys::Win32::UI::WindowsAndMessaging::MSG;
+use windows_sys::Win32::UI::WindowsAndMessaging::PBT_APMRESUMEAUTOMATIC;
+use windows_sys::Win32::UI::WindowsAndMessaging::SM_REMOTESESSION;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_CLOSE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_ENDSESSION;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_POWERBROADCAST;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_WTSSESSION_CHANGE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WNDCLASSW;
+use windows_sys::Win32::UI::WindowsAndMessaging::WTS_CONSOLE_CONNECT;
+use windows_sys::Win32::UI::WindowsAndMessaging::WTS_REMOTE_CONNECT;
+use windows_sys::Win32::UI::WindowsAndMessaging::WTS_SESSION_UNLOCK;
+
+lazy_static! {
+    static ref PROXY: Mutex<Option<EventLoopProxy<UserEvent>>> = Mutex::new(None);
+}
+
+/// A change of the session or power state after which registrations and shell
+/// sessions may have been lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    Unlocked,
+    /// A user has connected to the session, locally or over remote desktop
+    Connected {
+        remote: bool,
+    },
+    /// The machine has woken up from sleep or hibernation
+    Resumed,
+}
+
+impl Display for SessionEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unlocked => write!(f, "the session was unlocked"),
+            Self::Connected { remote: false } => write!(f, "the session was connected"),
+            Self::Connected { remote: true } => {
+                write!(f, "the session was connected over remote desktop")
+            }
+            Self::Resumed => write!(f, "the machine woke up"),
+        }
+    }
+}
+
+/// Receive session and power notifications on a hidden window of a dedicated
+/// thread and forward them to the event loop, and shut down when the session
+/// ends or the window is asked to close, e.g. by `taskkill`
+pub fn listen(proxy: EventLoopProxy<UserEvent>) -> Result<()> {
+    // `.remote_session` also applies when whkd is started in a remote session
+    if unsafe { GetSystemMetrics(SM_REMOTESESSION) } != 0 {
+        let _ = proxy.send_event(UserEvent::Session(SessionEvent::Connected { remote: true }));
+    }
+
+    *PROXY.lock() = Some(proxy);
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let created = create_window();
+        let failed = created.is_err();
+        let _ = sender.send(created);
+
+        if failed {
+            return;
+        }
+
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { GetMessageW(std::ptr::addr_of_mut!(msg), 0, 0, 0) } > 0 {
+            unsafe { DispatchMessageW(std::ptr::addr_of!(msg)) };
+        }
+    });
+
+    receiver.recv()?
+}
+
+fn create_window() -> Result<()> {
+    let class_name = "whkd_notifications\0".encode_utf16().collect::<Vec<u16>>();
+
+    unsafe {
+        let instance = GetModuleHandleW(std::ptr::null());
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance,
+            lpszClassName: class_name.as_ptr(),
+            ..std::mem::zeroed()
+        };
+
+        if RegisterClassW(std::ptr::addr_of!(class)) == 0 {
+            return Err(eyre!(
+                "could not register the notification window class: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        // a hidden top-level window rather than a message-only one, which
+        // wouldn't receive `WM_ENDSESSION` or the `WM_CLOSE` of `taskkill`
+        let hwnd = CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            std::ptr::null(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            instance,
+            std::ptr::null(),
+        );
+
+        if hwnd == 0 {
+            return Err(eyre!(
+                "could not create the notification window: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        if WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION) == 0 {
+            return Err(eyre!(
+                "could not register for session notifications: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        if RegisterSuspendResumeNotification(hwnd, DEVICE_NOTIFY_WINDOW_HANDLE) == 0 {
+            return Err(eyre!(
+                "could not register for power notifications: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        // closing the window would only stop the notifications
+        WM_CLOSE => {
+            shutdown::request("whkd was asked to close");
+            return 0;
+        }
+        // the process is ended as soon as this returns
+        WM_ENDSESSION if wparam != 0 => {
+            if shutdown::request("the session is ending") {
+                shutdown::wait();
+            }
+            return 0;
+        }
+        _ => {}
+    }
+
+    let event = match (msg, u32::try_from(wparam).unwrap_or_default()) {
+        (WM_WTSSESSION_CHANGE, WTS_SESSION_UNLOCK) => Some(SessionEvent::Unlocked),
+        (WM_WTSSESSION_CHANGE, WTS_CONSOLE_CONNECT) => {
+            Some(SessionEvent::Connected { remote: false })
+        }
+        (WM_WTSSESSION_CHANGE, WTS_REMOTE_CONNECT) => {
+            Some(SessionEvent::Connected { remote: true })
+        }
+        (WM_POWERBROADCAST, PBT_APMRESUMEAUTOMATIC) => Some(SessionEvent::Resumed),
+        _ => None,
+    };
+
+    if let Some(event) = event {
+        if let Some(proxy) = PROXY.lock().as_ref() {
+            let _ = proxy.send_event(UserEvent::Session(event));
+        }
+    }
+
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
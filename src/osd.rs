@@ -0,0 +1,333 @@
+use crate::dnd;
+use crate::log;
+use crate::window;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::sync::mpsc;
+use windows_sys::Win32::Foundation::HWND;
+use windows_sys::Win32::Foundation::LPARAM;
+use windows_sys::Win32::Foundation::LRESULT;
+use windows_sys::Win32::Foundation::RECT;
+use windows_sys::Win32::Foundation::WPARAM;
+use windows_sys::Win32::Graphics::Gdi::BeginPaint;
+use windows_sys::Win32::Graphics::Gdi::CreateFontW;
+use windows_sys::Win32::Graphics::Gdi::CreateSolidBrush;
+use windows_sys::Win32::Graphics::Gdi::DeleteObject;
+use windows_sys::Win32::Graphics::Gdi::DrawTextW;
+use windows_sys::Win32::Graphics::Gdi::EndPaint;
+use windows_sys::Win32::Graphics::Gdi::FillRect;
+use windows_sys::Win32::Graphics::Gdi::GetDC;
+use windows_sys::Win32::Graphics::Gdi::InvalidateRect;
+use windows_sys::Win32::Graphics::Gdi::MonitorFromWindow;
+use windows_sys::Win32::Graphics::Gdi::ReleaseDC;
+use windows_sys::Win32::Graphics::Gdi::SelectObject;
+use windows_sys::Win32::Graphics::Gdi::SetBkMode;
+use windows_sys::Win32::Graphics::Gdi::SetTextColor;
+use windows_sys::Win32::Graphics::Gdi::CLEARTYPE_QUALITY;
+use windows_sys::Win32::Graphics::Gdi::CLIP_DEFAULT_PRECIS;
+use windows_sys::Win32::Graphics::Gdi::DEFAULT_CHARSET;
+use windows_sys::Win32::Graphics::Gdi::DEFAULT_PITCH;
+use windows_sys::Win32::Graphics::Gdi::DT_CALCRECT;
+use windows_sys::Win32::Graphics::Gdi::DT_CENTER;
+use windows_sys::Win32::Graphics::Gdi::DT_NOPREFIX;
+use windows_sys::Win32::Graphics::Gdi::DT_SINGLELINE;
+use windows_sys::Win32::Graphics::Gdi::DT_VCENTER;
+use windows_sys::Win32::Graphics::Gdi::FW_SEMIBOLD;
+use windows_sys::Win32::Graphics::Gdi::HDC;
+use windows_sys::Win32::Graphics::Gdi::HFONT;
+use windows_sys::Win32::Graphics::Gdi::MONITOR_DEFAULTTOPRIMARY;
+use windows_sys::Win32::Graphics::Gdi::OUT_DEFAULT_PRECIS;
+use windows_sys::Win32::Graphics::Gdi::PAINTSTRUCT;
+use windows_sys::Win32::Graphics::Gdi::TRANSPARENT;
+use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows_sys::Win32::UI::WindowsAndMessaging::CreateWindowExW;
+use windows_sys::Win32::UI::WindowsAndMessaging::DefWindowProcW;
+use windows_sys::Win32::UI::WindowsAndMessaging::DispatchMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetClientRect;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::KillTimer;
+use windows_sys::Win32::UI::WindowsAndMessaging::PostMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::RegisterClassW;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetLayeredWindowAttributes;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetTimer;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetWindowPos;
+use windows_sys::Win32::UI::WindowsAndMessaging::ShowWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::HWND_TOPMOST;
+use windows_sys::Win32::UI::WindowsAndMessaging::LWA_ALPHA;
+use windows_sys::Win32::UI::WindowsAndMessaging::MSG;
+use windows_sys::Win32::UI::WindowsAndMessaging::SWP_NOACTIVATE;
+use windows_sys::Win32::UI::WindowsAndMessaging::SWP_SHOWWINDOW;
+use windows_sys::Win32::UI::WindowsAndMessaging::SW_HIDE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_APP;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_PAINT;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_TIMER;
+use windows_sys::Win32::UI::WindowsAndMessaging::WNDCLASSW;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_LAYERED;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_NOACTIVATE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_TOOLWINDOW;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_TOPMOST;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_TRANSPARENT;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_POPUP;
+
+/// How long a message stays on screen, unless another one replaces it
+const DURATION_MS: u32 = 1500;
+
+/// Space between the message and the edges of its window
+const PADDING: i32 = 16;
+
+/// Colors as `0x00BBGGRR`
+const BACKGROUND: u32 = 0x0020_2020;
+const FOREGROUND: u32 = 0x00ff_ffff;
+
+/// Opacity of the window out of 255
+const OPACITY: u8 = 230;
+
+/// Asks the window to show the message in `TEXT`
+const WM_SHOW: u32 = WM_APP + 1;
+
+const TIMER_ID: usize = 1;
+
+lazy_static! {
+    static ref TEXT: Mutex<Vec<u16>> = Mutex::new(vec![]);
+    /// Created on a thread of its own along with the first message
+    static ref WINDOW: Mutex<Option<HWND>> = Mutex::new(None);
+}
+
+/// Show a message of `~notify` at the bottom of the monitor of the foreground
+/// window, without taking the focus
+pub fn show(message: &str) {
+    if dnd::silenced() {
+        return;
+    }
+
+    *TEXT.lock() = message.encode_utf16().collect();
+
+    let mut window = WINDOW.lock();
+    if window.is_none() {
+        match create() {
+            Ok(hwnd) => *window = Some(hwnd),
+            Err(error) => {
+                log::error(format!("could not show '{message}': {error}"));
+                return;
+            }
+        }
+    }
+
+    if let Some(hwnd) = *window {
+        unsafe { PostMessageW(hwnd, WM_SHOW, 0, 0) };
+    }
+}
+
+fn create() -> Result<HWND> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let created = create_window();
+        let failed = created.is_err();
+        let _ = sender.send(created);
+
+        if failed {
+            return;
+        }
+
+        let mut msg: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { GetMessageW(std::ptr::addr_of_mut!(msg), 0, 0, 0) } > 0 {
+            unsafe { DispatchMessageW(std::ptr::addr_of!(msg)) };
+        }
+    });
+
+    receiver.recv()?
+}
+
+fn create_window() -> Result<HWND> {
+    let class_name = "whkd_osd\0".encode_utf16().collect::<Vec<u16>>();
+
+    unsafe {
+        let instance = GetModuleHandleW(std::ptr::null());
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance,
+            lpszClassName: class_name.as_ptr(),
+            ..std::mem::zeroed()
+        };
+
+        if RegisterClassW(std::ptr::addr_of!(class)) == 0 {
+            return Err(eyre!(
+                "could not register the message window class: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        // clicks go through the window to whatever is below it
+        let hwnd = CreateWindowExW(
+            WS_EX_LAYERED | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE | WS_EX_TRANSPARENT,
+            class_name.as_ptr(),
+            std::ptr::null(),
+            WS_POPUP,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            instance,
+            std::ptr::null(),
+        );
+
+        if hwnd == 0 {
+            return Err(eyre!(
+                "could not create the message window: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        SetLayeredWindowAttributes(hwnd, 0, OPACITY, LWA_ALPHA);
+        Ok(hwnd)
+    }
+}
+
+fn font() -> HFONT {
+    let face = "Segoe UI\0".encode_utf16().collect::<Vec<u16>>();
+
+    #[allow(clippy::cast_possible_wrap)]
+    unsafe {
+        CreateFontW(
+            -22,
+            0,
+            0,
+            0,
+            FW_SEMIBOLD as i32,
+            0,
+            0,
+            0,
+            u32::from(DEFAULT_CHARSET),
+            u32::from(OUT_DEFAULT_PRECIS),
+            u32::from(CLIP_DEFAULT_PRECIS),
+            u32::from(CLEARTYPE_QUALITY),
+            u32::from(DEFAULT_PITCH),
+            face.as_ptr(),
+        )
+    }
+}
+
+/// Draw the message with the font of the window, or only measure it
+fn draw_text(hdc: HDC, rect: &mut RECT, format: u32) {
+    let text = TEXT.lock().clone();
+
+    unsafe {
+        let font = font();
+        let previous = SelectObject(hdc, font);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        DrawTextW(
+            hdc,
+            text.as_ptr(),
+            text.len() as i32,
+            rect,
+            format | DT_SINGLELINE | DT_NOPREFIX,
+        );
+        SelectObject(hdc, previous);
+        DeleteObject(font);
+    }
+}
+
+/// The position of the window at the center of the bottom of the work area,
+/// above the taskbar and the toasts of Windows
+const fn placement(work: &RECT, width: i32, height: i32) -> (i32, i32) {
+    (
+        work.left + (work.right - work.left - width) / 2,
+        work.bottom - height - (work.bottom - work.top) / 10,
+    )
+}
+
+unsafe fn show_text(hwnd: HWND) {
+    let mut text = RECT {
+        left: 0,
+        top: 0,
+        right: 0,
+        bottom: 0,
+    };
+
+    let hdc = GetDC(hwnd);
+    draw_text(hdc, &mut text, DT_CALCRECT);
+    ReleaseDC(hwnd, hdc);
+
+    let width = text.right + 2 * PADDING;
+    let height = text.bottom + 2 * PADDING;
+
+    let monitor = MonitorFromWindow(GetForegroundWindow(), MONITOR_DEFAULTTOPRIMARY);
+    let Ok(info) = window::monitor_info(monitor) else {
+        return;
+    };
+
+    let (x, y) = placement(&info.rcWork, width, height);
+    SetWindowPos(
+        hwnd,
+        HWND_TOPMOST,
+        x,
+        y,
+        width,
+        height,
+        SWP_NOACTIVATE | SWP_SHOWWINDOW,
+    );
+    InvalidateRect(hwnd, std::ptr::null(), 1);
+
+    // replaces the timer of a message which is still shown
+    SetTimer(hwnd, TIMER_ID, DURATION_MS, None);
+}
+
+unsafe fn paint(hwnd: HWND) {
+    let mut paint: PAINTSTRUCT = std::mem::zeroed();
+    let hdc = BeginPaint(hwnd, std::ptr::addr_of_mut!(paint));
+
+    let mut rect: RECT = std::mem::zeroed();
+    GetClientRect(hwnd, std::ptr::addr_of_mut!(rect));
+
+    let brush = CreateSolidBrush(BACKGROUND);
+    FillRect(hdc, std::ptr::addr_of!(rect), brush);
+    DeleteObject(brush);
+
+    #[allow(clippy::cast_possible_wrap)]
+    SetBkMode(hdc, TRANSPARENT as i32);
+    SetTextColor(hdc, FOREGROUND);
+    draw_text(hdc, &mut rect, DT_CENTER | DT_VCENTER);
+
+    EndPaint(hwnd, std::ptr::addr_of!(paint));
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_SHOW => show_text(hwnd),
+        WM_TIMER if wparam == TIMER_ID => {
+            KillTimer(hwnd, TIMER_ID);
+            ShowWindow(hwnd, SW_HIDE);
+        }
+        WM_PAINT => paint(hwnd),
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placement() {
+        let work = RECT {
+            left: 1920,
+            top: 0,
+            right: 4480,
+            bottom: 1400,
+        };
+
+        assert_eq!(placement(&work, 300, 60), (3050, 1200));
+    }
+}
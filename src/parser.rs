@@ -1,220 +1,3900 @@
+use crate::condition::parse_days;
+use crate::condition::Condition;
+use crate::condition::Expression;
+use crate::hook;
+use crate::idle::ACTIVE_WITHIN;
+use crate::lint::Level;
+use crate::lint::Lint;
+use crate::lint::Lints;
+use crate::locale::Locale;
+use crate::state;
+use crate::whkdrc::Backend;
+use crate::whkdrc::Exec;
+use crate::whkdrc::LockKey;
+use crate::whkdrc::ModeIndicator;
+use crate::whkdrc::MqttBroker;
+use crate::whkdrc::Options;
+use crate::whkdrc::RateLimit;
+use crate::whkdrc::RatePolicy;
+use crate::whkdrc::RemotePolicy;
 use crate::whkdrc::Shell;
 use crate::whkdrc::Whkdrc;
+use crate::whkdrc::CURRENT_VERSION;
+use crate::whkdrc::DEFAULT_ROLLOVER;
+use crate::whkdrc::DEFAULT_TAP_THRESHOLD;
+use chrono::NaiveTime;
 use chumsky::prelude::*;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct HotkeyBinding {
     pub mode: Option<String>,
     pub keys: Vec<String>,
     pub command: Option<String>,
-    pub internal_action: Option<Option<String>>,
+    pub internal_action: Option<InternalAction>,
     pub process_name: Option<String>,
+    pub tags: Vec<String>,
+    pub conditions: Vec<Condition>,
+    pub passthrough: bool,
+    /// Overrides `.timeout` for the next key after this binding
+    pub timeout: Option<Duration>,
+    /// Presses within this long after the binding last fired are ignored
+    pub cooldown: Option<Duration>,
+    /// Wait for the command to exit, to report when it fails
+    pub wait: bool,
+    /// Runs when the command of a binding which waits exits with an error
+    pub on_fail: Option<String>,
+    /// Reported instead of the error when the command of the binding fails
+    pub on_error: Option<String>,
+    /// What the binding does, from a `##` comment at the end of its line
+    pub description: Option<String>,
+    /// Whether the input synthesized by whkd itself can fire the binding
+    pub allow_recursion: bool,
+    /// Ignore presses while do not disturb is on
+    pub respect_dnd: bool,
+    /// Shown on screen whenever the binding fires
+    pub notify: Option<String>,
+    /// A .wav file played when the binding fires, e.g. for commands whose
+    /// effect takes a while to show
+    pub sound: Option<PathBuf>,
+    /// Asked in a dialog before the binding fires, which only fires if the
+    /// answer is yes
+    pub confirm: Option<String>,
+    /// The handle which `whkd trigger`, statistics and other bindings refer to
+    /// the binding by, instead of its chord
+    pub name: Option<String>,
+    /// Keep the commands out of the output, events and history
+    pub redact: bool,
+    /// Keep the commands out of the output only
+    pub silent: bool,
+    pub profile: Option<String>,
+    /// Line of whkdrc which the binding is on, starting at 1
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InternalAction {
+    /// Switch to a mode, `None` being the default mode
+    Mode(Option<String>),
+    /// Swap the full set of bindings for those of another profile
+    Profile(String),
+    /// Re-register the bindings with a tag after they were disabled
+    EnableTag(String),
+    /// Unregister every binding with a tag until it is enabled again
+    DisableTag(String),
+    /// Unregister the binding with a `~name` if it is enabled and register it
+    /// again otherwise
+    ToggleBinding(String),
+    /// Dispatch the most recently dispatched command again
+    RepeatLast,
+    /// Switch to the mode declared after the active one, wrapping around
+    NextMode,
+    /// Switch to the mode declared before the active one, wrapping around
+    PrevMode,
+    /// Return to the mode which was active before the current one
+    ModeBack,
+    /// Dispatch the next of the commands on every press, wrapping around
+    Cycle(Vec<String>),
+    /// Alternate between two commands, or dispatch the one which flips the state
+    /// reported by the probe command
+    Toggle {
+        on: String,
+        off: String,
+        probe: Option<String>,
+    },
+    /// Append a digit to the count passed to the next command, bound to the
+    /// digit keys of the modes declared with `.mode <name> count`
+    Count(u32),
+    /// Replace the text on the clipboard
+    ClipboardSet(String),
+    /// Type out the text on the clipboard
+    ClipboardPaste,
+    ClipboardClear,
+    /// Bring a window of a process to the front, launching it if there is none
+    FocusOrLaunch(String),
+    /// Bring the window of a profile of Windows Terminal to the front, opening
+    /// the profile if there is none
+    Terminal(String),
+    /// Publish a message to the broker of `.mqtt`
+    Mqtt {
+        topic: String,
+        payload: String,
+    },
+    /// Manage the focused window without a window manager
+    Window(WindowAction),
+    /// Change how the desktop is shown on the displays
+    Display(DisplayAction),
+    /// Raise or lower the volume of the default output device by percentage points
+    Volume(i32),
+    ToggleMute,
+    /// Raise or lower the brightness of every monitor by percentage points
+    Brightness(i32),
+    /// Dispatch commands one after another, waiting between them at `@sleep` steps
+    Chain(Vec<Step>),
+    /// Run the steps of a `.macro` one after another
+    RunMacro(String),
+    /// Press the binding of a chord in a mode, or in the active mode if none is
+    /// given, where `default` is kept as written
+    Trigger {
+        mode: Option<String>,
+        keys: Vec<String>,
+    },
+    /// Press the binding with a `~name`, whichever mode it is in
+    TriggerName(String),
+    /// Open whkdrc in `$EDITOR`
+    EditConfig,
+    /// Show the bindings of the active mode with their descriptions
+    Help,
+    /// Run the commands of the modes without a shell of their own in a session
+    /// of another shell until whkdrc is reloaded
+    Shell(Shell),
+}
+
+impl InternalAction {
+    /// Whether the action is one of those written with `@`, which don't depend
+    /// on the active mode and can also be steps of a `.macro`
+    #[must_use]
+    pub const fn is_native(&self) -> bool {
+        matches!(
+            self,
+            Self::ClipboardSet(_)
+                | Self::ClipboardPaste
+                | Self::ClipboardClear
+                | Self::FocusOrLaunch(_)
+                | Self::Terminal(_)
+                | Self::Mqtt { .. }
+                | Self::Window(_)
+                | Self::Display(_)
+                | Self::Volume(_)
+                | Self::ToggleMute
+                | Self::Brightness(_)
+        )
+    }
+}
+
+/// A step of a `:: chain` or a `.macro`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// Dispatched like the command of a binding
+    Command(String),
+    Action(InternalAction),
+    /// Synthesize a chord, e.g. `@send ctrl + v`
+    Send(Vec<String>),
+    Sleep(Duration),
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Command(command) => write!(f, "{command}"),
+            Self::Action(action) => write!(f, "{action}"),
+            Self::Send(keys) => write!(f, "@send {}", keys.join(" + ")),
+            Self::Sleep(duration) => write!(f, "@sleep {}ms", duration.as_millis()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WindowAction {
+    Minimize,
+    /// Maximize the window, or restore it if it already is
+    Maximize,
+    Close,
+    ToggleTopmost,
+    /// Center the window in the work area of its monitor
+    Center,
+    MoveToMonitor(MonitorTarget),
+    /// Bring the topmost window of another monitor to the front
+    FocusMonitor(MonitorTarget),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MonitorTarget {
+    Next,
+    Prev,
+    /// Monitors are numbered from 1, left to right
+    Number(usize),
+}
+
+impl Display for WindowAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Minimize => write!(f, "@minimize"),
+            Self::Maximize => write!(f, "@maximize"),
+            Self::Close => write!(f, "@close"),
+            Self::ToggleTopmost => write!(f, "@toggle-topmost"),
+            Self::Center => write!(f, "@center"),
+            Self::MoveToMonitor(MonitorTarget::Next) => write!(f, "@move-to-monitor next"),
+            Self::MoveToMonitor(MonitorTarget::Prev) => write!(f, "@move-to-monitor prev"),
+            Self::MoveToMonitor(MonitorTarget::Number(number)) => {
+                write!(f, "@move-to-monitor {number}")
+            }
+            Self::FocusMonitor(MonitorTarget::Next) => write!(f, "@focus-monitor next"),
+            Self::FocusMonitor(MonitorTarget::Prev) => write!(f, "@focus-monitor prev"),
+            Self::FocusMonitor(MonitorTarget::Number(number)) => {
+                write!(f, "@focus-monitor {number}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisplayAction {
+    /// Spread the desktop across every display
+    Extend,
+    /// Show the same picture on every display
+    Duplicate,
+    /// Only use the built-in display, e.g. of a laptop
+    Internal,
+    /// Only use the external displays
+    External,
+    /// Make a monitor the primary one, numbered from 1 left to right
+    Primary(usize),
+}
+
+impl Display for DisplayAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Extend => write!(f, "@display extend"),
+            Self::Duplicate => write!(f, "@display duplicate"),
+            Self::Internal => write!(f, "@display internal"),
+            Self::External => write!(f, "@display external"),
+            Self::Primary(number) => write!(f, "@display primary {number}"),
+        }
+    }
+}
+
+impl Display for InternalAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mode(mode) => write!(f, "{}", mode.as_deref().unwrap_or("default")),
+            Self::Profile(profile) => write!(f, "profile {profile}"),
+            Self::EnableTag(tag) => write!(f, "enable {tag}"),
+            Self::DisableTag(tag) => write!(f, "disable {tag}"),
+            Self::ToggleBinding(name) => write!(f, "toggle-binding {name}"),
+            Self::RepeatLast => write!(f, "repeat-last"),
+            Self::NextMode => write!(f, "next-mode"),
+            Self::PrevMode => write!(f, "prev-mode"),
+            Self::ModeBack => write!(f, "mode-back"),
+            Self::Cycle(commands) => write!(f, "[ {} ]", commands.join(" | ")),
+            Self::Toggle { on, off, probe } => {
+                write!(f, "toggle {{ {on} }} {{ {off} }}")?;
+                if let Some(probe) = probe {
+                    write!(f, " probe {{ {probe} }}")?;
+                }
+
+                Ok(())
+            }
+            Self::Count(digit) => write!(f, "count {digit}"),
+            Self::ClipboardSet(text) => write!(f, "@clipboard-set \"{text}\""),
+            Self::ClipboardPaste => write!(f, "@clipboard-paste-as-keystrokes"),
+            Self::ClipboardClear => write!(f, "@clipboard-clear"),
+            Self::FocusOrLaunch(target) => write!(f, "@focus-or-launch \"{target}\""),
+            Self::Terminal(profile) => write!(f, "@terminal \"{profile}\""),
+            Self::Mqtt { topic, payload } => write!(f, "@mqtt {topic} \"{payload}\""),
+            Self::Window(action) => write!(f, "{action}"),
+            Self::Display(action) => write!(f, "{action}"),
+            Self::Volume(step) if *step < 0 => write!(f, "@volume down {}", -step),
+            Self::Volume(step) => write!(f, "@volume up {step}"),
+            Self::ToggleMute => write!(f, "@volume mute-toggle"),
+            Self::Brightness(step) if *step < 0 => write!(f, "@brightness down {}", -step),
+            Self::Brightness(step) => write!(f, "@brightness up {step}"),
+            Self::Chain(steps) => {
+                let steps = steps.iter().map(ToString::to_string).collect::<Vec<_>>();
+                write!(f, "chain {{ {} }}", steps.join("; "))
+            }
+            Self::RunMacro(name) => write!(f, "run-macro {name}"),
+            Self::Trigger { mode, keys } => {
+                write!(f, "trigger ")?;
+                if let Some(mode) = mode {
+                    write!(f, "{mode}/")?;
+                }
+
+                write!(f, "{}", keys.join(" + "))
+            }
+            Self::TriggerName(name) => write!(f, "trigger name({name})"),
+            Self::EditConfig => write!(f, "edit-config"),
+            Self::Help => write!(f, "help"),
+            Self::Shell(shell) => write!(f, "shell {shell}"),
+        }
+    }
+}
+
+/// What ends a command in the middle of a line as a comment
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comments {
+    /// Any `#`, so that `#` has to be written as `\#` in commands, as up to
+    /// version 2 of the syntax
+    Hash,
+    /// Only `##`, so that commands can contain `#`, e.g. in URLs and colors
+    DoubleHash,
+}
+
+impl Comments {
+    /// The rule set with `.comments`, or otherwise the one of the version of the
+    /// syntax, both of which can be anywhere in the file
+    fn detect(src: &str) -> Self {
+        let mut comments = None;
+        let mut version = 1;
+
+        for line in src.lines().map(str::trim) {
+            if let Some(rule) = line.strip_prefix(".comments") {
+                match rule.split_whitespace().next() {
+                    Some("hash") => comments = Some(Self::Hash),
+                    Some("double-hash") => comments = Some(Self::DoubleHash),
+                    _ => {}
+                }
+            } else if let Some(number) = line.strip_prefix(".version") {
+                version = number.trim().parse().unwrap_or(version);
+            }
+        }
+
+        comments.unwrap_or(if version >= 3 {
+            Self::DoubleHash
+        } else {
+            Self::Hash
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Directive {
+    Version(u32),
+    /// Applied while commands are parsed, see `Comments::detect`
+    Comments,
+    /// Applied while chords are parsed, see `Locale::detect`
+    Locale,
+    Exec(Exec),
+    Backend(Backend),
+    TapThreshold(Duration),
+    StickyModifiers(Duration),
+    Rollover(Duration),
+    Timeout(Duration),
+    RateLimit(RateLimit),
+    StartupDelay(Duration),
+    WaitForProcess(String),
+    StateFile(PathBuf),
+    ShellInstances(usize),
+    ShellInit(Vec<String>),
+    ShellIdle(Duration),
+    ShellHealthCheck(Duration),
+    OnError(String),
+    Mqtt(MqttBroker),
+    IncludeCmd(String),
+    Redact(String),
+    Echo(bool),
+    ModeSound(PathBuf),
+    ModeIndicator(ModeIndicator),
+    Options(Vec<String>),
+    Suppress(Vec<Vec<String>>),
+    AppMode(String, String),
+    Layer(String, String),
+    ModeShell(String, Shell),
+    ModeCount(String),
+    Ignore(Vec<String>),
+    RemoteSession(RemotePolicy),
+    Alias(String, String),
+    Template(String, Template),
+    Macro(String, Vec<Step>),
+    Strict,
+    Lints(Level, Vec<Lint>),
+}
+
+/// A command with parameters, e.g. `.template focus(dir) = komorebic focus {dir}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    params: Vec<String>,
+    body: String,
+}
+
+impl Template {
+    /// The command of a call such as `focus(left)`
+    fn call(&self, name: &str, args: &[&str]) -> Result<String, String> {
+        if args.len() != self.params.len() {
+            return Err(format!(
+                "template {name} takes {} argument(s) but is called with {}",
+                self.params.len(),
+                args.len()
+            ));
+        }
+
+        Ok(self
+            .params
+            .iter()
+            .zip(args)
+            .fold(self.body.clone(), |body, (param, arg)| {
+                body.replace(&format!("{{{param}}}"), arg)
+            }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Attribute {
+    Tags(Vec<String>),
+    Condition(Condition),
+    Passthrough,
+    Timeout(Duration),
+    Cooldown(Duration),
+    Wait,
+    OnFail(String),
+    OnError(String),
+    AllowRecursion,
+    RespectDnd,
+    Notify(String),
+    Sound(PathBuf),
+    Confirm(String),
+    Redact,
+    Silent,
+    Name(String),
+}
+
+impl Attribute {
+    fn apply(self, binding: &mut HotkeyBinding) {
+        match self {
+            Self::Tags(names) => binding.tags.extend(names),
+            Self::Condition(condition) => binding.conditions.push(condition),
+            Self::Passthrough => binding.passthrough = true,
+            Self::Timeout(duration) => binding.timeout = Some(duration),
+            Self::Cooldown(duration) => binding.cooldown = Some(duration),
+            Self::Wait => binding.wait = true,
+            // a follow-up on failure needs to wait for the exit code
+            Self::OnFail(command) => {
+                binding.wait = true;
+                binding.on_fail = Some(command);
+            }
+            Self::OnError(message) => binding.on_error = Some(message),
+            Self::AllowRecursion => binding.allow_recursion = true,
+            Self::RespectDnd => binding.respect_dnd = true,
+            Self::Notify(message) => binding.notify = Some(message),
+            Self::Sound(path) => binding.sound = Some(path),
+            Self::Confirm(question) => binding.confirm = Some(question),
+            Self::Redact => binding.redact = true,
+            Self::Silent => binding.silent = true,
+            Self::Name(handle) => binding.name = Some(handle),
+        }
+    }
+}
+
+// only lives until the items have been collected into a `Whkdrc`
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Profile(String),
+    AppBindings(Vec<String>, Vec<HotkeyBinding>),
+    Binding(Modes, HotkeyBinding),
+    Unbind(Option<String>, Vec<String>),
+    Inherit(String, Option<String>),
+    OnIdle(Duration, String),
+    /// The items of a `mode` declaration or the bindings of the chords of a
+    /// line, collected in its place
+    Block(Vec<Self>),
+}
+
+/// The modes a binding is written for, e.g. `window, resize >` or `* >`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Modes {
+    /// The default mode and every other mode of the file
+    All,
+    Listed(Vec<Option<String>>),
+}
+
+/// Lowercase modifiers and single character keys so that `Alt + H` is the same
+/// chord as `alt + h`, leaving key names such as `ArrowLeft` as they are written
+fn normalize_key(key: String) -> String {
+    if key.chars().count() == 1 || hook::is_modifier_name(&key) {
+        key.to_lowercase()
+    } else {
+        key
+    }
+}
+
+/// A key of the chord of a binding, or a group such as `{h,j,k,l}` which makes
+/// one binding of each of its keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KeyPattern {
+    Key(String),
+    Group(Vec<String>),
+}
+
+/// How many numbers a range such as `1-9` can stand for, far more than any
+/// chord needs, so that a range such as `1-4000000000` can't expand without bound
+const MAX_RANGE_LEN: u32 = 256;
+
+/// The numbers of a range such as `1-9` in a group, or an error if it stands
+/// for more than `MAX_RANGE_LEN` of them
+fn range(element: &str) -> Option<Result<Vec<String>, String>> {
+    let (start, end) = element.split_once('-')?;
+    let start = start.parse::<u32>().ok()?;
+    let end = end.parse::<u32>().ok()?;
+
+    if start > end {
+        return None;
+    }
+
+    if end - start >= MAX_RANGE_LEN {
+        return Some(Err(format!(
+            "{element} has more than {MAX_RANGE_LEN} numbers"
+        )));
+    }
+
+    Some(Ok((start..=end).map(|number| number.to_string()).collect()))
+}
+
+/// The span of a group in a command and the elements it expands to
+type CommandGroup = (std::ops::Range<usize>, Vec<String>);
+
+/// The groups such as `{left,down,up,right}` or `{0-8}` in a command, which
+/// are expanded along with the groups of its chord
+fn command_groups(command: &str) -> Result<Vec<CommandGroup>, String> {
+    let mut groups = vec![];
+    let mut start = 0;
+
+    while let Some(open) = command[start..].find('{').map(|offset| start + offset) {
+        let Some(close) = command[open..].find('}').map(|offset| open + offset) else {
+            break;
+        };
+
+        // script blocks such as `{ $_.Name }` are left alone
+        let inner = &command[open + 1..close];
+        let has_range = inner.split(',').any(|element| range(element).is_some());
+        let elements = inner
+            .split(',')
+            .map(|element| range(element).unwrap_or_else(|| Ok(vec![element.to_string()])))
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+
+        if (elements.len() > 1 || has_range)
+            && !inner.contains(|c: char| c == '{' || c.is_whitespace())
+            && elements.iter().all(|element| !element.is_empty())
+        {
+            groups.push((open..close + 1, elements));
+            start = close + 1;
+        } else {
+            start = open + 1;
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Expand the groups of a chord and the command of a binding into one binding
+/// for each of their elements, which are zipped together
+fn expand(pattern: &[KeyPattern], binding: &HotkeyBinding) -> Result<Vec<HotkeyBinding>, String> {
+    let lengths = pattern
+        .iter()
+        .filter_map(|part| match part {
+            KeyPattern::Group(keys) => Some(keys.len()),
+            KeyPattern::Key(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    let count = lengths.first().copied();
+    if lengths.iter().any(|len| Some(*len) != count) {
+        return Err(String::from(
+            "every {...} group of a chord needs the same number of keys",
+        ));
+    }
+
+    let groups = match (count, &binding.command) {
+        (Some(_), Some(command)) => command_groups(command)?,
+        _ => vec![],
+    };
+
+    if let Some((_, elements)) = groups
+        .iter()
+        .find(|(_, elements)| Some(elements.len()) != count)
+    {
+        return Err(format!(
+            "{{{}}} has {} elements, but the chord has {} keys in each group",
+            elements.join(","),
+            elements.len(),
+            count.unwrap_or_default()
+        ));
+    }
+
+    Ok((0..count.unwrap_or(1))
+        .map(|index| {
+            let keys = pattern
+                .iter()
+                .map(|part| match part {
+                    KeyPattern::Key(key) => key.clone(),
+                    KeyPattern::Group(keys) => keys[index].clone(),
+                })
+                .collect();
+
+            let command = binding.command.as_ref().map(|command| {
+                let mut expanded = command.clone();
+                for (range, elements) in groups.iter().rev() {
+                    expanded.replace_range(range.clone(), &elements[index]);
+                }
+
+                expanded
+            });
+
+            HotkeyBinding {
+                keys,
+                command,
+                ..binding.clone()
+            }
+        })
+        .collect())
+}
+
+/// Prepended to a single binding to make a whkdrc of it
+const BINDING_HEADER: &str = ".shell pwsh\n";
+
+/// Parse a single binding as if it was the only one in whkdrc, returning one
+/// binding for every app it is written for and the errors with the column they
+/// start at otherwise
+pub fn parse_binding(src: &str) -> Result<Vec<HotkeyBinding>, Vec<(usize, Simple<char>)>> {
+    let whkdrc = parser()
+        .parse(format!("{BINDING_HEADER}{src}"))
+        .map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| {
+                    (
+                        error.span().start.saturating_sub(BINDING_HEADER.len()) + 1,
+                        error,
+                    )
+                })
+                .collect::<Vec<_>>()
+        })?;
+
+    Ok(whkdrc
+        .app_bindings
+        .into_iter()
+        .flat_map(|(_, bindings)| bindings)
+        .chain(whkdrc.bindings)
+        .collect())
+}
+
+/// Parse whkdrc without reading any files or running any commands, which is
+/// what hot reloads and fuzzing go through, so it must return an error rather
+/// than panic or hang on any input
+pub fn parse_str(src: &str) -> Result<Whkdrc, Vec<Simple<char>>> {
+    parser().parse(src)
+}
+
+/// `try_map` for a closure which fails with a message, as a `Simple` is too
+/// large to be returned from each of the closures of the grammar
+trait TryMapMessage<O>: Parser<char, O, Error = Simple<char>> + Clone {
+    // the one closure which returns a `Simple`
+    #[allow(clippy::result_large_err)]
+    fn try_map_message<U, F>(self, f: F) -> impl Parser<char, U, Error = Simple<char>> + Clone
+    where
+        F: Fn(O, std::ops::Range<usize>) -> Result<U, String> + Clone,
+    {
+        self.try_map(move |value, span: std::ops::Range<usize>| {
+            f(value, span.clone()).map_err(|message| Simple::custom(span, message))
+        })
+    }
+}
+
+impl<O, P: Parser<char, O, Error = Simple<char>> + Clone> TryMapMessage<O> for P {}
+
+/// How deeply braces can be nested inside a braced command, far more than any
+/// command needs, so that malformed input can't nest them without bound
+const MAX_BRACE_DEPTH: usize = 16;
+
+/// A command in braces whose inner braces have to be balanced, e.g.
+/// `{ $_.Muted }`, nested at most `depth` deep as every level is a parser of
+/// its own instead of a recursive one
+fn braced(depth: usize) -> BoxedParser<'static, char, String, Simple<char>> {
+    let text = none_of("{}").repeated().at_least(1).collect::<String>();
+    let part = if depth == 0 {
+        text.boxed()
+    } else {
+        choice((braced(depth - 1).map(|inner| format!("{{{inner}}}")), text)).boxed()
+    };
+
+    part.repeated()
+        .map(|parts| parts.concat())
+        .delimited_by(just("{"), just("}"))
+        .boxed()
 }
 
 #[must_use]
 pub fn parser() -> impl Parser<char, Whkdrc, Error = Simple<char>> {
-    let comment = just::<_, _, Simple<char>>("#")
+    any()
+        .repeated()
+        .collect::<String>()
+        .rewind()
+        .then_with(|src| grammar(Comments::detect(&src), Locale::detect(&src)))
+}
+
+fn grammar(comments: Comments, locale: Locale) -> impl Parser<char, Whkdrc, Error = Simple<char>> {
+    // the whole source is kept to turn the offsets of the bindings into lines
+    let source = any().repeated().rewind();
+
+    source
+        .then(shell())
+        .then(directive(comments, locale).repeated())
+        .then(item(comments, locale).repeated().at_least(1))
+        .try_map_message(|(((source, (shell, shell_args)), directives), items), _| {
+            build(&source, shell, shell_args, directives, items)
+        })
+}
+
+fn comment() -> impl Parser<char, (), Error = Simple<char>> + Clone {
+    just::<_, _, Simple<char>>("#")
         .then(take_until(text::newline()))
         .padded()
-        .ignored();
+        .ignored()
+}
+
+/// A `#` which is part of a command, unless it starts a comment
+fn hash(comments: Comments) -> impl Parser<char, char, Error = Simple<char>> + Clone {
+    filter(move |c: &char| *c == '#' && comments == Comments::DoubleHash)
+        .then_ignore(just('#').not().rewind())
+}
+
+/// The command of a directive such as `.on_error`, up to the end of its line
+fn rest_of_line(comments: Comments) -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    filter(|c: &char| *c != '\n' && *c != '\r' && *c != '#')
+        .or(hash(comments))
+        .repeated()
+        .at_least(1)
+        .collect::<String>()
+        .map(|command| command.trim().to_string())
+}
 
-    let shell = just(".shell")
+/// e.g. `-NoProfile` of `.shell pwsh -NoProfile`, stopping at a trailing comment
+fn shell_arg() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    filter(|c: &char| *c == ' ' || *c == '\t')
+        .repeated()
+        .at_least(1)
+        .ignore_then(
+            filter(|c: &char| !c.is_whitespace() && *c != '#')
+                .repeated()
+                .at_least(1)
+                .collect::<String>(),
+        )
+}
+
+/// The first line of whkdrc, e.g. `.shell pwsh -NoProfile`
+fn shell() -> impl Parser<char, (Shell, Vec<String>), Error = Simple<char>> + Clone {
+    just(".shell")
         .padded()
         .ignore_then(choice((just("pwsh"), just("powershell"), just("cmd"))))
         .repeated()
         .exactly(1)
         .collect::<String>()
-        .map(Shell::from);
+        .map(Shell::from)
+        .then(shell_arg().repeated())
+}
 
-    let mode_delimiter = just(">").padded();
-    let mode_selector = (text::ident().padded().then_ignore(mode_delimiter))
-        .or_not()
-        .map(|a| {
-            if Some(String::from("default")) == a {
-                None
-            } else {
-                a
-            }
+fn shell_name() -> impl Parser<char, Shell, Error = Simple<char>> + Clone {
+    choice((just("pwsh"), just("powershell"), just("cmd")))
+        .map(|shell: &str| Shell::from(shell.to_string()))
+}
+
+fn key(locale: Locale) -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    choice((text::ident(), text::int(10)))
+        .padded()
+        .map(move |key| normalize_key(locale.translate(key)))
+}
+
+fn hotkeys(locale: Locale) -> impl Parser<char, Vec<String>, Error = Simple<char>> + Clone {
+    key(locale).separated_by(just("+")).collect::<Vec<String>>()
+}
+
+/// The keys of a chord which can stand for several chords, e.g. `alt + {h,j,k,l}`
+/// or `alt + {1-9,0}`, see `expand`
+fn chord_pattern(
+    locale: Locale,
+) -> impl Parser<char, Vec<KeyPattern>, Error = Simple<char>> + Clone {
+    // e.g. `1-9`
+    let key_range = text::int(10)
+        .then_ignore(just("-"))
+        .then(text::int(10))
+        .padded()
+        .try_map_message(|(start, end): (String, String), _| {
+            range(&format!("{start}-{end}"))
+                .unwrap_or_else(|| Err(format!("{start}-{end} is not a range")))
         });
 
-    let change_mode_delimiter = just(";").padded();
-    let change_mode = text::ident()
+    let key_group = choice((key_range, key(locale).map(|key| vec![key])))
+        .separated_by(just(","))
+        .at_least(1)
+        .delimited_by(just("{"), just("}"))
         .padded()
-        .map(|a| if a == "default" { None } else { Some(a) });
+        .map(|elements| elements.concat());
 
-    let hotkeys = choice((text::ident(), text::int(10)))
+    choice((
+        key_group.map(KeyPattern::Group),
+        key(locale).map(KeyPattern::Key),
+    ))
+    .separated_by(just("+"))
+}
+
+fn string() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    just('"')
+        .ignore_then(filter(|c| *c != '"').repeated())
+        .then_ignore(just('"'))
+        .collect::<String>()
         .padded()
-        .separated_by(just("+"))
-        .collect::<Vec<String>>();
+}
 
-    let delimiter = just(":").padded();
+/// e.g. `(5m)` or `(90s)`
+fn period() -> impl Parser<char, Duration, Error = Simple<char>> + Clone {
+    filter(|c| *c != ')')
+        .repeated()
+        .collect::<String>()
+        .delimited_by(just("("), just(")"))
+        .try_map_message(|period, _| {
+            humantime::parse_duration(period.trim())
+                .map_err(|_| format!("invalid duration '{}', expected e.g. 5m", period.trim()))
+        })
+}
 
-    let command = choice((
-        comment,
-        text::newline(),
-        change_mode_delimiter.ignored(),
-        end(),
+fn directive(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    choice((
+        file_directive(),
+        shell_directive(comments),
+        timing_directive(),
+        output_directive(),
+        chord_directive(locale),
+        command_directive(comments, locale),
+        lint_directive(),
     ))
-    .not()
-    .repeated()
     .padded()
-    .collect::<String>();
+    .padded_by(comment().repeated())
+}
 
-    let process_name = text::ident()
+/// The directives which say how the rest of whkdrc is read and registered
+fn file_directive() -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    let version = just(".version")
         .padded()
-        .repeated()
-        .at_least(1)
-        .map(|a| a.join(" "));
+        .ignore_then(text::int(10))
+        .try_map_message(|version: String, _| {
+            version
+                .parse::<u32>()
+                .ok()
+                .filter(|version| (1..=CURRENT_VERSION).contains(version))
+                .map(Directive::Version)
+                .ok_or_else(|| format!(".version must be between 1 and {CURRENT_VERSION}"))
+        });
 
-    let process_mapping = process_name
-        .then_ignore(delimiter)
-        .then(command.clone())
+    // e.g. `.comments double-hash`
+    let comments = just(".comments")
+        .padded()
+        .ignore_then(choice((just("hash"), just("double-hash"))))
+        .to(Directive::Comments);
+
+    // e.g. `.locale de`
+    let locale = just(".locale")
+        .padded()
+        .ignore_then(text::ident())
+        .try_map_message(|name: String, _| {
+            Locale::from_name(&name)
+                .map(|_| Directive::Locale)
+                .ok_or_else(|| format!("{name} is not a known locale"))
+        });
+
+    let backend = just(".backend")
+        .padded()
+        .ignore_then(choice((
+            just("register").to(Backend::Register),
+            just("hook").to(Backend::Hook),
+        )))
+        .map(Directive::Backend);
+
+    let options = just(".options")
+        .padded()
+        .ignore_then(text::ident().separated_by(just(",").padded()).at_least(1))
+        .try_map_message(|names: Vec<String>, _| {
+            let mut options = Options::default();
+            let unknown = names
+                .iter()
+                .filter(|name| !options.enable(name))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if unknown.is_empty() {
+                Ok(Directive::Options(names))
+            } else {
+                Err(format!("unknown options: {}", unknown.join(", ")))
+            }
+        });
+
+    choice((version, comments, locale, backend, options))
+}
+
+/// The directives for the shells which run the commands
+fn shell_directive(
+    comments: Comments,
+) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    let exec = just(".exec")
+        .padded()
+        .ignore_then(choice((
+            just("session").to(Exec::Session),
+            just("direct").to(Exec::Direct),
+        )))
+        .map(Directive::Exec);
+
+    let shell_instances = just(".shell_instances")
         .padded()
-        .padded_by(comment.repeated())
+        .ignore_then(text::int(10))
+        .try_map_message(|instances: String, _| {
+            instances
+                .parse::<usize>()
+                .ok()
+                .filter(|instances| *instances > 0)
+                .map(Directive::ShellInstances)
+                .ok_or_else(|| ".shell_instances must be a positive integer".to_string())
+        });
+
+    let init_line = filter(|c: &char| *c != '\n' && *c != '\r')
         .repeated()
-        .at_least(1);
+        .collect::<String>()
+        .try_map_message(|line, _| {
+            if line.trim() == "]" {
+                Err("end of .shell_init".to_string())
+            } else {
+                Ok(line.trim().to_string())
+            }
+        })
+        .then_ignore(text::newline());
 
-    let process_command_map = just("[")
-        .ignore_then(process_mapping)
+    let shell_init = just(".shell_init")
         .padded()
-        .padded_by(comment.repeated())
-        .then_ignore(just("]"))
-        .collect::<Vec<(String, String)>>();
+        .ignore_then(just("["))
+        .ignore_then(init_line.repeated())
+        .then_ignore(just("]").padded())
+        .map(|lines| {
+            Directive::ShellInit(lines.into_iter().filter(|line| !line.is_empty()).collect())
+        });
 
-    let action = choice((
-        delimiter
-            .ignore_then(command)
-            .then(change_mode_delimiter.ignore_then(change_mode).or_not())
-            .map(|(a, b)| (Some(a), b)),
-        change_mode_delimiter
-            .ignore_then(change_mode)
-            .map(|a| (None, Some(a))),
-    ));
+    // e.g. `.on_error msg * "whkd: $env:WHKD_ERROR"`
+    let on_error = just(".on_error")
+        .padded()
+        .ignore_then(rest_of_line(comments))
+        .map(Directive::OnError);
 
-    let binding = mode_selector.then(hotkeys).then(action);
-    let process_bindings = hotkeys.then(process_command_map);
+    choice((
+        exec,
+        shell_instances,
+        shell_init,
+        seconds(".shell_idle", Directive::ShellIdle),
+        seconds(".shell_health_check", Directive::ShellHealthCheck),
+        on_error,
+    ))
+}
 
-    shell
-        .then(
-            process_bindings
-                .map(|(keys, apps_commands)| {
-                    let mut collected = vec![];
-                    for (app, command) in apps_commands {
-                        collected.push(HotkeyBinding {
-                            mode: None,
-                            keys: keys.clone(),
-                            command: Some(command),
-                            internal_action: None,
-                            process_name: Option::from(app),
-                        });
-                    }
+/// A directive such as `.timeout 500` which takes a number of milliseconds
+fn millis(
+    name: &'static str,
+    directive: fn(Duration) -> Directive,
+) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    just(name)
+        .padded()
+        .ignore_then(text::int(10))
+        .try_map_message(move |millis: String, _| {
+            millis
+                .parse::<u64>()
+                .map(|millis| directive(Duration::from_millis(millis)))
+                .map_err(|_| format!("{name} must be a number of milliseconds"))
+        })
+}
 
-                    (keys, collected)
-                })
-                .padded()
-                .padded_by(comment.repeated())
+/// A directive such as `.shell_idle 300` which takes a positive number of
+/// seconds
+fn seconds(
+    name: &'static str,
+    directive: fn(Duration) -> Directive,
+) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    just(name)
+        .padded()
+        .ignore_then(text::int(10))
+        .try_map_message(move |seconds: String, _| {
+            seconds
+                .parse::<u64>()
+                .ok()
+                .filter(|seconds| *seconds > 0)
+                .map(|seconds| directive(Duration::from_secs(seconds)))
+                .ok_or_else(|| format!("{name} must be a positive number of seconds"))
+        })
+}
+
+/// The directives for when and how often bindings fire
+fn timing_directive() -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    // e.g. `.wait_for_process komorebi.exe`
+    let wait_for_process = just(".wait_for_process")
+        .padded()
+        .ignore_then(
+            filter(|c: &char| !matches!(c, '\n' | '\r' | '#'))
                 .repeated()
-                .at_least(0),
+                .at_least(1)
+                .collect::<String>(),
         )
+        .try_map_message(|image, _| {
+            let image = image.trim();
+            if image.is_empty() {
+                Err(".wait_for_process needs a process name".to_string())
+            } else {
+                Ok(Directive::WaitForProcess(image.to_string()))
+            }
+        });
+
+    // e.g. `.rate_limit 10 drop`
+    let rate_limit = just(".rate_limit")
+        .padded()
+        .ignore_then(text::int(10))
         .then(
-            binding
-                .map(|((mode, keys), (command, internal_action))| HotkeyBinding {
-                    mode,
-                    keys,
-                    command,
-                    internal_action,
-                    process_name: None,
+            choice((
+                just("queue").to(RatePolicy::Queue),
+                just("coalesce").to(RatePolicy::Coalesce),
+                just("drop").to(RatePolicy::Drop),
+            ))
+            .padded()
+            .or_not(),
+        )
+        .try_map_message(|(per_second, policy): (String, _), _| {
+            per_second
+                .parse::<u32>()
+                .ok()
+                .filter(|per_second| *per_second > 0)
+                .map(|per_second| {
+                    Directive::RateLimit(RateLimit {
+                        per_second,
+                        policy: policy.unwrap_or_default(),
+                    })
                 })
-                .padded()
-                .padded_by(comment.repeated())
+                .ok_or_else(|| ".rate_limit must be a positive number of commands".to_string())
+        });
+
+    choice((
+        millis(".tap_threshold", Directive::TapThreshold),
+        // e.g. `.sticky_modifiers 1000`, for entering chords one key at a time
+        millis(".sticky_modifiers", Directive::StickyModifiers),
+        // e.g. `.rollover 80`, for rolling chords such as `j + k`
+        millis(".rollover", Directive::Rollover),
+        millis(".timeout", Directive::Timeout),
+        millis(".startup_delay", Directive::StartupDelay),
+        wait_for_process,
+        rate_limit,
+    ))
+}
+
+/// The directives for what whkd shows, plays and publishes besides running
+/// commands
+fn output_directive() -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    // e.g. `.mqtt broker 192.168.1.10:1883`, optionally followed by a username
+    // and password
+    let mqtt = just(".mqtt")
+        .padded()
+        .ignore_then(text::keyword("broker"))
+        .ignore_then(shell_arg())
+        .then(shell_arg().then(shell_arg()).or_not())
+        .map(|(address, login)| Directive::Mqtt(MqttBroker { address, login }))
+        // see `mode_shell`
+        .boxed();
+
+    // e.g. `.state_file ~/whkd.json`, or `.state_file` for the default path
+    let state_file = just(".state_file")
+        .ignore_then(
+            filter(|c: &char| !matches!(c, '\n' | '\r' | '#'))
                 .repeated()
-                .at_least(1),
+                .collect::<String>(),
         )
-        .map(|((shell, app_bindings), bindings)| Whkdrc {
-            shell,
-            app_bindings,
-            bindings,
+        .map(|path| {
+            let path = path.trim();
+            Directive::StateFile(if path.is_empty() {
+                state::default_path()
+            } else {
+                state::expand(path)
+            })
+        });
+
+    // e.g. `.include-cmd "komorebic print-whkd-bindings"`
+    let include_cmd = just(".include-cmd")
+        .ignore_then(string())
+        .try_map_message(|command, _| {
+            if command.trim().is_empty() {
+                Err(".include-cmd can't run an empty command".to_string())
+            } else {
+                Ok(Directive::IncludeCmd(command))
+            }
         })
-}
+        // see `mode_shell`
+        .boxed();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // e.g. `.redact "--password"`, which masks the word after it
+    let redact = just(".redact")
+        .ignore_then(string())
+        .try_map_message(|pattern, _| {
+            if pattern.trim().is_empty() {
+                Err(".redact needs a pattern to look for".to_string())
+            } else {
+                Ok(Directive::Redact(pattern))
+            }
+        })
+        // see `mode_shell`
+        .boxed();
+
+    // e.g. `.echo off`, which keeps every command out of the output
+    let echo = just(".echo")
+        .padded()
+        .ignore_then(choice((
+            text::keyword("on").to(true),
+            text::keyword("off").to(false),
+        )))
+        .map(Directive::Echo);
+
+    // e.g. `.mode_sound "C:\sounds\mode.wav"`, played whenever the mode changes
+    let mode_sound = just(".mode_sound")
+        .ignore_then(string())
+        .map(|path| Directive::ModeSound(PathBuf::from(path)));
+
+    // e.g. `.mode_indicator scroll_lock` or `.mode_indicator cmd "set-led.ps1"`
+    let mode_indicator = just(".mode_indicator")
+        .padded()
+        .ignore_then(choice((
+            text::keyword("caps_lock").to(ModeIndicator::Lock(LockKey::Caps)),
+            text::keyword("num_lock").to(ModeIndicator::Lock(LockKey::Num)),
+            text::keyword("scroll_lock").to(ModeIndicator::Lock(LockKey::Scroll)),
+            text::keyword("cmd")
+                .ignore_then(string())
+                .map(ModeIndicator::Command),
+        )))
+        .map(Directive::ModeIndicator);
+
+    choice((
+        mqtt,
+        state_file,
+        include_cmd,
+        redact,
+        echo,
+        mode_sound,
+        mode_indicator,
+    ))
+}
+
+/// The directives for which chords are registered in which apps and modes, and
+/// the shells of the modes
+fn chord_directive(locale: Locale) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    let suppress = just(".suppress")
+        .padded()
+        .ignore_then(hotkeys(locale).separated_by(just(",")).at_least(1))
+        .map(Directive::Suppress);
+
+    // e.g. `.app_mode "Google Chrome" browser`
+    let app_mode = just(".app_mode")
+        .padded()
+        .ignore_then(
+            none_of("\"\n")
+                .repeated()
+                .at_least(1)
+                .collect::<String>()
+                .delimited_by(just('"'), just('"')),
+        )
+        .then(text::ident().padded())
+        .map(|(process_name, mode)| Directive::AppMode(process_name, mode));
+
+    // e.g. `.layer capslock nav`
+    let layer = just(".layer")
+        .padded()
+        .ignore_then(key(locale))
+        .then(text::ident().padded())
+        .map(|(key, mode)| Directive::Layer(key, mode));
+
+    // e.g. `.mode legacy shell cmd`
+    let mode_shell = just(".mode")
+        .padded()
+        .ignore_then(text::ident())
+        .then_ignore(text::keyword("shell").padded())
+        .then(shell_name())
+        .try_map_message(|(mode, shell): (String, Shell), _| {
+            if mode == "default" {
+                Err("the shell of the default mode is set with `.shell`".to_string())
+            } else {
+                Ok(Directive::ModeShell(mode, shell))
+            }
+        })
+        // keeps the parser within the size of the stack of the main thread
+        .boxed();
+
+    // e.g. `.mode resize count`
+    let mode_count = just(".mode")
+        .padded()
+        .ignore_then(text::ident())
+        .then_ignore(text::keyword("count").padded())
+        .try_map_message(|mode: String, _| {
+            if mode == "default" {
+                Err("the digits of the default mode can't add up to a count".to_string())
+            } else {
+                Ok(Directive::ModeCount(mode))
+            }
+        })
+        .boxed();
+
+    // e.g. `.ignore mstsc, VirtualBox VM`
+    let ignore = just(".ignore")
+        .padded()
+        .ignore_then(
+            filter(|c: &char| !matches!(c, ',' | '\n' | '\r' | '#'))
+                .repeated()
+                .at_least(1)
+                .collect::<String>()
+                .map(|process_name| process_name.trim().to_string())
+                .separated_by(just(","))
+                .at_least(1),
+        )
+        .map(Directive::Ignore);
+
+    // e.g. `.remote_session mode remote`
+    let remote_session = just(".remote_session")
+        .padded()
+        .ignore_then(choice((
+            just("keep").to(RemotePolicy::Keep),
+            just("pause").to(RemotePolicy::Pause),
+            text::keyword("mode")
+                .padded()
+                .ignore_then(text::ident())
+                .map(RemotePolicy::Mode),
+        )))
+        .map(Directive::RemoteSession);
+
+    choice((
+        suppress,
+        app_mode,
+        layer,
+        mode_shell,
+        mode_count,
+        ignore,
+        remote_session,
+    ))
+}
+
+/// The directives which define commands for the bindings to use
+fn command_directive(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    let alias = just(".alias")
+        .padded()
+        .ignore_then(text::ident())
+        .then(rest_of_line(comments))
+        .map(|(name, expansion)| Directive::Alias(name, expansion));
+
+    // e.g. `.template focus(dir) = komorebic focus {dir}`
+    let template = just(".template")
+        .padded()
+        .ignore_then(text::ident())
+        .then(
+            text::ident()
+                .padded()
+                .separated_by(just(","))
+                .delimited_by(just("("), just(")")),
+        )
+        .then_ignore(just("=").padded())
+        .then(rest_of_line(comments))
+        .map(|((name, params), body)| Directive::Template(name, Template { params, body }));
+
+    choice((alias, template, macro_definition(comments, locale)))
+}
+
+/// e.g. `.macro paste_date { komorebic focus-exe notepad.exe; @sleep 300ms; @send ctrl + v }`
+fn macro_definition(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    just(".macro")
+        .padded()
+        .ignore_then(text::ident())
+        .then(steps(comments, locale))
+        .map(|(name, steps)| Directive::Macro(name, steps))
+}
+
+/// The steps of a `.macro` or a `:: chain` between braces, separated by `;` or
+/// new lines
+fn steps(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Vec<Step>, Error = Simple<char>> + Clone {
+    // e.g. `300ms` or `2s`, in milliseconds without a unit
+    let delay = text::int(10)
+        .then(choice((just("ms"), just("s"))).or_not())
+        .try_map_message(|(amount, unit): (String, Option<&str>), _| {
+            let amount = amount
+                .parse::<u64>()
+                .map_err(|_| "expected a number of milliseconds".to_string())?;
+
+            Ok(match unit {
+                Some("s") => Duration::from_secs(amount),
+                _ => Duration::from_millis(amount),
+            })
+        });
+
+    let macro_step = choice((
+        just("@sleep").ignore_then(delay.padded()).map(Step::Sleep),
+        just("@send")
+            .ignore_then(hotkeys(locale))
+            .try_map_message(|keys, _| match hook::Chord::parse(&keys) {
+                Some(_) => Ok(Step::Send(keys)),
+                None => Err(format!(
+                    "{} has a key which whkd doesn't know",
+                    keys.join(" + ")
+                )),
+            }),
+        native_action().map(Step::Action),
+        // a misspelled step starting with `@` isn't taken for a command
+        filter(|c: &char| !matches!(c, '@' | ';' | '}' | '\n' | '\r' | '#'))
+            .chain(
+                filter(|c: &char| !matches!(c, ';' | '}' | '\n' | '\r' | '#'))
+                    .or(hash(comments))
+                    .repeated(),
+            )
+            .collect::<String>()
+            .map(|command| Step::Command(command.trim().to_string())),
+    ));
+
+    macro_step
+        .then_ignore(just(";").or_not())
+        .padded()
+        .padded_by(comment().repeated())
+        .repeated()
+        .at_least(1)
+        .delimited_by(just("{").padded(), just("}"))
+}
+
+fn lint_directive() -> impl Parser<char, Directive, Error = Simple<char>> + Clone {
+    let strict = just(".strict").to(Directive::Strict);
+
+    // e.g. `.allow duplicate_chord, unreachable_mode`
+    let lint_level = choice((
+        just(".allow").to(Level::Allow),
+        just(".deny").to(Level::Deny),
+    ))
+    .then(text::ident().padded().separated_by(just(",")).at_least(1))
+    .try_map_message(|(level, names): (Level, Vec<String>), _| {
+        let unknown = names
+            .iter()
+            .filter(|name| Lint::from_name(name).is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if unknown.is_empty() {
+            Ok(Directive::Lints(
+                level,
+                names
+                    .iter()
+                    .filter_map(|name| Lint::from_name(name))
+                    .collect(),
+            ))
+        } else {
+            Err(format!("unknown lints: {}", unknown.join(", ")))
+        }
+    });
+
+    // e.g. `.on_unknown_key skip`, another way of setting the level of the
+    // unknown_key lint
+    let on_unknown_key = just(".on_unknown_key")
+        .ignore_then(
+            choice((
+                text::keyword("skip").to(Level::Allow),
+                text::keyword("warn").to(Level::Warn),
+                text::keyword("error").to(Level::Deny),
+            ))
+            .padded(),
+        )
+        .map(|level| Directive::Lints(level, vec![Lint::UnknownKey]));
+
+    choice((strict, lint_level, on_unknown_key))
+}
+
+fn window_action() -> impl Parser<char, WindowAction, Error = Simple<char>> + Clone {
+    let monitor_target = choice((
+        text::keyword("next").to(MonitorTarget::Next),
+        text::keyword("prev").to(MonitorTarget::Prev),
+        text::int(10).try_map_message(|number: String, _| match number.parse() {
+            Ok(number) if number > 0 => Ok(MonitorTarget::Number(number)),
+            _ => Err("monitors are numbered from 1".to_string()),
+        }),
+    ));
+
+    choice((
+        just("@minimize").to(WindowAction::Minimize),
+        just("@maximize").to(WindowAction::Maximize),
+        just("@close").to(WindowAction::Close),
+        just("@toggle-topmost").to(WindowAction::ToggleTopmost),
+        just("@center").to(WindowAction::Center),
+        just("@move-to-monitor")
+            .ignore_then(monitor_target.clone().padded())
+            .map(WindowAction::MoveToMonitor),
+        just("@focus-monitor")
+            .ignore_then(monitor_target.padded())
+            .map(WindowAction::FocusMonitor),
+    ))
+}
+
+fn display_action() -> impl Parser<char, DisplayAction, Error = Simple<char>> + Clone {
+    choice((
+        text::keyword("extend").to(DisplayAction::Extend),
+        text::keyword("duplicate").to(DisplayAction::Duplicate),
+        text::keyword("internal").to(DisplayAction::Internal),
+        text::keyword("external").to(DisplayAction::External),
+        text::keyword("primary")
+            .ignore_then(text::int(10).padded())
+            .try_map_message(|number: String, _| match number.parse() {
+                Ok(number) if number > 0 => Ok(DisplayAction::Primary(number)),
+                _ => Err("monitors are numbered from 1".to_string()),
+            }),
+    ))
+}
+
+/// A step of `up` or `down` in percentage points, negative when going down
+fn step() -> impl Parser<char, i32, Error = Simple<char>> + Clone {
+    choice((text::keyword("up").to(1), text::keyword("down").to(-1)))
+        .then(text::int(10).padded())
+        .try_map_message(|(sign, step): (i32, String), _| match step.parse::<i32>() {
+            Ok(step) if step <= 100 => Ok(sign * step),
+            _ => Err("steps are at most 100 percentage points".to_string()),
+        })
+}
+
+/// The actions written with `@`, see `InternalAction::is_native`
+fn native_action() -> impl Parser<char, InternalAction, Error = Simple<char>> + Clone {
+    choice((
+        just("@clipboard-set")
+            .ignore_then(string())
+            .map(InternalAction::ClipboardSet),
+        just("@clipboard-paste-as-keystrokes").to(InternalAction::ClipboardPaste),
+        just("@clipboard-clear").to(InternalAction::ClipboardClear),
+        just("@focus-or-launch")
+            .ignore_then(string())
+            .map(InternalAction::FocusOrLaunch),
+        just("@terminal")
+            .ignore_then(string())
+            .map(InternalAction::Terminal),
+        // e.g. `@mqtt home/desk/light "toggle"`
+        just("@mqtt")
+            .ignore_then(
+                filter(|c: &char| !c.is_whitespace() && *c != '"')
+                    .repeated()
+                    .at_least(1)
+                    .collect::<String>()
+                    .padded(),
+            )
+            .then(string())
+            .map(|(topic, payload)| InternalAction::Mqtt { topic, payload }),
+        window_action().map(InternalAction::Window),
+        just("@display")
+            .ignore_then(display_action().padded())
+            .map(InternalAction::Display),
+        just("@volume").ignore_then(
+            choice((
+                just("mute-toggle").to(InternalAction::ToggleMute),
+                step().map(InternalAction::Volume),
+            ))
+            .padded(),
+        ),
+        just("@brightness")
+            .ignore_then(step().padded())
+            .map(InternalAction::Brightness),
+    ))
+    // see `mode_shell`
+    .boxed()
+}
+
+/// What a binding does after `;`, e.g. switching to a mode
+fn change_mode(locale: Locale) -> impl Parser<char, InternalAction, Error = Simple<char>> + Clone {
+    choice((
+        native_action(),
+        just("run-macro")
+            .padded()
+            .ignore_then(text::ident())
+            .map(InternalAction::RunMacro),
+        // e.g. `trigger name(screenshot)`
+        text::keyword("trigger")
+            .padded()
+            .ignore_then(just("name"))
+            .ignore_then(text::ident().padded().delimited_by(just("("), just(")")))
+            .map(InternalAction::TriggerName),
+        // e.g. `trigger resize/h` or `trigger alt + h` for the active mode
+        text::keyword("trigger")
+            .padded()
+            .ignore_then(text::ident().then_ignore(just("/")).or_not())
+            .then(hotkeys(locale))
+            .map(|(mode, keys)| InternalAction::Trigger { mode, keys }),
+        text::keyword("profile")
+            .padded()
+            .ignore_then(text::ident())
+            .map(InternalAction::Profile),
+        text::keyword("enable")
+            .padded()
+            .ignore_then(text::ident())
+            .map(InternalAction::EnableTag),
+        text::keyword("disable")
+            .padded()
+            .ignore_then(text::ident())
+            .map(InternalAction::DisableTag),
+        just("toggle-binding")
+            .padded()
+            .ignore_then(text::ident())
+            .map(InternalAction::ToggleBinding),
+        just("repeat-last").to(InternalAction::RepeatLast),
+        just("edit-config").to(InternalAction::EditConfig),
+        text::keyword("help").to(InternalAction::Help),
+        text::keyword("shell")
+            .padded()
+            .ignore_then(shell_name())
+            .map(InternalAction::Shell)
+            // see `mode_shell`
+            .boxed(),
+        just("next-mode").to(InternalAction::NextMode),
+        just("prev-mode").to(InternalAction::PrevMode),
+        just("mode-back").to(InternalAction::ModeBack),
+        text::ident().map(|a| InternalAction::Mode(if a == "default" { None } else { Some(a) })),
+    ))
+    .padded()
+}
+
+/// The attributes of a binding which decide whether it fires
+fn condition() -> impl Parser<char, Attribute, Error = Simple<char>> + Clone {
+    let when_cmd = just("~when-cmd")
+        .ignore_then(
+            string()
+                .then_ignore(just(","))
+                .then(string())
+                .delimited_by(just("("), just(")")),
+        )
+        .map(|(command, expected)| Attribute::Condition(Condition::WhenCmd { command, expected }));
+
+    let when = just("~when")
+        .ignore_then(string().delimited_by(just("("), just(")")))
+        .try_map_message(|expression, _| {
+            Expression::parse(&expression)
+                .map(|expression| Attribute::Condition(Condition::When(expression)))
+        });
+
+    let time = filter(|c: &char| c.is_ascii_digit() || *c == ':')
+        .repeated()
+        .at_least(1)
+        .collect::<String>()
+        .padded()
+        .try_map_message(|time, _| {
+            NaiveTime::parse_from_str(&time, "%H:%M")
+                .map_err(|_| format!("invalid time '{time}', expected HH:MM"))
+        });
+
+    let between = just("~between")
+        .ignore_then(
+            time.clone()
+                .then_ignore(just(","))
+                .then(time)
+                .delimited_by(just("("), just(")")),
+        )
+        .map(|(start, end)| Attribute::Condition(Condition::Between { start, end }));
+
+    let days = just("~days")
+        .ignore_then(
+            filter(|c| *c != ')')
+                .repeated()
+                .collect::<String>()
+                .delimited_by(just("("), just(")")),
+        )
+        .try_map_message(|days, _| {
+            parse_days(&days).map(|days| Attribute::Condition(Condition::Days(days)))
+        });
+
+    let only_when_idle = just("~only-when-idle")
+        .ignore_then(period())
+        .map(|idle| Attribute::Condition(Condition::Idle(idle)));
+
+    let only_when_active = just("~only-when-active")
+        .ignore_then(period().or_not())
+        .map(|within| Attribute::Condition(Condition::Active(within.unwrap_or(ACTIVE_WITHIN))));
+
+    choice((
+        when_cmd,
+        when,
+        between,
+        days,
+        only_when_idle,
+        only_when_active,
+    ))
+}
+
+fn braced_command() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    braced(MAX_BRACE_DEPTH)
+        .map(|command| command.trim().to_string())
+        .padded()
+}
+
+/// The attributes written with `~` between the chord and the action of a
+/// binding
+fn attributes() -> impl Parser<char, Vec<Attribute>, Error = Simple<char>> + Clone {
+    // `@tag` is still accepted from before attributes were written with `~`
+    let tag = choice((just("~tag"), just("@tag")))
+        .ignore_then(
+            text::ident()
+                .padded()
+                .separated_by(just(","))
+                .at_least(1)
+                .delimited_by(just("("), just(")")),
+        )
+        .map(Attribute::Tags);
+
+    // e.g. `(300)` or `(300ms)`
+    let millis = text::int(10)
+        .then_ignore(just("ms").or_not())
+        .padded()
+        .delimited_by(just("("), just(")"))
+        .try_map_message(|millis: String, _| {
+            millis
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| "expected a number of milliseconds".to_string())
+        });
+
+    // e.g. `~on-fail { msg "toggling failed" }`, in braces as `:` already ends
+    // the attributes
+    let on_fail = just("~on-fail")
+        .ignore_then(braced_command())
+        .try_map_message(|command, _| {
+            if command.is_empty() {
+                Err("~on-fail can't run an empty command".to_string())
+            } else {
+                Ok(Attribute::OnFail(command))
+            }
+        });
+
+    // e.g. `~notify("Moved window left")`, `~sound("C:\sounds\click.wav")` or
+    // `~confirm("Really shut down?")`
+    let quoted = || string().delimited_by(just("("), just(")"));
+
+    choice((
+        tag,
+        condition(),
+        just("~passthrough").to(Attribute::Passthrough),
+        just("~timeout")
+            .ignore_then(millis.clone())
+            .map(Attribute::Timeout),
+        just("~cooldown")
+            .ignore_then(millis)
+            .map(Attribute::Cooldown),
+        just("~wait").to(Attribute::Wait),
+        on_fail,
+        just("~on-error")
+            .ignore_then(quoted())
+            .map(Attribute::OnError),
+        just("~allow-recursion").to(Attribute::AllowRecursion),
+        just("~respect-dnd").to(Attribute::RespectDnd),
+        just("~notify").ignore_then(quoted()).map(Attribute::Notify),
+        just("~sound")
+            .ignore_then(quoted())
+            .map(|path| Attribute::Sound(PathBuf::from(path))),
+        just("~confirm")
+            .ignore_then(quoted())
+            .map(Attribute::Confirm),
+        just("~redact").to(Attribute::Redact),
+        just("~silent").to(Attribute::Silent),
+        // e.g. `~name(screenshot)`
+        just("~name")
+            .ignore_then(text::ident().padded().delimited_by(just("("), just(")")))
+            .map(Attribute::Name),
+    ))
+    .padded()
+    .repeated()
+}
+
+/// `:` before the command of a binding, where `::` starts a cycle instead
+fn delimiter() -> impl Parser<char, &'static str, Error = Simple<char>> + Clone {
+    just(":").then_ignore(just(":").not().rewind()).padded()
+}
+
+/// A `\` at the end of a line continues the command on the next line
+fn continuation() -> impl Parser<char, (), Error = Simple<char>> + Clone {
+    just('\\')
+        .then(filter(|c: &char| *c == ' ' || *c == '\t').repeated())
+        .then(text::newline())
+        .ignored()
+}
+
+/// Everything between fences of three backticks, which is taken literally, including `#`, `;`
+/// and newlines
+fn raw_command() -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    just("```")
+        .ignore_then(take_until(just("```")))
+        .map(|(command, _)| command.into_iter().collect::<String>().trim().to_string())
+        .padded()
+}
+
+/// The command of a binding, up to the end of its line or a `;`
+fn command(comments: Comments) -> impl Parser<char, String, Error = Simple<char>> + Clone {
+    // `\;` and `\#` don't end the command
+    let escape = just('\\').ignore_then(one_of(";#"));
+
+    // most characters can't start anything which ends the command, so they are
+    // taken before trying the parsers which could
+    let plain = filter(|c: &char| {
+        !matches!(
+            c,
+            '\\' | '#' | ';' | '\n' | '\r' | '\x0B' | '\x0C' | '\u{85}' | '\u{2028}' | '\u{2029}'
+        )
+    });
+
+    let command = choice((
+        plain,
+        escape,
+        hash(comments),
+        choice((
+            continuation(),
+            comment(),
+            text::newline(),
+            just(";").padded().ignored(),
+            end(),
+        ))
+        .not(),
+    ))
+    .repeated()
+    .collect::<String>()
+    .separated_by(continuation())
+    .padded()
+    .map(|lines| {
+        lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+
+    choice((raw_command(), command))
+}
+
+/// A cycle, a toggle or a chain after `::`
+fn stateful(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, InternalAction, Error = Simple<char>> + Clone {
+    // `\|` and `\]` don't end a command of a cycle
+    let cycle_escape = just('\\').ignore_then(one_of("|]"));
+    let cycle_command = choice((
+        raw_command(),
+        choice((cycle_escape, none_of("|]")))
+            .repeated()
+            .at_least(1)
+            .collect::<String>()
+            .map(|command| command.trim().to_string()),
+    ));
+
+    // e.g. `alt + t :: [ cmd1 | cmd2 | cmd3 ]`
+    let cycle = cycle_command
+        .separated_by(just("|"))
+        .at_least(1)
+        .delimited_by(just("["), just("]"))
+        .try_map_message(|commands: Vec<String>, _| {
+            if commands.iter().any(String::is_empty) {
+                Err("a cycle can't contain empty commands".to_string())
+            } else {
+                Ok(InternalAction::Cycle(commands))
+            }
+        });
+
+    // e.g. `alt + m :: toggle { mute-on } { mute-off } probe { is-muted }`
+    let toggle = text::keyword("toggle")
+        .padded()
+        .ignore_then(braced_command())
+        .then(braced_command())
+        .then(
+            text::keyword("probe")
+                .ignore_then(braced_command())
+                .or_not(),
+        )
+        .try_map_message(|((on, off), probe), _| {
+            if on.is_empty() || off.is_empty() || probe.as_ref().is_some_and(String::is_empty) {
+                Err("a toggle can't contain empty commands".to_string())
+            } else {
+                Ok(InternalAction::Toggle { on, off, probe })
+            }
+        });
+
+    // e.g. `alt + d :: chain { komorebic focus-exe notepad.exe; @sleep 300ms; notepad-macro.exe }`
+    let chain = text::keyword("chain")
+        .padded()
+        .ignore_then(steps(comments, locale))
+        .try_map_message(|steps: Vec<Step>, _| {
+            if steps.iter().all(|step| matches!(step, Step::Sleep(_))) {
+                Err("a chain can't contain only `@sleep`".to_string())
+            } else {
+                Ok(InternalAction::Chain(steps))
+            }
+        });
+
+    just("::")
+        .padded()
+        .ignore_then(choice((cycle, toggle, chain)))
+}
+
+/// The command and the internal action of a binding, either of which can be
+/// left out
+fn action(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, (Option<String>, Option<InternalAction>), Error = Simple<char>> + Clone {
+    let change_mode_delimiter = just(";").padded();
+
+    choice((
+        stateful(comments, locale).map(|action| (None, Some(action))),
+        delimiter()
+            .ignore_then(command(comments))
+            .then(
+                change_mode_delimiter
+                    .ignore_then(change_mode(locale))
+                    .or_not(),
+            )
+            .map(|(a, b)| (Some(a), b)),
+        change_mode_delimiter
+            .ignore_then(change_mode(locale))
+            .map(|a| (None, Some(a))),
+    ))
+}
+
+/// The bindings of every chord of a line without their modes, which are set
+/// once they are all known
+fn chord_binding(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Vec<HotkeyBinding>, Error = Simple<char>> + Clone {
+    // e.g. `alt + h | alt + left`, bound to the same action
+    let alternatives = chord_pattern(locale)
+        .separated_by(just("|").padded())
+        .at_least(1)
+        .try_map_message(|alternatives: Vec<Vec<KeyPattern>>, _| {
+            if alternatives.len() > 1 && alternatives.iter().any(Vec::is_empty) {
+                Err("expected a chord on both sides of `|`".to_string())
+            } else {
+                Ok(alternatives)
+            }
+        });
+
+    // where the line of a binding ends, following `\` on to the next lines
+    let line_end = choice((continuation(), filter(|c: &char| *c != '\n').ignored()))
+        .repeated()
+        .map_with_span(|_, span: std::ops::Range<usize>| span.end)
+        .rewind();
+
+    // e.g. `## Focus window to the left`, which only describes the binding when
+    // it is on the same line, as it is an ordinary comment on a line of its own
+    let description = text::whitespace()
+        .ignore_then(just("##").map_with_span(|_, span: std::ops::Range<usize>| span.start))
+        .then(take_until(text::newline().or(end())))
+        .map(|(start, (text, ()))| (start, text.into_iter().collect::<String>()));
+
+    line_end
+        .then(alternatives)
+        .then(attributes())
+        .then(action(comments, locale))
+        .then(description.or_not())
+        .try_map_message(
+            |(
+                (((line_end, alternatives), attributes), (command, internal_action)),
+                description,
+            ),
+             span| {
+                let mut binding = HotkeyBinding {
+                    command,
+                    internal_action,
+                    description: description
+                        .filter(|(start, _)| *start < line_end)
+                        .map(|(_, text)| text.trim().to_string())
+                        .filter(|text| !text.is_empty()),
+                    line: span.start,
+                    ..Default::default()
+                };
+
+                for attribute in attributes {
+                    attribute.apply(&mut binding);
+                }
+
+                let mut bindings = vec![];
+                for pattern in &alternatives {
+                    bindings.extend(expand(pattern, &binding)?);
+                }
+
+                Ok(bindings)
+            },
+        )
+}
+
+/// e.g. `mode resize : window`, `mode resize { ... }` or both at once
+fn mode(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Item, Error = Simple<char>> + Clone {
+    // the bindings of a mode without the `<mode> >` prefix on every line, up to a
+    // `}` on its own line
+    let mode_block = chord_binding(comments, locale)
+        .padded()
+        .padded_by(comment().repeated())
+        .repeated()
+        .delimited_by(just("{"), just("}"));
+
+    text::keyword("mode")
+        .padded()
+        .ignore_then(text::ident())
+        .then(just(":").padded().ignore_then(text::ident()).or_not())
+        .then(mode_block.padded().or_not())
+        .try_map_message(
+            |((mode, parent), block): ((String, Option<String>), _), _| {
+                let mut items = vec![];
+                match parent {
+                    Some(_) if mode == "default" => {
+                        return Err(
+                            "the default mode can't inherit the bindings of another mode"
+                                .to_string(),
+                        )
+                    }
+                    Some(parent) if parent == mode => {
+                        return Err(format!("mode {mode} can't inherit from itself"))
+                    }
+                    Some(parent) => items.push(Item::Inherit(
+                        mode.clone(),
+                        (parent != "default").then_some(parent),
+                    )),
+                    None if block.is_none() => {
+                        return Err(format!("mode {mode} needs a parent or a block of bindings"))
+                    }
+                    None => {}
+                }
+
+                let modes = Modes::Listed(vec![(mode != "default").then_some(mode)]);
+                for binding in block.into_iter().flatten().flatten() {
+                    items.push(Item::Binding(modes.clone(), binding));
+                }
+
+                Ok(Item::Block(items))
+            },
+        )
+}
+
+/// e.g. `alt + n [ Firefox : echo "hello firefox" ]`, a command for each app
+fn app_bindings(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Item, Error = Simple<char>> + Clone {
+    let process_name = text::ident()
+        .padded()
+        .repeated()
+        .at_least(1)
+        .map(|a| a.join(" "));
+
+    let process_mapping = process_name
+        .then_ignore(delimiter())
+        .then(command(comments))
+        .map_with_span(|(app, command), span: std::ops::Range<usize>| (app, command, span.start))
+        .padded()
+        .padded_by(comment().repeated())
+        .repeated()
+        .at_least(1);
+
+    let process_command_map = just("[")
+        .ignore_then(process_mapping)
+        .padded()
+        .padded_by(comment().repeated())
+        .then_ignore(just("]"))
+        .collect::<Vec<(String, String, usize)>>();
+
+    hotkeys(locale)
+        .then(process_command_map)
+        .map(|(keys, apps_commands)| {
+            let mut collected = vec![];
+            for (app, command, offset) in apps_commands {
+                collected.push(HotkeyBinding {
+                    keys: keys.clone(),
+                    command: Some(command),
+                    process_name: Option::from(app),
+                    // turned into the line once the whole file has been parsed
+                    line: offset,
+                    ..Default::default()
+                });
+            }
+
+            Item::AppBindings(keys, collected)
+        })
+}
+
+fn item(
+    comments: Comments,
+    locale: Locale,
+) -> impl Parser<char, Item, Error = Simple<char>> + Clone {
+    let mode_selector = (text::ident().padded().then_ignore(just(">").padded()))
+        .or_not()
+        .map(|a| {
+            if Some(String::from("default")) == a {
+                None
+            } else {
+                a
+            }
+        });
+
+    let mode_list = choice((
+        just("*").padded().to(Modes::All),
+        text::ident()
+            .padded()
+            .map(|mode: String| (mode != "default").then_some(mode))
+            .separated_by(just(","))
+            .at_least(1)
+            .map(Modes::Listed),
+    ))
+    .then_ignore(just(">").padded())
+    .or_not()
+    .map(|modes| modes.unwrap_or_else(|| Modes::Listed(vec![None])));
+
+    let profile = just(".profile")
+        .padded()
+        .ignore_then(text::ident())
+        .map(Item::Profile);
+
+    let unbind = text::keyword("unbind")
+        .padded()
+        .ignore_then(mode_selector)
+        .then(hotkeys(locale))
+        .map(|(mode, keys)| Item::Unbind(mode, keys));
+
+    // e.g. `@on-idle(10m) : rundll32 user32.dll,LockWorkStation`
+    let on_idle = just("@on-idle")
+        .ignore_then(period())
+        .then_ignore(delimiter())
+        .then(command(comments))
+        .try_map_message(|(idle, command), _| {
+            if command.is_empty() {
+                Err("@on-idle can't run an empty command".to_string())
+            } else {
+                Ok(Item::OnIdle(idle, command))
+            }
+        });
+
+    let binding = mode_list
+        .then(chord_binding(comments, locale))
+        .map(|(modes, bindings)| {
+            Item::Block(
+                bindings
+                    .into_iter()
+                    .map(|binding| Item::Binding(modes.clone(), binding))
+                    .collect(),
+            )
+        });
+
+    choice((
+        profile,
+        unbind,
+        on_idle,
+        mode(comments, locale),
+        app_bindings(comments, locale),
+        binding,
+    ))
+    .padded()
+    .padded_by(comment().repeated())
+}
+
+/// Collect the directives and items of whkdrc into a `Whkdrc`, with `source`
+/// to turn the offsets of the bindings into lines
+fn build(
+    source: &[char],
+    shell: Shell,
+    shell_args: Vec<String>,
+    directives: Vec<Directive>,
+    items: Vec<Item>,
+) -> Result<Whkdrc, String> {
+    // offsets of the line breaks, so that large files aren't scanned from the
+    // start for every binding
+    let newlines = source
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| **c == '\n')
+        .map(|(offset, _)| offset)
+        .collect::<Vec<_>>();
+
+    let line = |offset: usize| newlines.partition_point(|newline| *newline < offset) + 1;
+
+    let items = items
+        .into_iter()
+        .flat_map(|item| match item {
+            Item::Block(items) => items,
+            item => vec![item],
+        })
+        .collect::<Vec<_>>();
+
+    let mut whkdrc = empty(shell, shell_args);
+    let expander = apply_directives(&mut whkdrc, directives)?;
+
+    for (_, steps) in &mut whkdrc.macros {
+        for step in steps {
+            if let Step::Command(command) = step {
+                *command = expander.command(command)?;
+            }
+        }
+    }
+
+    let all_modes = all_modes(&items);
+
+    // bindings above the first `.profile` are shared by every profile
+    let mut profile = None;
+    for item in items {
+        match item {
+            Item::Profile(name) => {
+                if !whkdrc.profiles.contains(&name) {
+                    whkdrc.profiles.push(name.clone());
+                }
+
+                profile = Some(name);
+            }
+            Item::AppBindings(keys, mut bindings) => {
+                for binding in &mut bindings {
+                    binding.profile.clone_from(&profile);
+                    binding.line = line(binding.line);
+                    expander.binding(binding)?;
+                }
+
+                whkdrc.app_bindings.push((keys, bindings));
+            }
+            Item::Binding(modes, mut binding) => {
+                binding.profile.clone_from(&profile);
+                binding.line = line(binding.line);
+                expander.binding(&mut binding)?;
+
+                let modes = match modes {
+                    Modes::All => all_modes.clone(),
+                    Modes::Listed(listed) => listed,
+                };
+
+                for mode in modes {
+                    whkdrc.bindings.push(HotkeyBinding {
+                        mode,
+                        ..binding.clone()
+                    });
+                }
+            }
+            Item::Unbind(mode, keys) => {
+                whkdrc.unbind(mode.as_ref(), &keys);
+                whkdrc.unbound.push((mode, keys));
+            }
+            Item::Inherit(mode, parent) => whkdrc.mode_parents.push((mode, parent)),
+            Item::OnIdle(idle, command) => {
+                whkdrc.on_idle.push((idle, expander.command(&command)?));
+            }
+            Item::Block(_) => unreachable!("blocks are flattened above"),
+        }
+    }
+
+    // the bindings of one line share its name across its modes
+    let mut names = HashMap::new();
+    let bindings = whkdrc.bindings.iter().chain(
+        whkdrc
+            .app_bindings
+            .iter()
+            .flat_map(|(_, bindings)| bindings),
+    );
+    for binding in bindings {
+        if let Some(name) = &binding.name {
+            if *names.entry(name).or_insert(binding.line) != binding.line {
+                return Err(format!("there is more than one binding named {name}"));
+            }
+        }
+    }
+
+    Ok(whkdrc)
+}
+
+/// A whkdrc with the shell of its first line and nothing else set yet
+fn empty(shell: Shell, shell_args: Vec<String>) -> Whkdrc {
+    Whkdrc {
+        shell,
+        shell_args,
+        version: 1,
+        exec: Exec::default(),
+        backend: Backend::default(),
+        tap_threshold: DEFAULT_TAP_THRESHOLD,
+        timeout: None,
+        rate_limit: None,
+        startup_delay: None,
+        sticky_modifiers: None,
+        rollover: DEFAULT_ROLLOVER,
+        wait_for_processes: vec![],
+        state_file: None,
+        suppress: vec![],
+        app_modes: vec![],
+        layers: vec![],
+        on_idle: vec![],
+        mode_parents: vec![],
+        mode_shells: vec![],
+        count_modes: vec![],
+        ignore: vec![],
+        remote_session: RemotePolicy::Keep,
+        shell_instances: 1,
+        shell_init: vec![],
+        shell_idle: None,
+        shell_health_check: None,
+        on_error: None,
+        mqtt: None,
+        include_commands: vec![],
+        redact: vec![],
+        echo: true,
+        mode_sound: None,
+        mode_indicators: vec![],
+        options: Options::default(),
+        lints: Lints::default(),
+        shadowed_aliases: vec![],
+        macros: vec![],
+        profiles: vec![],
+        unbound: vec![],
+        app_bindings: vec![],
+        bindings: vec![],
+    }
+}
+
+/// Set what the directives say on whkdrc, keeping the aliases and templates
+/// to expand the commands with
+fn apply_directives(whkdrc: &mut Whkdrc, directives: Vec<Directive>) -> Result<Expander, String> {
+    let mut expander = Expander::default();
+    for directive in directives {
+        match directive {
+            Directive::Template(name, template) => {
+                if expander.templates.insert(name.clone(), template).is_some() {
+                    return Err(format!("template {name} is defined more than once"));
+                }
+            }
+            Directive::Alias(name, expansion) => {
+                if expander.aliases.insert(name.clone(), expansion).is_some()
+                    && !whkdrc.shadowed_aliases.contains(&name)
+                {
+                    whkdrc.shadowed_aliases.push(name);
+                }
+            }
+            Directive::Macro(name, steps) => whkdrc.macros.push((name, steps)),
+            Directive::Strict => whkdrc.lints.strict = true,
+            Directive::Lints(level, lints) => {
+                whkdrc
+                    .lints
+                    .levels
+                    .extend(lints.into_iter().map(|lint| (lint, level)));
+            }
+            Directive::Version(version) => whkdrc.version = version,
+            Directive::Comments | Directive::Locale => {}
+            Directive::Exec(exec) => whkdrc.exec = exec,
+            Directive::Backend(backend) => whkdrc.backend = backend,
+            Directive::TapThreshold(threshold) => whkdrc.tap_threshold = threshold,
+            Directive::StickyModifiers(window) => whkdrc.sticky_modifiers = Some(window),
+            Directive::Rollover(window) => whkdrc.rollover = window,
+            Directive::Timeout(timeout) => whkdrc.timeout = Some(timeout),
+            Directive::RateLimit(rate_limit) => whkdrc.rate_limit = Some(rate_limit),
+            Directive::StartupDelay(delay) => whkdrc.startup_delay = Some(delay),
+            Directive::WaitForProcess(image) => whkdrc.wait_for_processes.push(image),
+            Directive::ShellInstances(instances) => whkdrc.shell_instances = instances,
+            Directive::ShellInit(lines) => whkdrc.shell_init.extend(lines),
+            Directive::ShellIdle(idle) => whkdrc.shell_idle = Some(idle),
+            Directive::OnError(command) => whkdrc.on_error = Some(command),
+            Directive::Mqtt(broker) => whkdrc.mqtt = Some(broker),
+            Directive::StateFile(path) => whkdrc.state_file = Some(path),
+            Directive::IncludeCmd(command) => whkdrc.include_commands.push(command),
+            Directive::Redact(pattern) => whkdrc.redact.push(pattern),
+            Directive::Echo(echo) => whkdrc.echo = echo,
+            Directive::ModeSound(path) => whkdrc.mode_sound = Some(path),
+            Directive::ModeIndicator(indicator) => whkdrc.mode_indicators.push(indicator),
+            Directive::ShellHealthCheck(interval) => whkdrc.shell_health_check = Some(interval),
+            Directive::Suppress(chords) => whkdrc.suppress.extend(chords),
+            Directive::AppMode(process_name, mode) => whkdrc.app_modes.push((process_name, mode)),
+            Directive::Layer(key, mode) => whkdrc.layers.push((key, mode)),
+            Directive::ModeShell(mode, shell) => whkdrc.mode_shells.push((mode, shell)),
+            Directive::ModeCount(mode) => whkdrc.count_modes.push(mode),
+            Directive::Ignore(process_names) => whkdrc.ignore.extend(process_names),
+            Directive::RemoteSession(policy) => whkdrc.remote_session = policy,
+            Directive::Options(names) => {
+                for name in names {
+                    whkdrc.options.enable(&name);
+                }
+            }
+        }
+    }
+
+    Ok(expander)
+}
+
+/// `*` stands for the default mode and every mode which is bound or switched
+/// to anywhere in the file
+fn all_modes(items: &[Item]) -> Vec<Option<String>> {
+    let mut all_modes = vec![None];
+    for item in items {
+        if let Item::Inherit(mode, parent) = item {
+            for mode in [Some(mode.clone()), parent.clone()] {
+                if !all_modes.contains(&mode) {
+                    all_modes.push(mode);
+                }
+            }
+        }
+
+        if let Item::Binding(modes, binding) = item {
+            let listed = match modes {
+                Modes::Listed(listed) => listed.as_slice(),
+                Modes::All => &[],
+            };
+
+            let target = match &binding.internal_action {
+                Some(InternalAction::Mode(mode)) => Some(mode),
+                _ => None,
+            };
+
+            for mode in listed.iter().chain(target) {
+                if !all_modes.contains(mode) {
+                    all_modes.push(mode.clone());
+                }
+            }
+        }
+    }
+
+    all_modes
+}
+
+/// The aliases and templates of whkdrc, which the commands are expanded with
+#[derive(Debug, Default)]
+struct Expander {
+    aliases: HashMap<String, String>,
+    templates: HashMap<String, Template>,
+}
+
+impl Expander {
+    fn command(&self, command: &str) -> Result<String, String> {
+        call_template(&self.templates, command)
+            .and_then(|called| expand_aliases(&self.aliases, called.as_deref().unwrap_or(command)))
+    }
+
+    /// Expand the command of a binding and those of its cycle or toggle
+    fn binding(&self, binding: &mut HotkeyBinding) -> Result<(), String> {
+        if let Some(command) = &binding.command {
+            // the call says what the binding does, e.g. in `whkd help`
+            if binding.description.is_none()
+                && call_template(&self.templates, command).is_ok_and(|called| called.is_some())
+            {
+                binding.description = Some(command.trim().to_string());
+            }
+
+            binding.command = Some(self.command(command)?);
+        }
+
+        let commands = match &mut binding.internal_action {
+            Some(InternalAction::Cycle(commands)) => commands.iter_mut().collect(),
+            Some(InternalAction::Toggle { on, off, probe }) => {
+                [Some(on), Some(off), probe.as_mut()]
+                    .into_iter()
+                    .flatten()
+                    .collect()
+            }
+            Some(InternalAction::Chain(steps)) => steps
+                .iter_mut()
+                .filter_map(|step| match step {
+                    Step::Command(command) => Some(command),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        for command in commands {
+            *command = self.command(command)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The command of a `.template` when a command is a call such as
+/// `focus(left)`, and `None` for any other command
+fn call_template(
+    templates: &HashMap<String, Template>,
+    command: &str,
+) -> Result<Option<String>, String> {
+    let Some((name, args)) = command
+        .trim()
+        .strip_suffix(')')
+        .and_then(|call| call.split_once('('))
+    else {
+        return Ok(None);
+    };
+
+    let Some(template) = templates.get(name.trim()) else {
+        return Ok(None);
+    };
+
+    let args = if args.trim().is_empty() {
+        vec![]
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+
+    template.call(name.trim(), &args).map(Some)
+}
+
+/// Replace the first word of a command while it is the name of an `.alias`
+fn expand_aliases(aliases: &HashMap<String, String>, command: &str) -> Result<String, String> {
+    let mut command = command.to_string();
+    let mut expanded = HashSet::new();
+
+    loop {
+        let (first, rest) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command.as_str(), ""));
+
+        let Some(expansion) = aliases.get(first) else {
+            return Ok(command);
+        };
+
+        if !expanded.insert(first.to_string()) {
+            return Err(format!("alias {first} expands to itself"));
+        }
+
+        command = if rest.is_empty() {
+            expansion.clone()
+        } else {
+            format!("{expansion} {rest}")
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::condition::Operand;
+    use crate::lint;
+
+    #[test]
+    fn test_single_line_parse() {
+        let src = r#"
+.shell pwsh # can be one of cmd | pwsh | powershell
+
+alt + h : echo "Hello""#;
+
+        let output = parser().parse(src);
+        let expected = Whkdrc {
+            shell: Shell::Pwsh,
+            shell_args: vec![],
+            version: 1,
+            exec: Exec::Session,
+            backend: Backend::Register,
+            tap_threshold: DEFAULT_TAP_THRESHOLD,
+            timeout: None,
+            rate_limit: None,
+            startup_delay: None,
+            sticky_modifiers: None,
+            rollover: DEFAULT_ROLLOVER,
+            wait_for_processes: vec![],
+            state_file: None,
+            suppress: vec![],
+            app_modes: vec![],
+            layers: vec![],
+            on_idle: vec![],
+            mode_parents: vec![],
+            mode_shells: vec![],
+            count_modes: vec![],
+            ignore: vec![],
+            remote_session: RemotePolicy::Keep,
+            shell_instances: 1,
+            shell_init: vec![],
+            shell_idle: None,
+            shell_health_check: None,
+            on_error: None,
+            mqtt: None,
+            include_commands: vec![],
+            redact: vec![],
+            echo: true,
+            mode_sound: None,
+            mode_indicators: vec![],
+            options: Options::default(),
+            lints: Lints::default(),
+            shadowed_aliases: vec![],
+            macros: vec![],
+            profiles: vec![],
+            unbound: vec![],
+            app_bindings: vec![],
+            bindings: vec![HotkeyBinding {
+                mode: None,
+                keys: vec![String::from("alt"), String::from("h")],
+                command: Some(String::from("echo \"Hello\"")),
+                internal_action: None,
+                process_name: None,
+                tags: vec![],
+                conditions: vec![],
+                passthrough: false,
+                timeout: None,
+                cooldown: None,
+                wait: false,
+                on_fail: None,
+                on_error: None,
+                description: None,
+                allow_recursion: false,
+                respect_dnd: false,
+                notify: None,
+                sound: None,
+                confirm: None,
+                name: None,
+                redact: false,
+                silent: false,
+                profile: None,
+                line: 4,
+            }],
+        };
+
+        assert_eq!(output.unwrap(), expected);
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)]
+    fn test_multi_mode() {
+        let src = r#"
+.shell pwsh # can be one of cmd | pwsh | powershell
+
+alt + h ; window
+window > esc ; default
+
+window > m : echo "Hello"
+window > c : echo "Test" ; default"#;
+
+        let output = parser().parse(src);
+        let expected = Whkdrc {
+            shell: Shell::Pwsh,
+            shell_args: vec![],
+            version: 1,
+            exec: Exec::Session,
+            backend: Backend::Register,
+            tap_threshold: DEFAULT_TAP_THRESHOLD,
+            timeout: None,
+            rate_limit: None,
+            startup_delay: None,
+            sticky_modifiers: None,
+            rollover: DEFAULT_ROLLOVER,
+            wait_for_processes: vec![],
+            state_file: None,
+            suppress: vec![],
+            app_modes: vec![],
+            layers: vec![],
+            on_idle: vec![],
+            mode_parents: vec![],
+            mode_shells: vec![],
+            count_modes: vec![],
+            ignore: vec![],
+            remote_session: RemotePolicy::Keep,
+            shell_instances: 1,
+            shell_init: vec![],
+            shell_idle: None,
+            shell_health_check: None,
+            on_error: None,
+            mqtt: None,
+            include_commands: vec![],
+            redact: vec![],
+            echo: true,
+            mode_sound: None,
+            mode_indicators: vec![],
+            options: Options::default(),
+            lints: Lints::default(),
+            shadowed_aliases: vec![],
+            macros: vec![],
+            profiles: vec![],
+            unbound: vec![],
+            app_bindings: vec![],
+            bindings: vec![
+                HotkeyBinding {
+                    mode: None,
+                    keys: vec![String::from("alt"), String::from("h")],
+                    command: None,
+                    internal_action: Some(InternalAction::Mode(Some(String::from("window")))),
+                    process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 4,
+                },
+                HotkeyBinding {
+                    mode: Some(String::from("window")),
+                    keys: vec![String::from("esc")],
+                    command: None,
+                    internal_action: Some(InternalAction::Mode(None)),
+                    process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 5,
+                },
+                HotkeyBinding {
+                    mode: Some(String::from("window")),
+                    keys: vec![String::from("m")],
+                    command: Some(String::from("echo \"Hello\"")),
+                    internal_action: None,
+                    process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 7,
+                },
+                HotkeyBinding {
+                    mode: Some(String::from("window")),
+                    keys: vec![String::from("c")],
+                    command: Some(String::from("echo \"Test\"")),
+                    internal_action: Some(InternalAction::Mode(None)),
+                    process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 8,
+                },
+            ],
+        };
+
+        assert_eq!(output.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tags() {
+        let src = r"
+.shell pwsh
+
+alt + p @tag(media) : spotify play
+alt + m @tag(media, audio) @tag(volume) : nircmd mutesysvolume 2";
+
+        let output = parser().parse(src);
+        let expected = Whkdrc {
+            shell: Shell::Pwsh,
+            shell_args: vec![],
+            version: 1,
+            exec: Exec::Session,
+            backend: Backend::Register,
+            tap_threshold: DEFAULT_TAP_THRESHOLD,
+            timeout: None,
+            rate_limit: None,
+            startup_delay: None,
+            sticky_modifiers: None,
+            rollover: DEFAULT_ROLLOVER,
+            wait_for_processes: vec![],
+            state_file: None,
+            suppress: vec![],
+            app_modes: vec![],
+            layers: vec![],
+            on_idle: vec![],
+            mode_parents: vec![],
+            mode_shells: vec![],
+            count_modes: vec![],
+            ignore: vec![],
+            remote_session: RemotePolicy::Keep,
+            shell_instances: 1,
+            shell_init: vec![],
+            shell_idle: None,
+            shell_health_check: None,
+            on_error: None,
+            mqtt: None,
+            include_commands: vec![],
+            redact: vec![],
+            echo: true,
+            mode_sound: None,
+            mode_indicators: vec![],
+            options: Options::default(),
+            lints: Lints::default(),
+            shadowed_aliases: vec![],
+            macros: vec![],
+            profiles: vec![],
+            unbound: vec![],
+            app_bindings: vec![],
+            bindings: vec![
+                HotkeyBinding {
+                    mode: None,
+                    keys: vec![String::from("alt"), String::from("p")],
+                    command: Some(String::from("spotify play")),
+                    internal_action: None,
+                    process_name: None,
+                    tags: vec![String::from("media")],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 4,
+                },
+                HotkeyBinding {
+                    mode: None,
+                    keys: vec![String::from("alt"), String::from("m")],
+                    command: Some(String::from("nircmd mutesysvolume 2")),
+                    internal_action: None,
+                    process_name: None,
+                    tags: vec![
+                        String::from("media"),
+                        String::from("audio"),
+                        String::from("volume"),
+                    ],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 5,
+                },
+            ],
+        };
+
+        assert_eq!(output.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tag_actions() {
+        let src = r"
+.shell pwsh
+
+alt + p ~tag(media) : spotify play
+alt + shift + p ; disable media
+alt + ctrl + p ; enable media
+alt + r ; repeat-last
+alt + e ; edit-config
+alt + shift + h ; help
+alt + shift + c ; shell cmd";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::DisableTag(String::from("media")))
+        );
+        assert_eq!(
+            output.bindings[2].internal_action,
+            Some(InternalAction::EnableTag(String::from("media")))
+        );
+        assert_eq!(
+            output.bindings[3].internal_action,
+            Some(InternalAction::RepeatLast)
+        );
+        assert_eq!(
+            output.bindings[4].internal_action,
+            Some(InternalAction::EditConfig)
+        );
+        assert_eq!(
+            output.bindings[5].internal_action,
+            Some(InternalAction::Help)
+        );
+        assert_eq!(
+            output.bindings[6].internal_action,
+            Some(InternalAction::Shell(Shell::Cmd))
+        );
+    }
+
+    #[test]
+    fn test_mode_cycling() {
+        let src = r"
+.shell pwsh
+
+alt + n ; next-mode
+alt + p ; prev-mode
+window > alt + b ; mode-back";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::NextMode)
+        );
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::PrevMode)
+        );
+        assert_eq!(
+            output.bindings[2].internal_action,
+            Some(InternalAction::ModeBack)
+        );
+    }
+
+    #[test]
+    fn test_cycle_bindings() {
+        let src = r"
+.shell pwsh
+.alias wall wallpaper.exe
+
+alt + t :: [ wall one | wall two | wall three ]
+alt + a :: [
+    Get-AudioDevice -List \| Select-Object -First 1
+    | ```Write-Host [done] | Out-Null```
+]
+alt + h : echo hello";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings[0].command, None);
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Cycle(vec![
+                String::from("wallpaper.exe one"),
+                String::from("wallpaper.exe two"),
+                String::from("wallpaper.exe three"),
+            ]))
+        );
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::Cycle(vec![
+                String::from("Get-AudioDevice -List | Select-Object -First 1"),
+                String::from("Write-Host [done] | Out-Null"),
+            ]))
+        );
+        assert_eq!(output.bindings[2].command.as_deref(), Some("echo hello"));
+
+        let src = r"
+.shell pwsh
+
+alt + t :: [ one | | three ]";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_toggle_bindings() {
+        let src = r"
+.shell pwsh
+.alias vol nircmd mutesysvolume
+
+alt + m :: toggle { vol 1 } { vol 0 }
+alt + w :: toggle { wifi on } { wifi off } probe {
+    (Get-NetAdapter -Name Wi-Fi | Where-Object { $_.Status -eq 'Up' }) -ne $null
+}
+alt + h : echo hello";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Toggle {
+                on: String::from("nircmd mutesysvolume 1"),
+                off: String::from("nircmd mutesysvolume 0"),
+                probe: None,
+            })
+        );
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::Toggle {
+                on: String::from("wifi on"),
+                off: String::from("wifi off"),
+                probe: Some(String::from(
+                    "(Get-NetAdapter -Name Wi-Fi | Where-Object { $_.Status -eq 'Up' }) -ne $null"
+                )),
+            })
+        );
+        assert_eq!(output.bindings[2].command.as_deref(), Some("echo hello"));
+
+        let src = r"
+.shell pwsh
+
+alt + m :: toggle { mute } { }";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_chain_bindings() {
+        let src = r"
+.shell pwsh
+.alias kc komorebic
+
+alt + d :: chain { kc focus-exe notepad.exe; @sleep 300ms; kc retile }
+alt + s :: chain {
+    kc stop # comment
+    @sleep 2s
+    kc start
+}";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Chain(vec![
+                Step::Command(String::from("komorebic focus-exe notepad.exe")),
+                Step::Sleep(Duration::from_millis(300)),
+                Step::Command(String::from("komorebic retile")),
+            ]))
+        );
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::Chain(vec![
+                Step::Command(String::from("komorebic stop")),
+                Step::Sleep(Duration::from_secs(2)),
+                Step::Command(String::from("komorebic start")),
+            ]))
+        );
+
+        assert!(parser()
+            .parse(".shell pwsh\nalt + d :: chain { @sleep 300ms }")
+            .is_err());
+        assert!(parser()
+            .parse(".shell pwsh\nalt + d :: chain { kc start; @slep 300ms }")
+            .is_err());
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let src = r#"
+.shell pwsh
+
+alt + c ~passthrough : echo "copied"
+alt + v : echo "pasted""#;
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.bindings[0].passthrough);
+        assert!(!output.bindings[1].passthrough);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let src = r#"
+.shell pwsh
+
+alt + 2 ~tag(komorebi) ~when-cmd("komorebic query focused-workspace-index", "2") : echo "third"
+alt + w ~when("$env:WHKD_PROFILE == 'work'") : echo "work""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings[0].tags, vec![String::from("komorebi")]);
+        assert_eq!(
+            output.bindings[0].conditions,
+            vec![Condition::WhenCmd {
+                command: String::from("komorebic query focused-workspace-index"),
+                expected: String::from("2"),
+            }]
+        );
+        assert_eq!(
+            output.bindings[1].conditions,
+            vec![Condition::When(Expression::Equal(
+                Operand::Env(String::from("WHKD_PROFILE")),
+                Operand::Literal(String::from("work")),
+            ))]
+        );
+
+        let src = r#"
+.shell pwsh
+
+alt + w ~when("WHKD_PROFILE == 'work'") : echo "work""#;
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_time_conditions() {
+        let src = r#"
+.shell pwsh
+
+alt + t ~between(09:00, 17:00) ~days(mon-fri) : echo "working hours""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].conditions,
+            vec![
+                Condition::Between {
+                    start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+                },
+                Condition::Days(parse_days("mon, tue, wed, thu, fri").unwrap()),
+            ]
+        );
+
+        let src = r#"
+.shell pwsh
+
+alt + t ~between(9am, 5pm) : echo "working hours""#;
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_exec_directive() {
+        let src = r#"
+.shell pwsh
+.exec direct # spawn a new process for every command
+
+alt + h : echo "Hello""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.exec, Exec::Direct);
+        assert_eq!(output.bindings.len(), 1);
+    }
+
+    #[test]
+    fn test_backend_directive() {
+        let src = r#"
+.shell pwsh
+.backend hook
+
+lalt + h : echo "Hello"
+ctrl + shift : echo "World""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.backend, Backend::Hook);
+        assert_eq!(output.tap_threshold, DEFAULT_TAP_THRESHOLD);
+        assert_eq!(output.bindings.len(), 2);
+        assert_eq!(output.bindings[1].keys, vec!["ctrl", "shift"]);
+
+        let src = r#"
+.shell pwsh
+.backend hook
+.tap_threshold 150
+
+super : echo "tapped""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.tap_threshold, Duration::from_millis(150));
+        assert_eq!(output.sticky_modifiers, None);
+        assert_eq!(output.bindings[0].keys, vec!["super"]);
+
+        let src = r"
+.shell pwsh
+.backend hook
+.sticky_modifiers 1000
+
+alt + r ; resize";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.sticky_modifiers, Some(Duration::from_secs(1)));
+        assert_eq!(output.rollover, DEFAULT_ROLLOVER);
+
+        let src = r"
+.shell pwsh
+.backend hook
+.rollover 80
+
+j + k : komorebic toggle-monocle";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.rollover, Duration::from_millis(80));
+        assert_eq!(output.bindings[0].keys, vec!["j", "k"]);
+    }
 
     #[test]
-    fn test_single_line_parse() {
+    fn test_timeout() {
+        let src = r"
+.shell pwsh
+.timeout 800
+
+alt + r ~timeout(1500) ; resize
+resize > h : komorebic resize-axis horizontal increase";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.timeout, Some(Duration::from_millis(800)));
+        assert_eq!(
+            output.bindings[0].timeout,
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(output.bindings[1].timeout, None);
+    }
+
+    #[test]
+    fn test_startup() {
+        let src = r"
+.shell pwsh
+.startup_delay 3000
+.wait_for_process komorebi.exe # started by a scheduled task
+.wait_for_process AutoHotkey64.exe
+
+alt + h : komorebic focus left";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.startup_delay, Some(Duration::from_secs(3)));
+        assert_eq!(
+            output.wait_for_processes,
+            vec![
+                String::from("komorebi.exe"),
+                String::from("AutoHotkey64.exe")
+            ]
+        );
+
+        assert!(parser()
+            .parse(".shell pwsh\n.wait_for_process\nalt + h : echo")
+            .is_err());
+    }
+
+    #[test]
+    fn test_state_file() {
+        let src = r"
+.shell pwsh
+.state_file C:\Users\me\whkd.json # read by the prompt
+
+alt + h : komorebic focus left";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.state_file,
+            Some(PathBuf::from(r"C:\Users\me\whkd.json"))
+        );
+
+        let output = parser()
+            .parse(".shell pwsh\n.state_file\nalt + h : echo")
+            .unwrap();
+        assert_eq!(output.state_file, Some(state::default_path()));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let src = r"
+.shell pwsh
+.rate_limit 10 drop
+
+alt + h : echo hello";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.rate_limit,
+            Some(RateLimit {
+                per_second: 10,
+                policy: RatePolicy::Drop,
+            })
+        );
+
+        let src = r"
+.shell pwsh
+.rate_limit 5
+
+alt + h : echo hello";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.rate_limit.unwrap().policy, RatePolicy::Coalesce);
+
+        let src = r"
+.shell pwsh
+.rate_limit 0
+
+alt + h : echo hello";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let src = r"
+.shell pwsh
+
+alt + v ~cooldown(300ms) : vmrun start vm.vmx
+alt + s ~cooldown( 750 ) : Send-Mail
+alt + h : echo hello";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].cooldown,
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(
+            output.bindings[1].cooldown,
+            Some(Duration::from_millis(750))
+        );
+        assert_eq!(output.bindings[2].cooldown, None);
+    }
+
+    #[test]
+    fn test_wait() {
         let src = r#"
-.shell pwsh # can be one of cmd | pwsh | powershell
+.shell pwsh
+
+alt + b ~wait : backup.ps1
+alt + m ~on-fail { msg * "failed: $env:WHKD_EXIT_CODE" } : vpn-toggle.ps1
+alt + h : echo hello"#;
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.bindings[0].wait);
+        assert_eq!(output.bindings[0].on_fail, None);
+        assert!(output.bindings[1].wait);
+        assert_eq!(
+            output.bindings[1].on_fail.as_deref(),
+            Some(r#"msg * "failed: $env:WHKD_EXIT_CODE""#)
+        );
+        assert_eq!(
+            output.bindings[1].command.as_deref(),
+            Some("vpn-toggle.ps1")
+        );
+        assert!(!output.bindings[2].wait);
+
+        let src = r"
+.shell pwsh
+
+alt + m ~on-fail {} : vpn-toggle.ps1";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_on_error() {
+        let src = r#"
+.shell pwsh
+.on_error msg * "whkd: $env:WHKD_ERROR" # shows up right away
+
+alt + m ~wait ~on-error("could not toggle the VPN") : vpn-toggle.ps1
+alt + h : echo hello"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.on_error.as_deref(),
+            Some(r#"msg * "whkd: $env:WHKD_ERROR""#)
+        );
+        assert_eq!(
+            output.bindings[0].on_error.as_deref(),
+            Some("could not toggle the VPN")
+        );
+        assert!(output.bindings[0].wait);
+        assert_eq!(output.bindings[1].on_error, None);
+    }
+
+    #[test]
+    fn test_unbind() {
+        let src = r"
+.shell pwsh
+
+alt + h : komorebic focus left
+alt + n [
+    Firefox : echo firefox
+]
+window > h : komorebic focus left
+
+unbind alt + h
+unbind alt + n
+unbind window > h
+unbind default > alt + f
+alt + h : echo rebound";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.unbound,
+            vec![
+                (None, vec![String::from("alt"), String::from("h")]),
+                (None, vec![String::from("alt"), String::from("n")]),
+                (Some(String::from("window")), vec![String::from("h")]),
+                (None, vec![String::from("alt"), String::from("f")]),
+            ]
+        );
+        assert!(output.app_bindings.is_empty());
+        assert_eq!(output.bindings.len(), 1);
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from("echo rebound"))
+        );
+
+        // alt + h is bound again, the others are kept free
+        let free = output.kept_free();
+        assert_eq!(free.len(), 3);
+        assert_eq!(
+            free[1],
+            (Some(&String::from("window")), &vec![String::from("h")])
+        );
+    }
+
+    #[test]
+    fn test_app_mode_directive() {
+        let src = r#"
+.shell pwsh
+.app_mode "Blender" blender
+.app_mode "Google Chrome" browser
+
+blender > g : echo grab"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.app_modes,
+            vec![
+                (String::from("Blender"), String::from("blender")),
+                (String::from("Google Chrome"), String::from("browser")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layer_directive() {
+        let src = r"
+.shell pwsh
+.backend hook
+.layer CapsLock nav
+
+nav > h : komorebic focus left";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.layers,
+            vec![(String::from("CapsLock"), String::from("nav"))]
+        );
+        assert_eq!(output.bindings[0].mode, Some(String::from("nav")));
+    }
+
+    #[test]
+    fn test_mode_shell_directive() {
+        let src = r"
+.shell pwsh
+.mode legacy shell cmd
+
+alt + l ; legacy
+legacy > b : build.bat";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.mode_shells,
+            vec![(String::from("legacy"), Shell::Cmd)]
+        );
+        assert_eq!(
+            output.mode_shell(Some(&String::from("legacy"))),
+            Some(Shell::Cmd)
+        );
+        assert_eq!(output.mode_shell(None), None);
+
+        let src = r"
+.shell pwsh
+.mode default shell cmd
+
+alt + h : echo hello";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_mode_count_directive() {
+        let src = r"
+.shell pwsh
+.mode resize count
+
+alt + r ; resize
+resize > h : komorebic resize-axis horizontal increase";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.count_modes, vec![String::from("resize")]);
+
+        let src = r"
+.shell pwsh
+.mode default count
+
+alt + h : echo hello";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_ignore_directive() {
+        let src = r"
+.shell pwsh
+.ignore mstsc, VirtualBox VM # remote desktops and VMs
+.options unregister_ignored
+
+alt + h : komorebic focus left";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.ignore, vec!["mstsc", "VirtualBox VM"]);
+        assert!(output.options.unregister_ignored);
+    }
+
+    #[test]
+    fn test_remote_session_directive() {
+        let src = r"
+.shell pwsh
+.remote_session mode remote
+
+remote > h : echo remote";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.remote_session,
+            RemotePolicy::Mode(String::from("remote"))
+        );
+
+        let src = r"
+.shell pwsh
+.remote_session pause
+
+alt + h : echo local";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.remote_session, RemotePolicy::Pause);
+    }
+
+    #[test]
+    fn test_suppress_directive() {
+        let src = r"
+.shell pwsh
+.backend hook
+.suppress super + e, super + s
+
+super + s : komorebic stack left";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.suppress,
+            vec![vec!["super", "e"], vec!["super", "s"]]
+        );
+        assert_eq!(output.bindings.len(), 1);
+    }
+
+    #[test]
+    fn test_locale() {
+        let src = r"
+.shell pwsh
+.locale de
+
+strg + umschalt + entf : taskmgr
+alt + {h, pos1} : echo {left, home}
+resize > eingabe ; default";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings[0].keys, vec!["ctrl", "shift", "Delete"]);
+        assert_eq!(output.bindings[2].keys, vec!["alt", "Home"]);
+        assert_eq!(output.bindings[3].keys, vec!["Enter"]);
+
+        assert!(parser()
+            .parse(".shell pwsh\n.locale xx\nalt + h : echo")
+            .is_err());
+        assert!(parser().parse(".shell pwsh\nstrg + h : echo").is_ok());
+    }
+
+    #[test]
+    fn test_shell_instances_directive() {
+        let src = r#"
+.shell pwsh
+.shell_instances 3
 
 alt + h : echo "Hello""#;
 
-        let output = parser().parse(src);
-        let expected = Whkdrc {
-            shell: Shell::Pwsh,
-            app_bindings: vec![],
-            bindings: vec![HotkeyBinding {
-                mode: None,
-                keys: vec![String::from("alt"), String::from("h")],
-                command: Some(String::from("echo \"Hello\"")),
-                internal_action: None,
-                process_name: None,
-            }],
-        };
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.shell_instances, 3);
 
-        assert_eq!(output.unwrap(), expected);
+        let src = r#"
+.shell pwsh
+.shell_instances 0
+
+alt + h : echo "Hello""#;
+
+        assert!(parser().parse(src).is_err());
     }
 
     #[test]
-    fn test_multi_mode() {
+    fn test_shell_idle_directive() {
         let src = r#"
-.shell pwsh # can be one of cmd | pwsh | powershell
+.shell pwsh
+.shell_idle 90
 
-alt + h ; window
-window > esc ; default
+alt + h : echo "Hello""#;
 
-window > m : echo "Hello"
-window > c : echo "Test" ; default"#;
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.shell_idle, Some(Duration::from_secs(90)));
+        assert_eq!(output.shell_health_check, None);
 
-        let output = parser().parse(src);
-        let expected = Whkdrc {
-            shell: Shell::Pwsh,
-            app_bindings: vec![],
-            bindings: vec![
-                HotkeyBinding {
-                    mode: None,
-                    keys: vec![String::from("alt"), String::from("h")],
-                    command: None,
-                    internal_action: Some(Some(String::from("window"))),
-                    process_name: None,
-                },
-                HotkeyBinding {
-                    mode: Some(String::from("window")),
-                    keys: vec![String::from("esc")],
-                    command: None,
-                    internal_action: Some(None),
-                    process_name: None,
-                },
-                HotkeyBinding {
-                    mode: Some(String::from("window")),
-                    keys: vec![String::from("m")],
-                    command: Some(String::from("echo \"Hello\"")),
-                    internal_action: None,
-                    process_name: None,
-                },
-                HotkeyBinding {
-                    mode: Some(String::from("window")),
-                    keys: vec![String::from("c")],
-                    command: Some(String::from("echo \"Test\"")),
-                    internal_action: Some(None),
-                    process_name: None,
-                },
-            ],
-        };
+        let src = r#"
+.shell pwsh
+.shell_health_check 30
+
+alt + h : echo "Hello""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.shell_health_check, Some(Duration::from_secs(30)));
+
+        let src = r#"
+.shell pwsh
+.shell_idle 0
+
+alt + h : echo "Hello""#;
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let src = r#"
+.shell pwsh
+
+alt + h : komorebic focus \
+    left ; window
+alt + p : Start-Process C:\Tools\app.exe \
+    -ArgumentList "--new-window" \
+    -WindowStyle hidden
+alt + j : echo "done""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings.len(), 3);
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from("komorebic focus left"))
+        );
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Mode(Some(String::from("window"))))
+        );
+        assert_eq!(
+            output.bindings[1].command,
+            Some(String::from(
+                r#"Start-Process C:\Tools\app.exe -ArgumentList "--new-window" -WindowStyle hidden"#
+            ))
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        let src = r"
+.shell pwsh
+
+alt + h : Get-Process \; Write-Host \#1 ; window
+alt + j : dir C:\Windows # comment
+alt + k : echo done";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from("Get-Process ; Write-Host #1"))
+        );
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Mode(Some(String::from("window"))))
+        );
+        assert_eq!(
+            output.bindings[1].command,
+            Some(String::from(r"dir C:\Windows"))
+        );
+    }
+
+    #[test]
+    fn test_double_hash_comments() {
+        let src = r"
+.shell pwsh
+.version 3
+.alias web start https://example.com/#top
+
+alt + h : Write-Host '#1' # still the command ## Say the number
+alt + j : dir C:\Windows ## comment
+alt + k : web # also the command
+alt + c : komorebic border-colour #ff0000 ; window";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].command.as_deref(),
+            Some("Write-Host '#1' # still the command")
+        );
+        assert_eq!(
+            output.bindings[0].description.as_deref(),
+            Some("Say the number")
+        );
+        assert_eq!(
+            output.bindings[1].command.as_deref(),
+            Some(r"dir C:\Windows")
+        );
+        assert_eq!(
+            output.bindings[2].command.as_deref(),
+            Some("start https://example.com/#top # also the command")
+        );
+        assert_eq!(
+            output.bindings[3].command.as_deref(),
+            Some("komorebic border-colour #ff0000")
+        );
+
+        // `.comments` picks the rule regardless of the version
+        let src = ".shell pwsh\n.version 3\n.comments hash\nalt + h : echo hi # comment\n";
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings[0].command.as_deref(), Some("echo hi"));
+
+        let src = ".shell pwsh\n.comments double-hash\nalt + h : echo #1";
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings[0].command.as_deref(), Some("echo #1"));
+    }
+
+    #[test]
+    fn test_raw_commands() {
+        let src = r##"
+.shell pwsh
+
+alt + h : ```Get-Process; Write-Host "#1"``` ; window
+alt + j : ```
+$count = 3 # retries
+komorebic focus left
+```
+alt + k : echo "done""##;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings.len(), 3);
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from(r##"Get-Process; Write-Host "#1""##))
+        );
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Mode(Some(String::from("window"))))
+        );
+        assert_eq!(
+            output.bindings[1].command,
+            Some(String::from("$count = 3 # retries\nkomorebic focus left"))
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let src = r#"
+.shell pwsh
+.alias focus komorebic focus
+.alias kc komorebic
+
+alt + h : focus left
+alt + k : echo "focus"
+alt + q [
+    Firefox : kc close
+]"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from("komorebic focus left"))
+        );
+        assert_eq!(
+            output.bindings[1].command,
+            Some(String::from(r#"echo "focus""#))
+        );
+        assert_eq!(
+            output.app_bindings[0].1[0].command,
+            Some(String::from("komorebic close"))
+        );
+
+        let src = r"
+.shell pwsh
+.alias a b one
+.alias b a two
+
+alt + h : a";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_templates() {
+        let src = r"
+.shell pwsh
+.template focus(dir) = komorebic focus {dir}
+.template resize(axis, direction) = komorebic resize-axis {axis} {direction}
+.alias kc komorebic
+.template close() = kc close
+
+alt + h : focus(left)
+alt + shift + h : resize(horizontal, decrease) ## narrower
+alt + q : close()
+alt + e : echo focus(left)";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from("komorebic focus left"))
+        );
+        assert_eq!(
+            output.bindings[0].description,
+            Some(String::from("focus(left)"))
+        );
+        assert_eq!(
+            output.bindings[1].command,
+            Some(String::from("komorebic resize-axis horizontal decrease"))
+        );
+        assert_eq!(
+            output.bindings[1].description,
+            Some(String::from("narrower"))
+        );
+        assert_eq!(
+            output.bindings[2].command,
+            Some(String::from("komorebic close"))
+        );
+        assert_eq!(
+            output.bindings[3].command,
+            Some(String::from("echo focus(left)"))
+        );
+
+        let src = r"
+.shell pwsh
+.template focus(dir) = komorebic focus {dir}
+
+alt + h : focus(left, right)";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_shell_args() {
+        let src = r#"
+.shell pwsh -NoProfile -NonInteractive # faster startup
+
+alt + h : echo "Hello""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.shell, Shell::Pwsh);
+        assert_eq!(output.shell_args, vec!["-NoProfile", "-NonInteractive"]);
+        assert_eq!(output.bindings.len(), 1);
+    }
+
+    #[test]
+    fn test_shell_init_directive() {
+        let src = r"
+.shell pwsh
+.shell_init [
+    . ~/whkd-functions.ps1
+
+    Set-Alias kc komorebic
+]
+
+alt + h : kc focus left";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.shell_init,
+            vec![". ~/whkd-functions.ps1", "Set-Alias kc komorebic"]
+        );
+        assert_eq!(output.bindings.len(), 1);
+        assert_eq!(
+            output.bindings[0].command,
+            Some(String::from("kc focus left"))
+        );
+    }
+
+    #[test]
+    fn test_options_directive() {
+        let src = r#"
+.shell pwsh
+.options check_updates
+
+alt + h : echo "Hello""#;
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.options.check_updates);
+        assert!(!output.options.verbose);
+
+        let src = r#"
+.shell pwsh
+.options check_updates, verbose
+
+alt + h : echo "Hello""#;
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.options.verbose);
+
+        let src = r#"
+.shell pwsh
+.options telemetry
+
+alt + h : echo "Hello""#;
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let src = r#"
+.shell pwsh
+
+alt + q : echo "shared"
 
-        assert_eq!(output.unwrap(), expected);
+.profile work
+alt + h : echo "work"
+alt + g ; profile gaming
+
+.profile gaming
+alt + h : echo "gaming"
+alt + w ; profile work"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.profiles, vec!["work", "gaming"]);
+
+        let profiles = output
+            .bindings
+            .iter()
+            .map(|binding| binding.profile.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            profiles,
+            vec![
+                None,
+                Some("work"),
+                Some("work"),
+                Some("gaming"),
+                Some("gaming")
+            ]
+        );
+
+        assert_eq!(
+            output.bindings[2].internal_action,
+            Some(InternalAction::Profile(String::from("gaming")))
+        );
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn test_parse() {
         let src = r#"
 .shell cmd
@@ -246,6 +3926,44 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
         let output = parser().parse(src);
         let expected = Whkdrc {
             shell: Shell::Cmd,
+            shell_args: vec![],
+            version: 1,
+            exec: Exec::Session,
+            backend: Backend::Register,
+            tap_threshold: DEFAULT_TAP_THRESHOLD,
+            timeout: None,
+            rate_limit: None,
+            startup_delay: None,
+            sticky_modifiers: None,
+            rollover: DEFAULT_ROLLOVER,
+            wait_for_processes: vec![],
+            state_file: None,
+            suppress: vec![],
+            app_modes: vec![],
+            layers: vec![],
+            on_idle: vec![],
+            mode_parents: vec![],
+            mode_shells: vec![],
+            count_modes: vec![],
+            ignore: vec![],
+            remote_session: RemotePolicy::Keep,
+            shell_instances: 1,
+            shell_init: vec![],
+            shell_idle: None,
+            shell_health_check: None,
+            on_error: None,
+            mqtt: None,
+            include_commands: vec![],
+            redact: vec![],
+            echo: true,
+            mode_sound: None,
+            mode_indicators: vec![],
+            options: Options::default(),
+            lints: Lints::default(),
+            shadowed_aliases: vec![],
+            macros: vec![],
+            profiles: vec![],
+            unbound: vec![],
             app_bindings: vec![(
                 vec![String::from("alt"), String::from("n")],
                 vec![
@@ -255,6 +3973,25 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                         command: Some(String::from(r#"echo "hello firefox""#)),
                         internal_action: None,
                         process_name: Option::from("Firefox".to_string()),
+                        tags: vec![],
+                        conditions: vec![],
+                        passthrough: false,
+                        timeout: None,
+                        cooldown: None,
+                        wait: false,
+                        on_fail: None,
+                        on_error: None,
+                        description: None,
+                        allow_recursion: false,
+                        respect_dnd: false,
+                        notify: None,
+                        sound: None,
+                        confirm: None,
+                        name: None,
+                        redact: false,
+                        silent: false,
+                        profile: None,
+                        line: 7,
                     },
                     HotkeyBinding {
                         mode: None,
@@ -262,6 +3999,25 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                         command: Some(String::from(r#"echo "hello chrome""#)),
                         internal_action: None,
                         process_name: Option::from("Google Chrome".to_string()),
+                        tags: vec![],
+                        conditions: vec![],
+                        passthrough: false,
+                        timeout: None,
+                        cooldown: None,
+                        wait: false,
+                        on_fail: None,
+                        on_error: None,
+                        description: None,
+                        allow_recursion: false,
+                        respect_dnd: false,
+                        notify: None,
+                        sound: None,
+                        confirm: None,
+                        name: None,
+                        redact: false,
+                        silent: false,
+                        profile: None,
+                        line: 10,
                     },
                 ],
             )],
@@ -272,6 +4028,25 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                     command: Some(String::from("komorebic focus left")),
                     internal_action: None,
                     process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 15,
                 },
                 HotkeyBinding {
                     mode: None,
@@ -279,6 +4054,25 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                     command: Some(String::from("komorebic focus down")),
                     internal_action: None,
                     process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 16,
                 },
                 HotkeyBinding {
                     mode: None,
@@ -286,6 +4080,25 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                     command: Some(String::from("komorebic focus up")),
                     internal_action: None,
                     process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 17,
                 },
                 HotkeyBinding {
                     mode: None,
@@ -293,6 +4106,25 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                     command: Some(String::from("komorebic focus right")),
                     internal_action: None,
                     process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 18,
                 },
                 HotkeyBinding {
                     mode: None,
@@ -300,10 +4132,1059 @@ alt + 1 : komorebic focus-workspace 0 # digits are fine in the hotkeys section
                     command: Some(String::from("komorebic focus-workspace 0")),
                     internal_action: None,
                     process_name: None,
+                    tags: vec![],
+                    conditions: vec![],
+                    passthrough: false,
+                    timeout: None,
+                    cooldown: None,
+                    wait: false,
+                    on_fail: None,
+                    on_error: None,
+                    description: None,
+                    allow_recursion: false,
+                    respect_dnd: false,
+                    notify: None,
+                    sound: None,
+                    confirm: None,
+                    name: None,
+                    redact: false,
+                    silent: false,
+                    profile: None,
+                    line: 21,
                 },
             ],
         };
 
         assert_eq!(output.unwrap(), expected);
     }
+
+    #[test]
+    fn test_mode_lists() {
+        let src = r#"
+.shell pwsh
+
+* > f1 : echo "help"
+alt + r ; resize
+window, resize > escape ; default
+resize > h : echo "left"
+"#;
+
+        let output = parser().parse(src).unwrap();
+        let modes_of = |keys: &[&str]| {
+            output
+                .bindings
+                .iter()
+                .filter(|binding| binding.keys == keys)
+                .map(|binding| binding.mode.as_deref())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            modes_of(&["f1"]),
+            vec![None, Some("resize"), Some("window")]
+        );
+        assert_eq!(modes_of(&["escape"]), vec![Some("window"), Some("resize")]);
+        assert_eq!(modes_of(&["alt", "r"]), vec![None]);
+        assert!(output
+            .bindings
+            .iter()
+            .filter(|binding| binding.keys == ["f1"])
+            .all(|binding| binding.line == 4));
+    }
+
+    #[test]
+    fn test_clipboard_actions() {
+        let src = r#"
+.shell pwsh
+
+alt + e ; @clipboard-set "me@example.com"
+alt + v ; @clipboard-paste-as-keystrokes
+alt + c : echo "copied" ; @clipboard-clear
+"#;
+
+        let output = parser().parse(src).unwrap();
+        let actions = output
+            .bindings
+            .iter()
+            .map(|binding| binding.internal_action.clone().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            actions,
+            vec![
+                InternalAction::ClipboardSet(String::from("me@example.com")),
+                InternalAction::ClipboardPaste,
+                InternalAction::ClipboardClear,
+            ]
+        );
+        assert_eq!(actions[0].to_string(), "@clipboard-set \"me@example.com\"");
+        assert_eq!(
+            output.bindings[2].command.as_deref(),
+            Some("echo \"copied\"")
+        );
+    }
+
+    #[test]
+    fn test_focus_or_launch() {
+        let bindings = parse_binding(r#"alt + f ; @focus-or-launch "firefox.exe""#).unwrap();
+        assert_eq!(
+            bindings[0].internal_action,
+            Some(InternalAction::FocusOrLaunch(String::from("firefox.exe")))
+        );
+    }
+
+    #[test]
+    fn test_terminal() {
+        let bindings = parse_binding(r#"alt + enter ; @terminal "Ubuntu""#).unwrap();
+        assert_eq!(
+            bindings[0].internal_action,
+            Some(InternalAction::Terminal(String::from("Ubuntu")))
+        );
+    }
+
+    #[test]
+    fn test_mqtt() {
+        let src = r#"
+.shell pwsh
+.mqtt broker 192.168.1.10:1883 whkd secret
+
+alt + l ; @mqtt home/desk/light "toggle""#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.mqtt,
+            Some(MqttBroker {
+                address: String::from("192.168.1.10:1883"),
+                login: Some((String::from("whkd"), String::from("secret"))),
+            })
+        );
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Mqtt {
+                topic: String::from("home/desk/light"),
+                payload: String::from("toggle"),
+            })
+        );
+
+        let src = r"
+.shell pwsh
+.mqtt broker localhost:1883
+
+alt + h : echo hello";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.mqtt.unwrap().login, None);
+    }
+
+    #[test]
+    fn test_include_cmd() {
+        let src = r#"
+.shell pwsh
+.include-cmd "komorebic print-whkd-bindings"
+.include-cmd "type $env:USERPROFILE\bindings.whkdrc"
+
+alt + h : echo hello"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.include_commands,
+            [
+                "komorebic print-whkd-bindings",
+                "type $env:USERPROFILE\\bindings.whkdrc"
+            ]
+        );
+
+        let src = r#"
+.shell pwsh
+.include-cmd " "
+
+alt + h : echo hello"#;
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_window_actions() {
+        let src = r"
+.shell pwsh
+
+alt + n ; @minimize
+alt + m ; @maximize
+alt + q ; @close
+alt + t ; @toggle-topmost
+alt + c ; @center
+alt + o ; @move-to-monitor next
+alt + shift + o ; @move-to-monitor prev
+alt + 2 ; @move-to-monitor 2
+alt + i ; @focus-monitor next
+alt + 1 ; @focus-monitor 1
+";
+
+        let output = parser().parse(src).unwrap();
+        let actions = output
+            .bindings
+            .iter()
+            .map(|binding| binding.internal_action.clone().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            actions,
+            [
+                WindowAction::Minimize,
+                WindowAction::Maximize,
+                WindowAction::Close,
+                WindowAction::ToggleTopmost,
+                WindowAction::Center,
+                WindowAction::MoveToMonitor(MonitorTarget::Next),
+                WindowAction::MoveToMonitor(MonitorTarget::Prev),
+                WindowAction::MoveToMonitor(MonitorTarget::Number(2)),
+                WindowAction::FocusMonitor(MonitorTarget::Next),
+                WindowAction::FocusMonitor(MonitorTarget::Number(1)),
+            ]
+            .map(InternalAction::Window)
+        );
+
+        for (binding, line) in output.bindings.iter().zip(src.lines().skip(3)) {
+            let action = binding.internal_action.as_ref().unwrap().to_string();
+            assert!(line.ends_with(&action));
+        }
+
+        assert!(parse_binding("alt + 0 ; @move-to-monitor 0").is_err());
+    }
+
+    #[test]
+    fn test_display_actions() {
+        let src = r"
+.shell pwsh
+
+alt + p ; @display extend
+alt + shift + p ; @display duplicate
+alt + i ; @display internal
+alt + e ; @display external
+alt + 2 ; @display primary 2
+";
+
+        let output = parser().parse(src).unwrap();
+        let actions = output
+            .bindings
+            .iter()
+            .map(|binding| binding.internal_action.clone().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            actions,
+            [
+                DisplayAction::Extend,
+                DisplayAction::Duplicate,
+                DisplayAction::Internal,
+                DisplayAction::External,
+                DisplayAction::Primary(2),
+            ]
+            .map(InternalAction::Display)
+        );
+
+        for (binding, line) in output.bindings.iter().zip(src.lines().skip(3)) {
+            let action = binding.internal_action.as_ref().unwrap().to_string();
+            assert!(line.ends_with(&action));
+            assert!(binding.internal_action.as_ref().unwrap().is_native());
+        }
+
+        assert!(parse_binding("alt + 0 ; @display primary 0").is_err());
+        assert!(parse_binding("alt + 0 ; @display mirror").is_err());
+    }
+
+    #[test]
+    fn test_volume_and_brightness() {
+        let src = r"
+.shell pwsh
+
+alt + up ; @volume up 5
+alt + down ; @volume down 5
+alt + m ; @volume mute-toggle
+alt + shift + up ; @brightness up 10
+alt + shift + down ; @brightness down 10
+";
+
+        let output = parser().parse(src).unwrap();
+        let actions = output
+            .bindings
+            .iter()
+            .map(|binding| binding.internal_action.clone().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            actions,
+            vec![
+                InternalAction::Volume(5),
+                InternalAction::Volume(-5),
+                InternalAction::ToggleMute,
+                InternalAction::Brightness(10),
+                InternalAction::Brightness(-10),
+            ]
+        );
+
+        for (action, line) in actions.iter().zip(src.lines().skip(3)) {
+            assert!(line.ends_with(&action.to_string()));
+        }
+
+        assert!(parse_binding("alt + up ; @volume up 200").is_err());
+    }
+
+    #[test]
+    fn test_macros() {
+        let src = r#"
+.shell pwsh
+.alias kc komorebic
+.macro paste_date {
+    kc focus-exe notepad.exe # comment
+    @sleep 300ms; @send ctrl + v
+    @clipboard-set "done"; @sleep 2s
+}
+.macro mute { @volume mute-toggle }
+
+alt + d ; run-macro paste_date
+tools > d ; run-macro paste_date
+"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.macros,
+            vec![
+                (
+                    String::from("paste_date"),
+                    vec![
+                        Step::Command(String::from("komorebic focus-exe notepad.exe")),
+                        Step::Sleep(Duration::from_millis(300)),
+                        Step::Send(vec![String::from("ctrl"), String::from("v")]),
+                        Step::Action(InternalAction::ClipboardSet(String::from("done"))),
+                        Step::Sleep(Duration::from_secs(2)),
+                    ]
+                ),
+                (
+                    String::from("mute"),
+                    vec![Step::Action(InternalAction::ToggleMute)]
+                ),
+            ]
+        );
+
+        assert_eq!(output.bindings.len(), 2);
+        assert!(output.bindings.iter().all(|binding| binding.internal_action
+            == Some(InternalAction::RunMacro(String::from("paste_date")))));
+        assert_eq!(output.macros().unwrap().len(), 2);
+
+        let undefined = parser()
+            .parse(".shell pwsh\nalt + d ; run-macro missing")
+            .unwrap();
+        assert!(undefined.macros().is_err());
+
+        assert!(parser()
+            .parse(".shell pwsh\n.macro bad { @send ctrl + nope }\nalt + d : echo")
+            .is_err());
+
+        let output = parser()
+            .parse(".shell pwsh\nalt + v :: chain { @clipboard-set \"done\"; @send ctrl + v }")
+            .unwrap();
+        assert_eq!(
+            output.bindings[0].internal_action,
+            Some(InternalAction::Chain(vec![
+                Step::Action(InternalAction::ClipboardSet(String::from("done"))),
+                Step::Send(vec![String::from("ctrl"), String::from("v")]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_trigger() {
+        let src = r"
+.shell pwsh
+
+alt + h : komorebic focus left
+alt + escape ; trigger default/alt + h
+resize > q ; trigger Shift + Q
+";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::Trigger {
+                mode: Some(String::from("default")),
+                keys: vec![String::from("alt"), String::from("h")],
+            })
+        );
+        assert_eq!(
+            output.bindings[2].internal_action,
+            Some(InternalAction::Trigger {
+                mode: None,
+                keys: vec![String::from("shift"), String::from("q")],
+            })
+        );
+
+        for (binding, line) in output.bindings.iter().zip(src.lines().skip(3)).skip(1) {
+            let action = binding.internal_action.as_ref().unwrap().to_string();
+            assert!(line.to_lowercase().ends_with(&action));
+        }
+    }
+
+    #[test]
+    fn test_lint_directives() {
+        let src = r"
+.shell pwsh
+.strict
+.allow duplicate_chord, unreachable_mode
+.deny unreachable_mode
+.on_unknown_key skip
+
+alt + h : komorebic focus left
+";
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.lints.strict);
+        assert_eq!(
+            output.lints.levels,
+            vec![
+                (Lint::DuplicateChord, Level::Allow),
+                (Lint::UnreachableMode, Level::Allow),
+                (Lint::UnreachableMode, Level::Deny),
+                (Lint::UnknownKey, Level::Allow),
+            ]
+        );
+        assert_eq!(output.lints.level(Lint::UnknownKey), Level::Allow);
+
+        assert!(parser()
+            .parse(".shell pwsh\n.allow everything\nalt + h : echo")
+            .is_err());
+    }
+
+    #[test]
+    fn test_unknown_modifier() {
+        let src = r"
+.shell pwsh
+.on_unknown_key error
+
+ctlr + a : echo typo
+";
+
+        let output = parser().parse(src).unwrap();
+        let lints = lint::check(&output)
+            .into_iter()
+            .map(|(lint, _)| lint)
+            .collect::<Vec<_>>();
+        assert_eq!(lints, vec![Lint::UnknownKey]);
+        assert!(lint::run(&output).is_err());
+    }
+
+    #[test]
+    fn test_alternative_chords() {
+        let src = r"
+.shell pwsh
+
+alt + h | alt + left : komorebic focus left
+resize > h | left ~tag(resize) : komorebic resize-axis horizontal decrease
+alt + j : komorebic focus down";
+
+        let output = parser().parse(src).unwrap();
+        let keys = output
+            .bindings
+            .iter()
+            .map(|binding| binding.keys.join(" + "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            ["alt + h", "alt + left", "h", "left", "alt + j"].map(String::from)
+        );
+
+        assert_eq!(output.bindings[0].command, output.bindings[1].command);
+        assert_eq!(output.bindings[0].line, output.bindings[1].line);
+        assert_eq!(output.bindings[3].mode.as_deref(), Some("resize"));
+        assert_eq!(output.bindings[3].tags, vec![String::from("resize")]);
+
+        let src = r"
+.shell pwsh
+
+alt + h | : komorebic focus left";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        let src = r"
+.shell pwsh
+
+alt + {h,j,k,l} : komorebic focus {left,down,up,right}
+alt + shift + {h,l} : komorebic resize-edge {left,right} {increase,decrease}
+alt + {m,n} : Get-Process | ForEach-Object { $_.Name }";
+
+        let output = parser().parse(src).unwrap();
+        let bindings = output
+            .bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.keys.join(" + "),
+                    binding.command.clone().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            bindings[..4],
+            [
+                ("alt + h", "komorebic focus left"),
+                ("alt + j", "komorebic focus down"),
+                ("alt + k", "komorebic focus up"),
+                ("alt + l", "komorebic focus right"),
+            ]
+            .map(|(keys, command)| (String::from(keys), String::from(command)))
+        );
+        assert_eq!(bindings[5].1, "komorebic resize-edge right decrease");
+        assert_eq!(bindings[6].1, "Get-Process | ForEach-Object { $_.Name }");
+        assert_eq!(bindings.len(), 8);
+
+        let src = r"
+.shell pwsh
+
+alt + {a,b} : echo {1,2,3}";
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_range_expansion() {
+        let src = r"
+.shell pwsh
+
+alt + {1-9} : komorebic focus-workspace {0-8}
+alt + shift + {1-3,0} : komorebic move-to-workspace {0-2,9}";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings.len(), 13);
+        assert_eq!(output.bindings[0].keys, ["alt", "1"]);
+        assert_eq!(
+            output.bindings[8].command.as_deref(),
+            Some("komorebic focus-workspace 8")
+        );
+        assert_eq!(output.bindings[12].keys, ["alt", "shift", "0"]);
+        assert_eq!(
+            output.bindings[12].command.as_deref(),
+            Some("komorebic move-to-workspace 9")
+        );
+
+        let src = r"
+.shell pwsh
+
+alt + {1-9} : komorebic focus-workspace {0-7}";
+
+        assert!(parser().parse(src).is_err());
+
+        let src = r"
+.shell pwsh
+
+alt + {9-1} : komorebic focus-workspace {8-0}";
+
+        assert!(parser().parse(src).is_err());
+
+        let src = r"
+.shell pwsh
+
+alt + {1-4000000000} : komorebic focus-workspace {0-3999999999}";
+
+        assert!(parse_str(src).is_err());
+
+        let src = r"
+.shell pwsh
+
+alt + {1-3} : komorebic focus-workspace {0-3999999999}";
+
+        assert!(parse_str(src).is_err());
+    }
+
+    #[test]
+    fn test_allow_recursion() {
+        let src = r"
+.shell pwsh
+.backend hook
+.macro close { @send ctrl + w }
+
+alt + 4 ; run-macro close
+alt + h ~allow-recursion ~passthrough : komorebic focus left";
+
+        let output = parser().parse(src).unwrap();
+        assert!(!output.bindings[0].allow_recursion);
+        assert!(output.bindings[1].allow_recursion);
+        assert!(output.bindings[1].passthrough);
+    }
+
+    #[test]
+    fn test_notify() {
+        let src = r#"
+.shell pwsh
+
+alt + h ~notify("Moved window left") : komorebic move left
+alt + s ~notify("Sync stopped") ~wait : Stop-Service sync"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].notify.as_deref(),
+            Some("Moved window left")
+        );
+        assert_eq!(output.bindings[1].notify.as_deref(), Some("Sync stopped"));
+        assert!(output.bindings[1].wait);
+    }
+
+    #[test]
+    fn test_sound() {
+        let src = r#"
+.shell pwsh
+.mode_sound "C:\sounds\mode.wav"
+
+alt + b ~sound("C:\sounds\click.wav") : komorebic retile
+alt + n : komorebic retile"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.mode_sound,
+            Some(PathBuf::from(r"C:\sounds\mode.wav"))
+        );
+        assert_eq!(
+            output.bindings[0].sound,
+            Some(PathBuf::from(r"C:\sounds\click.wav"))
+        );
+        assert_eq!(output.bindings[1].sound, None);
+    }
+
+    #[test]
+    fn test_mode_indicator() {
+        let src = r#"
+.shell pwsh
+.mode_indicator scroll_lock
+.mode_indicator cmd "Write-Output $env:WHKD_MODE > ~/mode.txt"
+
+alt + r ; resize
+resize > escape ; default"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.mode_indicators,
+            vec![
+                ModeIndicator::Lock(LockKey::Scroll),
+                ModeIndicator::Command(String::from("Write-Output $env:WHKD_MODE > ~/mode.txt")),
+            ]
+        );
+
+        assert!(parser()
+            .parse(".shell pwsh\n.mode_indicator kana_lock\nalt + r ; resize")
+            .is_err());
+    }
+
+    #[test]
+    fn test_idle() {
+        let src = r"
+.shell pwsh
+
+@on-idle(10m) : rundll32 user32.dll,LockWorkStation
+alt + p ~only-when-idle(5m) : spotify-pause
+alt + r ~only-when-active : komorebic retile
+alt + s ~only-when-active(30s) : sync-now
+@on-idle(1h 30m) : shutdown /h";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.on_idle,
+            vec![
+                (
+                    Duration::from_mins(10),
+                    String::from("rundll32 user32.dll,LockWorkStation")
+                ),
+                (Duration::from_mins(90), String::from("shutdown /h")),
+            ]
+        );
+        assert_eq!(output.bindings.len(), 3);
+        assert_eq!(
+            output.bindings[0].conditions,
+            vec![Condition::Idle(Duration::from_mins(5))]
+        );
+        assert_eq!(
+            output.bindings[1].conditions,
+            vec![Condition::Active(ACTIVE_WITHIN)]
+        );
+        assert_eq!(
+            output.bindings[2].conditions,
+            vec![Condition::Active(Duration::from_secs(30))]
+        );
+
+        assert!(parser()
+            .parse(".shell pwsh\n@on-idle(soon) : lock")
+            .is_err());
+        assert!(parser().parse(".shell pwsh\n@on-idle(5m) : ").is_err());
+    }
+
+    #[test]
+    fn test_confirm() {
+        let src = r#"
+.shell pwsh
+
+ctrl + alt + end ~confirm("Really shut down?") : shutdown /s /t 0
+alt + h : komorebic focus left"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(
+            output.bindings[0].confirm.as_deref(),
+            Some("Really shut down?")
+        );
+        assert_eq!(output.bindings[1].confirm, None);
+        assert!(parser()
+            .parse(".shell pwsh\nalt + q ~confirm : exit")
+            .is_err());
+    }
+
+    #[test]
+    fn test_name() {
+        let src = r"
+.shell pwsh
+
+alt + s ~name(screenshot) : snippingtool /clip
+resize > alt + s ; trigger name(screenshot)
+alt + shift + s ; toggle-binding screenshot";
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.bindings[0].name.as_deref(), Some("screenshot"));
+        assert_eq!(output.bindings[1].name, None);
+        assert_eq!(
+            output.bindings[1].internal_action,
+            Some(InternalAction::TriggerName(String::from("screenshot")))
+        );
+        assert_eq!(
+            output.bindings[1]
+                .internal_action
+                .as_ref()
+                .unwrap()
+                .to_string(),
+            "trigger name(screenshot)"
+        );
+        assert_eq!(
+            output.bindings[2].internal_action,
+            Some(InternalAction::ToggleBinding(String::from("screenshot")))
+        );
+
+        // a name is the handle of a single binding
+        assert!(parser()
+            .parse(
+                ".shell pwsh
+alt + s ~name(shot) : a
+alt + d ~name(shot) : b"
+            )
+            .is_err());
+        assert!(parser()
+            .parse(
+                ".shell pwsh
+* > alt + s ~name(shot) : a
+mode > q ; default"
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_redact() {
+        let src = r#"
+.shell pwsh
+.redact "token="
+.redact "--password"
+
+alt + v ~redact : vpn connect --password hunter2
+alt + g : gh api -f token=abc123"#;
+
+        let output = parser().parse(src).unwrap();
+        assert_eq!(output.redact, ["token=", "--password"]);
+        assert!(output.bindings[0].redact);
+        assert!(!output.bindings[1].redact);
+
+        let src = r#"
+.shell pwsh
+.redact ""
+
+alt + h : echo hello"#;
+
+        assert!(parser().parse(src).is_err());
+    }
+
+    #[test]
+    fn test_silent() {
+        let src = r"
+.shell pwsh
+
+alt + k ~silent : komorebic focus up
+alt + j : komorebic focus down";
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.echo);
+        assert!(output.bindings[0].silent);
+        assert!(!output.bindings[1].silent);
+
+        let src = r"
+.shell pwsh
+.echo off
+
+alt + k : komorebic focus up";
+
+        assert!(!parser().parse(src).unwrap().echo);
+        assert!(parser()
+            .parse(".shell pwsh\n.echo maybe\nalt + k : up")
+            .is_err());
+    }
+
+    #[test]
+    fn test_respect_dnd() {
+        let src = r"
+.shell pwsh
+
+alt + r ~respect-dnd ~cooldown(500) : Start-Recording
+alt + s : Stop-Recording";
+
+        let output = parser().parse(src).unwrap();
+        assert!(output.bindings[0].respect_dnd);
+        assert_eq!(
+            output.bindings[0].cooldown,
+            Some(Duration::from_millis(500))
+        );
+        assert!(!output.bindings[1].respect_dnd);
+    }
+
+    #[test]
+    fn test_descriptions() {
+        let src = r"
+.shell pwsh
+
+alt + h : komorebic focus left  ## Focus window to the left
+alt + p : Start-Process firefox \
+    -WindowStyle hidden ## Open firefox
+alt + r ; resize ## Resize windows
+alt + 1 : komorebic focus-workspace 0
+## Workspaces
+alt + 2 : komorebic focus-workspace 1 # not a description";
+
+        let output = parser().parse(src).unwrap();
+        let descriptions = output
+            .bindings
+            .iter()
+            .map(|binding| binding.description.as_deref())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            descriptions,
+            [
+                Some("Focus window to the left"),
+                Some("Open firefox"),
+                Some("Resize windows"),
+                None,
+                None,
+            ]
+        );
+        assert_eq!(
+            output.bindings[0].command.as_deref(),
+            Some("komorebic focus left")
+        );
+        assert_eq!(output.bindings[4].line, 10);
+    }
+
+    #[test]
+    fn test_mode_blocks() {
+        let src = r"
+.shell pwsh
+
+alt + w ; window
+
+mode window {
+    # moving windows
+    h : komorebic move left
+    c : Get-Process | % { $_.Name }
+    escape ; default
+}
+
+mode resize : window {
+    h : komorebic resize-axis horizontal decrease
+}
+
+alt + q : komorebic close
+";
+
+        let output = parser().parse(src).unwrap();
+        let bindings = output
+            .bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.mode.as_deref(),
+                    binding.keys.join(" + "),
+                    binding.line,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            bindings,
+            vec![
+                (None, String::from("alt + w"), 4),
+                (Some("window"), String::from("h"), 8),
+                (Some("window"), String::from("c"), 9),
+                (Some("window"), String::from("escape"), 10),
+                (Some("resize"), String::from("h"), 14),
+                (None, String::from("alt + q"), 17),
+            ]
+        );
+        assert_eq!(
+            output.bindings[2].command.as_deref(),
+            Some("Get-Process | % { $_.Name }")
+        );
+        assert_eq!(
+            output.mode_parents,
+            vec![(String::from("resize"), Some(String::from("window")))]
+        );
+
+        assert!(parser().parse(".shell pwsh\n\nmode window\n").is_err());
+    }
+
+    #[test]
+    fn test_key_case_and_spacing() {
+        for line in [
+            "alt + shift + h : echo",
+            "Alt+Shift+H : echo",
+            "ALT +shift+  h:echo",
+            "alt\t+\tSHIFT + H : echo",
+        ] {
+            let bindings = parse_binding(line).unwrap();
+            assert_eq!(bindings[0].keys, vec!["alt", "shift", "h"], "{line}");
+        }
+
+        let bindings = parse_binding("LCtrl + ArrowLeft : echo").unwrap();
+        assert_eq!(bindings[0].keys, vec!["lctrl", "ArrowLeft"]);
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        let bindings =
+            parse_binding("resize > alt + shift + h : komorebic resize-axis horizontal decrease")
+                .unwrap();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!(bindings[0].mode.as_deref(), Some("resize"));
+        assert_eq!(bindings[0].keys, vec!["alt", "shift", "h"]);
+        assert_eq!(
+            bindings[0].command.as_deref(),
+            Some("komorebic resize-axis horizontal decrease")
+        );
+
+        let bindings =
+            parse_binding("alt + n [\n    Firefox : echo firefox\n    Code : echo code\n]")
+                .unwrap();
+        assert_eq!(bindings.len(), 2);
+
+        let line = "alt + : echo missing";
+        let errors = parse_binding(line).unwrap_err();
+        assert_eq!(errors[0].0, line.len() + 1);
+    }
+
+    /// A generated config of a few thousand lines, like those of tools which
+    /// write whkdrc from another keymap
+    fn large_config(modes: usize) -> String {
+        let mut lines = vec![
+            String::from(".shell pwsh"),
+            String::from(".timeout 800"),
+            String::new(),
+        ];
+
+        for mode in 0..modes {
+            lines.push(format!("# mode {mode}"));
+            for key in 'a'..='z' {
+                lines.push(format!(
+                    "mode{mode} > alt + shift + {key} ~tag(generated) : komorebic send-to-workspace {mode} ; default"
+                ));
+            }
+
+            lines.push(format!("alt + {mode} ["));
+            lines.push(format!("    Firefox : echo \"firefox {mode}\""));
+            lines.push(format!("    Code : echo \"code {mode}\""));
+            lines.push(String::from("]"));
+        }
+
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_large_config() {
+        let src = large_config(120);
+        let output = parser().parse(src.as_str()).unwrap();
+
+        assert_eq!(output.bindings.len(), 120 * 26);
+        assert_eq!(output.app_bindings.len(), 120);
+
+        let last = output.bindings.last().unwrap();
+        assert_eq!(last.mode.as_deref(), Some("mode119"));
+        assert_eq!(last.line, src.lines().count() - 4);
+        assert_eq!(output.app_bindings[119].1[1].line, src.lines().count() - 1);
+    }
+
+    #[test]
+    fn test_malformed() {
+        for src in [
+            ".shell pwsh\n\nalt + n [\n    Firefox : echo firefox\n",
+            ".shell pwsh\n\n>\n",
+            ".shell pwsh\n\nresize >\n",
+            ".shell pwsh\n\n> alt + h : echo\n",
+        ] {
+            assert!(parse_str(src).is_err(), "{src:?} was parsed");
+        }
+
+        let nested = |depth| {
+            format!(
+                ".shell pwsh\n\nalt + m ~on-fail {{ {}{} }} : echo\n",
+                "{ ".repeat(depth),
+                " }".repeat(depth)
+            )
+        };
+
+        let output = parse_str(&nested(MAX_BRACE_DEPTH)).unwrap();
+        assert!(output.bindings[0].on_fail.is_some());
+        assert!(parse_str(&nested(100_000)).is_err());
+    }
+
+    /// Inputs which mutated copies of are parsed by `test_fuzz_corpus`
+    const CORPUS: [&str; 4] = [
+        include_str!("../fuzz/corpus/parse_str/example"),
+        include_str!("../fuzz/corpus/parse_str/modes"),
+        include_str!("../fuzz/corpus/parse_str/attributes"),
+        include_str!("../fuzz/corpus/parse_str/malformed"),
+    ];
+
+    #[test]
+    fn test_fuzz_corpus() {
+        // xorshift with a fixed seed, so that a failure can be reproduced
+        let mut state: usize = 0x2545_f491;
+        let mut random = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+
+        let alphabet: Vec<char> = "[]{}()<>:;|+#@~\\\"\n ".chars().collect();
+        for src in CORPUS {
+            for _ in 0..250 {
+                let mut chars: Vec<char> = src.chars().collect();
+                for _ in 0..=random(4) {
+                    let at = random(chars.len());
+                    let c = alphabet[random(alphabet.len())];
+                    match random(3) {
+                        0 => chars.insert(at, c),
+                        1 => {
+                            chars.remove(at);
+                        }
+                        _ => chars[at] = c,
+                    }
+                }
+
+                // only has to return without panicking
+                let _ = parse_str(&chars.into_iter().collect::<String>());
+            }
+        }
+    }
 }
@@ -0,0 +1,35 @@
+use windows_sys::Win32::Foundation::CloseHandle;
+use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+use windows_sys::Win32::System::Diagnostics::ToolHelp::CreateToolhelp32Snapshot;
+use windows_sys::Win32::System::Diagnostics::ToolHelp::Process32FirstW;
+use windows_sys::Win32::System::Diagnostics::ToolHelp::Process32NextW;
+use windows_sys::Win32::System::Diagnostics::ToolHelp::PROCESSENTRY32W;
+use windows_sys::Win32::System::Diagnostics::ToolHelp::TH32CS_SNAPPROCESS;
+
+/// Whether a process with an image name such as `komorebi.exe` is running
+pub fn is_running(image: &str) -> bool {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        return false;
+    }
+
+    let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+    #[allow(clippy::cast_possible_truncation)]
+    let size = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+    entry.dwSize = size;
+
+    let mut found = false;
+    let mut more = unsafe { Process32FirstW(snapshot, std::ptr::addr_of_mut!(entry)) } != 0;
+    while more && !found {
+        let length = entry
+            .szExeFile
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(entry.szExeFile.len());
+        found = String::from_utf16_lossy(&entry.szExeFile[..length]).eq_ignore_ascii_case(image);
+        more = unsafe { Process32NextW(snapshot, std::ptr::addr_of_mut!(entry)) } != 0;
+    }
+
+    unsafe { CloseHandle(snapshot) };
+    found
+}
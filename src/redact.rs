@@ -0,0 +1,127 @@
+use crate::parser::HotkeyBinding;
+use crate::parser::InternalAction;
+use crate::whkdrc::Whkdrc;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+
+/// What the commands of bindings with `~redact` are shown as
+const REDACTED: &str = "[redacted]";
+
+/// What the secrets which follow the patterns of `.redact` are shown as
+const MASK: &str = "***";
+
+lazy_static! {
+    static ref REDACTIONS: Mutex<Redactions> = Mutex::new(Redactions::default());
+}
+
+#[derive(Debug, Default)]
+struct Redactions {
+    patterns: Vec<String>,
+    /// Every command which a binding with `~redact` can run
+    commands: HashSet<String>,
+}
+
+/// Take the redactions of a whkdrc which has been loaded
+pub fn configure(whkdrc: &Whkdrc) {
+    let bindings = whkdrc
+        .bindings
+        .iter()
+        .chain(
+            whkdrc
+                .app_bindings
+                .iter()
+                .flat_map(|(_, bindings)| bindings),
+        )
+        .filter(|binding| binding.redact);
+
+    *REDACTIONS.lock() = Redactions {
+        patterns: whkdrc.redact.clone(),
+        commands: bindings.flat_map(commands).collect(),
+    };
+}
+
+fn commands(binding: &HotkeyBinding) -> Vec<String> {
+    let mut commands = binding.command.iter().cloned().collect::<Vec<_>>();
+    match &binding.internal_action {
+        Some(InternalAction::Cycle(cycle)) => commands.extend(cycle.iter().cloned()),
+        Some(InternalAction::Toggle { on, off, .. }) => commands.extend([on.clone(), off.clone()]),
+        _ => {}
+    }
+
+    commands
+}
+
+/// A command as it is printed, sent to `whkd watch` and kept in the history
+pub fn command(command: &str) -> String {
+    let redactions = REDACTIONS.lock();
+    if redactions.commands.contains(command) {
+        return String::from(REDACTED);
+    }
+
+    mask(command, &redactions.patterns)
+}
+
+/// Mask the word following every match of a pattern, e.g. `token=***` for
+/// `token=` or `--password ***` for `--password`, along with its quotes
+fn mask(command: &str, patterns: &[String]) -> String {
+    let mut masked = command.to_string();
+
+    for pattern in patterns {
+        let mut start = 0;
+        while let Some(found) = masked[start..].find(pattern.as_str()) {
+            let after = start + found + pattern.len();
+            let secret = after + (masked[after..].len() - masked[after..].trim_start().len());
+
+            let end = match masked[secret..].chars().next() {
+                Some(quote @ ('"' | '\'')) => masked[secret + 1..]
+                    .find(quote)
+                    .map_or(masked.len(), |end| secret + 1 + end + 1),
+                _ => masked[secret..]
+                    .find(char::is_whitespace)
+                    .map_or(masked.len(), |end| secret + end),
+            };
+
+            if end == secret {
+                start = after;
+                continue;
+            }
+
+            masked.replace_range(secret..end, MASK);
+            start = secret + MASK.len();
+        }
+    }
+
+    masked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        let patterns = [String::from("token="), String::from("--password")];
+
+        assert_eq!(
+            mask("gh api -f token=abc123 repos", &patterns),
+            "gh api -f token=*** repos"
+        );
+        assert_eq!(
+            mask("vpn --password 'hunter 2' --user me", &patterns),
+            "vpn --password *** --user me"
+        );
+        assert_eq!(
+            mask(
+                "login --password hunter2 && sync token=a token=b",
+                &patterns
+            ),
+            "login --password *** && sync token=*** token=***"
+        );
+        assert_eq!(mask("echo token=", &patterns), "echo token=");
+        assert_eq!(
+            mask("komorebic focus left", &patterns),
+            "komorebic focus left"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+#[cfg(feature = "runtime")]
+lazy_static::lazy_static! {
+    /// Runs the timers on a single worker thread and the blocking work on a pool
+    /// of threads which are reused and exit once they have been idle for a while
+    static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("whkd-runtime")
+        .enable_time()
+        .build()
+        .expect("could not start the runtime");
+}
+
+/// Call `f` once `delay` has passed, e.g. to send an event to the event loop
+/// when the timeout of a mode runs out. `f` must not block
+#[cfg(feature = "runtime")]
+pub fn after(delay: Duration, f: impl FnOnce() + Send + 'static) {
+    RUNTIME.spawn(async move {
+        tokio::time::sleep(delay).await;
+        f();
+    });
+}
+
+/// Do blocking work away from the event loop, e.g. waiting for a command to exit
+/// or talking to a monitor
+#[cfg(feature = "runtime")]
+pub fn spawn_blocking(f: impl FnOnce() + Send + 'static) {
+    RUNTIME.spawn_blocking(f);
+}
+
+/// Without the `runtime` feature every timer sleeps on a thread of its own
+#[cfg(not(feature = "runtime"))]
+pub fn after(delay: Duration, f: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        f();
+    });
+}
+
+/// Without the `runtime` feature every piece of blocking work gets a thread of
+/// its own
+#[cfg(not(feature = "runtime"))]
+pub fn spawn_blocking(f: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(f);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_after() {
+        let (sender, receiver) = mpsc::channel();
+        let start = Instant::now();
+
+        let timer = sender.clone();
+        after(Duration::from_millis(50), move || {
+            let _ = timer.send("timer");
+        });
+        spawn_blocking(move || {
+            let _ = sender.send("blocking");
+        });
+
+        assert_eq!(receiver.recv().unwrap(), "blocking");
+        assert_eq!(receiver.recv().unwrap(), "timer");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
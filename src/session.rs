@@ -0,0 +1,488 @@
+use crate::job;
+use crate::runtime;
+use crate::whkdrc::Shell;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::ChildStdin;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long the sessions get to finish their commands and exit as whkd shuts
+/// down before they are killed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A set of long-lived shell sessions which commands are dispatched to in
+/// round-robin order, so that one long-running command doesn't hold up the
+/// commands of every hotkey pressed after it
+///
+/// The sessions are only spawned once there is a command for them.
+#[derive(Debug)]
+pub struct SessionPool {
+    shell: Shell,
+    args: Vec<String>,
+    /// The `.shell_init` lines written to every session once it is spawned
+    init: Vec<String>,
+    instances: usize,
+    sessions: Vec<Session>,
+    next: usize,
+    last_used: Instant,
+    last_checked: Instant,
+    /// Numbers the files created by health checks
+    probes: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    process: Child,
+    stdin: ChildStdin,
+    /// The health check which the session hasn't gotten to yet
+    probe: Option<Probe>,
+    /// Whether a command was written to the session since the last probe
+    busy: bool,
+}
+
+/// A file which a session creates once it gets to a health check
+#[derive(Debug)]
+struct Probe {
+    path: PathBuf,
+    /// Whether commands were written before the probe, which the session may
+    /// still be running
+    behind_commands: bool,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(probe) = &self.probe {
+            let _ = std::fs::remove_file(&probe.path);
+        }
+    }
+}
+
+impl SessionPool {
+    pub fn new(shell: Shell, args: &[String], instances: usize, init: &[String]) -> Self {
+        Self {
+            shell,
+            args: args.to_vec(),
+            init: init.to_vec(),
+            instances,
+            sessions: vec![],
+            next: 0,
+            last_used: Instant::now(),
+            last_checked: Instant::now(),
+            probes: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> Result<&mut ChildStdin> {
+        if self.sessions.len() < self.instances {
+            self.sessions
+                .push(spawn_initialized(self.shell, &self.args, &self.init)?);
+            self.next = self.sessions.len() - 1;
+        }
+
+        let idx = self.next % self.sessions.len();
+        self.next = idx + 1;
+        self.last_used = Instant::now();
+
+        let session = &mut self.sessions[idx];
+        session.busy = true;
+        Ok(&mut session.stdin)
+    }
+
+    /// Spawn the sessions which have exited again, returning how many there were
+    pub fn revive(&mut self) -> Result<usize> {
+        let mut revived = 0;
+        for session in &mut self.sessions {
+            if matches!(session.process.try_wait(), Ok(None)) {
+                continue;
+            }
+
+            *session = spawn_initialized(self.shell, &self.args, &self.init)?;
+            revived += 1;
+        }
+
+        Ok(revived)
+    }
+
+    /// Spawn the sessions which have exited, or which were idle and haven't
+    /// gotten to the previous health check, again and check on the others, at
+    /// most once every `interval`, returning how many sessions were spawned
+    /// again
+    pub fn check_health(&mut self, interval: Duration) -> Result<usize> {
+        if self.last_checked.elapsed() < interval {
+            return Ok(0);
+        }
+
+        self.last_checked = Instant::now();
+
+        let mut restarted = 0;
+        for session in &mut self.sessions {
+            let alive = matches!(session.process.try_wait(), Ok(None));
+            let stuck = match &session.probe {
+                None => false,
+                Some(probe) if probe.path.exists() => {
+                    let _ = std::fs::remove_file(&probe.path);
+                    session.probe = None;
+                    false
+                }
+                // a session which is still running a long command gets to the
+                // probe once the command is done
+                Some(probe) if probe.behind_commands && alive => continue,
+                Some(_) => true,
+            };
+
+            if alive && !stuck {
+                self.probes += 1;
+                let path = probe_path(self.probes);
+
+                // writing fails once the session can't read its stdin anymore
+                if writeln!(session.stdin, "{}", probe_command(self.shell, &path)).is_ok() {
+                    session.probe = Some(Probe {
+                        path,
+                        behind_commands: std::mem::take(&mut session.busy),
+                    });
+                    continue;
+                }
+            }
+
+            let _ = session.process.kill();
+            *session = spawn_initialized(self.shell, &self.args, &self.init)?;
+            restarted += 1;
+        }
+
+        Ok(restarted)
+    }
+
+    /// Have the session which the last command was written to create a file
+    /// once it has run the command, returning the file, see `--trace-latency`
+    pub fn probe_last(&mut self) -> Result<PathBuf> {
+        let session = self
+            .next
+            .checked_sub(1)
+            .and_then(|idx| self.sessions.get_mut(idx))
+            .ok_or_else(|| eyre!("no command has been written to a session"))?;
+
+        self.probes += 1;
+        let probe = probe_path(self.probes);
+        writeln!(session.stdin, "{}", probe_command(self.shell, &probe))?;
+
+        Ok(probe)
+    }
+
+    /// Stop the sessions if no command has been written to them for `idle`,
+    /// returning how many there were
+    pub fn retire(&mut self, idle: Duration) -> usize {
+        if self.last_used.elapsed() < idle {
+            return 0;
+        }
+
+        // a session exits once its stdin is closed, after finishing the command
+        // it may still be running
+        let retired = self.sessions.len();
+        self.sessions.clear();
+        self.next = 0;
+
+        retired
+    }
+
+    /// Ask every session to exit once it is done with its commands, killing the
+    /// ones which haven't exited after `EXIT_TIMEOUT`
+    pub fn shut_down(&mut self) {
+        for session in &mut self.sessions {
+            let _ = writeln!(session.stdin, "exit");
+        }
+
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        for session in &mut self.sessions {
+            while matches!(session.process.try_wait(), Ok(None)) {
+                if Instant::now() >= deadline {
+                    let _ = session.process.kill();
+                    break;
+                }
+
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+
+        self.sessions.clear();
+    }
+}
+
+fn spawn_initialized(shell: Shell, args: &[String], init: &[String]) -> Result<Session> {
+    let (process, mut stdin) = spawn_session(shell, args)?;
+    job::assign(&process);
+
+    for line in init {
+        writeln!(stdin, "{line}")?;
+    }
+
+    Ok(Session {
+        process,
+        stdin,
+        probe: None,
+        busy: false,
+    })
+}
+
+/// Where a session creates the file of a probe, numbered by the pool
+fn probe_path(number: u64) -> PathBuf {
+    std::env::temp_dir().join(format!("whkd-probe-{}-{number}", std::process::id()))
+}
+
+/// A command which does nothing but create a file once a session gets to it
+fn probe_command(shell: Shell, probe: &Path) -> String {
+    match shell {
+        Shell::Powershell | Shell::Pwsh => format!(
+            "$null = New-Item -ItemType File -Force -Path '{}'",
+            probe.display()
+        ),
+        // `@` keeps cmd from echoing the command
+        Shell::Cmd => format!("@type nul > \"{}\"", probe.display()),
+    }
+}
+
+fn spawn_session(shell: Shell, args: &[String]) -> Result<(Child, ChildStdin)> {
+    let shell_binary = shell.to_string();
+
+    match shell {
+        Shell::Powershell | Shell::Pwsh => {
+            let mut process = Command::new(&shell_binary)
+                .stdin(Stdio::piped())
+                .args(args)
+                .args(["-Command", "-"])
+                .spawn()?;
+
+            let mut stdin = process
+                .stdin
+                .take()
+                .ok_or_else(|| eyre!("could not take stdin from powershell session"))?;
+
+            writeln!(stdin, "$wshell = New-Object -ComObject wscript.shell")?;
+
+            Ok((process, stdin))
+        }
+        Shell::Cmd => {
+            let mut process = Command::new(&shell_binary)
+                .stdin(Stdio::piped())
+                .args(args)
+                .args(["-"])
+                .spawn()?;
+
+            let mut stdin = process
+                .stdin
+                .take()
+                .ok_or_else(|| eyre!("could not take stdin from cmd session"))?;
+
+            writeln!(stdin, "prompt $S")?;
+
+            Ok((process, stdin))
+        }
+    }
+}
+
+fn direct_command(shell: Shell, cmd: &str) -> Command {
+    let mut command = Command::new(shell.to_string());
+
+    match shell {
+        Shell::Powershell | Shell::Pwsh => command.args(["-NoProfile", "-Command", cmd]),
+        Shell::Cmd => command.args(["/C", cmd]),
+    };
+
+    command.stdin(Stdio::null());
+    command
+}
+
+pub fn spawn_direct(shell: Shell, cmd: &str, env: &[(&str, String)]) -> std::io::Result<Child> {
+    let child = direct_command(shell, cmd)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .spawn()?;
+
+    job::assign_command(&child);
+    Ok(child)
+}
+
+/// Prefix a command with assignments of environment variables, for commands
+/// which are written to a long-lived session
+///
+/// cmd expands the variables of a line before running any of it, so each `set`
+/// goes on a line of its own before the one of the command.
+pub fn with_env(shell: Shell, env: &[(&str, String)], cmd: &str) -> String {
+    let mut prefixed = String::new();
+    for (name, value) in env {
+        // a line break, e.g. in a window title, would end the line which the
+        // session reads early and run the rest of the value as a command
+        let value = value
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect::<String>();
+
+        let _ = match shell {
+            Shell::Powershell | Shell::Pwsh => {
+                write!(prefixed, "$env:{name} = '{}'; ", quote_single(&value))
+            }
+            Shell::Cmd => writeln!(prefixed, "set {name}={}", escape_cmd(&value)),
+        };
+    }
+
+    prefixed.push_str(cmd);
+    prefixed
+}
+
+/// Escape a value for a single-quoted PowerShell string by doubling its quotes,
+/// which include the curly quotes that PowerShell reads as `'` too
+fn quote_single(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            quoted.push(c);
+        }
+
+        quoted.push(c);
+    }
+
+    quoted
+}
+
+/// Escape a value for an unquoted `set` in cmd by putting a caret before its
+/// metacharacters, which keeps `%` from expanding variables too
+fn escape_cmd(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '^' | '%' | '"' | '&' | '|' | '<' | '>' | '(' | ')') {
+            escaped.push('^');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Run a command in a new shell process and wait for its stdout, killing it if
+/// it hasn't exited within `timeout`
+pub fn output_within(shell: Shell, cmd: &str, timeout: Duration) -> Result<String> {
+    let mut child = direct_command(shell, cmd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    runtime::spawn_blocking(move || {
+        let mut output = vec![];
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+
+    let Ok(output) = receiver.recv_timeout(timeout) else {
+        let _ = child.kill();
+        return Err(eyre!("it didn't exit within {}s", timeout.as_secs()));
+    };
+
+    let output = output?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(eyre!("it exited with {status}"));
+    }
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_env() {
+        let env = [
+            ("WHKD_APP", String::from("firefox")),
+            ("WHKD_TITLE", String::from("It's \"quoted\"")),
+        ];
+
+        assert_eq!(
+            with_env(Shell::Pwsh, &env, "echo $env:WHKD_APP"),
+            r#"$env:WHKD_APP = 'firefox'; $env:WHKD_TITLE = 'It''s "quoted"'; echo $env:WHKD_APP"#
+        );
+        assert_eq!(
+            with_env(Shell::Cmd, &env, "echo %WHKD_APP%"),
+            "set WHKD_APP=firefox\nset WHKD_TITLE=It's ^\"quoted^\"\necho %WHKD_APP%"
+        );
+
+        let env = [("WHKD_TITLE", String::from("a\r\nshutdown /s\tnow"))];
+        assert_eq!(
+            with_env(Shell::Pwsh, &env, "echo"),
+            "$env:WHKD_TITLE = 'a  shutdown /s now'; echo"
+        );
+        assert_eq!(
+            with_env(Shell::Cmd, &env, "echo"),
+            "set WHKD_TITLE=a  shutdown /s now\necho"
+        );
+
+        let env = [("WHKD_TITLE", String::from("tab \u{2019}; calc; \u{2019}"))];
+        assert_eq!(
+            with_env(Shell::Pwsh, &env, "echo"),
+            "$env:WHKD_TITLE = 'tab \u{2019}\u{2019}; calc; \u{2019}\u{2019}'; echo"
+        );
+
+        let env = [("WHKD_TITLE", String::from("\u{2018}a\u{201A}b\u{201B}"))];
+        assert_eq!(
+            with_env(Shell::Powershell, &env, "echo"),
+            "$env:WHKD_TITLE = '\u{2018}\u{2018}a\u{201A}\u{201A}b\u{201B}\u{201B}'; echo"
+        );
+    }
+
+    /// Write lines to a cmd session the way the pool does and collect what it
+    /// prints until it exits
+    fn run_in_cmd(lines: &str) -> String {
+        let mut process = Command::new("cmd")
+            .args(["/Q"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stdin = process.stdin.take().unwrap();
+        writeln!(stdin, "{lines}").unwrap();
+        writeln!(stdin, "exit").unwrap();
+        drop(stdin);
+
+        let output = process.wait_with_output().unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    #[test]
+    fn test_with_env_in_cmd() {
+        let env = [("WHKD_APP", String::from("firefox"))];
+        let output = run_in_cmd(&with_env(Shell::Cmd, &env, "echo [%WHKD_APP%]"));
+
+        assert!(output.contains("[firefox]"), "{output}");
+
+        let title = r#"50% "off" ^ (sale) & more | %PATH% <3"#;
+        let env = [("WHKD_TITLE", String::from(title))];
+        let output = run_in_cmd(&with_env(Shell::Cmd, &env, "set WHKD_TITLE"));
+
+        assert!(output.contains(&format!("WHKD_TITLE={title}")), "{output}");
+    }
+
+    #[test]
+    fn test_probe_command() {
+        let probe = Path::new("whkd-probe-1-2");
+
+        assert_eq!(
+            probe_command(Shell::Pwsh, probe),
+            "$null = New-Item -ItemType File -Force -Path 'whkd-probe-1-2'"
+        );
+        assert_eq!(
+            probe_command(Shell::Cmd, probe),
+            r#"@type nul > "whkd-probe-1-2""#
+        );
+    }
+}
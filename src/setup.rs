@@ -0,0 +1,224 @@
+use crate::ipc;
+use crate::ipc::SocketMessage;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use std::path::Path;
+use windows_sys::Win32::Foundation::ERROR_FILE_NOT_FOUND;
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+use windows_sys::Win32::System::Registry::RegDeleteKeyValueW;
+use windows_sys::Win32::System::Registry::RegDeleteTreeW;
+use windows_sys::Win32::System::Registry::RegSetKeyValueW;
+use windows_sys::Win32::System::Registry::HKEY_CURRENT_USER;
+use windows_sys::Win32::System::Registry::REG_SZ;
+
+/// Key of the `whkd://` scheme, under the classes of the user so that setting
+/// it up doesn't need an elevated prompt
+const SCHEME_KEY: &str = r"Software\Classes\whkd";
+
+/// Key of the "Validate with whkd" entry in the context menu of Explorer, which
+/// is only shown for files named whkdrc
+const CONTEXT_MENU_KEY: &str = r"Software\Classes\*\shell\whkd.check";
+
+/// Key of the apps which Windows starts once the user has logged on, which are
+/// listed under Startup apps in the settings
+const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+
+/// Register the `whkd://` links, and the entry in the context menu of Explorer
+/// if asked to, for whkd where it is installed now
+pub fn install(context_menu: bool) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let exe = exe.display();
+
+    set(SCHEME_KEY, "", "URL:whkd")?;
+    set(SCHEME_KEY, "URL Protocol", "")?;
+    set(
+        &format!(r"{SCHEME_KEY}\shell\open\command"),
+        "",
+        &format!(r#""{exe}" open-uri "%1""#),
+    )?;
+    println!("registered whkd://reload and whkd://mode/<name>");
+
+    if context_menu {
+        set(CONTEXT_MENU_KEY, "", "Validate with whkd")?;
+        set(CONTEXT_MENU_KEY, "AppliesTo", r#"System.FileName:"whkdrc""#)?;
+        set(CONTEXT_MENU_KEY, "Icon", &exe.to_string())?;
+        // the console stays open to show the warnings, and the commands of
+        // `.include-cmd` aren't run for any file which happens to be named whkdrc
+        set(
+            &format!(r"{CONTEXT_MENU_KEY}\command"),
+            "",
+            &format!(r#"cmd.exe /k ""{exe}" --config "%1" --no-include-cmd check""#),
+        )?;
+        println!("added \"Validate with whkd\" to the context menu of whkdrc files");
+    }
+
+    Ok(())
+}
+
+/// Start whkd where it is installed now once the user has logged on, with the
+/// whkdrc at `config` instead of the default one if it is given
+pub fn autostart(config: Option<&Path>) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let command = config.map_or_else(
+        || format!(r#""{}""#, exe.display()),
+        |config| format!(r#""{}" --config "{}""#, exe.display(), config.display()),
+    );
+
+    set(RUN_KEY, "whkd", &command)?;
+    println!(
+        "whkd will start after logon, which can be turned off under Startup apps in the settings"
+    );
+    Ok(())
+}
+
+/// Remove whatever `whkd setup` registered, and starting after logon which
+/// `whkd init` may have turned on
+pub fn uninstall() -> Result<()> {
+    for key in [SCHEME_KEY, CONTEXT_MENU_KEY] {
+        let wide = key.encode_utf16().chain([0]).collect::<Vec<u16>>();
+        removed(key, unsafe {
+            RegDeleteTreeW(HKEY_CURRENT_USER, wide.as_ptr())
+        })?;
+    }
+
+    let wide_key = RUN_KEY.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let wide_name = "whkd".encode_utf16().chain([0]).collect::<Vec<u16>>();
+    removed(RUN_KEY, unsafe {
+        RegDeleteKeyValueW(HKEY_CURRENT_USER, wide_key.as_ptr(), wide_name.as_ptr())
+    })?;
+
+    println!("removed the whkd:// links, the context menu entry and starting after logon");
+    Ok(())
+}
+
+/// A key or value which was already missing counts as removed
+fn removed(key: &str, error: u32) -> Result<()> {
+    match error {
+        ERROR_SUCCESS | ERROR_FILE_NOT_FOUND => Ok(()),
+        #[allow(clippy::cast_possible_wrap)]
+        error => Err(eyre!(
+            "could not remove {key} from the registry: {}",
+            std::io::Error::from_raw_os_error(error as i32)
+        )),
+    }
+}
+
+/// Set a string value of a key of the user, creating the key if it is missing
+fn set(key: &str, name: &str, value: &str) -> Result<()> {
+    let wide_key = key.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let wide_name = name.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let wide_value = value.encode_utf16().chain([0]).collect::<Vec<u16>>();
+
+    #[allow(clippy::cast_possible_truncation)]
+    let error = unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            wide_key.as_ptr(),
+            wide_name.as_ptr(),
+            REG_SZ,
+            wide_value.as_ptr().cast(),
+            (wide_value.len() * std::mem::size_of::<u16>()) as u32,
+        )
+    };
+
+    if error != ERROR_SUCCESS {
+        #[allow(clippy::cast_possible_wrap)]
+        return Err(eyre!(
+            "could not write {key} to the registry: {}",
+            std::io::Error::from_raw_os_error(error as i32)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Pass a `whkd://` link which Windows opened whkd for on to the running whkd
+/// instance
+pub fn open_uri(uri: &str) -> Result<()> {
+    ipc::send(&message(uri)?)
+        .map(drop)
+        .map_err(|error| eyre!("could not reach the running whkd instance: {error}"))
+}
+
+/// The message for the running whkd instance which a link asks for, e.g.
+/// `whkd://reload` or `whkd://mode/resize`
+fn message(uri: &str) -> Result<SocketMessage> {
+    let invalid = || eyre!("{uri} isn't a whkd link such as whkd://reload or whkd://mode/resize");
+
+    // browsers may add a trailing slash
+    let path = uri
+        .strip_prefix("whkd://")
+        .ok_or_else(invalid)?
+        .trim_end_matches('/');
+
+    match path.split_once('/') {
+        None if path.eq_ignore_ascii_case("reload") => Ok(SocketMessage::Reload),
+        Some((action, mode))
+            if action.eq_ignore_ascii_case("mode") && !mode.is_empty() && !mode.contains('/') =>
+        {
+            // names with spaces arrive as e.g. `my%20mode`
+            let mode = percent_decode(mode).ok_or_else(invalid)?;
+            Ok(SocketMessage::Mode((mode != "default").then_some(mode)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Decode the `%XX` escapes of a part of a link, `None` if an escape is
+/// incomplete or the decoded bytes aren't UTF-8
+fn percent_decode(part: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = part.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        assert!(matches!(
+            message("whkd://reload"),
+            Ok(SocketMessage::Reload)
+        ));
+        assert!(matches!(
+            message("whkd://reload/"),
+            Ok(SocketMessage::Reload)
+        ));
+        assert!(matches!(
+            message("whkd://mode/resize"),
+            Ok(SocketMessage::Mode(Some(mode))) if mode == "resize"
+        ));
+        assert!(matches!(
+            message("whkd://mode/default"),
+            Ok(SocketMessage::Mode(None))
+        ));
+        assert!(matches!(
+            message("whkd://mode/my%20mode"),
+            Ok(SocketMessage::Mode(Some(mode))) if mode == "my mode"
+        ));
+        assert!(matches!(
+            message("whkd://mode/r%C3%A9sum%C3%A9"),
+            Ok(SocketMessage::Mode(Some(mode))) if mode == "résumé"
+        ));
+
+        assert!(message("whkd://mode/").is_err());
+        assert!(message("whkd://mode/a/b").is_err());
+        assert!(message("whkd://mode/a%2").is_err());
+        assert!(message("whkd://mode/a%zz").is_err());
+        assert!(message("whkd://stop").is_err());
+        assert!(message("https://reload").is_err());
+    }
+}
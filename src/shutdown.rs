@@ -0,0 +1,106 @@
+use crate::event_loop::EventLoopProxy;
+use crate::UserEvent;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Condvar;
+use parking_lot::Mutex;
+use std::io::Write;
+use std::time::Duration;
+use windows_sys::Win32::Foundation::BOOL;
+use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+use windows_sys::Win32::System::Console::CTRL_BREAK_EVENT;
+use windows_sys::Win32::System::Console::CTRL_CLOSE_EVENT;
+use windows_sys::Win32::System::Console::CTRL_C_EVENT;
+use windows_sys::Win32::System::Console::CTRL_LOGOFF_EVENT;
+use windows_sys::Win32::System::Console::CTRL_SHUTDOWN_EVENT;
+
+/// How long Windows is held up while whkd shuts down after the console was
+/// closed or the session ended, which ends the process after 5 seconds anyway
+const GRACE_PERIOD: Duration = Duration::from_secs(4);
+
+lazy_static! {
+    static ref PROXY: Mutex<Option<EventLoopProxy<UserEvent>>> = Mutex::new(None);
+    static ref STATE: (Mutex<State>, Condvar) = (Mutex::new(State::Running), Condvar::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    /// The event loop has been asked to shut down and hasn't finished yet
+    Requested,
+    Finished,
+}
+
+/// Shut down when ctrl + c is pressed or the console is closed
+pub fn install(proxy: EventLoopProxy<UserEvent>) -> Result<()> {
+    *PROXY.lock() = Some(proxy);
+
+    if unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), 1) } == 0 {
+        return Err(eyre!(
+            "could not install the console control handler: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Ask the event loop to shut down, returning false if it has been asked
+/// before or has already stopped
+pub fn request(reason: &str) -> bool {
+    let mut state = STATE.0.lock();
+    if *state != State::Running {
+        return false;
+    }
+
+    let Some(proxy) = PROXY.lock().clone() else {
+        return false;
+    };
+
+    println!("{reason}, shutting down");
+    if proxy.send_event(UserEvent::Shutdown).is_err() {
+        return false;
+    }
+
+    *state = State::Requested;
+    true
+}
+
+/// Wait until whkd has shut down, for requests after which Windows ends the
+/// process as soon as they have been handled
+pub fn wait() {
+    let (state, finished) = &*STATE;
+    let mut state = state.lock();
+    let _ = finished.wait_while_for(&mut state, |state| *state != State::Finished, GRACE_PERIOD);
+}
+
+/// Flush what has been printed and let the requests waiting for the shutdown
+/// return
+pub fn finish() {
+    let _ = std::io::stdout().flush();
+
+    let (state, finished) = &*STATE;
+    *state.lock() = State::Finished;
+    finished.notify_all();
+}
+
+unsafe extern "system" fn ctrl_handler(ctrl_type: u32) -> BOOL {
+    let reason = match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => "ctrl + c was pressed",
+        CTRL_CLOSE_EVENT => "the console was closed",
+        CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => "the session is ending",
+        _ => return 0,
+    };
+
+    // pressing ctrl + c again while whkd is shutting down ends it right away
+    if !request(reason) {
+        return 0;
+    }
+
+    if ctrl_type != CTRL_C_EVENT && ctrl_type != CTRL_BREAK_EVENT {
+        wait();
+    }
+
+    1
+}
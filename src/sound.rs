@@ -0,0 +1,41 @@
+use crate::log;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+use windows_sys::Win32::Media::Audio::PlaySoundW;
+use windows_sys::Win32::Media::Audio::SND_ASYNC;
+use windows_sys::Win32::Media::Audio::SND_FILENAME;
+use windows_sys::Win32::Media::Audio::SND_NODEFAULT;
+
+lazy_static! {
+    /// The .wav file of `.mode_sound`, played whenever the mode changes
+    static ref MODE_SOUND: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+pub fn set_mode_sound(sound: Option<PathBuf>) {
+    *MODE_SOUND.lock() = sound;
+}
+
+pub fn mode_changed() {
+    let sound = MODE_SOUND.lock().clone();
+    if let Some(sound) = sound {
+        play(&sound);
+    }
+}
+
+/// Start playing a .wav file and return right away, so that a dispatch never
+/// waits for it. A sound which is still playing is cut off by the next one
+pub fn play(path: &Path) {
+    let wide = path
+        .as_os_str()
+        .encode_wide()
+        .chain([0])
+        .collect::<Vec<u16>>();
+
+    // without SND_NODEFAULT a missing file would play the default beep instead
+    if unsafe { PlaySoundW(wide.as_ptr(), 0, SND_ASYNC | SND_FILENAME | SND_NODEFAULT) } == 0 {
+        log::error(format!("could not play {}", path.display()));
+    }
+}
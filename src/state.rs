@@ -0,0 +1,90 @@
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// What `.state_file` tells the tools which can't connect to the whkd socket,
+/// e.g. the segment of a PowerShell prompt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub mode: Option<String>,
+    pub profile: Option<String>,
+    /// Whether every chord is unregistered, e.g. while an app of `.ignore` is
+    /// focused
+    pub paused: bool,
+}
+
+/// Where the state is written when `.state_file` isn't given a path
+pub fn default_path() -> PathBuf {
+    dirs::data_local_dir()
+        .expect("there is no local data directory")
+        .join("whkd")
+        .join("state.json")
+}
+
+/// A path of `.state_file`, where `~` stands for the home directory
+pub fn expand(path: &str) -> PathBuf {
+    path.strip_prefix("~/")
+        .or_else(|| path.strip_prefix("~\\"))
+        .and_then(|rest| Some(dirs::home_dir()?.join(rest)))
+        .unwrap_or_else(|| PathBuf::from(path))
+}
+
+/// Replace the state file in one go, so that it is never read half written
+pub fn write(path: &Path, state: &State) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+
+    std::fs::write(&partial, serde_json::to_string(state)?)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Remove the state file once whkd stops, so that it doesn't show a mode
+/// which isn't active anymore
+pub fn remove(path: &Path) {
+    if let Err(error) = std::fs::remove_file(path) {
+        if error.kind() != std::io::ErrorKind::NotFound {
+            println!(
+                "Error while removing the state file {}: {error}",
+                path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let path = std::env::temp_dir()
+            .join(format!("whkd-state-{}", std::process::id()))
+            .join("state.json");
+
+        let state = State {
+            mode: Some(String::from("resize")),
+            profile: None,
+            paused: false,
+        };
+
+        write(&path, &state).unwrap();
+        write(&path, &state).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            written,
+            r#"{"mode":"resize","profile":null,"paused":false}"#
+        );
+
+        remove(&path);
+        assert!(!path.exists());
+        std::fs::remove_dir(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,447 @@
+use crate::ipc;
+use crate::ipc::SocketMessage;
+use crate::redact;
+use crate::runtime;
+use crate::HkmData;
+use color_eyre::eyre::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Set by `--trace-latency` to time each stage of handling a binding
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+/// How many of the latest timings of each stage are kept for every binding
+const MAX_SAMPLES: usize = 1000;
+
+/// How long to wait for a command to finish before leaving it out of the
+/// timings of the last stage
+const FINISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref STATS: Mutex<Vec<BindingStats>> = Mutex::new(vec![]);
+}
+
+/// The stages of handling a binding which `--trace-latency` times, each from
+/// the end of the one before
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// From the keypress until the binding has been looked up
+    Resolve,
+    /// Until its command has been written to the shell
+    Write,
+    /// Until the shell has finished running the command
+    Finish,
+}
+
+/// How often a binding has been invoked since whkd started, and how long it
+/// took from the keypress until its command was handed to the shell
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BindingStats {
+    pub mode: Option<String>,
+    pub keys: Vec<String>,
+    /// The `~name` of the binding, which its statistics follow when its chord
+    /// changes across reloads
+    #[serde(default)]
+    pub name: Option<String>,
+    pub process_name: Option<String>,
+    pub command: Option<String>,
+    pub invocations: u64,
+    pub dispatches: u64,
+    pub total_latency_us: u64,
+    pub max_latency_us: u64,
+    /// The percentiles of each stage, with `--trace-latency`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<Latency>,
+    #[serde(skip)]
+    samples: Samples,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Samples {
+    resolve: VecDeque<u64>,
+    write: VecDeque<u64>,
+    finish: VecDeque<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Latency {
+    pub resolve: Percentiles,
+    pub write: Percentiles,
+    /// Only known for commands run in a shell session, once the session has
+    /// gotten past them
+    pub finish: Option<Percentiles>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+}
+
+impl Percentiles {
+    fn of(samples: &VecDeque<u64>) -> Option<Self> {
+        let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+
+        // the nearest rank, so that p99 of a handful of samples is the slowest
+        let rank = |percent: usize| sorted[(sorted.len() * percent).div_ceil(100) - 1];
+
+        (!sorted.is_empty()).then(|| Self {
+            samples: sorted.len(),
+            p50_us: rank(50),
+            p90_us: rank(90),
+            p99_us: rank(99),
+        })
+    }
+
+    fn show(&self) -> String {
+        format!(
+            "{:.1} / {:.1} / {:.1}",
+            milliseconds(self.p50_us),
+            milliseconds(self.p90_us),
+            milliseconds(self.p99_us)
+        )
+    }
+}
+
+impl BindingStats {
+    fn matches(&self, data: &HkmData) -> bool {
+        if let (Some(name), Some(other)) = (&self.name, &data.name) {
+            return name == other && self.mode == data.mode;
+        }
+
+        self.mode == data.mode
+            && self.keys == data.keys
+            && self.process_name == data.process_name
+            && self.command == data.command
+    }
+
+    fn mean_latency_us(&self) -> u64 {
+        self.total_latency_us
+            .checked_div(self.dispatches)
+            .unwrap_or(0)
+    }
+
+    fn latency(&self) -> Option<Latency> {
+        Some(Latency {
+            resolve: Percentiles::of(&self.samples.resolve)?,
+            write: Percentiles::of(&self.samples.write)?,
+            finish: Percentiles::of(&self.samples.finish),
+        })
+    }
+}
+
+/// Start tracking a binding so that it shows up in `whkd stats` even if it is
+/// never used
+pub fn track(data: &HkmData) {
+    let mut stats = STATS.lock();
+    if let Some(stats) = stats.iter_mut().find(|stats| stats.matches(data)) {
+        stats.keys.clone_from(&data.keys);
+        stats.process_name.clone_from(&data.process_name);
+        stats.command.clone_from(&data.command);
+    } else {
+        stats.push(BindingStats {
+            mode: data.mode.clone(),
+            keys: data.keys.clone(),
+            name: data.name.clone(),
+            process_name: data.process_name.clone(),
+            command: data.command.clone(),
+            invocations: 0,
+            dispatches: 0,
+            total_latency_us: 0,
+            max_latency_us: 0,
+            latency: None,
+            samples: Samples::default(),
+        });
+    }
+}
+
+pub fn record_invocation(data: &HkmData) {
+    if let Some(stats) = STATS.lock().iter_mut().find(|stats| stats.matches(data)) {
+        stats.invocations += 1;
+    }
+}
+
+pub fn record_dispatch(data: &HkmData, latency: Duration) {
+    let latency = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+
+    if let Some(stats) = STATS.lock().iter_mut().find(|stats| stats.matches(data)) {
+        stats.dispatches += 1;
+        stats.total_latency_us = stats.total_latency_us.saturating_add(latency);
+        stats.max_latency_us = stats.max_latency_us.max(latency);
+    }
+}
+
+pub fn set_tracing(tracing: bool) {
+    TRACING.store(tracing, Ordering::Relaxed);
+}
+
+pub fn is_tracing() -> bool {
+    TRACING.load(Ordering::Relaxed)
+}
+
+pub fn record_trace(data: &HkmData, stage: Stage, duration: Duration) {
+    let duration = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+
+    if let Some(stats) = STATS.lock().iter_mut().find(|stats| stats.matches(data)) {
+        let samples = match stage {
+            Stage::Resolve => &mut stats.samples.resolve,
+            Stage::Write => &mut stats.samples.write,
+            Stage::Finish => &mut stats.samples.finish,
+        };
+
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(duration);
+    }
+}
+
+/// Wait for the shell to create `probe` once it has finished the command of a
+/// binding, which was written just now, and record how long that took
+pub fn await_finish(data: HkmData, probe: PathBuf) {
+    let written = Instant::now();
+
+    runtime::spawn_blocking(move || {
+        // the timer of Windows makes this accurate to about 15ms at worst,
+        // which is still plenty to tell a slow command from a slow whkd
+        while !probe.exists() {
+            if written.elapsed() > FINISH_TIMEOUT {
+                return;
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        record_trace(&data, Stage::Finish, written.elapsed());
+        let _ = std::fs::remove_file(&probe);
+    });
+}
+
+pub fn snapshot() -> Vec<BindingStats> {
+    let mut stats = STATS.lock().clone();
+    for stats in &mut stats {
+        stats.command = stats.command.as_deref().map(redact::command);
+        stats.latency = stats.latency();
+    }
+
+    stats
+}
+
+/// Fetch the statistics of the running whkd instance and print them as a table
+/// with the most used bindings first, or as JSON
+pub fn show(json: bool) -> Result<()> {
+    let stream = ipc::send(&SocketMessage::Stats)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+
+    if json {
+        print!("{line}");
+        return Ok(());
+    }
+
+    let mut stats: Vec<BindingStats> = serde_json::from_str(&line)?;
+    stats.sort_by_key(|stats| std::cmp::Reverse(stats.invocations));
+
+    println!(
+        "{:>8}  {:>10}  {:>10}  {:<10}  {:<24}  command",
+        "count", "mean ms", "max ms", "mode", "chord"
+    );
+
+    for stats in &stats {
+        let mut chord = stats.keys.join(" + ");
+        if let Some(name) = &stats.name {
+            chord = format!("{chord} ~name({name})");
+        }
+        if let Some(process_name) = &stats.process_name {
+            chord = format!("{chord} [{process_name}]");
+        }
+
+        println!(
+            "{:>8}  {:>10.1}  {:>10.1}  {:<10}  {:<24}  {}",
+            stats.invocations,
+            milliseconds(stats.mean_latency_us()),
+            milliseconds(stats.max_latency_us),
+            stats.mode.as_deref().unwrap_or("default"),
+            chord,
+            stats.command.as_deref().unwrap_or("-"),
+        );
+    }
+
+    if stats.iter().any(|stats| stats.latency.is_some()) {
+        show_latency(&stats);
+    }
+
+    Ok(())
+}
+
+/// Print the percentiles of each stage which `--trace-latency` has timed
+fn show_latency(stats: &[BindingStats]) {
+    println!();
+    println!(
+        "{:<24}  {:>8}  {:>22}  {:>22}  {:>22}",
+        "p50 / p90 / p99 ms", "samples", "pressed → resolved", "→ written", "→ finished"
+    );
+
+    for stats in stats {
+        let Some(latency) = &stats.latency else {
+            continue;
+        };
+
+        let mut chord = stats.keys.join(" + ");
+        if let Some(mode) = &stats.mode {
+            chord = format!("{mode}: {chord}");
+        }
+
+        println!(
+            "{:<24}  {:>8}  {:>22}  {:>22}  {:>22}",
+            chord,
+            latency.resolve.samples,
+            latency.resolve.show(),
+            latency.write.show(),
+            latency
+                .finish
+                .as_ref()
+                .map_or_else(|| String::from("-"), Percentiles::show),
+        );
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn milliseconds(us: u64) -> f64 {
+    us as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_record() {
+        let whkdrc = parser()
+            .parse(
+                r#"
+.shell pwsh
+
+alt + h : echo "hello"
+alt + j : echo "unused"
+"#,
+            )
+            .unwrap();
+
+        let bindings = whkdrc
+            .bindings
+            .iter()
+            .map(|binding| HkmData::try_from(binding).unwrap())
+            .collect::<Vec<_>>();
+
+        for data in &bindings {
+            track(data);
+        }
+
+        record_invocation(&bindings[0]);
+        record_dispatch(&bindings[0], Duration::from_millis(2));
+        record_invocation(&bindings[0]);
+        record_dispatch(&bindings[0], Duration::from_millis(4));
+
+        let stats = snapshot();
+        let hello = stats
+            .iter()
+            .find(|stats| stats.matches(&bindings[0]))
+            .unwrap();
+        assert_eq!(hello.invocations, 2);
+        assert_eq!(hello.mean_latency_us(), 3000);
+        assert_eq!(hello.max_latency_us, 4000);
+
+        let unused = stats
+            .iter()
+            .find(|stats| stats.matches(&bindings[1]))
+            .unwrap();
+        assert_eq!(unused.invocations, 0);
+        assert_eq!(unused.mean_latency_us(), 0);
+    }
+
+    #[test]
+    fn test_percentiles() {
+        assert_eq!(Percentiles::of(&VecDeque::new()), None);
+
+        let samples = (1..=200).rev().collect::<VecDeque<_>>();
+        assert_eq!(
+            Percentiles::of(&samples),
+            Some(Percentiles {
+                samples: 200,
+                p50_us: 100,
+                p90_us: 180,
+                p99_us: 198,
+            })
+        );
+
+        let percentiles = Percentiles::of(&VecDeque::from([1500, 500])).unwrap();
+        assert_eq!(percentiles.p50_us, 500);
+        assert_eq!(percentiles.p99_us, 1500);
+        assert_eq!(percentiles.show(), "0.5 / 1.5 / 1.5");
+    }
+
+    #[test]
+    fn test_trace() {
+        let whkdrc = parser()
+            .parse(
+                r#"
+.shell pwsh
+
+alt + t : echo "traced"
+"#,
+            )
+            .unwrap();
+
+        let data = HkmData::try_from(&whkdrc.bindings[0]).unwrap();
+        track(&data);
+
+        let traced = || {
+            snapshot()
+                .into_iter()
+                .find(|stats| stats.matches(&data))
+                .unwrap()
+        };
+        assert_eq!(traced().latency, None);
+
+        record_trace(&data, Stage::Resolve, Duration::from_micros(200));
+        record_trace(&data, Stage::Write, Duration::from_millis(1));
+        let latency = traced().latency.unwrap();
+        assert_eq!(latency.resolve.p50_us, 200);
+        assert_eq!(latency.write.p99_us, 1000);
+        assert_eq!(latency.finish, None);
+
+        let probe = std::env::temp_dir().join(format!("whkd-trace-{}", std::process::id()));
+        await_finish(data.clone(), probe.clone());
+        std::fs::write(&probe, "").unwrap();
+
+        let start = Instant::now();
+        while traced().latency.unwrap().finish.is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!probe.exists());
+
+        for _ in 0..MAX_SAMPLES + 10 {
+            record_trace(&data, Stage::Resolve, Duration::from_micros(100));
+        }
+        assert_eq!(traced().latency.unwrap().resolve.samples, MAX_SAMPLES);
+        assert_eq!(traced().latency.unwrap().resolve.p99_us, 100);
+    }
+}
@@ -0,0 +1,61 @@
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/LGUG2Z/whkd/releases/latest";
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: String,
+}
+
+/// Ask the GitHub API for the latest release and print whether it is newer than
+/// the running version; nothing about this machine is sent besides the request
+pub fn check() -> Result<()> {
+    let body = ureq::get(LATEST_RELEASE_URL)
+        .set("User-Agent", concat!("whkd/", env!("CARGO_PKG_VERSION")))
+        .call()?
+        .into_string()?;
+
+    let release: Release = serde_json::from_str(&body)?;
+    let running = env!("CARGO_PKG_VERSION");
+
+    let latest = parse_version(&release.tag_name)
+        .ok_or_else(|| eyre!("could not parse release version {}", release.tag_name))?;
+    let current =
+        parse_version(running).ok_or_else(|| eyre!("could not parse running version {running}"))?;
+
+    if latest > current {
+        println!(
+            "whkd {} is available (running v{running}): {}",
+            release.tag_name, release.html_url
+        );
+    } else {
+        println!("whkd v{running} is up to date");
+    }
+
+    Ok(())
+}
+
+/// Check for updates without holding up the start of whkd
+pub fn check_in_background() {
+    std::thread::spawn(|| {
+        if let Err(error) = check() {
+            println!("Error while checking for updates: {error}");
+        }
+    });
+}
+
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version
+        .trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map(str::parse::<u64>);
+
+    Some((
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    ))
+}
@@ -1,26 +1,268 @@
-use crate::parser::parser;
+use crate::lint::Lints;
+use crate::parser::parse_str;
 use crate::parser::HotkeyBinding;
-use chumsky::Parser;
+use crate::parser::InternalAction;
+use crate::parser::Step;
+use crate::session;
 use color_eyre::eyre::eyre;
 use color_eyre::eyre::Result;
+use color_eyre::eyre::WrapErr;
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Version of the whkdrc syntax understood by this build, bumped whenever the
+/// grammar changes in a way that `whkd migrate` needs to rewrite
+pub const CURRENT_VERSION: u32 = 3;
+
+/// How long a command of `.include-cmd` may take before whkdrc can't be loaded
+const INCLUDE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Cleared by `--no-include-cmd` to load whkdrc without running any command
+static INCLUDE_COMMANDS: AtomicBool = AtomicBool::new(true);
+
+pub fn set_include_commands(include_commands: bool) {
+    INCLUDE_COMMANDS.store(include_commands, Ordering::Relaxed);
+}
+
+/// How long a modifier-only chord can be held and still count as a tap
+pub const DEFAULT_TAP_THRESHOLD: Duration = Duration::from_millis(250);
+
+/// How far apart the keys of a rolling chord such as `j + k` can go down
+pub const DEFAULT_ROLLOVER: Duration = Duration::from_millis(50);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Whkdrc {
     pub shell: Shell,
+    /// Extra arguments for the shell sessions, e.g. `-NoProfile`
+    pub shell_args: Vec<String>,
+    pub version: u32,
+    pub exec: Exec,
+    pub backend: Backend,
+    pub tap_threshold: Duration,
+    /// How long a modifier which was pressed and released on its own still
+    /// counts towards the next chord, for entering chords one key at a time
+    pub sticky_modifiers: Option<Duration>,
+    /// How far apart the keys of a rolling chord can go down, see `.rollover`
+    pub rollover: Duration,
+    /// How long a mode other than the default one waits for the next key before
+    /// whkd returns to the default mode
+    pub timeout: Option<Duration>,
+    pub rate_limit: Option<RateLimit>,
+    /// How long whkd waits after starting before it registers the bindings
+    pub startup_delay: Option<Duration>,
+    /// Processes of `.wait_for_process`, e.g. `komorebi.exe`, which have to be
+    /// running before the bindings are registered
+    pub wait_for_processes: Vec<String>,
+    /// Chords which the hook swallows in every mode, e.g. Windows shortcuts
+    pub suppress: Vec<Vec<String>>,
+    /// Modes which are activated while the window of a process is focused
+    pub app_modes: Vec<(String, String)>,
+    /// Keys and the mode which is active while they are held down, e.g.
+    /// `CapsLock` for a navigation layer
+    pub layers: Vec<(String, String)>,
+    /// Commands of `@on-idle`, which run once there has been no input for
+    /// their duration
+    pub on_idle: Vec<(Duration, String)>,
+    /// Modes declared with `mode <name> : <parent>`, which inherit every binding
+    /// of the parent whose chord they don't bind themselves
+    pub mode_parents: Vec<(String, Option<String>)>,
+    /// Shells declared with `.mode <name> shell <shell>`, which run the commands
+    /// of the mode instead of the shell of `.shell`
+    pub mode_shells: Vec<(String, Shell)>,
+    /// Modes declared with `.mode <name> count`, in which digits which aren't
+    /// bound add up to a count for the next binding
+    pub count_modes: Vec<String>,
+    /// Processes which chords are passed through to instead of dispatching their
+    /// commands while they are focused
+    pub ignore: Vec<String>,
+    /// What happens to the bindings while the session is used over remote desktop
+    pub remote_session: RemotePolicy,
+    pub shell_instances: usize,
+    /// Lines written to every shell session right after it has been spawned
+    pub shell_init: Vec<String>,
+    /// How long the shell sessions can go without a command before they are
+    /// stopped, to be started again by the next one
+    pub shell_idle: Option<Duration>,
+    /// How often the shell sessions are checked for having exited or stopped
+    /// responding, to be started again before the next command is lost
+    pub shell_health_check: Option<Duration>,
+    /// Runs with the error in `WHKD_ERROR` whenever a command fails
+    pub on_error: Option<String>,
+    /// The broker which `@mqtt` publishes to
+    pub mqtt: Option<MqttBroker>,
+    /// Where the active mode is written for tools which can't connect to the
+    /// socket, see `.state_file`
+    pub state_file: Option<PathBuf>,
+    /// Commands of `.include-cmd`, whose output has been merged into the
+    /// bindings once whkdrc is loaded
+    pub include_commands: Vec<String>,
+    /// Patterns of `.redact`, which are followed by secrets in the commands
+    pub redact: Vec<String>,
+    /// Whether the commands are printed before they are run, see `.echo`
+    pub echo: bool,
+    /// A .wav file played whenever the mode changes, see `.mode_sound`
+    pub mode_sound: Option<PathBuf>,
+    /// How the active mode is shown outside of whkd, see `.mode_indicator`
+    pub mode_indicators: Vec<ModeIndicator>,
+    pub options: Options,
+    /// Levels of the warnings about whkdrc, set with `.strict`, `.allow` and `.deny`
+    pub lints: Lints,
+    /// Aliases which are defined more than once in the same file
+    pub shadowed_aliases: Vec<String>,
+    /// Steps defined with `.macro` and run by `run-macro`
+    pub macros: Vec<(String, Vec<Step>)>,
+    pub profiles: Vec<String>,
+    /// Chords removed with `unbind` and the mode they were removed from, which
+    /// also removes them from the files merged before this one
+    pub unbound: Vec<(Option<String>, Vec<String>)>,
     pub app_bindings: Vec<(Vec<String>, Vec<HotkeyBinding>)>,
     pub bindings: Vec<HotkeyBinding>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// An MQTT broker, e.g. `192.168.1.10:1883`, with the username and password to
+/// log in with if it requires them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttBroker {
+    pub address: String,
+    pub login: Option<(String, String)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Shell {
     Cmd,
     Powershell,
     Pwsh,
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Exec {
+    /// Commands are written to a long-lived shell session
+    #[default]
+    Session,
+    /// Every command is spawned as its own shell process
+    Direct,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// Hotkeys are registered with `RegisterHotKey`
+    #[default]
+    Register,
+    /// Key events are captured with a low-level keyboard hook, which can also
+    /// bind chords owned by other apps, modifier-only chords and chords with
+    /// left or right modifiers
+    Hook,
+}
+
+/// At most `per_second` commands are dispatched every second
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub policy: RatePolicy,
+}
+
+impl RateLimit {
+    pub fn interval(self) -> Duration {
+        Duration::from_secs(1) / self.per_second
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum RemotePolicy {
+    /// The bindings stay as they are
+    #[default]
+    Keep,
+    /// Every chord is unregistered until the session is local again
+    Pause,
+    /// The mode is active until the session is local again
+    Mode(String),
+}
+
+/// A way of showing the active mode without an overlay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeIndicator {
+    /// A lock key whose light is on while a mode other than the default one is
+    /// active
+    Lock(LockKey),
+    /// A command which runs with the mode in `WHKD_MODE` whenever it changes
+    Command(String),
+}
+
+/// Caps lock, num lock or scroll lock
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LockKey {
+    Caps,
+    Num,
+    Scroll,
+}
+
+/// What happens to a command which arrives before the next one may be dispatched
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum RatePolicy {
+    /// Wait for its turn
+    Queue,
+    /// Wait for its turn, dropping repeats of the commands which are waiting
+    #[default]
+    Coalesce,
+    /// Drop it
+    Drop,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Options {
+    /// Compare the running version against the latest GitHub release at startup
+    pub check_updates: bool,
+    /// Unregister every chord while a process of `.ignore` is focused
+    pub unregister_ignored: bool,
+    /// Print what is done for every keypress even when stdout isn't a console
+    pub verbose: bool,
+    /// Kill the processes of direct commands along with whkd, like the shell
+    /// sessions
+    pub kill_commands: bool,
+    /// Hold back the error hooks and `~notify` while do not disturb is on
+    pub respect_dnd: bool,
+    /// Send common `komorebic` commands straight to the socket of komorebi
+    pub komorebi_pipe: bool,
+}
+
+impl Options {
+    /// Enable the option with the given name, returning false if it is not known
+    pub fn enable(&mut self, name: &str) -> bool {
+        match name {
+            "check_updates" => self.check_updates = true,
+            "unregister_ignored" => self.unregister_ignored = true,
+            "verbose" => self.verbose = true,
+            "kill_commands" => self.kill_commands = true,
+            "respect_dnd" => self.respect_dnd = true,
+            "komorebi_pipe" => self.komorebi_pipe = true,
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Whether `--config` asks for whkdrc to be read from stdin
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Where whkdrc is read from, for messages
+pub fn source(path: &Path) -> String {
+    if is_stdin(path) {
+        String::from("stdin")
+    } else {
+        path.display().to_string()
+    }
+}
+
 #[allow(clippy::fallible_impl_from)]
 impl From<String> for Shell {
     fn from(value: String) -> Self {
@@ -44,11 +286,569 @@ impl Display for Shell {
 }
 
 impl Whkdrc {
+    /// The config file defaults to `~/.config/whkdrc`, or `<WHKD_CONFIG_HOME>/whkdrc`
+    pub fn default_path() -> Result<PathBuf> {
+        let home = match std::env::var("WHKD_CONFIG_HOME") {
+            Ok(home_path) => {
+                let home = PathBuf::from(&home_path);
+                if !home.is_dir() {
+                    return Err(eyre!(
+                        "$Env:WHKD_CONFIG_HOME is set to '{home_path}', which is not a valid directory"
+                    ));
+                }
+
+                home
+            }
+            Err(_) => dirs::home_dir()
+                .ok_or_else(|| eyre!("no home directory found"))?
+                .join(".config"),
+        };
+
+        Ok(home.join("whkdrc"))
+    }
+
+    /// A whkdrc without any bindings, which whkd runs with while it waits for
+    /// whkdrc to be created or fixed
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            shell: Shell::Pwsh,
+            shell_args: vec![],
+            version: CURRENT_VERSION,
+            exec: Exec::Direct,
+            backend: Backend::default(),
+            tap_threshold: DEFAULT_TAP_THRESHOLD,
+            timeout: None,
+            rate_limit: None,
+            startup_delay: None,
+            sticky_modifiers: None,
+            rollover: DEFAULT_ROLLOVER,
+            wait_for_processes: vec![],
+            state_file: None,
+            suppress: vec![],
+            app_modes: vec![],
+            layers: vec![],
+            on_idle: vec![],
+            mode_parents: vec![],
+            mode_shells: vec![],
+            count_modes: vec![],
+            ignore: vec![],
+            remote_session: RemotePolicy::Keep,
+            shell_instances: 1,
+            shell_init: vec![],
+            shell_idle: None,
+            shell_health_check: None,
+            on_error: None,
+            mqtt: None,
+            include_commands: vec![],
+            redact: vec![],
+            echo: true,
+            mode_sound: None,
+            mode_indicators: vec![],
+            options: Options::default(),
+            lints: Lints::default(),
+            shadowed_aliases: vec![],
+            macros: vec![],
+            profiles: vec![],
+            unbound: vec![],
+            app_bindings: vec![],
+            bindings: vec![],
+        }
+    }
+
+    /// Load whkdrc from a file, or from stdin if the path is `-`
     pub fn load(path: &PathBuf) -> Result<Self> {
-        let contents = std::fs::read_to_string(path)?;
+        let contents = if is_stdin(path) {
+            std::io::read_to_string(std::io::stdin())?
+        } else {
+            std::fs::read_to_string(path)?
+        };
+
+        let mut whkdrc =
+            parse_str(&contents).map_err(|error| eyre!("could not parse whkdrc: {:?}", error))?;
+
+        whkdrc.include()?;
+        Ok(whkdrc)
+    }
+
+    /// Merge the bindings printed by the commands of `.include-cmd`, which are
+    /// parsed with the shell of this file as if they were a file of their own
+    fn include(&mut self) -> Result<()> {
+        if self.include_commands.is_empty() {
+            return Ok(());
+        }
+
+        if !INCLUDE_COMMANDS.load(Ordering::Relaxed) {
+            println!(
+                "not running the commands of .include-cmd, which were disabled with --no-include-cmd"
+            );
+            return Ok(());
+        }
+
+        for command in self.include_commands.clone() {
+            let output = session::output_within(self.shell, &command, INCLUDE_TIMEOUT)
+                .wrap_err_with(|| format!("could not run '{command}' of .include-cmd"))?;
+
+            if output.trim().is_empty() {
+                continue;
+            }
+
+            let mut fragment =
+                parse_str(&format!(".shell {}\n{output}", self.shell)).map_err(|error| {
+                    eyre!("could not parse the output of '{command}' of .include-cmd: {error:?}")
+                })?;
+
+            if !fragment.include_commands.is_empty() {
+                return Err(eyre!(
+                    "the output of '{command}' of .include-cmd can't include more commands"
+                ));
+            }
+
+            // the output is written for the syntax of this file
+            fragment.version = self.version;
+            self.merge(fragment);
+        }
+
+        Ok(())
+    }
+
+    /// The whkdrc files which exist out of `%PROGRAMDATA%\whkd\whkdrc` for the
+    /// whole machine, the one of the user and `./whkdrc`, in the order they are merged
+    pub fn layers() -> Result<Vec<PathBuf>> {
+        let candidates = [
+            std::env::var_os("PROGRAMDATA")
+                .map(|data| PathBuf::from(data).join("whkd").join("whkdrc")),
+            Some(Self::default_path()?),
+            std::env::current_dir().ok().map(|dir| dir.join("whkdrc")),
+        ];
+
+        let mut layers: Vec<PathBuf> = vec![];
+        for path in candidates.iter().flatten() {
+            let Ok(canonical) = path.canonicalize() else {
+                continue;
+            };
+
+            if path.is_file() && !layers.contains(&canonical) {
+                layers.push(canonical);
+            }
+        }
+
+        if layers.is_empty() {
+            return Err(eyre!(
+                "no whkdrc was found in {}",
+                candidates
+                    .iter()
+                    .flatten()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        Ok(layers)
+    }
+
+    /// Load every file and merge each one into those before it
+    pub fn load_layers(paths: &[PathBuf]) -> Result<Self> {
+        let mut merged: Option<Self> = None;
+
+        for path in paths {
+            let layer = Self::load(path)
+                .wrap_err_with(|| format!("could not load whkdrc from {}", source(path)))?;
+
+            merged = Some(match merged {
+                Some(mut merged) => {
+                    merged.merge(layer);
+                    merged
+                }
+                None => layer,
+            });
+        }
+
+        merged.ok_or_else(|| eyre!("no whkdrc to load"))
+    }
+
+    /// Merge a file which takes precedence into this one: its directives override
+    /// those which it doesn't leave at their defaults, its `unbind` statements
+    /// remove the chords bound here and its bindings are added after these
+    pub fn merge(&mut self, layer: Self) {
+        self.shell = layer.shell;
+        if !layer.shell_args.is_empty() {
+            self.shell_args = layer.shell_args;
+        }
+
+        // warn about the oldest syntax in any of the files
+        self.version = self.version.min(layer.version);
+
+        if layer.exec != Exec::default() {
+            self.exec = layer.exec;
+        }
+
+        if layer.backend != Backend::default() {
+            self.backend = layer.backend;
+        }
+
+        if layer.tap_threshold != DEFAULT_TAP_THRESHOLD {
+            self.tap_threshold = layer.tap_threshold;
+        }
+
+        if layer.rollover != DEFAULT_ROLLOVER {
+            self.rollover = layer.rollover;
+        }
+
+        if layer.remote_session != RemotePolicy::default() {
+            self.remote_session = layer.remote_session;
+        }
+
+        if layer.shell_instances != 1 {
+            self.shell_instances = layer.shell_instances;
+        }
+
+        self.timeout = layer.timeout.or(self.timeout);
+        self.rate_limit = layer.rate_limit.or(self.rate_limit);
+        self.startup_delay = layer.startup_delay.or(self.startup_delay);
+        self.sticky_modifiers = layer.sticky_modifiers.or(self.sticky_modifiers);
+        self.shell_idle = layer.shell_idle.or(self.shell_idle);
+        self.shell_health_check = layer.shell_health_check.or(self.shell_health_check);
+        if layer.on_error.is_some() {
+            self.on_error = layer.on_error;
+        }
+        if layer.mqtt.is_some() {
+            self.mqtt = layer.mqtt;
+        }
+        if layer.state_file.is_some() {
+            self.state_file = layer.state_file;
+        }
+        self.include_commands.extend(layer.include_commands);
+        self.redact.extend(layer.redact);
+        self.echo &= layer.echo;
+        if layer.mode_sound.is_some() {
+            self.mode_sound = layer.mode_sound;
+        }
+        self.mode_indicators.extend(layer.mode_indicators);
+        self.options.check_updates |= layer.options.check_updates;
+        self.options.unregister_ignored |= layer.options.unregister_ignored;
+        self.options.verbose |= layer.options.verbose;
+        self.options.kill_commands |= layer.options.kill_commands;
+        self.options.respect_dnd |= layer.options.respect_dnd;
+        self.options.komorebi_pipe |= layer.options.komorebi_pipe;
+        self.lints.strict |= layer.lints.strict;
+        self.lints.levels.extend(layer.lints.levels);
+        self.shadowed_aliases.extend(layer.shadowed_aliases);
+        self.macros.extend(layer.macros);
+        self.suppress.extend(layer.suppress);
+        self.app_modes.extend(layer.app_modes);
+        self.layers.extend(layer.layers);
+        self.on_idle.extend(layer.on_idle);
+        self.mode_parents.extend(layer.mode_parents);
+        self.mode_shells.extend(layer.mode_shells);
+        self.count_modes.extend(layer.count_modes);
+        self.ignore.extend(layer.ignore);
+        self.wait_for_processes.extend(layer.wait_for_processes);
+        self.shell_init.extend(layer.shell_init);
+
+        for profile in layer.profiles {
+            if !self.profiles.contains(&profile) {
+                self.profiles.push(profile);
+            }
+        }
+
+        for (mode, keys) in &layer.unbound {
+            self.unbind(mode.as_ref(), keys);
+        }
+
+        self.unbound.extend(layer.unbound);
+        self.app_bindings.extend(layer.app_bindings);
+        self.bindings.extend(layer.bindings);
+    }
+
+    /// Remove the bindings of a chord in a mode, including the app-specific ones
+    /// for the default mode
+    pub fn unbind(&mut self, mode: Option<&String>, keys: &[String]) {
+        self.bindings
+            .retain(|binding| binding.mode.as_ref() != mode || !same_chord(&binding.keys, keys));
+
+        if mode.is_none() {
+            self.app_bindings
+                .retain(|(chord, _)| !same_chord(chord, keys));
+        }
+    }
+
+    /// Chords which are unbound and not bound again afterwards, which are kept
+    /// free for other apps
+    #[must_use]
+    pub fn kept_free(&self) -> Vec<(Option<&String>, &Vec<String>)> {
+        let mut free: Vec<(Option<&String>, &Vec<String>)> = vec![];
+
+        for (mode, keys) in &self.unbound {
+            let bound = self
+                .bindings
+                .iter()
+                .any(|binding| binding.mode == *mode && same_chord(&binding.keys, keys))
+                || (mode.is_none()
+                    && self
+                        .app_bindings
+                        .iter()
+                        .any(|(chord, _)| same_chord(chord, keys)));
+
+            if !bound
+                && !free.iter().any(|(free_mode, free_keys)| {
+                    *free_mode == mode.as_ref() && same_chord(free_keys, keys)
+                })
+            {
+                free.push((mode.as_ref(), keys));
+            }
+        }
+
+        free
+    }
+
+    /// The shell declared for a mode, where a later declaration overrides an
+    /// earlier one
+    #[must_use]
+    pub fn mode_shell(&self, mode: Option<&String>) -> Option<Shell> {
+        let mode = mode?;
+        self.mode_shells
+            .iter()
+            .rev()
+            .find(|(name, _)| name == mode)
+            .map(|(_, shell)| *shell)
+    }
+
+    /// The parent of a mode, where a later declaration overrides an earlier one
+    fn parent(&self, mode: &str) -> Option<&Option<String>> {
+        self.mode_parents
+            .iter()
+            .rev()
+            .find(|(child, _)| child == mode)
+            .map(|(_, parent)| parent)
+    }
+
+    /// Copies of the bindings which the modes with a parent inherit from it and
+    /// its own parents, leaving out the chords which a mode closer to the child
+    /// already binds
+    pub fn inherited_bindings(&self) -> Result<Vec<HotkeyBinding>> {
+        let all = self
+            .app_bindings
+            .iter()
+            .flat_map(|(_, bindings)| bindings)
+            .chain(&self.bindings)
+            .collect::<Vec<_>>();
+
+        let mut inherited = vec![];
+        let mut children = vec![];
+        for (mode, _) in &self.mode_parents {
+            if children.contains(&mode) {
+                continue;
+            }
+
+            children.push(mode);
+
+            let mut ancestors: Vec<&Option<String>> = vec![];
+            let mut current = mode.as_str();
+            while let Some(parent) = self.parent(current) {
+                if parent.as_ref() == Some(mode) || ancestors.contains(&parent) {
+                    return Err(eyre!("mode {mode} inherits from itself"));
+                }
+
+                ancestors.push(parent);
+                match parent {
+                    Some(parent) => current = parent,
+                    None => break,
+                }
+            }
+
+            let mut bound = all
+                .iter()
+                .filter(|binding| binding.mode.as_ref() == Some(mode))
+                .map(|binding| &binding.keys)
+                .collect::<Vec<_>>();
+
+            for ancestor in ancestors {
+                let bindings = all
+                    .iter()
+                    .filter(|binding| binding.mode == *ancestor)
+                    .collect::<Vec<_>>();
+
+                if bindings.is_empty() {
+                    return Err(eyre!(
+                        "mode {mode} inherits from mode {}, which has no bindings",
+                        ancestor.as_deref().unwrap_or("default")
+                    ));
+                }
+
+                for binding in &bindings {
+                    if !bound.iter().any(|keys| same_chord(keys, &binding.keys)) {
+                        inherited.push(HotkeyBinding {
+                            mode: Some(mode.clone()),
+                            ..(**binding).clone()
+                        });
+                    }
+                }
+
+                bound.extend(bindings.iter().map(|binding| &binding.keys));
+            }
+        }
+
+        Ok(inherited)
+    }
+
+    /// The steps of every macro by name, where a later definition overrides an
+    /// earlier one, failing if a binding runs a macro which isn't defined
+    pub fn macros(&self) -> Result<HashMap<String, Vec<Step>>> {
+        let macros = self.macros.iter().cloned().collect::<HashMap<_, _>>();
+
+        for binding in self
+            .app_bindings
+            .iter()
+            .flat_map(|(_, bindings)| bindings)
+            .chain(&self.bindings)
+        {
+            if let Some(InternalAction::RunMacro(name)) = &binding.internal_action {
+                if !macros.contains_key(name) {
+                    return Err(eyre!(
+                        "{} on line {} runs macro {name}, which isn't defined",
+                        binding.keys.join(" + "),
+                        binding.line
+                    ));
+                }
+            }
+        }
+
+        Ok(macros)
+    }
+}
+
+/// Whether two chords are made up of the same keys, ignoring the order of the
+/// modifiers and case
+fn same_chord(a: &[String], b: &[String]) -> bool {
+    let normalize = |keys: &[String]| {
+        let mut keys = keys
+            .iter()
+            .map(|key| key.to_lowercase())
+            .collect::<Vec<_>>();
+        let trigger = keys.pop();
+        keys.sort();
+        keys.extend(trigger);
+        keys
+    };
+
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser;
+    use chumsky::Parser;
+
+    #[test]
+    fn test_empty() {
+        let whkdrc = Whkdrc::empty();
+        assert_eq!(whkdrc.exec, Exec::Direct);
+        assert!(whkdrc.bindings.is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut system = parser()
+            .parse(
+                r"
+.shell pwsh
+.timeout 800
+
+alt + h : komorebic focus left
+alt + q : komorebic close",
+            )
+            .unwrap();
+
+        let user = parser()
+            .parse(
+                r"
+.shell cmd
+.backend hook
+
+unbind Alt + H
+alt + l : komorebic focus right",
+            )
+            .unwrap();
+
+        system.merge(user);
+
+        assert_eq!(system.shell, Shell::Cmd);
+        assert_eq!(system.backend, Backend::Hook);
+        assert_eq!(system.timeout, Some(Duration::from_millis(800)));
+        assert_eq!(
+            system
+                .bindings
+                .iter()
+                .map(|binding| binding.keys.join(" + "))
+                .collect::<Vec<_>>(),
+            vec!["alt + q", "alt + l"]
+        );
+    }
+
+    #[test]
+    fn test_inherited_bindings() {
+        let whkdrc = parser()
+            .parse(
+                r"
+.shell pwsh
+
+mode resize : window
+mode fine : resize
+
+alt + w ; window
+window > h : komorebic focus left
+window > l : komorebic focus right
+window > escape ; default
+resize > h : komorebic resize-axis horizontal decrease
+fine > l : echo fine",
+            )
+            .unwrap();
+
+        let inherited = whkdrc
+            .inherited_bindings()
+            .unwrap()
+            .into_iter()
+            .map(|binding| {
+                (
+                    binding.mode.unwrap(),
+                    binding.keys.join(" + "),
+                    binding.command,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            inherited,
+            vec![
+                (
+                    String::from("resize"),
+                    String::from("l"),
+                    Some(String::from("komorebic focus right"))
+                ),
+                (String::from("resize"), String::from("escape"), None),
+                (
+                    String::from("fine"),
+                    String::from("h"),
+                    Some(String::from("komorebic resize-axis horizontal decrease"))
+                ),
+                (String::from("fine"), String::from("escape"), None),
+            ]
+        );
+
+        let cycle = parser()
+            .parse(
+                r"
+.shell pwsh
+
+mode a : b
+mode b : a
+a > h : echo a",
+            )
+            .unwrap();
 
-        parser()
-            .parse(contents)
-            .map_err(|error| eyre!("could not parse whkdrc: {:?}", error))
+        assert!(cycle.inherited_bindings().is_err());
     }
 }
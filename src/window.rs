@@ -0,0 +1,354 @@
+use crate::focus;
+use crate::log;
+use crate::parser::MonitorTarget;
+use crate::parser::WindowAction;
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use windows_sys::Win32::Foundation::BOOL;
+use windows_sys::Win32::Foundation::HWND;
+use windows_sys::Win32::Foundation::LPARAM;
+use windows_sys::Win32::Foundation::RECT;
+use windows_sys::Win32::Graphics::Dwm::DwmGetWindowAttribute;
+use windows_sys::Win32::Graphics::Dwm::DWMWA_CLOAKED;
+use windows_sys::Win32::Graphics::Gdi::EnumDisplayMonitors;
+use windows_sys::Win32::Graphics::Gdi::GetMonitorInfoW;
+use windows_sys::Win32::Graphics::Gdi::MonitorFromWindow;
+use windows_sys::Win32::Graphics::Gdi::HDC;
+use windows_sys::Win32::Graphics::Gdi::HMONITOR;
+use windows_sys::Win32::Graphics::Gdi::MONITORINFO;
+use windows_sys::Win32::Graphics::Gdi::MONITOR_DEFAULTTONEAREST;
+use windows_sys::Win32::Graphics::Gdi::MONITOR_DEFAULTTOPRIMARY;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetClassNameW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetWindowLongW;
+use windows_sys::Win32::UI::WindowsAndMessaging::GetWindowRect;
+use windows_sys::Win32::UI::WindowsAndMessaging::IsIconic;
+use windows_sys::Win32::UI::WindowsAndMessaging::IsZoomed;
+use windows_sys::Win32::UI::WindowsAndMessaging::PostMessageW;
+use windows_sys::Win32::UI::WindowsAndMessaging::SetWindowPos;
+use windows_sys::Win32::UI::WindowsAndMessaging::ShowWindow;
+use windows_sys::Win32::UI::WindowsAndMessaging::GWL_EXSTYLE;
+use windows_sys::Win32::UI::WindowsAndMessaging::HWND_NOTOPMOST;
+use windows_sys::Win32::UI::WindowsAndMessaging::HWND_TOPMOST;
+use windows_sys::Win32::UI::WindowsAndMessaging::SWP_NOACTIVATE;
+use windows_sys::Win32::UI::WindowsAndMessaging::SWP_NOMOVE;
+use windows_sys::Win32::UI::WindowsAndMessaging::SWP_NOSIZE;
+use windows_sys::Win32::UI::WindowsAndMessaging::SWP_NOZORDER;
+use windows_sys::Win32::UI::WindowsAndMessaging::SW_MAXIMIZE;
+use windows_sys::Win32::UI::WindowsAndMessaging::SW_MINIMIZE;
+use windows_sys::Win32::UI::WindowsAndMessaging::SW_RESTORE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WM_CLOSE;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_TOOLWINDOW;
+use windows_sys::Win32::UI::WindowsAndMessaging::WS_EX_TOPMOST;
+
+/// Classes of the desktop and the taskbar, which are in the foreground when no
+/// window is and shouldn't be closed or moved
+const SHELL_CLASSES: [&str; 3] = ["Progman", "WorkerW", "Shell_TrayWnd"];
+
+pub fn perform(action: WindowAction) {
+    if let Err(error) = perform_on_foreground(action) {
+        log::error(format!("could not perform {action}: {error}"));
+    }
+}
+
+fn perform_on_foreground(action: WindowAction) -> Result<()> {
+    let hwnd = unsafe { GetForegroundWindow() };
+
+    // the focus can move on from the desktop, unlike the other actions
+    if let WindowAction::FocusMonitor(target) = action {
+        return focus_monitor(hwnd, target);
+    }
+
+    if hwnd == 0 || is_shell(hwnd) {
+        return Err(eyre!("no window is focused"));
+    }
+
+    unsafe {
+        match action {
+            WindowAction::Minimize => {
+                ShowWindow(hwnd, SW_MINIMIZE);
+            }
+            WindowAction::Maximize => {
+                ShowWindow(
+                    hwnd,
+                    if IsZoomed(hwnd) == 0 {
+                        SW_MAXIMIZE
+                    } else {
+                        SW_RESTORE
+                    },
+                );
+            }
+            // asking the window to close lets it prompt to save unsaved changes
+            WindowAction::Close => {
+                PostMessageW(hwnd, WM_CLOSE, 0, 0);
+            }
+            WindowAction::ToggleTopmost => {
+                #[allow(clippy::cast_sign_loss)]
+                let topmost = GetWindowLongW(hwnd, GWL_EXSTYLE) as u32 & WS_EX_TOPMOST != 0;
+                let insert_after = if topmost {
+                    HWND_NOTOPMOST
+                } else {
+                    HWND_TOPMOST
+                };
+                SetWindowPos(hwnd, insert_after, 0, 0, 0, 0, SWP_NOMOVE | SWP_NOSIZE);
+            }
+            WindowAction::Center => {
+                let window = window_rect(hwnd)?;
+                let work = monitor_info(MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST))?.rcWork;
+                let (x, y) = centered(&window, &work);
+                move_window(hwnd, x, y);
+            }
+            WindowAction::MoveToMonitor(target) => move_to_monitor(hwnd, target)?,
+            WindowAction::FocusMonitor(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn is_shell(hwnd: HWND) -> bool {
+    let mut buffer = [0u16; 64];
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let len = unsafe { GetClassNameW(hwnd, buffer.as_mut_ptr(), buffer.len() as i32) };
+
+    #[allow(clippy::cast_sign_loss)]
+    let class = String::from_utf16_lossy(&buffer[..len.max(0) as usize]);
+    SHELL_CLASSES.contains(&class.as_str())
+}
+
+/// Whether a window can be switched to with alt + tab, which leaves out tool
+/// windows and the windows hidden by DWM, such as suspended UWP apps
+fn is_switchable(hwnd: HWND) -> bool {
+    #[allow(clippy::cast_sign_loss)]
+    let tool = unsafe { GetWindowLongW(hwnd, GWL_EXSTYLE) } as u32 & WS_EX_TOOLWINDOW != 0;
+
+    let mut cloaked = 0u32;
+    #[allow(clippy::cast_possible_truncation)]
+    let size = std::mem::size_of::<u32>() as u32;
+    unsafe {
+        DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_CLOAKED as u32,
+            std::ptr::addr_of_mut!(cloaked).cast(),
+            size,
+        );
+    }
+
+    !tool && cloaked == 0 && unsafe { IsIconic(hwnd) } == 0 && !is_shell(hwnd)
+}
+
+fn window_rect(hwnd: HWND) -> Result<RECT> {
+    let mut rect = RECT {
+        left: 0,
+        top: 0,
+        right: 0,
+        bottom: 0,
+    };
+
+    if unsafe { GetWindowRect(hwnd, std::ptr::addr_of_mut!(rect)) } == 0 {
+        return Err(eyre!(
+            "could not get the position of the window: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(rect)
+}
+
+pub fn monitor_info(monitor: HMONITOR) -> Result<MONITORINFO> {
+    let empty = RECT {
+        left: 0,
+        top: 0,
+        right: 0,
+        bottom: 0,
+    };
+
+    let mut info = MONITORINFO {
+        #[allow(clippy::cast_possible_truncation)]
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        rcMonitor: empty,
+        rcWork: empty,
+        dwFlags: 0,
+    };
+
+    if unsafe { GetMonitorInfoW(monitor, std::ptr::addr_of_mut!(info)) } == 0 {
+        return Err(eyre!("could not get the size of the monitor"));
+    }
+
+    Ok(info)
+}
+
+/// Every monitor from left to right, and top to bottom where they are stacked
+pub fn monitors() -> Vec<(HMONITOR, MONITORINFO)> {
+    unsafe extern "system" fn visit(
+        monitor: HMONITOR,
+        _: HDC,
+        _: *mut RECT,
+        lparam: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(lparam as *mut Vec<HMONITOR>);
+        monitors.push(monitor);
+        1
+    }
+
+    let mut handles: Vec<HMONITOR> = vec![];
+    unsafe {
+        EnumDisplayMonitors(
+            0,
+            std::ptr::null(),
+            Some(visit),
+            std::ptr::addr_of_mut!(handles) as LPARAM,
+        );
+    }
+
+    let mut monitors = handles
+        .into_iter()
+        .filter_map(|monitor| Some((monitor, monitor_info(monitor).ok()?)))
+        .collect::<Vec<_>>();
+
+    monitors.sort_by_key(|(_, info)| (info.rcMonitor.left, info.rcMonitor.top));
+    monitors
+}
+
+/// The index of the monitor to move to from the current one, if there is one
+fn target_index(current: usize, count: usize, target: MonitorTarget) -> Option<usize> {
+    match target {
+        MonitorTarget::Next => Some((current + 1) % count),
+        MonitorTarget::Prev => Some((current + count - 1) % count),
+        MonitorTarget::Number(number) => (number <= count).then(|| number - 1),
+    }
+}
+
+/// The position which centers the window in the area
+const fn centered(window: &RECT, area: &RECT) -> (i32, i32) {
+    let width = window.right - window.left;
+    let height = window.bottom - window.top;
+
+    (
+        area.left + (area.right - area.left - width) / 2,
+        area.top + (area.bottom - area.top - height) / 2,
+    )
+}
+
+/// The position which keeps the window where it was relative to the work area
+/// it was in, while keeping it inside of the work area it moves to
+fn relocated(window: &RECT, from: &RECT, to: &RECT) -> (i32, i32) {
+    let width = window.right - window.left;
+    let height = window.bottom - window.top;
+
+    let x = to.left + (window.left - from.left);
+    let y = to.top + (window.top - from.top);
+
+    (
+        x.min(to.right - width).max(to.left),
+        y.min(to.bottom - height).max(to.top),
+    )
+}
+
+fn move_window(hwnd: HWND, x: i32, y: i32) {
+    unsafe {
+        SetWindowPos(
+            hwnd,
+            0,
+            x,
+            y,
+            0,
+            0,
+            SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+        );
+    }
+}
+
+fn move_to_monitor(hwnd: HWND, target: MonitorTarget) -> Result<()> {
+    let monitors = monitors();
+    let current = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) };
+    let Some(current) = monitors.iter().position(|(monitor, _)| *monitor == current) else {
+        return Err(eyre!("could not find the monitor of the window"));
+    };
+
+    let Some(index) = target_index(current, monitors.len(), target) else {
+        return Err(eyre!("there are only {} monitors", monitors.len()));
+    };
+
+    if index == current {
+        return Ok(());
+    }
+
+    // maximized windows are moved at their restored size and maximized again
+    let maximized = unsafe { IsZoomed(hwnd) } != 0;
+    if maximized {
+        unsafe { ShowWindow(hwnd, SW_RESTORE) };
+    }
+
+    let window = window_rect(hwnd)?;
+    let (x, y) = relocated(
+        &window,
+        &monitors[current].1.rcWork,
+        &monitors[index].1.rcWork,
+    );
+    move_window(hwnd, x, y);
+
+    if maximized {
+        unsafe { ShowWindow(hwnd, SW_MAXIMIZE) };
+    }
+
+    Ok(())
+}
+
+fn focus_monitor(hwnd: HWND, target: MonitorTarget) -> Result<()> {
+    let monitors = monitors();
+    // with nothing in the foreground the monitor of the desktop is the
+    // primary one, where Windows puts it
+    let current = unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTOPRIMARY) };
+    let Some(current) = monitors.iter().position(|(monitor, _)| *monitor == current) else {
+        return Err(eyre!("could not find the monitor of the focused window"));
+    };
+
+    let Some(index) = target_index(current, monitors.len(), target) else {
+        return Err(eyre!("there are only {} monitors", monitors.len()));
+    };
+
+    let monitor = monitors[index].0;
+    let Some(window) = focus::find_window(&|hwnd| {
+        is_switchable(hwnd)
+            && unsafe { MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST) } == monitor
+    }) else {
+        return Err(eyre!("there is no window on monitor {}", index + 1));
+    };
+
+    focus::focus(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn rect(left: i32, top: i32, right: i32, bottom: i32) -> RECT {
+        RECT {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        let left = rect(0, 0, 1920, 1040);
+        let right = rect(1920, 0, 4480, 1400);
+
+        assert_eq!(centered(&rect(0, 0, 800, 600), &left), (560, 220));
+
+        let window = rect(100, 50, 900, 650);
+        assert_eq!(relocated(&window, &left, &right), (2020, 50));
+
+        // too far to the right and bottom of the monitor it moves to
+        let window = rect(3600, 900, 4400, 1500);
+        assert_eq!(relocated(&window, &right, &left), (1120, 440));
+
+        assert_eq!(target_index(1, 2, MonitorTarget::Next), Some(0));
+        assert_eq!(target_index(0, 2, MonitorTarget::Prev), Some(1));
+        assert_eq!(target_index(0, 2, MonitorTarget::Number(2)), Some(1));
+        assert_eq!(target_index(0, 2, MonitorTarget::Number(3)), None);
+    }
+}